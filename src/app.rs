@@ -1,16 +1,173 @@
-use crate::git;
-use ratatui::style::{Color, Style};
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-use std::sync::mpsc;
+use crate::apply_preview::{format_size, ApplyPreview, SparseSpec};
+use crate::backend::{CliBackend, GitBackend};
+use crate::config::Config;
+use crate::dir_index::DirIndex;
+use crate::dir_stats::{self, DirStats, TreeSizes};
+use crate::fuzzy;
+use crate::git::{self, ChangeKind, FileStatus, SparseMode};
+use crate::history::{PendingSnapshot, UndoHistory};
+use crate::keymap::Action;
+use crate::message_log::{Level, MessageLog};
+use crate::profiles::{self, Profile, ProfileDiff, Profiles, ResolvedProfile};
+use crate::repo_path::RepoPath;
+use crate::sparse_patterns::{self, MatchCount, PatternMatch};
+use crate::watcher::{RepoStamp, WatchedPaths};
+use ratatui::layout::Rect;
+use ratatui::style::Style;
+use ratatui::text::Span;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
 use std::thread;
 
 // Define messages that can be sent from background threads to the main thread
 #[derive(Debug)] // Add this line
 pub enum AppMessage {
-    ApplyChangesCompleted(Result<(), git::Error>),
-    ChildrenLoaded(Result<(usize, Vec<String>), git::Error>), // Changed Vec<PathBuf> to Vec<String>
-    RefreshCompleted(Result<(Vec<String>, HashSet<String>), git::Error>), // Changed Vec<PathBuf>, HashSet<PathBuf> to Vec<String>, HashSet<String>
+    ApplyChangesCompleted(Result<(), git::Error>, Option<RepoStamp>), // With the watched files as the write left them
+    ChildrenLoaded(Result<(usize, Vec<RepoPath>), git::Error>),
+    RefreshCompleted(u64, Result<(SparseState, HashMap<RepoPath, FileStatus>), git::Error>), // With the refresh's number
+    UntrackedLoaded(u64, Result<HashMap<RepoPath, FileStatus>, git::Error>), // With the listing's number
+    RevisionLoaded(String, Result<InitialTree, git::Error>), // The top-level tree of the revision to switch to
+    RevisionsListed(Result<Vec<String>, git::Error>), // Branches and tags for the revision picker
+    DirIndexBuilt(Result<DirIndexUpdate, git::Error>),
+    SparseModeChanged(Result<(), git::Error>, Option<RepoStamp>), // `sparse-checkout init` or `disable` finished
+    ApplyPreviewReady(Result<ApplyPreview, git::Error>),
+    TreeSizesLoaded(Result<Vec<TreeSizes>, git::Error>),
+    RepositoryChanged(RepoStamp), // Git changed `HEAD`, the index, the sparse-checkout or a branch
+    GitCommandFinished(git::CommandRecord), // For the message log
+}
+
+/// What the tree view starts from: the uncommitted paths and the top-level directories
+/// of the browsed revision.
+pub type InitialTree = (HashMap<RepoPath, FileStatus>, Vec<RepoPath>);
+
+/// The sparse-checkout configuration of the worktree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SparseState {
+    pub mode: SparseMode,
+    pub dirs: Vec<RepoPath>,       // The cone-mode directory set; empty in pattern mode
+    pub patterns: Vec<Vec<u8>>,    // The pattern-mode file lines; empty in cone mode
+}
+
+/// State of the sparse-checkout pattern editor popup (non-cone mode).
+#[derive(Debug, Clone, Default)]
+pub struct PatternEditor {
+    pub selected: usize,       // Index into the pattern lines
+    pub input: Option<String>, // Text being typed while a line is edited
+    pub is_new_line: bool,     // Whether `input` is inserted below `selected` rather than replacing it
+}
+
+/// Dialogs for turning sparse-checkout on or off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SparseModeDialog {
+    Init { sparse_index: bool }, // Offered on startup when the repository is not sparse
+    ConfirmDisable,
+}
+
+/// Directory indexes built in the background for the browsed revision and for `HEAD`.
+#[derive(Debug)]
+pub struct DirIndexUpdate {
+    pub revision: String,         // The revision the indexes were built for
+    pub browsed: Option<DirIndex>, // None if the browsed revision's tree is unchanged
+    pub head: Option<DirIndex>,    // None if unchanged or the same tree as `browsed`
+    pub head_is_browsed: bool,     // Whether the browsed revision has HEAD's tree
+}
+
+/// State of the revision picker popup.
+#[derive(Debug, Clone, Default)]
+pub struct RevisionPicker {
+    pub input: String,
+    pub candidates: Vec<String>, // Branches and tags, with "HEAD" first
+    pub selected: usize,         // Index into `matches()`
+}
+
+impl RevisionPicker {
+    /// Candidates containing the typed text.
+    pub fn matches(&self) -> Vec<&str> {
+        self.candidates
+            .iter()
+            .filter(|candidate| candidate.contains(self.input.as_str()))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The selected candidate, or the typed text itself if nothing matches it
+    /// (so that any commit-ish such as `v1.2~3` can be entered).
+    pub fn choice(&self) -> Option<String> {
+        match self.matches().get(self.selected) {
+            Some(candidate) => Some(candidate.to_string()),
+            None if !self.input.trim().is_empty() => Some(self.input.trim().to_string()),
+            None => None,
+        }
+    }
+}
+
+/// How many search matches filter mode reveals in the tree.
+const MAX_FILTERED_MATCHES: usize = 1000;
+
+/// State of the `/` search over every directory of the browsed revision.
+#[derive(Debug, Clone, Default)]
+pub struct Search {
+    pub query: String,
+    pub is_typing: bool,    // Whether keys go to the query
+    pub is_filtering: bool, // Whether the tree only shows matches and their ancestors
+    candidates: Vec<(RepoPath, String)>, // Every directory with its display form
+    pub matches: Vec<RepoPath>, // Best first
+    pub selected: usize,        // Index into `matches`
+    filter_paths: HashSet<RepoPath>, // Shown matches and their ancestors, in filter mode
+}
+
+/// State of the profiles popup.
+#[derive(Debug, Clone, Default)]
+pub struct ProfilePicker {
+    pub profiles: Profiles,
+    pub selected: usize,                            // Index into `profiles.names()`
+    pub name_input: Option<String>,                 // Name typed to save the current set under
+    pub diff_base: Option<String>,                  // First profile picked for a diff
+    pub diff: Option<(String, String, ProfileDiff)>, // Shown until the next key
+}
+
+impl ProfilePicker {
+    pub fn selected_name(&self) -> Option<String> {
+        self.profiles.names().get(self.selected).cloned()
+    }
+}
+
+/// Outcome of a bulk selection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BulkResult {
+    pub changed: usize, // Directories whose pending change was set or cleared
+    pub locked: usize,  // Directories left alone because they contain uncommitted changes
+}
+
+/// What to do with every item of the visual range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeAction {
+    Toggle, // As `Space` would, item by item
+    Add,
+    Remove,
+    Clear, // Drop pending changes
+}
+
+/// Which panel the navigation keys go to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Focus {
+    #[default]
+    Tree,
+    Grid, // The list of uncommitted files below the selected directory
+    Log,  // The message log, while it is shown
+}
+
+/// Where the last frame drew the panels, for mouse hit-testing. Empty while a progress
+/// screen replaces them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScreenLayout {
+    pub tree: Rect,
+    pub tree_offset: usize, // First tree row in view, as the list scrolled it
+    pub grid: Rect,         // The table and the list of uncommitted files
+    pub files: Rect,        // Only the list of uncommitted files
+    pub log: Rect,          // The message log, while it is shown
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
@@ -24,23 +181,40 @@ pub enum ChangeType {
 pub struct TuiTreeItemViewModel {
     pub display_text: String,
     pub style: Style,
+    pub size_text: String, // Right-aligned file count and size; empty while not loaded
+    pub expansion_columns: Range<u16>, // Where `display_text` shows the expansion glyph
+    pub state_columns: Range<u16>,     // Where `display_text` shows the state glyph
+}
+
+/// One line of the grid view's list of uncommitted files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UncommittedFileViewModel {
+    pub status: String, // Two-letter code, as in `git status --porcelain=v2`
+    pub path: String,
+    pub blocks_removal: bool, // Whether this file locks the directory
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct GridViewModel {
     pub name: String,
-    pub path: String, // Keep as String for display purposes (unescaped)
+    pub path: String, // Lossless display form of the path (see RepoPath::display)
     pub status: String,
-    pub uncommitted: String,
+    pub uncommitted: String,         // e.g. "3 files: 2 modified, 1 untracked"
+    pub uncommitted_files: Vec<UncommittedFileViewModel>, // In path order
     pub subdirectories_total: String,
     pub subdirectories_checked_out: String,
     pub pending_changes: String,
+    pub revision: String,
+    pub in_head: String, // Empty when browsing HEAD itself
+    pub files: String,   // Recursive file count and size at the browsed revision
+    pub on_disk: String, // The part of HEAD's files that is checked out
+    pub profile: String, // How the last loaded profile includes the directory, if it does
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TreeItem {
-    pub path: String, // Changed from PathBuf to String (unescaped path)
-    pub name: String, // Unescaped name for display
+    pub path: RepoPath, // Raw path relative to the repo root
+    pub name: RepoPath, // Last path component
     pub children_indices: Vec<usize>, // Indices of direct children in the App's items vec
     pub parent_index: Option<usize>,
     pub is_expanded: bool,
@@ -48,7 +222,7 @@ pub struct TreeItem {
     pub is_checked_out: bool,
     pub pending_change: Option<ChangeType>,
     pub is_locked: bool,                    // If this item cannot be deselected
-    pub contains_uncommitted_changes: bool, // For determining `is_locked`
+    pub change_kinds: BTreeSet<ChangeKind>, // Of the uncommitted paths below, for determining `is_locked`
     pub has_checked_out_descendant: bool,
     pub is_implicitly_checked_out: bool,
     pub is_loading: bool,
@@ -57,7 +231,7 @@ pub struct TreeItem {
 }
 
 impl TreeItem {
-    pub fn new(path: String, name: String, is_checked_out: bool) -> Self { // Changed path to String
+    pub fn new(path: RepoPath, name: RepoPath, is_checked_out: bool) -> Self {
         TreeItem {
             path,
             name,
@@ -68,7 +242,7 @@ impl TreeItem {
             is_checked_out,
             pending_change: None,
             is_locked: false,
-            contains_uncommitted_changes: false,
+            change_kinds: BTreeSet::new(),
             has_checked_out_descendant: false,
             is_implicitly_checked_out: false,
             is_loading: false,
@@ -76,13 +250,12 @@ impl TreeItem {
             cached_pending_changes: 0,
         }
     }
-}
 
-// Helper to check if a string path starts with another string path as a component
-// e.g., "foo/bar" starts with "foo" -> true
-//       "foo/bar" starts with "foobar" -> false
-fn path_starts_with_component(path: &str, prefix: &str) -> bool {
-    path.starts_with(prefix) && (path.len() == prefix.len() || path.as_bytes()[prefix.len()] == b'/')
+    /// Locks the directory if any of the changes below it block removal.
+    pub fn set_change_kinds(&mut self, change_kinds: BTreeSet<ChangeKind>) {
+        self.is_locked = change_kinds.iter().any(|kind| kind.blocks_removal());
+        self.change_kinds = change_kinds;
+    }
 }
 
 #[derive(Debug)]
@@ -90,22 +263,82 @@ pub struct App {
     #[allow(dead_code)] // Will be used in UI and other places
     pub current_repo_root: PathBuf, // Remains PathBuf
     pub items: Vec<TreeItem>, // Flat list of all directories
-    pub path_to_index: HashMap<String, usize>, // Changed key from PathBuf to String
+    pub path_to_index: HashMap<RepoPath, usize>,
     pub filtered_item_indices: Vec<usize>, // Indices of items currently visible in the TUI
     pub selected_item_index: usize, // Index into `filtered_item_indices`
     #[allow(dead_code)] // Will be used for TUI scrolling
     pub scroll_offset: usize, // For scrolling the TUI view
-    pub last_git_error: Option<String>, // To display transient git errors
+    last_git_error: Option<(Level, String)>, // Shown in the footer until the next key; set by `report`
     pub is_applying_changes: bool, // New field to indicate if changes are being applied
 
     pub is_refreshing: bool, // New field to indicate if a refresh is in progress
+    refresh_generation: u64, // Number of the latest refresh, whose result is the one to keep
+    pub is_indexing: bool, // Whether the directory index is being (re)built
+    reindex_requested: bool, // Whether a rebuild was asked for while one was running
     pub tx: mpsc::Sender<AppMessage>, // Sender for background tasks to send messages to App
     #[allow(dead_code)] // Will be used by the main loop
     pub rx: mpsc::Receiver<AppMessage>, // Receiver for App to get messages from background tasks
+    pub backend: Arc<dyn GitBackend>, // Where git queries and operations are sent
 
     // Cached git state
-    pub sparse_checkout_dirs: Vec<String>, // Changed from Vec<PathBuf> to Vec<String>
-    pub uncommitted_paths: HashSet<String>, // Changed from HashSet<PathBuf> to HashSet<String>
+    pub sparse_checkout_dirs: Vec<RepoPath>,
+    pub uncommitted_paths: HashMap<RepoPath, FileStatus>, // Tracked paths only, which decide locks
+    pub untracked_paths: HashMap<RepoPath, FileStatus>, // Untracked and ignored paths under `untracked_dir`
+    pub untracked_dir: Option<RepoPath>, // The directory they were listed for, or are being listed for
+    pub is_loading_untracked: bool,
+    untracked_generation: u64, // Number of the latest listing, whose result is the one to keep
+    warn_when_untracked_loaded: bool, // Whether `untracked_dir` was marked for removal before its listing arrived
+    pub revision: String, // The commit-ish whose tree is being browsed
+    pub loading_revision: Option<String>, // The revision to switch to, while it is checked and its tree loaded
+    pub dir_index: Option<Arc<DirIndex>>, // All directories of the browsed revision, once built
+    pub head_dir_index: Option<Arc<DirIndex>>, // All directories at HEAD, to compare against
+    pub revision_picker: Option<RevisionPicker>, // Open while choosing a revision
+    pub is_listing_revisions: bool, // Whether the picker's revisions are being listed
+
+    // Non-cone (pattern) mode
+    pub sparse_mode: SparseMode,
+    pub sparse_patterns: Vec<Vec<u8>>, // The patterns as applied
+    pub pending_patterns: Option<Vec<Vec<u8>>>, // Edited patterns, until applied
+    pub pattern_match_counts: HashMap<RepoPath, MatchCount>, // Per directory, for the effective patterns
+    pub pattern_editor: Option<PatternEditor>, // Open while editing patterns
+
+    pub sparse_mode_dialog: Option<SparseModeDialog>, // Open while offering to init or disable
+    pub is_changing_sparse_mode: bool, // Whether `sparse-checkout init` or `disable` is running
+    pub watched_paths: Option<WatchedPaths>, // Set while the repository is watched, to stamp pickit's own writes
+    own_write_stamp: Option<RepoStamp>, // The watched files as pickit's last sparse-checkout write left them
+    pub force_remove_dialog: Option<usize>, // The locked item to remove anyway, once confirmed
+
+    pub is_previewing: bool, // Whether the effect of the pending changes is being computed
+    pub apply_preview: Option<ApplyPreview>, // Shown for confirmation before applying
+
+    // Directory sizes
+    pub tree_sizes: HashMap<String, Arc<TreeSizes>>, // Blob sizes per root tree id
+    pub is_loading_sizes: bool,
+    pub on_disk_totals: HashMap<RepoPath, DirStats>, // HEAD's files under the current sparse set
+    pub show_size_columns: bool, // Whether the tree shows file counts and sizes
+
+    pub profile_picker: Option<ProfilePicker>, // Open while browsing profiles
+    pub search: Option<Search>, // Active from `/` until cleared
+    pub visual_anchor: Option<usize>, // Item where the visual range started, while in visual mode
+    pub focus: Focus,
+    pub uncommitted_scroll: usize, // First shown line of the grid's list of uncommitted files
+    pub uncommitted_page: usize,   // How many lines of that list fit, as last rendered
+    pub layout: ScreenLayout,
+    pub help_scroll: Option<usize>, // First shown line of the key help, while it is open
+    pub help_page: usize,           // How many lines of the help fit, as last rendered
+    pub log: MessageLog,
+    pub show_log: bool,  // Whether the message log pane is shown
+    pub log_scroll: usize, // Lines the log is scrolled up from its newest line
+    pub log_page: usize,   // How many lines of the log fit, as last rendered
+    pub config: Config, // Keys, colors and glyphs of the TUI
+    pub loaded_profile: Option<ResolvedProfile>, // The profile last loaded into pending changes
+
+    // History
+    pub undo_history: UndoHistory<PendingSnapshot>,
+    is_recording_undo: bool, // Whether an undoable action is running, so nested ones are part of it
+    pub applied_history: Vec<SparseState>, // The state before each apply, oldest first
+    state_before_apply: Option<SparseState>, // Pushed to `applied_history` once an apply succeeds
+    is_reverting_apply: bool, // Whether the pending changes go back to the last entry of `applied_history`
 }
 
 impl Default for App {
@@ -121,42 +354,66 @@ impl Default for App {
             is_applying_changes: false,
 
             is_refreshing: false, // Initialize new field
+            refresh_generation: 0,
+            is_indexing: false,
+            reindex_requested: false,
             tx: mpsc::channel().0,      // Initialize sender (dummy, will be replaced in App::new)
             rx: mpsc::channel().1,      // Initialize receiver (dummy, will be replaced in App::new)
+            backend: Arc::new(CliBackend),
             sparse_checkout_dirs: Vec::new(),
-            uncommitted_paths: HashSet::new(),
+            uncommitted_paths: HashMap::new(),
+            untracked_paths: HashMap::new(),
+            untracked_dir: None,
+            is_loading_untracked: false,
+            untracked_generation: 0,
+            warn_when_untracked_loaded: false,
+            revision: "HEAD".to_string(),
+            loading_revision: None,
+            dir_index: None,
+            head_dir_index: None,
+            revision_picker: None,
+            is_listing_revisions: false,
+            sparse_mode: SparseMode::default(),
+            sparse_patterns: Vec::new(),
+            pending_patterns: None,
+            pattern_match_counts: HashMap::new(),
+            pattern_editor: None,
+            sparse_mode_dialog: None,
+            is_changing_sparse_mode: false,
+            watched_paths: None,
+            own_write_stamp: None,
+            force_remove_dialog: None,
+            is_previewing: false,
+            apply_preview: None,
+            tree_sizes: HashMap::new(),
+            is_loading_sizes: false,
+            on_disk_totals: HashMap::new(),
+            show_size_columns: false,
+            profile_picker: None,
+            search: None,
+            visual_anchor: None,
+            focus: Focus::default(),
+            uncommitted_scroll: 0,
+            uncommitted_page: 1,
+            layout: ScreenLayout::default(),
+            help_scroll: None,
+            help_page: 1,
+            log: MessageLog::default(),
+            show_log: false,
+            log_scroll: 0,
+            log_page: 1,
+            config: Config::default(),
+            loaded_profile: None,
+            undo_history: UndoHistory::default(),
+            is_recording_undo: false,
+            applied_history: Vec::new(),
+            state_before_apply: None,
+            is_reverting_apply: false,
         }
     }
 }
 
 impl App {
-    // Helper to extract the parent path from a path string
-    // e.g., "foo bar/baz" -> "foo bar"
-    //       "foo" -> ""
-    #[allow(dead_code)]
-    fn path_parent(path: &str) -> Option<String> {
-        path.rfind('/').map(|i| path[..i].to_string())
-    }
-
-    // Helper to extract the file/directory name from a path string
-    // e.g., "foo bar/baz" -> "baz"
-    //       "foo" -> "foo"
-    #[allow(dead_code)]
-    fn path_file_name(path: &str) -> Option<String> {
-        path.rfind('/').map(|i| path[i + 1..].to_string())
-    }
-
-    // Helper to join two path strings
-    // This assumes `other` is a simple component or relative path
-    #[allow(dead_code)]
-    fn path_join(base: &str, other: &str) -> String {
-        if base.is_empty() {
-            other.to_string()
-        } else {
-            format!("{}/{}", base, other)
-        }
-    }
-
     // New helper function to recursively update cached_pending_changes
     fn update_pending_changes_cache(&mut self, item_idx: usize) {
         let mut current_pending_changes = 0;
@@ -185,24 +442,213 @@ impl App {
         }
     }
 
-    pub fn handle_refresh_completed(&mut self, result: Result<(Vec<String>, HashSet<String>), git::Error>) {
+    /// Dispatches a message from background work to its handler.
+    pub fn handle_message(&mut self, message: AppMessage) {
+        match message {
+            AppMessage::ApplyChangesCompleted(result, stamp) => self.handle_apply_changes_completed(result, stamp),
+            AppMessage::ChildrenLoaded(result) => self.handle_children_loaded(result),
+            AppMessage::RefreshCompleted(generation, result) => self.handle_refresh_completed(generation, result),
+            AppMessage::UntrackedLoaded(generation, result) => self.handle_untracked_loaded(generation, result),
+            AppMessage::RevisionLoaded(revision, result) => self.handle_revision_loaded(revision, result),
+            AppMessage::RevisionsListed(result) => self.handle_revisions_listed(result),
+            AppMessage::DirIndexBuilt(result) => self.handle_dir_index_built(result),
+            AppMessage::SparseModeChanged(result, stamp) => self.handle_sparse_mode_changed(result, stamp),
+            AppMessage::ApplyPreviewReady(result) => self.handle_apply_preview_ready(result),
+            AppMessage::TreeSizesLoaded(result) => self.handle_tree_sizes_loaded(result),
+            AppMessage::RepositoryChanged(stamp) => self.handle_repository_changed(stamp),
+            AppMessage::GitCommandFinished(record) => self.log.push_command(&record),
+        }
+    }
+
+    /// Shows `message` in the footer until the next key, and keeps it in the message log.
+    pub fn report(&mut self, level: Level, message: impl Into<String>) {
+        let message = message.into();
+        self.log.push(level, message.clone());
+        self.last_git_error = Some((level, message));
+    }
+
+    /// Refuses an action that needs sparse-checkout, pointing to the key that initializes it.
+    fn report_sparse_disabled(&mut self) {
+        let message = format!("Sparse checkout is not enabled. Press {} to initialize it", self.config.keys.hint(Action::Init));
+        self.report(Level::Warn, message);
+    }
+
+    /// The message the footer shows, if any, with its level.
+    pub fn message(&self) -> Option<(Level, &str)> {
+        self.last_git_error.as_ref().map(|(level, message)| (*level, message.as_str()))
+    }
+
+    /// Clears the footer's message. It stays in the message log.
+    pub fn dismiss_message(&mut self) {
+        self.last_git_error = None;
+    }
+
+    pub fn take_message(&mut self) -> Option<String> {
+        self.last_git_error.take().map(|(_, message)| message)
+    }
+
+    /// Handles messages from background work until `done` holds, as the TUI's main loop
+    /// would. For running without a terminal; the TUI would stop drawing meanwhile.
+    pub fn wait_until(&mut self, done: impl Fn(&App) -> bool) {
+        while !done(self) {
+            match self.rx.recv() {
+                Ok(message) => self.handle_message(message),
+                Err(_) => break,
+            }
+        }
+    }
+
+    pub fn handle_apply_changes_completed(&mut self, result: Result<(), git::Error>, stamp: Option<RepoStamp>) {
+        self.is_applying_changes = false;
+        self.own_write_stamp = stamp;
+        let is_reverting_apply = std::mem::take(&mut self.is_reverting_apply);
+        let state_before_apply = self.state_before_apply.take();
+        match result {
+            Ok(()) => {
+                for item in self.items.iter_mut() {
+                    item.pending_change = None;
+                }
+                self.pending_patterns = None;
+                self.undo_history.clear(); // The pending changes are relative to the old set
+                if is_reverting_apply {
+                    self.applied_history.pop();
+                } else if let Some(state) = state_before_apply {
+                    self.applied_history.push(state);
+                }
+                self.refresh();
+            }
+            Err(e) => {
+                self.report(Level::Error, e.to_string());
+            }
+        }
+    }
+
+    /// Refreshes after a change made outside pickit. Pickit's own operations refresh when
+    /// they complete, so changes while they run are left to them, and so is the watcher's
+    /// report of their write, recognized by the files being as the write left them.
+    pub fn handle_repository_changed(&mut self, stamp: RepoStamp) {
+        let is_own_write_echo = self.own_write_stamp.as_ref() == Some(&stamp);
+        if self.is_applying_changes || self.is_changing_sparse_mode || is_own_write_echo {
+            return;
+        }
+        let message = self.last_git_error.take(); // Not the user's refresh, so keep what they are reading
+        self.refresh();
+        self.last_git_error = message;
+    }
+
+    pub fn handle_refresh_completed(
+        &mut self,
+        generation: u64,
+        result: Result<(SparseState, HashMap<RepoPath, FileStatus>), git::Error>,
+    ) {
+        if generation != self.refresh_generation {
+            return; // A later refresh is running, and read the repository after this one
+        }
         self.is_refreshing = false; // Refresh is complete
         match result {
-            Ok((sparse_checkout_dirs, uncommitted_paths)) => {
-                self.update_state_from_git_info(sparse_checkout_dirs, uncommitted_paths);
+            Ok((sparse_state, uncommitted_paths)) => {
+                let mode_changed = sparse_state.mode != self.sparse_mode;
+                self.sparse_mode = sparse_state.mode;
+                self.sparse_patterns = sparse_state.patterns;
+                if self.pending_patterns.as_ref() == Some(&self.sparse_patterns) {
+                    self.pending_patterns = None; // The edits were applied externally
+                }
+                self.update_state_from_git_info(sparse_state.dirs, uncommitted_paths);
+                self.update_on_disk_totals();
                 self.build_visible_items(); // Rebuild visible items after state update
+                if mode_changed {
+                    self.rebuild_dir_index(); // Pattern mode also needs the file list
+                    self.applied_history.clear();
+                    self.undo_history.clear();
+                }
+                self.update_pattern_matches();
+                if let Some(dir) = self.untracked_dir.clone() {
+                    self.load_untracked(dir); // Files may have been created or ignored meanwhile
+                }
+            }
+            Err(e) => {
+                self.report(Level::Error, e.to_string());
+            }
+        }
+    }
+
+    pub fn handle_untracked_loaded(&mut self, generation: u64, result: Result<HashMap<RepoPath, FileStatus>, git::Error>) {
+        if generation != self.untracked_generation {
+            return; // Another directory was selected, or the repository changed, meanwhile
+        }
+        self.is_loading_untracked = false;
+        match result {
+            Ok(untracked_paths) => {
+                self.untracked_paths = untracked_paths;
+                let idx = self.untracked_dir.as_ref().and_then(|dir| self.path_to_index.get(dir)).copied();
+                if let Some(idx) = idx.filter(|_| std::mem::take(&mut self.warn_when_untracked_loaded)) {
+                    if let Some(warning) = self.removal_warning(idx).filter(|_| self.items[idx].pending_change == Some(ChangeType::Remove)) {
+                        self.report(Level::Warn, warning);
+                    }
+                }
             }
             Err(e) => {
-                self.last_git_error = Some(e.to_string());
+                self.report(Level::Error, e.to_string());
             }
         }
     }
 
 
 
+    pub fn handle_dir_index_built(&mut self, result: Result<DirIndexUpdate, git::Error>) {
+        self.is_indexing = false;
+        // HEAD may have moved while indexing, so a skipped rebuild runs now
+        let mut rebuild = std::mem::take(&mut self.reindex_requested);
+        match result {
+            Ok(update) if update.revision != self.revision => {
+                // The revision was switched while indexing; start over for the new one
+                rebuild = true;
+            }
+            Ok(update) => {
+                if let Some(index) = update.browsed {
+                    self.dir_index = Some(Arc::new(index));
+                }
+                if update.head_is_browsed {
+                    self.head_dir_index = self.dir_index.clone();
+                } else if let Some(index) = update.head {
+                    self.head_dir_index = Some(Arc::new(index));
+                }
+                self.update_pattern_matches();
+                self.load_tree_sizes();
+                if let Some(search) = &mut self.search {
+                    search.candidates = Vec::new(); // Listed again from the new index
+                    if search.is_typing {
+                        self.update_search_matches();
+                    }
+                }
+            }
+            Err(e) => {
+                self.report(Level::Error, e.to_string());
+            }
+        }
+        if rebuild {
+            self.rebuild_dir_index();
+        }
+    }
+
+    pub fn handle_tree_sizes_loaded(&mut self, result: Result<Vec<TreeSizes>, git::Error>) {
+        self.is_loading_sizes = false;
+        match result {
+            Ok(loaded) => {
+                for sizes in loaded {
+                    self.tree_sizes.insert(sizes.tree_id.clone(), Arc::new(sizes));
+                }
+                self.load_tree_sizes(); // In case the indexed trees moved meanwhile
+            }
+            Err(e) => {
+                self.report(Level::Error, e.to_string());
+            }
+        }
+    }
+
     pub fn handle_children_loaded(
         &mut self,
-        result: Result<(usize, Vec<String>), git::Error>, // Changed Vec<PathBuf> to Vec<String>
+        result: Result<(usize, Vec<RepoPath>), git::Error>,
     ) {
         match result {
             Ok((parent_idx, sub_dirs)) => {
@@ -216,36 +662,27 @@ impl App {
 
                     let parent_item_path = self.items[parent_idx].path.clone(); // Get the full path of the parent
 
-                    for dir_name in sorted_sub_dirs { // dir_name is now the simple name of the directory
-                        let full_child_path = if parent_item_path == "." {
-                            dir_name.clone() // If parent is root, child path is just its name
-                        } else {
-                            format!("{}/{}", parent_item_path, dir_name)
-                        };
-                        let name = dir_name.clone(); // Name for display remains just the component name
+                    for dir_name in sorted_sub_dirs { // dir_name is the simple name of the directory
+                        let full_child_path = parent_item_path.join(&dir_name); // The root contributes no prefix
+                        let name = dir_name; // Name remains just the component name
 
                         if self.path_to_index.contains_key(&full_child_path) {
                             continue;
                         }
 
-                        let is_checked_out = self.sparse_checkout_dirs.contains(&full_child_path); // Compare String with String
+                        let is_checked_out = self.is_dir_checked_out(&full_child_path);
 
-                        let contains_uncommitted_changes = self
-                            .uncommitted_paths
-                            .iter()
-                            .any(|p| path_starts_with_component(p, &full_child_path)); // Use custom helper
-                        let is_locked = contains_uncommitted_changes;
+                        let change_kinds = change_kinds_under(&self.uncommitted_paths, &full_child_path);
 
-                        let mut item = TreeItem::new(full_child_path.clone(), name, is_checked_out); // Pass String for path
-                        item.contains_uncommitted_changes = contains_uncommitted_changes;
-                        item.is_locked = is_locked;
+                        let mut item = TreeItem::new(full_child_path.clone(), name, is_checked_out);
+                        item.set_change_kinds(change_kinds);
                         item.parent_index = Some(parent_idx);
                         item.indentation_level = self.items[parent_idx].indentation_level + 1;
                         item.cached_pending_changes = 0;
                         
                         let new_idx = self.items.len();
                         self.items[parent_idx].children_indices.push(new_idx);
-                        self.path_to_index.insert(full_child_path, new_idx); // Insert String
+                        self.path_to_index.insert(full_child_path, new_idx);
                         self.items.push(item);
                     }
                 }
@@ -262,7 +699,7 @@ impl App {
                 if let Some(loading_item) = self.items.iter_mut().find(|i| i.is_loading) {
                     loading_item.is_loading = false;
                 }
-                self.last_git_error = Some(e.to_string());
+                self.report(Level::Error, e.to_string());
             }
         }
     }
@@ -270,22 +707,13 @@ impl App {
         // Pass 1: Determine `has_checked_out_descendant` by checking `sparse_checkout_dirs`.
         // This is done once all items are loaded and `sparse_checkout_dirs` is up-to-date.
         for i in 0..self.items.len() {
-            let item_path = &self.items[i].path; // item_path is now &String (unescaped path)
+            let item_path = &self.items[i].path;
+            // For the root item, any sparse checkout path that is not "." itself indicates a
+            // checked out descendant; for other items `sco_path` must lie strictly below it.
             let has_descendant = self
                 .sparse_checkout_dirs
                 .iter()
-                .any(|sco_path| { // sco_path is now &String (unescaped path)
-                    if item_path == "." { // Compare String with "." (root path)
-                        // For the root item, any sparse checkout path that is not "." itself
-                        // indicates a checked out descendant.
-                        sco_path != "."
-                    } else {
-                        // For other items, check for true descendant paths.
-                        // `sco_path` must start with `item_path` as a component.
-                        path_starts_with_component(sco_path, item_path) &&
-                        sco_path.len() > item_path.len() // Ensure it's longer
-                    }
-                });
+                .any(|sco_path| sco_path.is_descendant_of(item_path));
             self.items[i].has_checked_out_descendant = has_descendant;
         }
 
@@ -320,17 +748,25 @@ impl App {
                     false
                 };
 
-            if parent_is_effectively_checked_out {
-                self.items[i].is_implicitly_checked_out = true;
-            } else {
-                self.items[i].is_implicitly_checked_out = false;
-            }
+            self.items[i].is_implicitly_checked_out = parent_is_effectively_checked_out;
         }
     }
 
     fn load_initial_tree(&mut self) -> Result<(), git::Error> {
-        // self.sparse_checkout_dirs is now loaded asynchronously in App::new
-        self.uncommitted_paths = git::get_uncommitted_paths(&self.current_repo_root)?;
+        let initial_tree = Self::read_initial_tree(self.backend.as_ref(), &self.revision, &self.current_repo_root)?;
+        self.build_initial_tree(initial_tree);
+        Ok(())
+    }
+
+    fn read_initial_tree(backend: &dyn GitBackend, revision: &str, repo_root: &Path) -> Result<InitialTree, git::Error> {
+        let uncommitted_paths = backend.get_uncommitted_paths(repo_root)?;
+        let top_level_dirs = backend.get_dirs_at_path(&RepoPath::root(), revision, repo_root)?;
+        Ok((uncommitted_paths, top_level_dirs))
+    }
+
+    /// Replaces the tree with the root and its top-level directories.
+    fn build_initial_tree(&mut self, (uncommitted_paths, top_level_dirs): InitialTree) {
+        self.uncommitted_paths = uncommitted_paths;
 
         // --- Build Initial Tree ---
         self.items.clear();
@@ -338,52 +774,41 @@ impl App {
 
         // 1. Create Root Item
         // For the root, the path is represented as "." internally.
-        let root_path = ".".to_string(); // Path is now unescaped, use "."
-        let root_name = self
-            .current_repo_root
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        let mut root_item = TreeItem::new(root_path.clone(), root_name, true); // Pass String
+        let root_path = RepoPath::root();
+        let root_name = RepoPath::from(self.current_repo_root.file_name().unwrap_or_default());
+        let mut root_item = TreeItem::new(root_path.clone(), root_name, true);
         root_item.is_locked = true;
         root_item.is_expanded = true;
         root_item.children_loaded = true;
         root_item.indentation_level = 0; // Root is at level 0
         root_item.cached_pending_changes = 0;
         self.items.push(root_item);
-        self.path_to_index.insert(root_path, 0); // Insert String
+        self.path_to_index.insert(root_path, 0);
 
-        // 2. Load Top-Level Dirs
-        let top_level_dirs = git::get_dirs_at_path(".", &self.current_repo_root)?; // Returns Vec<String> (unescaped paths)
+        // 2. Add Top-Level Dirs
         let mut sorted_top_level_dirs = top_level_dirs;
         sorted_top_level_dirs.sort();
 
-        for dir_path in sorted_top_level_dirs { // dir_path is now String (unescaped path)
-            let name = dir_path.clone(); // Path is now unescaped, use directly
+        for dir_path in sorted_top_level_dirs { // Top-level paths are their own names
+            let name = dir_path.clone();
 
             if self.path_to_index.contains_key(&dir_path) {
                 continue;
             }
 
-            let is_checked_out = self.sparse_checkout_dirs.contains(&dir_path); // Compare String with String
+            let is_checked_out = self.is_dir_checked_out(&dir_path);
 
-            let contains_uncommitted_changes = self
-                .uncommitted_paths
-                .iter()
-                .any(|p| path_starts_with_component(p, &dir_path)); // Use custom helper
-            let is_locked = contains_uncommitted_changes;
+            let change_kinds = change_kinds_under(&self.uncommitted_paths, &dir_path);
 
-            let mut item = TreeItem::new(dir_path.clone(), name, is_checked_out); // Pass String
-            item.contains_uncommitted_changes = contains_uncommitted_changes;
-            item.is_locked = is_locked;
+            let mut item = TreeItem::new(dir_path.clone(), name, is_checked_out);
+            item.set_change_kinds(change_kinds);
             item.parent_index = Some(0);
             item.indentation_level = 1; // Direct children of root are at level 1
             item.cached_pending_changes = 0; // Initialize to 0
             
             let new_idx = self.items.len();
             self.items[0].children_indices.push(new_idx);
-            self.path_to_index.insert(dir_path, new_idx); // Insert String
+            self.path_to_index.insert(dir_path, new_idx);
             self.items.push(item);
         }
         self.update_tree_item_states();
@@ -393,219 +818,1390 @@ impl App {
         for i in (0..self.items.len()).rev() {
             self.update_pending_changes_cache(i);
         }
-        
-        Ok(())
     }
 
     /// Applies the pending changes to the git sparse-checkout set in a separate thread.
     pub fn apply_changes(&mut self) {
         self.is_applying_changes = true;
-        self.last_git_error = None; // Clear previous errors
+        self.dismiss_message(); // Clear previous errors
+
+        if self.sparse_mode == SparseMode::Disabled {
+            self.is_applying_changes = false;
+            self.report_sparse_disabled();
+            return;
+        }
 
+        self.state_before_apply = Some(self.current_sparse_state());
         let repo_root = self.current_repo_root.clone();
         let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        let watched = self.watched_paths.clone();
+
+        if self.sparse_mode == SparseMode::Pattern {
+            let patterns = self.pattern_lines().to_vec();
+            thread::spawn(move || {
+                let result = backend.set_sparse_checkout_patterns(patterns, &repo_root);
+                let stamp = watched.as_ref().map(WatchedPaths::stamp);
+                let _ = tx_clone.send(AppMessage::ApplyChangesCompleted(result, stamp));
+            });
+            return;
+        }
+
+        let (dirs_to_add, dirs_to_remove) = self.pending_dir_changes();
 
-        let current_actual_sparse_list = match git::get_sparse_checkout_list(&repo_root) {
+        // Only additions: extend the set instead of rewriting it
+        if dirs_to_remove.is_empty() && !dirs_to_add.is_empty() {
+            thread::spawn(move || {
+                let result = backend.add_sparse_checkout_dirs(dirs_to_add, &repo_root);
+                let stamp = watched.as_ref().map(WatchedPaths::stamp);
+                let _ = tx_clone.send(AppMessage::ApplyChangesCompleted(result, stamp));
+            });
+            return;
+        }
+
+        let current_actual_sparse_list = match backend.get_sparse_checkout_list(&repo_root) {
             Ok(list) => list,
             Err(e) => {
-                let _ = tx_clone.send(AppMessage::ApplyChangesCompleted(Err(e)));
+                let _ = tx_clone.send(AppMessage::ApplyChangesCompleted(Err(e), None));
                 return;
             }
         };
-        // Convert Vec<String> (unescaped paths) to HashSet<String>
-        let mut final_sparse_checkout_set: HashSet<String> = current_actual_sparse_list.into_iter().collect();
-
-        // Apply pending changes from self.items on top of the actual git state
-        for item in self.items.iter() {
-            if item.path == "." { continue; } // Root is always implicitly checked out and cannot be changed
-
-            match item.pending_change {
-                Some(ChangeType::Add) => {
-                    final_sparse_checkout_set.insert(item.path.clone());
-                }
-                Some(ChangeType::Remove) => {
-                    final_sparse_checkout_set.remove(&item.path);
-                }
-                None => { /* No pending change, its state is already reflected in `final_sparse_checkout_set` */ }
-            }
-        }
-        
-        // Convert the final set to a Vec<String> for the git command (already unescaped paths)
-        let dirs_to_checkout: Vec<String> = final_sparse_checkout_set.into_iter().collect();
+        // Apply pending changes on top of the actual git state
+        let dirs_to_checkout = Self::proposed_dirs(current_actual_sparse_list, &dirs_to_add, &dirs_to_remove);
 
         // Spawn a new thread to perform the potentially long-running git operation
         thread::spawn(move || {
-            let result = git::set_sparse_checkout_dirs(dirs_to_checkout, &repo_root);
+            let result = backend.set_sparse_checkout_dirs(dirs_to_checkout, &repo_root);
+            let stamp = watched.as_ref().map(WatchedPaths::stamp);
             // Send the result back to the main thread
-            let _ = tx_clone.send(AppMessage::ApplyChangesCompleted(result));
+            let _ = tx_clone.send(AppMessage::ApplyChangesCompleted(result, stamp));
         });
 
         // The main thread returns immediately, letting the TUI continue to render.
     }
 
-    /// Refreshes the application state by re-reading the git repository.
-    /// Initiates an asynchronous refresh of the application state by re-reading the git repository.
-    pub fn refresh(&mut self) {
-        self.last_git_error = None; // Clear previous errors
-        let repo_root_clone = self.current_repo_root.clone();
+    /// Directories with a pending addition, and directories with a pending removal.
+    /// The root is always checked out and cannot be changed.
+    fn pending_dir_changes(&self) -> (Vec<RepoPath>, Vec<RepoPath>) {
+        let with_change = |change: ChangeType| -> Vec<RepoPath> {
+            self.items
+                .iter()
+                .filter(|item| !item.path.is_root() && item.pending_change == Some(change))
+                .map(|item| item.path.clone())
+                .collect()
+        };
+        (with_change(ChangeType::Add), with_change(ChangeType::Remove))
+    }
+
+    /// The cone-mode set after applying additions and removals to `current`.
+    fn proposed_dirs(current: Vec<RepoPath>, add: &[RepoPath], remove: &[RepoPath]) -> Vec<RepoPath> {
+        let mut dirs: HashSet<RepoPath> = current.into_iter().collect();
+        for dir in remove {
+            dirs.remove(dir);
+        }
+        dirs.extend(add.iter().cloned());
+        dirs.into_iter().collect()
+    }
+
+    /// Computes what applying the pending changes would do in a separate thread, for the
+    /// user to confirm before anything is run.
+    pub fn preview_changes(&mut self) {
+        self.dismiss_message();
+        if self.sparse_mode == SparseMode::Disabled {
+            self.report_sparse_disabled();
+            return;
+        }
+        let has_pending_changes = match self.sparse_mode {
+            SparseMode::Pattern => self.pending_patterns.is_some(),
+            _ => self.items.iter().any(|item| item.pending_change.is_some()),
+        };
+        if !has_pending_changes {
+            self.report(Level::Info, "No pending changes to apply");
+            return;
+        }
+
+        self.is_previewing = true;
+        let repo_root = self.current_repo_root.clone();
         let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        let mode = self.sparse_mode;
+        let (dirs_to_add, dirs_to_remove) = self.pending_dir_changes();
+        let (applied_patterns, pending_patterns) = (self.sparse_patterns.clone(), self.pattern_lines().to_vec());
 
         thread::spawn(move || {
-            let result: Result<(Vec<String>, HashSet<String>), git::Error> = (|| {
-                let sparse_checkout_dirs = git::get_sparse_checkout_list(&repo_root_clone)?;
-                let uncommitted_paths = git::get_uncommitted_paths(&repo_root_clone)?;
-                Ok((sparse_checkout_dirs, uncommitted_paths))
+            let result = (|| {
+                let (current, proposed) = match mode {
+                    SparseMode::Pattern => (SparseSpec::Patterns(applied_patterns), SparseSpec::Patterns(pending_patterns)),
+                    _ => {
+                        let current = backend.get_sparse_checkout_list(&repo_root)?;
+                        let proposed = Self::proposed_dirs(current.clone(), &dirs_to_add, &dirs_to_remove);
+                        (SparseSpec::Cone(current), SparseSpec::Cone(proposed))
+                    }
+                };
+                // The working tree follows HEAD, whatever revision is being browsed
+                let files = match backend.get_tree_id("HEAD", &repo_root)? {
+                    Some(tree_id) => backend.get_file_sizes(&tree_id, &repo_root)?,
+                    None => Vec::new(),
+                };
+                Ok(ApplyPreview::compute(&current, &proposed, &files))
             })();
-            // Send the result back to the main thread
-            let _ = tx_clone.send(AppMessage::RefreshCompleted(result));
+            let _ = tx_clone.send(AppMessage::ApplyPreviewReady(result));
         });
     }
 
-    // Helper function to update app state based on fetched git info
-    fn update_state_from_git_info(&mut self, new_sparse_checkout_dirs: Vec<String>, new_uncommitted_paths: HashSet<String>) {
-        self.sparse_checkout_dirs = new_sparse_checkout_dirs;
-        self.uncommitted_paths = new_uncommitted_paths;
+    pub fn handle_apply_preview_ready(&mut self, result: Result<ApplyPreview, git::Error>) {
+        self.is_previewing = false;
+        match result {
+            Ok(preview) => self.apply_preview = Some(preview),
+            Err(e) => self.report(Level::Error, e.to_string()),
+        }
+    }
 
-        // Update all loaded items in-place
-        for i in 0..self.items.len() {
-            let item = &mut self.items[i];
+    /// Applies the previewed changes.
+    pub fn confirm_apply_preview(&mut self) {
+        if self.apply_preview.take().is_some() {
+            self.apply_changes();
+        }
+    }
 
-            // Root item is special
-            if item.path == "." { // Compare String with "."
-                item.is_checked_out = true;
-                item.is_locked = true; // Root is always locked
-                continue;
-            }
+    pub fn cancel_apply_preview(&mut self) {
+        self.apply_preview = None;
+        self.is_reverting_apply = false;
+    }
 
-            // Update checked-out status
-            item.is_checked_out = self.sparse_checkout_dirs.contains(&item.path);
+    fn current_sparse_state(&self) -> SparseState {
+        SparseState { mode: self.sparse_mode, dirs: self.sparse_checkout_dirs.clone(), patterns: self.sparse_patterns.clone() }
+    }
 
-            // Update lock status
-            let contains_uncommitted_changes = self
-                .uncommitted_paths
-                .iter()
-                .any(|p| path_starts_with_component(p, &item.path)); // Use custom helper
-            item.contains_uncommitted_changes = contains_uncommitted_changes;
-            item.is_locked = contains_uncommitted_changes;
+    /// Runs `action` as one undoable step: the pending changes from before it are recorded
+    /// if it changes them. Steps run by `action` itself are part of it.
+    fn with_undo<R>(&mut self, action: impl FnOnce(&mut Self) -> R) -> R {
+        if self.is_recording_undo {
+            return action(self);
+        }
+        let before = self.pending_snapshot();
+        self.is_recording_undo = true;
+        let result = action(self);
+        self.is_recording_undo = false;
+        if self.pending_snapshot() != before {
+            self.undo_history.record(before);
         }
+        result
+    }
 
-        // Update tree item states and pending changes cache
-        self.update_tree_item_states();
-        for i in (0..self.items.len()).rev() {
-            self.update_pending_changes_cache(i);
+    fn pending_snapshot(&self) -> PendingSnapshot {
+        PendingSnapshot {
+            dirs: self
+                .items
+                .iter()
+                .filter_map(|item| item.pending_change.map(|change| (item.path.clone(), change)))
+                .collect(),
+            patterns: self.pending_patterns.clone(),
         }
-        
-        // After refreshing, clear any pending changes that might have been applied externally
+    }
+
+    fn restore_pending(&mut self, snapshot: PendingSnapshot) {
         for item in self.items.iter_mut() {
-            if let Some(change_type) = &item.pending_change {
-                let current_checked_out = self.sparse_checkout_dirs.contains(&item.path);
-                let needs_removal = match change_type {
-                    ChangeType::Add => current_checked_out, // If added and now checked out, clear pending
-                    ChangeType::Remove => !current_checked_out, // If removed and now not checked out, clear pending
-                };
-                if needs_removal {
-                    item.pending_change = None;
-                }
+            item.pending_change = None;
+            item.cached_pending_changes = 0;
+        }
+        for (path, change) in snapshot.dirs {
+            if let Some(idx) = self.reveal_path(&path) {
+                self.items[idx].pending_change = Some(change);
+                self.update_pending_changes_cache(idx);
             }
         }
+        self.pending_patterns = snapshot.patterns;
+        self.update_pattern_matches();
     }
 
-    pub fn get_grid_view_model(&self) -> Option<GridViewModel> {
-        self.filtered_item_indices
-            .get(self.selected_item_index)
-            .map(|&global_idx| {
-                let item = &self.items[global_idx];
+    pub fn undo(&mut self) {
+        match self.undo_history.undo(self.pending_snapshot()) {
+            Some(previous) => self.restore_pending(previous),
+            None => self.report(Level::Info, "Nothing to undo"),
+        }
+    }
 
-                let status = if item.is_locked {
-                    "Locked".to_string()
-                } else if item.is_checked_out {
-                    "Checked Out".to_string()
-                } else {
-                    "Not Checked Out".to_string()
-                };
+    pub fn redo(&mut self) {
+        match self.undo_history.redo(self.pending_snapshot()) {
+            Some(next) => self.restore_pending(next),
+            None => self.report(Level::Info, "Nothing to redo"),
+        }
+    }
 
-                let uncommitted = if item.contains_uncommitted_changes {
-                    "Yes".to_string()
-                } else {
-                    "No".to_string()
-                };
+    /// Replaces the pending changes with those that go back to the sparse set from before
+    /// the last apply, and shows them for confirmation.
+    pub fn revert_last_apply(&mut self) {
+        let Some(previous) = self.applied_history.last().cloned() else {
+            self.report(Level::Info, "No earlier sparse set to revert to");
+            return;
+        };
+        if self.sparse_mode != SparseMode::Pattern && !self.check_indexed() {
+            return;
+        }
+        let skipped = self.with_undo(|app| match app.sparse_mode {
+            SparseMode::Pattern => {
+                app.edit_patterns(|patterns| *patterns = previous.patterns);
+                Vec::new()
+            }
+            _ => app.set_pending_to_dirs(&previous.dirs.into_iter().collect()),
+        });
+        self.is_reverting_apply = true;
+        self.preview_changes();
+        if !self.is_previewing {
+            self.is_reverting_apply = false;
+        }
+        if !skipped.is_empty() {
+            self.report(Level::Warn, format!("Cannot revert {}", format_skipped(&skipped)));
+        }
+    }
 
-                let subdirectories_checked_out = item
-                    .children_indices
-                    .iter()
-                    .filter(|&&child_idx| self.items[child_idx].is_checked_out)
-                    .count();
+    /// Refreshes the application state by re-reading the git repository.
+    /// Initiates an asynchronous refresh of the application state by re-reading the git repository.
+    pub fn refresh(&mut self) {
+        self.is_refreshing = true;
+        self.refresh_generation += 1;
+        let generation = self.refresh_generation;
+        self.dismiss_message(); // Clear previous errors
+        let repo_root_clone = self.current_repo_root.clone();
+        let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
 
-                let pending_changes = self.items[global_idx].cached_pending_changes;
+        self.rebuild_dir_index(); // HEAD may have moved since the index was built
 
-                GridViewModel {
-                    name: item.name.clone(),
-                    path: item.path.clone(), // Path is now unescaped
-                    status,
-                    uncommitted,
-                    subdirectories_total: item.children_indices.len().to_string(),
-                    subdirectories_checked_out: subdirectories_checked_out.to_string(),
-                    pending_changes: pending_changes.to_string(),
-                }
-            })
-    }
+        thread::spawn(move || {
+            let result: Result<(SparseState, HashMap<RepoPath, FileStatus>), git::Error> = (|| {
+                let sparse_state = Self::read_sparse_state(backend.as_ref(), &repo_root_clone)?;
+                let uncommitted_paths = backend.get_uncommitted_paths(&repo_root_clone)?;
+                Ok((sparse_state, uncommitted_paths))
+            })();
+            // Send the result back to the main thread
+            let _ = tx_clone.send(AppMessage::RefreshCompleted(generation, result));
+        });
+    }
+
+    /// Whether the directory is in the working tree. Without sparse-checkout, everything is.
+    fn is_dir_checked_out(&self, path: &RepoPath) -> bool {
+        self.sparse_mode == SparseMode::Disabled || self.sparse_checkout_dirs.contains(path)
+    }
+
+    pub fn open_init_dialog(&mut self) {
+        if self.sparse_mode != SparseMode::Disabled {
+            self.report(Level::Info, "Sparse checkout is already enabled");
+            return;
+        }
+        self.sparse_mode_dialog = Some(SparseModeDialog::Init { sparse_index: false });
+    }
+
+    pub fn open_disable_dialog(&mut self) {
+        if self.sparse_mode == SparseMode::Disabled {
+            self.report(Level::Info, "Sparse checkout is not enabled");
+            return;
+        }
+        self.sparse_mode_dialog = Some(SparseModeDialog::ConfirmDisable);
+    }
+
+    pub fn close_sparse_mode_dialog(&mut self) {
+        self.sparse_mode_dialog = None;
+    }
+
+    pub fn toggle_sparse_index_option(&mut self) {
+        if let Some(SparseModeDialog::Init { sparse_index }) = &mut self.sparse_mode_dialog {
+            *sparse_index = !*sparse_index;
+        }
+    }
+
+    /// Runs the command the open dialog offers in a separate thread.
+    pub fn confirm_sparse_mode_dialog(&mut self) {
+        let Some(dialog) = self.sparse_mode_dialog.take() else {
+            return;
+        };
+        self.is_changing_sparse_mode = true;
+        self.dismiss_message();
+        let repo_root = self.current_repo_root.clone();
+        let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        let watched = self.watched_paths.clone();
+        thread::spawn(move || {
+            let result = match dialog {
+                SparseModeDialog::Init { sparse_index } => backend.init_sparse_checkout(sparse_index, &repo_root),
+                SparseModeDialog::ConfirmDisable => backend.disable_sparse_checkout(&repo_root),
+            };
+            let stamp = watched.as_ref().map(WatchedPaths::stamp);
+            let _ = tx_clone.send(AppMessage::SparseModeChanged(result, stamp));
+        });
+    }
+
+    pub fn handle_sparse_mode_changed(&mut self, result: Result<(), git::Error>, stamp: Option<RepoStamp>) {
+        self.is_changing_sparse_mode = false;
+        self.own_write_stamp = stamp;
+        match result {
+            Ok(()) => {
+                // Pending changes were made against the previous mode
+                for item in self.items.iter_mut() {
+                    item.pending_change = None;
+                }
+                self.pending_patterns = None;
+                self.refresh();
+            }
+            Err(e) => {
+                self.report(Level::Error, e.to_string());
+            }
+        }
+    }
+
+    /// Reads the sparse-checkout mode and, depending on it, the directory set or patterns.
+    fn read_sparse_state(backend: &dyn GitBackend, repo_root: &Path) -> Result<SparseState, git::Error> {
+        let mode = backend.get_sparse_mode(repo_root)?;
+        Ok(match mode {
+            SparseMode::Pattern => SparseState {
+                mode,
+                dirs: Vec::new(),
+                patterns: backend.get_sparse_checkout_patterns(repo_root)?,
+            },
+            SparseMode::Cone | SparseMode::Disabled => SparseState {
+                mode,
+                dirs: backend.get_sparse_checkout_list(repo_root)?,
+                patterns: Vec::new(),
+            },
+        })
+    }
+
+    /// Builds the directory indexes of the browsed revision and of HEAD in a separate
+    /// thread, skipping trees that are already indexed. Until they arrive, expands fall
+    /// back to querying git directly. While a build is running, another one follows it.
+    pub fn rebuild_dir_index(&mut self) {
+        if self.is_indexing {
+            self.reindex_requested = true;
+            return;
+        }
+        self.is_indexing = true;
+
+        let repo_root = self.current_repo_root.clone();
+        let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        let revision = self.revision.clone();
+        // Pattern mode matches against the files of the browsed tree, so it needs them listed
+        let with_files = self.sparse_mode == SparseMode::Pattern;
+        let indexed_tree_id = self
+            .dir_index
+            .as_ref()
+            .filter(|index| !with_files || index.files.is_some())
+            .map(|index| index.tree_id.clone());
+        let indexed_head_tree_id = self.head_dir_index.as_ref().map(|index| index.tree_id.clone());
+
+        thread::spawn(move || {
+            let result = (|| {
+                let build = |tree_id: &Option<String>, indexed: &Option<Option<String>>, with_files: bool| {
+                    if indexed.as_ref() == Some(tree_id) {
+                        return Ok(None);
+                    }
+                    DirIndex::build_for_tree(backend.as_ref(), tree_id.clone(), with_files, &repo_root).map(Some)
+                };
+                let tree_id = backend.get_tree_id(&revision, &repo_root)?;
+                let head_tree_id = if revision == "HEAD" {
+                    tree_id.clone()
+                } else {
+                    backend.get_tree_id("HEAD", &repo_root)?
+                };
+                let head_is_browsed = head_tree_id == tree_id;
+                let browsed = build(&tree_id, &indexed_tree_id, with_files)?;
+                let head = if head_is_browsed { None } else { build(&head_tree_id, &indexed_head_tree_id, false)? };
+                Ok(DirIndexUpdate { revision, browsed, head, head_is_browsed })
+            })();
+            let _ = tx_clone.send(AppMessage::DirIndexBuilt(result));
+        });
+    }
+
+    /// Checks that `revision` names a tree-ish; an unborn HEAD is allowed.
+    fn verify_revision(backend: &dyn GitBackend, revision: &str, repo_root: &Path) -> Result<(), git::Error> {
+        match backend.get_tree_id(revision, repo_root)? {
+            None if revision != "HEAD" => Err(git::Error::UnknownRevision(revision.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks `revision` and loads its top-level tree in a separate thread, and once they
+    /// arrive rebuilds the tree from them.
+    pub fn set_revision(&mut self, revision: String) {
+        if revision == self.revision {
+            self.loading_revision = None; // Stays on the current one
+            return;
+        }
+        if !self.can_switch_revision() {
+            return;
+        }
+        self.loading_revision = Some(revision.clone());
+        let repo_root = self.current_repo_root.clone();
+        let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        thread::spawn(move || {
+            let result = Self::verify_revision(backend.as_ref(), &revision, &repo_root)
+                .and_then(|()| Self::read_initial_tree(backend.as_ref(), &revision, &repo_root));
+            let _ = tx_clone.send(AppMessage::RevisionLoaded(revision, result));
+        });
+    }
+
+    fn can_switch_revision(&mut self) -> bool {
+        let has_pending = self.items.iter().any(|item| item.pending_change.is_some());
+        if has_pending {
+            self.report(Level::Warn, "Apply pending changes before switching revisions");
+        }
+        !has_pending
+    }
+
+    pub fn handle_revision_loaded(&mut self, revision: String, result: Result<InitialTree, git::Error>) {
+        if self.loading_revision.as_ref() != Some(&revision) {
+            return; // Another revision was picked meanwhile
+        }
+        self.loading_revision = None;
+        match result {
+            // Changes may have been marked while the revision was loaded
+            Ok(initial_tree) if self.can_switch_revision() => self.switch_revision(revision, initial_tree),
+            Ok(_) => {}
+            Err(e) => self.report(Level::Error, e.to_string()),
+        }
+    }
+
+    /// Rebuilds the tree from the top-level directories of `revision`.
+    fn switch_revision(&mut self, revision: String, initial_tree: InitialTree) {
+        self.revision = revision;
+        self.dir_index = None;
+        self.build_initial_tree(initial_tree);
+        self.selected_item_index = 0;
+        self.build_visible_items();
+        self.rebuild_dir_index();
+    }
+
+    /// Lists the branches and tags in a separate thread, and opens the picker once they arrive.
+    pub fn open_revision_picker(&mut self) {
+        if self.is_listing_revisions {
+            return;
+        }
+        self.is_listing_revisions = true;
+        let repo_root = self.current_repo_root.clone();
+        let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        thread::spawn(move || {
+            let result = backend.list_revisions(&repo_root);
+            let _ = tx_clone.send(AppMessage::RevisionsListed(result));
+        });
+    }
+
+    pub fn handle_revisions_listed(&mut self, result: Result<Vec<String>, git::Error>) {
+        self.is_listing_revisions = false;
+        match result {
+            Ok(revisions) => {
+                let mut candidates = vec!["HEAD".to_string()];
+                candidates.extend(revisions);
+                self.revision_picker = Some(RevisionPicker { candidates, ..Default::default() });
+            }
+            Err(e) => {
+                self.report(Level::Error, e.to_string());
+            }
+        }
+    }
+
+    pub fn close_revision_picker(&mut self) {
+        self.revision_picker = None;
+    }
+
+    pub fn revision_picker_input(&mut self, c: char) {
+        if let Some(picker) = &mut self.revision_picker {
+            picker.input.push(c);
+            picker.selected = 0;
+        }
+    }
+
+    pub fn revision_picker_backspace(&mut self) {
+        if let Some(picker) = &mut self.revision_picker {
+            picker.input.pop();
+            picker.selected = 0;
+        }
+    }
+
+    pub fn revision_picker_move(&mut self, down: bool) {
+        if let Some(picker) = &mut self.revision_picker {
+            let last = picker.matches().len().saturating_sub(1);
+            picker.selected = if down {
+                std::cmp::min(picker.selected + 1, last)
+            } else {
+                picker.selected.saturating_sub(1)
+            };
+        }
+    }
+
+    /// Switches to the picked revision and closes the picker.
+    pub fn confirm_revision_picker(&mut self) {
+        if let Some(revision) = self.revision_picker.take().and_then(|picker| picker.choice()) {
+            self.set_revision(revision);
+        }
+    }
+
+    /// Starts typing a search query.
+    pub fn open_search(&mut self) {
+        let mut search = Search { is_typing: true, ..Default::default() };
+        search.candidates = self.search_candidates();
+        if self.dir_index.is_none() {
+            self.report(Level::Info, "Indexing directories... Matches appear once it is done");
+        }
+        self.search = Some(search);
+        self.build_visible_items();
+    }
+
+    fn search_candidates(&self) -> Vec<(RepoPath, String)> {
+        let Some(index) = &self.dir_index else {
+            return Vec::new();
+        };
+        let mut dirs = index.all_dirs();
+        dirs.sort();
+        dirs.into_iter()
+            .map(|dir| {
+                let text = dir.display().into_owned();
+                (dir, text)
+            })
+            .collect()
+    }
+
+    /// Closes the search and shows the whole tree again.
+    pub fn clear_search(&mut self) {
+        if self.search.take().is_some_and(|search| search.is_filtering) {
+            let selected = self.filtered_item_indices.get(self.selected_item_index).copied();
+            self.build_visible_items();
+            self.select_item(selected);
+        }
+    }
+
+    /// Stops typing, keeping the matches for `n` and `N`.
+    pub fn confirm_search(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.is_typing = false;
+            if search.query.is_empty() {
+                self.clear_search();
+            }
+        }
+    }
+
+    pub fn search_input(&mut self, c: char) {
+        if let Some(search) = &mut self.search {
+            search.query.push(c);
+            self.update_search_matches();
+        }
+    }
+
+    pub fn search_backspace(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            self.update_search_matches();
+        }
+    }
+
+    /// Jumps to the next or previous match, wrapping around.
+    pub fn search_move(&mut self, down: bool) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let count = search.matches.len();
+        if count == 0 {
+            return;
+        }
+        search.selected = if down { (search.selected + 1) % count } else { (search.selected + count - 1) % count };
+        self.jump_to_search_match();
+    }
+
+    /// Shows only the matches and their ancestors, or the whole tree again.
+    pub fn toggle_search_filter(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.is_filtering = !search.is_filtering;
+            self.update_search_filter();
+            self.jump_to_search_match();
+        }
+    }
+
+    fn update_search_matches(&mut self) {
+        // The index may have been built since the search was opened
+        let candidates = match &self.search {
+            Some(search) if search.candidates.is_empty() => self.search_candidates(),
+            _ => Vec::new(),
+        };
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        if search.candidates.is_empty() {
+            search.candidates = candidates;
+        }
+        search.matches = if search.query.is_empty() {
+            Vec::new()
+        } else {
+            fuzzy::rank(&search.query, &search.candidates, |(_, text)| text)
+                .into_iter()
+                .map(|(dir, _)| dir.clone())
+                .collect()
+        };
+        search.selected = 0;
+        self.update_search_filter();
+        self.jump_to_search_match();
+    }
+
+    /// Reveals the best matches so that filter mode can show them.
+    fn update_search_filter(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        if !search.is_filtering {
+            self.build_visible_items();
+            return;
+        }
+        let shown: Vec<RepoPath> = search.matches.iter().take(MAX_FILTERED_MATCHES).cloned().collect();
+        let mut filter_paths = HashSet::new();
+        for dir in shown {
+            if self.reveal_path(&dir).is_none() {
+                continue;
+            }
+            let mut path = dir;
+            while filter_paths.insert(path.clone()) && !path.is_root() {
+                path = path.parent();
+            }
+        }
+        filter_paths.insert(RepoPath::root());
+        if let Some(search) = &mut self.search {
+            search.filter_paths = filter_paths;
+        }
+        self.build_visible_items();
+        self.selected_item_index = self.selected_item_index.min(self.filtered_item_indices.len().saturating_sub(1));
+    }
+
+    /// Expands the ancestors of the selected match and moves the cursor to it.
+    fn jump_to_search_match(&mut self) {
+        let Some(dir) = self.search.as_ref().and_then(|search| search.matches.get(search.selected)).cloned() else {
+            return;
+        };
+        let idx = self.reveal_path(&dir);
+        self.build_visible_items();
+        self.select_item(idx);
+    }
+
+    /// Moves the cursor to the item `idx` if it is visible.
+    fn select_item(&mut self, idx: Option<usize>) {
+        if let Some(position) = idx.and_then(|idx| self.filtered_item_indices.iter().position(|&i| i == idx)) {
+            self.selected_item_index = position;
+        }
+    }
+
+    /// Root tree ids of the browsed revision and of HEAD, once indexed.
+    fn indexed_tree_ids(&self) -> Vec<String> {
+        let mut tree_ids: Vec<String> = [&self.dir_index, &self.head_dir_index]
+            .into_iter()
+            .filter_map(|index| index.as_ref()?.tree_id.clone())
+            .collect();
+        tree_ids.dedup();
+        tree_ids
+    }
+
+    /// Loads the blob sizes of the browsed tree and of HEAD's tree in a separate thread,
+    /// unless they are cached already.
+    fn load_tree_sizes(&mut self) {
+        if self.is_loading_sizes {
+            return; // The handler calls back once the running load is done
+        }
+        let tree_ids = self.indexed_tree_ids();
+        // Trees that are no longer browsed or checked out are not worth keeping
+        self.tree_sizes.retain(|tree_id, _| tree_ids.contains(tree_id));
+        let missing: Vec<String> = tree_ids.into_iter().filter(|id| !self.tree_sizes.contains_key(id)).collect();
+        if missing.is_empty() {
+            self.update_on_disk_totals();
+            return;
+        }
+
+        self.is_loading_sizes = true;
+        let repo_root = self.current_repo_root.clone();
+        let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        thread::spawn(move || {
+            let result = missing
+                .iter()
+                .map(|tree_id| TreeSizes::load(backend.as_ref(), tree_id, &repo_root))
+                .collect();
+            let _ = tx_clone.send(AppMessage::TreeSizesLoaded(result));
+        });
+    }
+
+    /// Lists the untracked and ignored files under the selected directory, unless they are
+    /// listed already. The root is always checked out, so nothing there needs the listing,
+    /// which would walk the whole working tree.
+    pub fn load_selected_untracked(&mut self) {
+        let Some(&idx) = self.filtered_item_indices.get(self.selected_item_index) else {
+            return;
+        };
+        let path = &self.items[idx].path;
+        if !path.is_root() && self.untracked_dir.as_ref() != Some(path) {
+            self.load_untracked(path.clone());
+        }
+    }
+
+    /// Lists the untracked and ignored files under `dir` in a separate thread. A listing of
+    /// the same directory stays shown until the new one arrives.
+    pub fn load_untracked(&mut self, dir: RepoPath) {
+        if self.untracked_dir.as_ref() != Some(&dir) {
+            self.untracked_paths.clear();
+            self.warn_when_untracked_loaded = false;
+        }
+        self.untracked_generation += 1;
+        self.untracked_dir = Some(dir.clone());
+        self.is_loading_untracked = true;
+        let generation = self.untracked_generation;
+        let repo_root = self.current_repo_root.clone();
+        let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        thread::spawn(move || {
+            let result = backend.get_untracked_paths(&dir, &repo_root);
+            let _ = tx_clone.send(AppMessage::UntrackedLoaded(generation, result));
+        });
+    }
+
+    fn sizes_of(&self, index: &Option<Arc<DirIndex>>) -> Option<&TreeSizes> {
+        let tree_id = index.as_ref()?.tree_id.as_ref()?;
+        self.tree_sizes.get(tree_id).map(Arc::as_ref)
+    }
+
+    /// Sums up the files of HEAD that the current sparse-checkout configuration checks out.
+    fn update_on_disk_totals(&mut self) {
+        let Some(head) = self.sizes_of(&self.head_dir_index) else {
+            self.on_disk_totals.clear();
+            return;
+        };
+        let spec = match self.sparse_mode {
+            SparseMode::Disabled => None,
+            SparseMode::Cone => Some(SparseSpec::Cone(self.sparse_checkout_dirs.clone())),
+            SparseMode::Pattern => Some(SparseSpec::Patterns(self.sparse_patterns.clone())),
+        };
+        let totals = match spec {
+            None => head.totals.clone(),
+            Some(spec) => {
+                let is_checked_out = spec.matcher();
+                dir_stats::sum_by_dir(head.files.iter().filter(|(file, _)| is_checked_out(file)))
+            }
+        };
+        self.on_disk_totals = totals;
+    }
+
+    pub fn toggle_size_columns(&mut self) {
+        self.show_size_columns = !self.show_size_columns;
+    }
+
+    /// The patterns in effect for display: the pending edits if any, else the applied ones.
+    pub fn pattern_lines(&self) -> &[Vec<u8>] {
+        self.pending_patterns.as_deref().unwrap_or(&self.sparse_patterns)
+    }
+
+    /// Re-evaluates the effective patterns against the files of the browsed tree.
+    fn update_pattern_matches(&mut self) {
+        self.pattern_match_counts.clear();
+        if self.sparse_mode != SparseMode::Pattern {
+            return;
+        }
+        if let Some(files) = self.dir_index.as_ref().and_then(|index| index.files.as_ref()) {
+            let patterns = sparse_patterns::parse(self.pattern_lines());
+            self.pattern_match_counts = sparse_patterns::count_matches(&patterns, files);
+        }
+    }
+
+    /// Number of pattern lines added or removed by the pending edits.
+    fn pending_pattern_changes(&self) -> usize {
+        let Some(pending) = &self.pending_patterns else {
+            return 0;
+        };
+        let added = pending.iter().filter(|line| !self.sparse_patterns.contains(line)).count();
+        let removed = self.sparse_patterns.iter().filter(|line| !pending.contains(line)).count();
+        added + removed
+    }
+
+    /// Applies `edit` to the pending patterns, starting from the applied ones.
+    fn edit_patterns(&mut self, edit: impl FnOnce(&mut Vec<Vec<u8>>)) {
+        self.with_undo(|app| {
+            let mut patterns = app.pattern_lines().to_vec();
+            edit(&mut patterns);
+            app.pending_patterns = (patterns != app.sparse_patterns).then_some(patterns);
+            app.update_pattern_matches();
+        });
+    }
+
+    /// Pattern-mode counterpart of toggling a directory: adds a line that includes it, or
+    /// excludes it if it is fully checked out, or removes such a line added earlier.
+    fn toggle_pattern_for(&mut self, path: &RepoPath) {
+        let include_line = dir_pattern_line(path);
+        let exclude_line = [b"!".as_slice(), &include_line].concat();
+        let fully_matched = self
+            .pattern_match_counts
+            .get(path)
+            .is_some_and(|count| count.pattern_match() == PatternMatch::Full);
+        let applied = self.sparse_patterns.clone();
+        let is_added_line =
+            |line: &Vec<u8>| (*line == include_line || *line == exclude_line) && !applied.contains(line);
+        let previously_added =
+            self.pending_patterns.as_ref().is_some_and(|pending| pending.iter().any(is_added_line));
+        self.edit_patterns(|patterns| {
+            if previously_added {
+                patterns.retain(|line| !is_added_line(line));
+            } else if fully_matched {
+                patterns.push(exclude_line.clone());
+            } else {
+                patterns.push(include_line.clone());
+            }
+        });
+    }
+
+    /// Pattern-mode counterpart of clearing a directory's pending change: drops the lines
+    /// that include or exclude it added since the last apply, and restores such lines that
+    /// were removed. Returns whether the patterns changed.
+    fn clear_patterns_for(&mut self, path: &RepoPath) -> bool {
+        let include_line = dir_pattern_line(path);
+        let exclude_line = [b"!".as_slice(), &include_line].concat();
+        let is_dir_line = |line: &Vec<u8>| *line == include_line || *line == exclude_line;
+        let applied = self.sparse_patterns.clone();
+        let mut patterns = self.pattern_lines().to_vec();
+        patterns.retain(|line| !is_dir_line(line) || applied.contains(line));
+        for (i, line) in applied.iter().enumerate().filter(|(_, line)| is_dir_line(line)) {
+            if !patterns.contains(line) {
+                patterns.insert(i.min(patterns.len()), line.clone()); // Where it was, as order matters
+            }
+        }
+        if patterns == self.pattern_lines() {
+            return false;
+        }
+        self.edit_patterns(|edited| *edited = patterns);
+        true
+    }
+
+    pub fn open_pattern_editor(&mut self) {
+        if self.sparse_mode != SparseMode::Pattern {
+            self.report(Level::Warn, "Patterns can only be edited in non-cone mode");
+            return;
+        }
+        self.pattern_editor = Some(PatternEditor::default());
+    }
+
+    pub fn close_pattern_editor(&mut self) {
+        self.pattern_editor = None;
+    }
+
+    pub fn pattern_editor_move(&mut self, down: bool) {
+        let last = self.pattern_lines().len().saturating_sub(1);
+        if let Some(editor) = &mut self.pattern_editor {
+            editor.selected = if down {
+                std::cmp::min(editor.selected + 1, last)
+            } else {
+                editor.selected.saturating_sub(1)
+            };
+        }
+    }
+
+    /// Starts typing a new line below the selected one, or replacing the selected one.
+    pub fn pattern_editor_begin_edit(&mut self, is_new_line: bool) {
+        let current = self
+            .pattern_editor
+            .as_ref()
+            .and_then(|editor| self.pattern_lines().get(editor.selected))
+            .map(|line| String::from_utf8_lossy(line).into_owned());
+        if let Some(editor) = &mut self.pattern_editor {
+            editor.is_new_line = is_new_line || current.is_none();
+            editor.input = Some(if editor.is_new_line { String::new() } else { current.unwrap_or_default() });
+        }
+    }
+
+    pub fn pattern_editor_input(&mut self, c: char) {
+        if let Some(input) = self.pattern_editor.as_mut().and_then(|editor| editor.input.as_mut()) {
+            input.push(c);
+        }
+    }
+
+    pub fn pattern_editor_backspace(&mut self) {
+        if let Some(input) = self.pattern_editor.as_mut().and_then(|editor| editor.input.as_mut()) {
+            input.pop();
+        }
+    }
+
+    pub fn pattern_editor_cancel_edit(&mut self) {
+        if let Some(editor) = &mut self.pattern_editor {
+            editor.input = None;
+        }
+    }
+
+    /// Stores the typed line. Submitting an empty line deletes the one being edited.
+    pub fn pattern_editor_commit_edit(&mut self) {
+        let Some(editor) = &mut self.pattern_editor else {
+            return;
+        };
+        let Some(input) = editor.input.take() else {
+            return;
+        };
+        let line = input.trim_end_matches(['\r', '\n']).as_bytes().to_vec();
+        let (selected, is_new_line) = (editor.selected, editor.is_new_line);
+        let is_empty = self.pattern_lines().is_empty();
+        let line_was_empty = line.is_empty();
+        self.edit_patterns(|patterns| match (is_new_line, line.is_empty()) {
+            (true, true) => {}
+            (true, false) if is_empty => patterns.push(line),
+            (true, false) => patterns.insert(selected + 1, line),
+            (false, true) => {
+                patterns.remove(selected);
+            }
+            (false, false) => patterns[selected] = line,
+        });
+        // Select the new line, and keep the selection in bounds after a deletion
+        let last = self.pattern_lines().len().saturating_sub(1);
+        if let Some(editor) = &mut self.pattern_editor {
+            let target = if is_new_line && !is_empty && !line_was_empty { selected + 1 } else { selected };
+            editor.selected = std::cmp::min(target, last);
+        }
+    }
+
+    pub fn pattern_editor_delete(&mut self) {
+        let Some(selected) = self.pattern_editor.as_ref().map(|editor| editor.selected) else {
+            return;
+        };
+        if selected < self.pattern_lines().len() {
+            self.edit_patterns(|patterns| {
+                patterns.remove(selected);
+            });
+            if selected >= self.pattern_lines().len() {
+                self.pattern_editor_move(false);
+            }
+        }
+    }
+
+    /// Adds or removes the leading `!` of the selected line.
+    pub fn pattern_editor_toggle_negation(&mut self) {
+        let Some(selected) = self.pattern_editor.as_ref().map(|editor| editor.selected) else {
+            return;
+        };
+        if selected < self.pattern_lines().len() {
+            self.edit_patterns(|patterns| {
+                let line = &mut patterns[selected];
+                match line.strip_prefix(b"!") {
+                    Some(rest) => *line = rest.to_vec(),
+                    None => line.insert(0, b'!'),
+                }
+            });
+        }
+    }
+
+    /// Stores the current cone directories as the profile `name`, replacing any profile
+    /// of that name.
+    pub fn save_profile(&self, name: &str) -> Result<(), profiles::Error> {
+        let mut profiles = Profiles::load(self.backend.as_ref(), &self.current_repo_root)?;
+        profiles.profiles.insert(name.to_string(), Profile::from_dirs(&self.sparse_checkout_dirs));
+        profiles.save(&self.current_repo_root)
+    }
+
+    /// Resolves the profile `name` against the directories of the browsed revision, as far
+    /// as they are indexed.
+    pub fn resolve_profile(&self, name: &str) -> Result<ResolvedProfile, profiles::Error> {
+        let profiles = Profiles::load(self.backend.as_ref(), &self.current_repo_root)?;
+        let index = &self.dir_index;
+        profiles.resolve(name, &self.revision, &|dir| index.as_ref().is_none_or(|index| index.contains(dir)))
+    }
+
+    /// Compares the resolved sets of the profiles `a` and `b`.
+    pub fn diff_profiles(&self, a: &str, b: &str) -> Result<ProfileDiff, profiles::Error> {
+        let (a, b) = (self.resolve_profile(a)?.dir_set(), self.resolve_profile(b)?.dir_set());
+        Ok(ProfileDiff::between(&a, &b))
+    }
+
+    /// Replaces the pending changes with those that turn the current cone set into the
+    /// resolved profile `name`. Returns the directories that could not be marked, with the
+    /// reason.
+    pub fn load_profile(&mut self, name: &str) -> Result<Vec<(RepoPath, String)>, Box<dyn std::error::Error>> {
+        if self.sparse_mode != SparseMode::Cone {
+            return Err("profiles can only be loaded in cone mode".into());
+        }
+        let resolved = self.resolve_profile(name)?;
+        let target = resolved.dir_set();
+        self.loaded_profile = Some(resolved);
+        Ok(self.with_undo(|app| app.set_pending_to_dirs(&target)))
+    }
+
+    /// Replaces the pending changes with those that turn the current cone set into
+    /// `target`. Returns the directories that could not be marked, with the reason.
+    fn set_pending_to_dirs(&mut self, target: &BTreeSet<RepoPath>) -> Vec<(RepoPath, String)> {
+        let current: HashSet<RepoPath> = self.sparse_checkout_dirs.iter().cloned().collect();
+
+        for item in self.items.iter_mut() {
+            item.pending_change = None;
+            item.cached_pending_changes = 0;
+        }
+        let mut skipped = Vec::new();
+        let mut changes: Vec<(RepoPath, ChangeType)> =
+            current.iter().filter(|dir| !target.contains(*dir)).map(|dir| (dir.clone(), ChangeType::Remove)).collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        changes.extend(target.iter().filter(|dir| !current.contains(*dir)).map(|dir| (dir.clone(), ChangeType::Add)));
+        for (dir, change) in changes {
+            let Some(idx) = self.reveal_path(&dir) else {
+                skipped.push((dir, format!("no such directory at {}", self.revision)));
+                continue;
+            };
+            // A directory under one that the profile drops is no longer implied by it
+            let is_covered = current.iter().any(|kept| target.contains(kept) && dir.is_descendant_of(kept));
+            let item = &self.items[idx];
+            let result = if change == ChangeType::Add && item.is_implicitly_checked_out && !is_covered && !item.is_locked {
+                self.items[idx].pending_change = Some(change);
+                self.update_pending_changes_cache(idx);
+                Ok(())
+            } else {
+                self.set_pending_change(idx, change)
+            };
+            if let Err(reason) = result {
+                skipped.push((dir, reason));
+            }
+        }
+        skipped
+    }
+
+    pub fn open_profile_picker(&mut self) {
+        match Profiles::load(self.backend.as_ref(), &self.current_repo_root) {
+            Ok(profiles) => self.profile_picker = Some(ProfilePicker { profiles, ..Default::default() }),
+            Err(e) => self.report(Level::Error, e.to_string()),
+        }
+    }
+
+    /// Backs out of the diff, the name being typed or the diff selection, or else closes
+    /// the popup.
+    pub fn close_profile_picker(&mut self) {
+        if let Some(picker) = &mut self.profile_picker {
+            if picker.diff.take().is_some() || picker.name_input.take().is_some() || picker.diff_base.take().is_some() {
+                return;
+            }
+        }
+        self.profile_picker = None;
+    }
+
+    pub fn profile_picker_move(&mut self, down: bool) {
+        if let Some(picker) = &mut self.profile_picker {
+            let last = picker.profiles.profiles.len().saturating_sub(1);
+            picker.selected = if down {
+                std::cmp::min(picker.selected + 1, last)
+            } else {
+                picker.selected.saturating_sub(1)
+            };
+        }
+    }
+
+    pub fn profile_picker_begin_save(&mut self) {
+        if let Some(picker) = &mut self.profile_picker {
+            picker.name_input = Some(picker.selected_name().unwrap_or_default());
+        }
+    }
+
+    pub fn profile_picker_input(&mut self, c: char) {
+        if let Some(input) = self.profile_picker.as_mut().and_then(|picker| picker.name_input.as_mut()) {
+            input.push(c);
+        }
+    }
+
+    pub fn profile_picker_backspace(&mut self) {
+        if let Some(input) = self.profile_picker.as_mut().and_then(|picker| picker.name_input.as_mut()) {
+            input.pop();
+        }
+    }
+
+    /// Saves the current cone set under the typed name.
+    pub fn profile_picker_commit_save(&mut self) {
+        let Some(name) = self.profile_picker.as_mut().and_then(|picker| picker.name_input.take()) else {
+            return;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+        match self.save_profile(name) {
+            Ok(()) => {
+                self.open_profile_picker();
+                if let Some(picker) = &mut self.profile_picker {
+                    picker.selected = picker.profiles.names().iter().position(|n| n == name).unwrap_or_default();
+                }
+                self.report(Level::Info, format!("Saved profile {name} to {}", profiles::PROFILES_PATH));
+            }
+            Err(e) => self.report(Level::Error, e.to_string()),
+        }
+    }
+
+    /// Picks the selected profile as the first side of a diff, or diffs it against the
+    /// one picked before.
+    pub fn profile_picker_diff(&mut self) {
+        let Some(picker) = &mut self.profile_picker else {
+            return;
+        };
+        let Some(name) = picker.selected_name() else {
+            return;
+        };
+        if picker.diff_base.is_none() {
+            picker.diff_base = Some(name);
+            return;
+        }
+        if !self.check_indexed() {
+            return; // Keeps the first side picked
+        }
+        let Some(base) = self.profile_picker.as_mut().and_then(|picker| picker.diff_base.take()) else {
+            return;
+        };
+        match self.diff_profiles(&base, &name) {
+            Ok(diff) => {
+                if let Some(picker) = &mut self.profile_picker {
+                    picker.diff = Some((base, name, diff));
+                }
+            }
+            Err(e) => self.report(Level::Error, e.to_string()),
+        }
+    }
+
+    /// Loads the selected profile into the pending changes and closes the popup.
+    pub fn profile_picker_load(&mut self) {
+        let Some(name) = self.profile_picker.as_ref().and_then(ProfilePicker::selected_name) else {
+            return;
+        };
+        if !self.check_indexed() {
+            return;
+        }
+        self.profile_picker = None;
+        match self.load_profile(&name) {
+            Ok(skipped) if skipped.is_empty() => {}
+            Ok(skipped) => {
+                self.report(Level::Warn, format!("Loaded {name}, skipping {}", format_skipped(&skipped)));
+            }
+            Err(e) => self.report(Level::Error, e.to_string()),
+        }
+    }
+
+    // Helper function to update app state based on fetched git info
+    fn update_state_from_git_info(
+        &mut self,
+        new_sparse_checkout_dirs: Vec<RepoPath>,
+        new_uncommitted_paths: HashMap<RepoPath, FileStatus>,
+    ) {
+        self.sparse_checkout_dirs = new_sparse_checkout_dirs;
+        self.uncommitted_paths = new_uncommitted_paths;
+
+        // Update all loaded items in-place
+        for i in 0..self.items.len() {
+            let item = &mut self.items[i];
+
+            // Root item is special
+            if item.path.is_root() {
+                item.is_checked_out = true;
+                item.is_locked = true; // Root is always locked
+                continue;
+            }
+
+            // Update checked-out status
+            item.is_checked_out = self.sparse_mode == SparseMode::Disabled || self.sparse_checkout_dirs.contains(&item.path);
+
+            // Update lock status
+            item.set_change_kinds(change_kinds_under(&self.uncommitted_paths, &item.path));
+        }
+
+        // Update tree item states and pending changes cache
+        self.update_tree_item_states();
+        for i in (0..self.items.len()).rev() {
+            self.update_pending_changes_cache(i);
+        }
+        
+        // After refreshing, clear any pending changes that might have been applied externally
+        for item in self.items.iter_mut() {
+            if let Some(change_type) = &item.pending_change {
+                let current_checked_out = self.sparse_checkout_dirs.contains(&item.path);
+                let needs_removal = match change_type {
+                    ChangeType::Add => current_checked_out, // If added and now checked out, clear pending
+                    ChangeType::Remove => !current_checked_out, // If removed and now not checked out, clear pending
+                };
+                if needs_removal {
+                    item.pending_change = None;
+                }
+            }
+        }
+    }
+
+    pub fn get_grid_view_model(&self) -> Option<GridViewModel> {
+        self.filtered_item_indices
+            .get(self.selected_item_index)
+            .map(|&global_idx| {
+                let item = &self.items[global_idx];
+
+                let pattern_count = self.pattern_match_counts.get(&item.path);
+                let blocking_kinds: Vec<&ChangeKind> =
+                    item.change_kinds.iter().filter(|kind| kind.blocks_removal()).collect();
+                let status = if item.is_locked && !blocking_kinds.is_empty() {
+                    format!("Locked ({})", format_change_kinds(blocking_kinds))
+                } else if item.is_locked {
+                    "Locked".to_string()
+                } else if self.sparse_mode == SparseMode::Pattern {
+                    match pattern_count {
+                        Some(count) => {
+                            let label = match count.pattern_match() {
+                                PatternMatch::Full => "Fully Matched",
+                                PatternMatch::Partial => "Partially Matched",
+                                PatternMatch::None => "Not Matched",
+                            };
+                            format!("{label} ({}/{} files)", count.matched, count.total)
+                        }
+                        None => "Matching...".to_string(),
+                    }
+                } else if item.is_checked_out {
+                    "Checked Out".to_string()
+                } else {
+                    "Not Checked Out".to_string()
+                };
+
+                let uncommitted_files = self.uncommitted_under(&item.path);
+                let uncommitted = if uncommitted_files.is_empty() {
+                    "No".to_string()
+                } else {
+                    let kinds: BTreeSet<ChangeKind> =
+                        uncommitted_files.iter().flat_map(|(_, status)| status.kinds()).collect();
+                    let counts: Vec<String> = kinds
+                        .into_iter()
+                        .map(|kind| {
+                            let count = uncommitted_files.iter().filter(|(_, status)| status.kinds().contains(&kind)).count();
+                            format!("{count} {}", kind.label())
+                        })
+                        .collect();
+                    let files = if uncommitted_files.len() == 1 { "file" } else { "files" };
+                    format!("{} {files}: {}", uncommitted_files.len(), counts.join(", "))
+                };
+
+                let uncommitted_files = uncommitted_files
+                    .iter()
+                    .map(|(path, status)| UncommittedFileViewModel {
+                        status: status.as_str().to_string(),
+                        path: path.display().into_owned(),
+                        blocks_removal: status.kinds().iter().any(|kind| kind.blocks_removal()),
+                    })
+                    .collect();
+
+                let subdirectories_checked_out = item
+                    .children_indices
+                    .iter()
+                    .filter(|&&child_idx| self.items[child_idx].is_checked_out)
+                    .count();
+
+                let pending_changes = if self.sparse_mode == SparseMode::Pattern {
+                    self.pending_pattern_changes() as u32 // Pattern edits are not per directory
+                } else {
+                    self.items[global_idx].cached_pending_changes
+                };
+
+                // Only meaningful when browsing another revision than HEAD
+                let in_head = if self.revision == "HEAD" {
+                    String::new()
+                } else {
+                    match &self.head_dir_index {
+                        Some(head_index) if head_index.contains(&item.path) => "Yes".to_string(),
+                        Some(_) => format!("No (only in {})", self.revision),
+                        None => "Unknown (indexing)".to_string(),
+                    }
+                };
+
+                GridViewModel {
+                    name: item.name.display().into_owned(),
+                    path: item.path.display().into_owned(),
+                    status,
+                    uncommitted,
+                    uncommitted_files,
+                    subdirectories_total: item.children_indices.len().to_string(),
+                    subdirectories_checked_out: subdirectories_checked_out.to_string(),
+                    pending_changes: pending_changes.to_string(),
+                    revision: self.revision.clone(),
+                    in_head,
+                    files: match self.sizes_of(&self.dir_index) {
+                        Some(sizes) => format_stats(sizes.totals.get(&item.path).copied().unwrap_or_default()),
+                        None => "Calculating...".to_string(),
+                    },
+                    on_disk: match self.sizes_of(&self.head_dir_index) {
+                        Some(_) => format_stats(self.on_disk_totals.get(&item.path).copied().unwrap_or_default()),
+                        None => "Calculating...".to_string(),
+                    },
+                    profile: self
+                        .loaded_profile
+                        .as_ref()
+                        .and_then(|profile| profile.provenance(&item.path))
+                        .unwrap_or_default(),
+                }
+            })
+    }
 
     pub fn get_tui_tree_items(&self) -> Vec<TuiTreeItemViewModel> {
+        let browsed_sizes = self.sizes_of(&self.dir_index).filter(|_| self.show_size_columns);
+        let visual_range: HashSet<usize> = self.visual_range().into_iter().collect();
         self.filtered_item_indices
             .iter()
             .enumerate()
             .map(|(view_idx, &global_idx)| {
                 let item = &self.items[global_idx];
+                let pattern_match = self.pattern_match_counts.get(&item.path).map(MatchCount::pattern_match);
 
                 // 1. Determine Style (Color)
+                let theme = &self.config.theme;
                 let mut style = Style::default();
-                if item.is_locked {
-                    style = style.fg(Color::Red);
+                if item.is_locked && item.pending_change.is_none() {
+                    style = style.fg(theme.locked);
+                } else if let Some(pattern_match) = pattern_match {
+                    style = style.fg(match pattern_match {
+                        PatternMatch::Full => theme.checked_out,
+                        PatternMatch::Partial => theme.partial,
+                        PatternMatch::None => theme.not_checked_out,
+                    });
                 } else if item.pending_change.is_some() {
-                    style = style.fg(Color::Yellow);
+                    style = style.fg(theme.pending);
                 } else if item.is_checked_out {
-                    style = style.fg(Color::Green);
+                    style = style.fg(theme.checked_out);
                 } else if item.is_implicitly_checked_out || item.has_checked_out_descendant {
-                    style = style.fg(Color::White);
+                    style = style.fg(theme.partial);
                 } else {
-                    style = style.fg(Color::DarkGray);
+                    style = style.fg(theme.not_checked_out);
                 }
 
-                // Highlight the selected item
+                // Highlight the selected item, and the rest of the visual range
                 if view_idx == self.selected_item_index {
-                    style = style.bg(Color::Blue);
+                    style = style.bg(theme.selected);
+                } else if visual_range.contains(&global_idx) {
+                    style = style.bg(theme.visual_range);
                 }
 
                 // 2. Determine Expansion Symbol
+                let glyphs = &self.config.glyphs;
                 let expansion_symbol = if item.is_loading {
-                    "◌ " // Spinner for loading state
+                    glyphs.loading
                 } else if !item.children_loaded {
-                    "▸ "
+                    glyphs.collapsed
                 } else if !item.children_indices.is_empty() {
                     if item.is_expanded {
-                        "▾ "
+                        glyphs.expanded
                     } else {
-                        "▸ "
+                        glyphs.collapsed
                     }
                 } else {
-                    "  " // No children
-                }
-                ;
+                    glyphs.leaf // No children
+                };
 
                 // 3. Determine State Symbol
-                let state_symbol = if item.is_locked {
-                    "🔒 "
+                let state_symbol = if item.is_locked && item.pending_change.is_none() {
+                    glyphs.locked
+                } else if let Some(pattern_match) = pattern_match {
+                    match pattern_match {
+                        PatternMatch::Full => glyphs.checked_out,
+                        PatternMatch::Partial => glyphs.partial_match,
+                        PatternMatch::None => glyphs.not_checked_out,
+                    }
                 } else {
                     match item.pending_change {
-                        Some(ChangeType::Add) => "+ ",
-                        Some(ChangeType::Remove) => "- ",
+                        Some(ChangeType::Add) => glyphs.add,
+                        Some(ChangeType::Remove) => glyphs.remove,
                         None => {
                             if item.is_checked_out {
-                                "✔ "
+                                glyphs.checked_out
                             } else if item.has_checked_out_descendant {
-                                "☐·"
+                                glyphs.has_checked_out_descendant
                             } else {
-                                "☐ "
+                                glyphs.not_checked_out
                             }
                         }
                     }
@@ -616,32 +2212,51 @@ impl App {
 
                 let display_text = format!(
                     "{indent}{expansion_symbol}{state_symbol}{}",
-                    item.name
+                    item.name.display()
                 );
+                let expansion_start = indent.len() as u16;
+                let state_start = expansion_start + Span::raw(expansion_symbol).width() as u16;
+                let state_end = state_start + Span::raw(state_symbol).width() as u16;
+
+                let size_text = match browsed_sizes {
+                    Some(sizes) => {
+                        let stats = sizes.totals.get(&item.path).copied().unwrap_or_default();
+                        format!("{:>7} {:>10}", stats.files, format_size(stats.bytes))
+                    }
+                    None => String::new(),
+                };
 
                 TuiTreeItemViewModel {
                     display_text,
                     style,
+                    size_text,
+                    expansion_columns: expansion_start..state_start,
+                    state_columns: state_start..state_end,
                 }
             })
             .collect()
     }
 
-    pub fn new(repo_path: Option<&PathBuf>) -> Result<Self, git::Error> {
+    pub fn new(repo_path: Option<&PathBuf>, backend: Arc<dyn GitBackend>, revision: String) -> Result<Self, git::Error> {
         let current_repo_root = match repo_path {
             Some(path) => path.clone(),
-            None => git::find_repo_root()?,
+            None => git::find_repo_root(None)?,
         };
+        Self::verify_revision(backend.as_ref(), &revision, &current_repo_root)?;
         let (tx, rx) = mpsc::channel(); // Create the channel
         
-        // Synchronously load sparse checkout list at startup
-        let initial_sparse_checkout_dirs = git::get_sparse_checkout_list(&current_repo_root)?;
+        // Synchronously load the sparse checkout configuration at startup
+        let sparse_state = Self::read_sparse_state(backend.as_ref(), &current_repo_root)?;
 
         let mut app = App {
             current_repo_root,
             tx, // Assign the sender
             rx, // Assign the receiver
-            sparse_checkout_dirs: initial_sparse_checkout_dirs, // Populated synchronously
+            backend,
+            revision,
+            sparse_checkout_dirs: sparse_state.dirs, // Populated synchronously
+            sparse_mode: sparse_state.mode,
+            sparse_patterns: sparse_state.patterns,
             is_refreshing: false, // Initialize new field
             ..Default::default()
         };
@@ -651,12 +2266,17 @@ impl App {
 
         app.load_initial_tree()?; // Now sparse_checkout_dirs is populated here
         app.build_visible_items();
+        app.rebuild_dir_index();
+        if app.sparse_mode == SparseMode::Disabled {
+            app.open_init_dialog(); // First run on this repository
+        }
         Ok(app)
     }
 
     fn build_visible_items_recursive(
         items: &Vec<TreeItem>,
         item_idx: usize,
+        filter: Option<&HashSet<RepoPath>>,
         visible_indices: &mut Vec<usize>,
     ) {
         visible_indices.push(item_idx);
@@ -664,7 +2284,9 @@ impl App {
         let item = &items[item_idx];
         if item.is_expanded {
             for &child_idx in &item.children_indices {
-                Self::build_visible_items_recursive(items, child_idx, visible_indices);
+                if filter.is_none_or(|paths| paths.contains(&items[child_idx].path)) {
+                    Self::build_visible_items_recursive(items, child_idx, filter, visible_indices);
+                }
             }
         }
     }
@@ -673,7 +2295,9 @@ impl App {
     fn build_visible_items(&mut self) {
         self.filtered_item_indices.clear();
         if !self.items.is_empty() {
-            Self::build_visible_items_recursive(&self.items, 0, &mut self.filtered_item_indices);
+            // In filter mode, only search matches and their ancestors are shown
+            let filter = self.search.as_ref().filter(|search| search.is_filtering).map(|search| &search.filter_paths);
+            Self::build_visible_items_recursive(&self.items, 0, filter, &mut self.filtered_item_indices);
         }
     }
 
@@ -693,7 +2317,7 @@ impl App {
     }
 
     // Helper to start loading children and expand the item
-    fn load_children_and_expand(&mut self, global_idx: usize) {
+    pub fn load_children_and_expand(&mut self, global_idx: usize) {
         let item = &mut self.items[global_idx];
 
         // If children are already loaded, just expand and rebuild
@@ -710,6 +2334,13 @@ impl App {
             return;
         }
 
+        // Serve children straight from the directory index once it is built
+        if let Some(index) = &self.dir_index {
+            let children = index.children(&self.items[global_idx].path).to_vec();
+            self.handle_children_loaded(Ok((global_idx, children)));
+            return;
+        }
+
         // Start loading children
         item.is_loading = true;
         
@@ -718,10 +2349,14 @@ impl App {
 
         let repo_root = self.current_repo_root.clone();
         let tx_clone = self.tx.clone();
+        let backend = self.backend.clone();
+        let revision = self.revision.clone();
 
         thread::spawn(move || {
             // Pass the reconstructed full path to get_dirs_at_path
-            let result = git::get_dirs_at_path(&full_path_to_expand, &repo_root).map(|dirs| (global_idx, dirs));
+            let result = backend
+                .get_dirs_at_path(&full_path_to_expand, &revision, &repo_root)
+                .map(|dirs| (global_idx, dirs));
             let _ = tx_clone.send(AppMessage::ChildrenLoaded(result));
         });
     }
@@ -735,6 +2370,25 @@ impl App {
         }
     }
 
+    /// Moves the cursor to the visible row `view_idx`, if there is one.
+    pub fn select_row(&mut self, view_idx: usize) {
+        if view_idx < self.filtered_item_indices.len() {
+            self.selected_item_index = view_idx;
+        }
+    }
+
+    /// Collapses the selected item if it is expanded, and expands it otherwise.
+    pub fn toggle_selected_expansion(&mut self) {
+        let Some(&global_idx) = self.filtered_item_indices.get(self.selected_item_index) else {
+            return;
+        };
+        if self.items[global_idx].is_expanded {
+            self.handle_left_key();
+        } else {
+            self.expand_selected_item();
+        }
+    }
+
     pub fn handle_left_key(&mut self) {
         if let Some(&global_idx) = self.filtered_item_indices.get(self.selected_item_index) {
             // Check if the current item is expanded
@@ -754,46 +2408,471 @@ impl App {
         }
     }
 
-    pub fn move_cursor_page_up(&mut self, _tree_view_height: u16) { // Marked as unused
-        if self.filtered_item_indices.is_empty() {
+    pub fn move_cursor_page_up(&mut self, _tree_view_height: u16) { // Marked as unused
+        if self.filtered_item_indices.is_empty() {
+            return;
+        }
+
+        let page_size = _tree_view_height as usize;
+        let target_index = self.selected_item_index.saturating_sub(page_size);
+
+        self.selected_item_index = target_index;
+        self.scroll_offset = self.scroll_offset.saturating_sub(page_size);
+
+        // Ensure scroll_offset doesn't go below the selected_item_index's visible start
+        if self.selected_item_index < self.scroll_offset {
+            self.scroll_offset = self.selected_item_index;
+        }
+    }
+
+    pub fn move_cursor_page_down(&mut self, _tree_view_height: u16) { // Marked as unused
+        if self.filtered_item_indices.is_empty() {
+            return;
+        }
+
+        let page_size = _tree_view_height as usize;
+        let max_index = self.filtered_item_indices.len().saturating_sub(1);
+        let target_index = std::cmp::min(self.selected_item_index.saturating_add(page_size), max_index);
+
+        self.selected_item_index = target_index;
+        self.scroll_offset = std::cmp::min(self.scroll_offset.saturating_add(page_size), max_index.saturating_sub(page_size));
+
+        // Ensure selected item is always visible after calculation
+        if self.selected_item_index >= self.scroll_offset + page_size {
+            self.scroll_offset = self.selected_item_index.saturating_sub(page_size).saturating_add(1);
+        }
+    }
+
+    /// The uncommitted paths at or below `dir` with their status, in path order. Untracked
+    /// and ignored files are included once they are listed for `dir` or a directory above it.
+    pub fn uncommitted_under(&self, dir: &RepoPath) -> Vec<(&RepoPath, FileStatus)> {
+        let untracked = Some(&self.untracked_paths).filter(|_| self.has_untracked_listing(dir));
+        let mut paths: Vec<(&RepoPath, FileStatus)> = self
+            .uncommitted_paths
+            .iter()
+            .chain(untracked.into_iter().flatten())
+            .filter(|(path, _)| dir.is_root() || path.starts_with_component(dir))
+            .map(|(path, &status)| (path, status))
+            .collect();
+        paths.sort_by_key(|&(path, _)| path);
+        paths
+    }
+
+    /// Whether `untracked_paths` covers everything at or below `dir`.
+    fn has_untracked_listing(&self, dir: &RepoPath) -> bool {
+        self.untracked_dir.as_ref().is_some_and(|untracked_dir| dir.starts_with_component(untracked_dir))
+    }
+
+    /// What happens to the files that do not block removing the directory at `idx`, as far
+    /// as the untracked and ignored files under it are listed.
+    fn removal_warning(&self, idx: usize) -> Option<String> {
+        let item = &self.items[idx];
+        if !self.has_untracked_listing(&item.path) {
+            return None;
+        }
+        let change_kinds = change_kinds_under(&self.untracked_paths, &item.path);
+        let dir = item.path.display();
+        if change_kinds.contains(&ChangeKind::Untracked) {
+            Some(format!("Warning: {dir} contains untracked files, which stay on disk after it is removed"))
+        } else if change_kinds.contains(&ChangeKind::Ignored) {
+            Some(format!("Warning: {dir} contains ignored files, which are deleted when it is removed"))
+        } else {
+            None
+        }
+    }
+
+    /// Asks for confirmation before marking the selected locked directory for removal.
+    pub fn open_force_remove_dialog(&mut self) {
+        let Some(&idx) = self.filtered_item_indices.get(self.selected_item_index) else {
+            return;
+        };
+        let item = &self.items[idx];
+        let refusal = if item.path.is_root() {
+            Some("The root is always checked out".to_string())
+        } else if !item.is_locked {
+            Some("Only locked directories need to be force-removed; press [Space] instead".to_string())
+        } else if self.sparse_mode != SparseMode::Cone {
+            Some("Force-remove needs cone mode".to_string())
+        } else if !item.is_checked_out {
+            Some(format!("{} is not checked out", item.path.display()))
+        } else if item.pending_change == Some(ChangeType::Remove) {
+            Some(format!("{} is already marked for removal", item.path.display()))
+        } else {
+            None
+        };
+        match refusal {
+            Some(refusal) => self.report(Level::Warn, refusal),
+            None => self.force_remove_dialog = Some(idx),
+        }
+    }
+
+    pub fn cancel_force_remove(&mut self) {
+        self.force_remove_dialog = None;
+    }
+
+    /// Marks the directory of the force-remove dialog for removal despite its lock. Git
+    /// leaves files with changes in place, so nothing uncommitted is lost.
+    pub fn confirm_force_remove(&mut self) {
+        let Some(idx) = self.force_remove_dialog.take() else {
+            return;
+        };
+        let dir = self.items[idx].path.display().into_owned();
+        match self.with_undo(|app| app.mark_pending_change(idx, ChangeType::Remove, true)) {
+            Ok(()) => self.report(Level::Info, format!("Marked {dir} for removal; git keeps its uncommitted files on disk")),
+            Err(reason) => self.report(Level::Warn, format!("Cannot remove {dir}: {reason}")),
+        }
+    }
+
+    /// Loads and expands the ancestors of `path` so that it has an item, and returns its index.
+    /// Directories that are not loaded yet come from the directory index, so without it
+    /// only loaded ones are found.
+    pub fn reveal_path(&mut self, path: &RepoPath) -> Option<usize> {
+        let mut ancestors = vec![path.clone()];
+        while let Some(ancestor) = ancestors.last().filter(|ancestor| !ancestor.is_root()) {
+            ancestors.push(ancestor.parent());
+        }
+        for ancestor in ancestors.iter().rev().skip(1) {
+            let parent_idx = *self.path_to_index.get(&ancestor.parent())?;
+            if !self.load_children_from_index(parent_idx) {
+                return None;
+            }
+        }
+        self.path_to_index.get(path).copied()
+    }
+
+    /// Adds and expands the children of the item `idx` from the directory index, unless they
+    /// are loaded already. Returns whether they are loaded.
+    fn load_children_from_index(&mut self, idx: usize) -> bool {
+        if !self.items[idx].children_loaded {
+            let Some(index) = &self.dir_index else {
+                return false;
+            };
+            let children = index.children(&self.items[idx].path).to_vec();
+            self.handle_children_loaded(Ok((idx, children)));
+        }
+        true
+    }
+
+    /// Whether the directory index is built, which actions that reach beyond the loaded
+    /// directories need. Otherwise reports that it is still being built.
+    fn check_indexed(&mut self) -> bool {
+        if self.dir_index.is_none() {
+            self.report(Level::Warn, "Indexing directories... Try again once it is done");
+        }
+        self.dir_index.is_some()
+    }
+
+    /// Marks the directory at `idx` to be added or removed, as toggling it would, or returns
+    /// why that is not possible.
+    pub fn set_pending_change(&mut self, idx: usize, change: ChangeType) -> Result<(), String> {
+        self.with_undo(|app| app.mark_pending_change(idx, change, false))
+    }
+
+    /// Like `set_pending_change`, but `is_forced` lets a locked directory be marked anyway.
+    fn mark_pending_change(&mut self, idx: usize, change: ChangeType, is_forced: bool) -> Result<(), String> {
+        let item = &self.items[idx];
+        if item.path.is_root() {
+            return Err("the root is always checked out".to_string());
+        }
+        if item.is_locked && !is_forced {
+            let blocking = item.change_kinds.iter().filter(|kind| kind.blocks_removal());
+            return Err(format!("contains {} files", format_change_kinds(blocking)));
+        }
+        match self.sparse_mode {
+            SparseMode::Disabled => Err("sparse checkout is not enabled".to_string()),
+            SparseMode::Pattern => {
+                let pattern_match = self.pattern_match_counts.get(&item.path).map(MatchCount::pattern_match);
+                let line = dir_pattern_line(&item.path);
+                match (change, pattern_match) {
+                    (ChangeType::Add, Some(PatternMatch::Full)) => Err("already checked out".to_string()),
+                    (ChangeType::Remove, Some(PatternMatch::None) | None) => Err("not checked out".to_string()),
+                    (ChangeType::Add, _) => {
+                        self.edit_patterns(|patterns| patterns.push(line));
+                        Ok(())
+                    }
+                    (ChangeType::Remove, _) => {
+                        self.edit_patterns(|patterns| patterns.push([b"!".as_slice(), &line].concat()));
+                        Ok(())
+                    }
+                }
+            }
+            SparseMode::Cone => {
+                match change {
+                    ChangeType::Add if item.is_checked_out => return Err("already checked out".to_string()),
+                    ChangeType::Add if item.is_implicitly_checked_out => {
+                        return Err("already checked out through a parent directory".to_string())
+                    }
+                    ChangeType::Remove if item.is_implicitly_checked_out && !item.is_checked_out => {
+                        return Err("checked out through a parent directory".to_string())
+                    }
+                    ChangeType::Remove if !item.is_checked_out => return Err("not checked out".to_string()),
+                    _ => {}
+                }
+                self.items[idx].pending_change = Some(change);
+                self.update_pending_changes_cache(idx);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn toggle_selection(&mut self) {
+        self.with_undo(Self::toggle_selected_item);
+    }
+
+    /// Marks the selected directory and its visible descendants to be checked out.
+    pub fn select_subtree(&mut self) -> BulkResult {
+        let indices = self.visible_subtree();
+        self.bulk_add("Selected", indices)
+    }
+
+    /// Marks every child of the selected directory to be checked out, loading them first.
+    pub fn select_children(&mut self) -> BulkResult {
+        let Some(&idx) = self.filtered_item_indices.get(self.selected_item_index) else {
+            return BulkResult::default();
+        };
+        if !self.items[idx].children_loaded && !self.check_indexed() {
+            return BulkResult::default();
+        }
+        self.load_children_from_index(idx);
+        let indices = self.items[idx].children_indices.clone();
+        self.bulk_add("Selected", indices)
+    }
+
+    /// Marks every search match to be checked out.
+    pub fn select_search_matches(&mut self) -> BulkResult {
+        let Some(matches) = self.search.as_ref().map(|search| search.matches.clone()) else {
+            self.report(Level::Warn, format!("No search to take matches from. Press {} to search", self.config.keys.hint(Action::Search)));
+            return BulkResult::default();
+        };
+        let indices: Vec<usize> = matches.iter().filter_map(|dir| self.reveal_path(dir)).collect();
+        self.build_visible_items();
+        self.bulk_add("Added", indices)
+    }
+
+    /// Toggles the selected directory and each of its visible descendants, as `Space` would.
+    pub fn invert_subtree(&mut self) -> BulkResult {
+        let indices = self.visible_subtree();
+        if !self.check_bulk_mode() {
+            return BulkResult::default();
+        }
+        let result = self.bulk_apply(indices, RangeAction::Toggle);
+        self.report_bulk("Inverted", result);
+        result
+    }
+
+    /// Moves the navigation keys from the tree to the grid's list of uncommitted files, to
+    /// the message log while it is shown, and back.
+    pub fn cycle_focus(&mut self) {
+        self.set_focus(match self.focus {
+            Focus::Tree => Focus::Grid,
+            Focus::Grid if self.show_log => Focus::Log,
+            Focus::Grid | Focus::Log => Focus::Tree,
+        });
+    }
+
+    /// Gives the navigation keys to `focus`.
+    pub fn set_focus(&mut self, focus: Focus) {
+        if self.focus != focus {
+            self.focus = focus;
+            self.uncommitted_scroll = 0;
+        }
+    }
+
+    /// Shows the message log pane with the focus, or hides it.
+    pub fn toggle_log(&mut self) {
+        self.show_log = !self.show_log;
+        self.log_scroll = 0;
+        if self.show_log {
+            self.set_focus(Focus::Log);
+        } else if self.focus == Focus::Log {
+            self.set_focus(Focus::Tree);
+        }
+    }
+
+    /// Scrolls the message log by `delta` lines, negative towards older entries. Drawing
+    /// keeps the first page full.
+    pub fn scroll_log(&mut self, delta: isize) {
+        self.log_scroll = self.log_scroll.saturating_add_signed(delta.saturating_neg());
+    }
+
+    pub fn open_help(&mut self) {
+        self.help_scroll = Some(0);
+    }
+
+    pub fn close_help(&mut self) {
+        self.help_scroll = None;
+    }
+
+    /// Scrolls the key help by `delta` lines. Drawing keeps the last page full.
+    pub fn scroll_help(&mut self, delta: isize) {
+        if let Some(scroll) = &mut self.help_scroll {
+            *scroll = scroll.saturating_add_signed(delta);
+        }
+    }
+
+    /// Scrolls the grid's list of uncommitted files by `delta` lines, keeping the last page full.
+    pub fn scroll_uncommitted_files(&mut self, delta: isize) {
+        let Some(&idx) = self.filtered_item_indices.get(self.selected_item_index) else {
             return;
-        }
+        };
+        let max_scroll = self.uncommitted_under(&self.items[idx].path).len().saturating_sub(self.uncommitted_page);
+        self.uncommitted_scroll = self.uncommitted_scroll.saturating_add_signed(delta).min(max_scroll);
+    }
 
-        let page_size = _tree_view_height as usize;
-        let target_index = self.selected_item_index.saturating_sub(page_size);
+    /// Starts a visual range at the cursor, or leaves visual mode.
+    pub fn toggle_visual_mode(&mut self) {
+        self.visual_anchor = match self.visual_anchor {
+            Some(_) => None,
+            None => self.filtered_item_indices.get(self.selected_item_index).copied(),
+        };
+    }
 
-        self.selected_item_index = target_index;
-        self.scroll_offset = self.scroll_offset.saturating_sub(page_size);
+    /// The visible items between the anchor and the cursor, in tree order.
+    pub fn visual_range(&self) -> Vec<usize> {
+        let Some(anchor) = self.visual_anchor else {
+            return Vec::new();
+        };
+        let Some(anchor_position) = self.filtered_item_indices.iter().position(|&idx| idx == anchor) else {
+            return Vec::new(); // Collapsed away
+        };
+        let (start, end) = if anchor_position <= self.selected_item_index {
+            (anchor_position, self.selected_item_index)
+        } else {
+            (self.selected_item_index, anchor_position)
+        };
+        self.filtered_item_indices[start..=end.min(self.filtered_item_indices.len().saturating_sub(1))].to_vec()
+    }
 
-        // Ensure scroll_offset doesn't go below the selected_item_index's visible start
-        if self.selected_item_index < self.scroll_offset {
-            self.scroll_offset = self.selected_item_index;
+    /// Applies `action` to every item of the visual range and leaves visual mode.
+    pub fn apply_to_visual_range(&mut self, action: RangeAction) -> BulkResult {
+        let indices = self.visual_range();
+        self.visual_anchor = None;
+        if !self.check_bulk_mode() {
+            return BulkResult::default();
         }
+        let result = self.bulk_apply(indices, action);
+        let verb = match action {
+            RangeAction::Toggle => "Toggled",
+            RangeAction::Add => "Added",
+            RangeAction::Remove => "Removed",
+            RangeAction::Clear => "Cleared",
+        };
+        self.report_bulk(verb, result);
+        result
     }
 
-    pub fn move_cursor_page_down(&mut self, _tree_view_height: u16) { // Marked as unused
-        if self.filtered_item_indices.is_empty() {
-            return;
-        }
+    /// Applies `action` to each of `indices` as one undoable step, leaving out the root and
+    /// locked directories.
+    fn bulk_apply(&mut self, indices: Vec<usize>, action: RangeAction) -> BulkResult {
+        self.with_undo(|app| {
+            let mut result = BulkResult::default();
+            for idx in indices {
+                let item = &app.items[idx];
+                if item.path.is_root() {
+                    continue;
+                }
+                // A force-removed directory can still have its removal cleared
+                if item.is_locked && item.pending_change.is_none() {
+                    result.locked += 1;
+                    continue;
+                }
+                let is_changed = match (action, item.pending_change) {
+                    (RangeAction::Toggle, _) if app.sparse_mode == SparseMode::Pattern => {
+                        let path = item.path.clone();
+                        app.toggle_pattern_for(&path);
+                        true
+                    }
+                    (RangeAction::Clear, _) if app.sparse_mode == SparseMode::Pattern => {
+                        let path = item.path.clone();
+                        app.clear_patterns_for(&path)
+                    }
+                    // Adding a directory marked for removal keeps it, and vice versa
+                    (RangeAction::Toggle | RangeAction::Clear, Some(_))
+                    | (RangeAction::Add, Some(ChangeType::Remove))
+                    | (RangeAction::Remove, Some(ChangeType::Add)) => {
+                        app.items[idx].pending_change = None;
+                        app.update_pending_changes_cache(idx);
+                        true
+                    }
+                    (RangeAction::Clear, None) => false,
+                    (RangeAction::Toggle, None) if item.is_checked_out => app.set_pending_change(idx, ChangeType::Remove).is_ok(),
+                    (RangeAction::Toggle, None) => app.set_pending_change(idx, ChangeType::Add).is_ok(),
+                    (RangeAction::Add, Some(ChangeType::Add)) | (RangeAction::Remove, Some(ChangeType::Remove)) => false,
+                    (RangeAction::Add, _) => app.set_pending_change(idx, ChangeType::Add).is_ok(),
+                    (RangeAction::Remove, _) => app.set_pending_change(idx, ChangeType::Remove).is_ok(),
+                };
+                result.changed += usize::from(is_changed);
+            }
+            result
+        })
+    }
 
-        let page_size = _tree_view_height as usize;
-        let max_index = self.filtered_item_indices.len().saturating_sub(1);
-        let target_index = std::cmp::min(self.selected_item_index.saturating_add(page_size), max_index);
+    /// The selected item followed by its descendants in the visible tree.
+    fn visible_subtree(&self) -> Vec<usize> {
+        let Some(&idx) = self.filtered_item_indices.get(self.selected_item_index) else {
+            return Vec::new();
+        };
+        let level = self.items[idx].indentation_level;
+        let descendants = self.filtered_item_indices[self.selected_item_index + 1..]
+            .iter()
+            .take_while(|&&i| self.items[i].indentation_level > level);
+        std::iter::once(idx).chain(descendants.copied()).collect()
+    }
 
-        self.selected_item_index = target_index;
-        self.scroll_offset = std::cmp::min(self.scroll_offset.saturating_add(page_size), max_index.saturating_sub(page_size).max(0));
+    fn check_bulk_mode(&mut self) -> bool {
+        if self.sparse_mode == SparseMode::Disabled {
+            self.report_sparse_disabled();
+        }
+        self.sparse_mode != SparseMode::Disabled
+    }
 
-        // Ensure selected item is always visible after calculation
-        if self.selected_item_index >= self.scroll_offset + page_size {
-            self.scroll_offset = self.selected_item_index.saturating_sub(page_size).saturating_add(1);
+    /// Marks `indices` to be checked out, leaving out locked directories and those already
+    /// checked out.
+    fn bulk_add(&mut self, verb: &str, indices: Vec<usize>) -> BulkResult {
+        if !self.check_bulk_mode() {
+            return BulkResult::default();
         }
+        let result = self.bulk_apply(indices, RangeAction::Add);
+        self.report_bulk(verb, result);
+        result
     }
 
-    pub fn toggle_selection(&mut self) {
+    /// Shows how many directories a bulk selection changed and how many locks it skipped.
+    fn report_bulk(&mut self, verb: &str, result: BulkResult) {
+        let directories = if result.changed == 1 { "directory" } else { "directories" };
+        let mut message = format!("{verb} {} {directories}", result.changed);
+        if result.locked > 0 {
+            message.push_str(&format!(", skipped {} with uncommitted changes", result.locked));
+        }
+        self.report(Level::Info, message);
+    }
+
+    fn toggle_selected_item(&mut self) {
         if let Some(&global_idx) = self.filtered_item_indices.get(self.selected_item_index) {
             let item = &mut self.items[global_idx];
-            if item.is_locked {
-                // Cannot toggle selection on locked items
+            if item.is_locked && item.pending_change.is_none() {
+                // Cannot toggle selection on locked items, unless to undo a force-remove
+                if !item.path.is_root() {
+                    let blocking = item.change_kinds.iter().filter(|kind| kind.blocks_removal());
+                    let message = format!(
+                        "{} contains {} files. Press {} to remove it anyway",
+                        item.path.display(),
+                        format_change_kinds(blocking),
+                        self.config.keys.hint(Action::ForceRemove)
+                    );
+                    self.report(Level::Warn, message);
+                }
+                return;
+            }
+            if self.sparse_mode == SparseMode::Disabled {
+                self.report_sparse_disabled();
+                return;
+            }
+            if self.sparse_mode == SparseMode::Pattern {
+                let path = item.path.clone();
+                if !path.is_root() {
+                    self.toggle_pattern_for(&path);
+                }
                 return;
             }
 
@@ -810,81 +2889,93 @@ impl App {
                 }
             };
             self.update_pending_changes_cache(global_idx); // Update cache after toggling selection
+            if self.items[global_idx].pending_change == Some(ChangeType::Remove) {
+                match self.removal_warning(global_idx) {
+                    Some(warning) => self.report(Level::Warn, warning),
+                    None => self.dismiss_message(),
+                }
+                // Otherwise it warns once the listing arrives
+                self.warn_when_untracked_loaded =
+                    self.is_loading_untracked && self.untracked_dir.as_ref() == Some(&self.items[global_idx].path);
+            }
+        }
+    }
+}
+
+/// The kinds of change of the uncommitted paths at or below `dir`.
+pub fn change_kinds_under(uncommitted_paths: &HashMap<RepoPath, FileStatus>, dir: &RepoPath) -> BTreeSet<ChangeKind> {
+    uncommitted_paths
+        .iter()
+        .filter(|(path, _)| path.starts_with_component(dir))
+        .flat_map(|(_, status)| status.kinds())
+        .collect()
+}
+
+/// e.g. "staged and modified"
+fn format_change_kinds<'a>(kinds: impl IntoIterator<Item = &'a ChangeKind>) -> String {
+    let labels: Vec<&str> = kinds.into_iter().map(|kind| kind.label()).collect();
+    match labels.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {last}", rest.join(", ")),
+        _ => labels.join(""),
+    }
+}
+
+/// e.g. "docs (contains uncommitted changes), web (already checked out)"
+fn format_skipped(skipped: &[(RepoPath, String)]) -> String {
+    let reasons: Vec<String> = skipped.iter().map(|(dir, reason)| format!("{} ({reason})", dir.display())).collect();
+    reasons.join(", ")
+}
+
+/// e.g. "1234 files, 5.6 MiB"
+fn format_stats(stats: DirStats) -> String {
+    format!("{} files, {}", stats.files, format_size(stats.bytes))
+}
+
+/// The pattern that checks out the directory `path` recursively, e.g. `/docs/api/`.
+fn dir_pattern_line(path: &RepoPath) -> Vec<u8> {
+    let mut line = vec![b'/'];
+    for &b in path.as_bytes() {
+        // Escape glob characters so that the name is matched literally
+        if matches!(b, b'*' | b'?' | b'[' | b'\\') {
+            line.push(b'\\');
         }
+        line.push(b);
     }
+    line.push(b'/');
+    line
 }
 
 #[cfg(test)]
 mod app_tests {
     use super::*;
+    use crate::fake_backend::FakeBackend;
+    use crate::git::tests::{git, setup_git_repo};
     use std::fs;
     use std::time::Duration;
     use std::process::Command; // Import Command for tests
-    use tempfile::tempdir;
-
-    // --- Helper functions copied from git_test.rs for self-containment ---
-    pub fn setup_git_repo() -> (PathBuf, tempfile::TempDir) {
-        let dir = tempdir().unwrap();
-        let path = dir.path().to_path_buf();
-        Command::new("git")
-            .arg("init")
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        Command::new("git")
-            .args(&["config", "user.email", "test@example.com"])
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        Command::new("git")
-            .args(&["config", "user.name", "Test User"])
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        // Ensure core.quotepath is false for consistent unescaped test output
-        Command::new("git")
-            .args(&["config", "core.quotepath", "false"])
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        (path, dir)
-    }
 
-    fn create_and_commit_files(repo_path: &PathBuf) {
-        fs::create_dir_all(&repo_path.join("dir1/subdir1")).unwrap();
-        fs::write(&repo_path.join("dir1/subdir1/file1.txt"), "content").unwrap();
-        fs::create_dir_all(&repo_path.join("dir1/subdir2")).unwrap();
-        fs::write(&repo_path.join("dir1/subdir2/file2.txt"), "content").unwrap();
-        fs::create_dir_all(&repo_path.join("dir2/subdir3/subdir4")).unwrap();
-        fs::write(&repo_path.join("dir2/subdir3/subdir4/file3.txt"), "content").unwrap();
-        fs::create_dir_all(&repo_path.join("dir3")).unwrap(); // Empty dir
-        fs::write(&repo_path.join("dir3/.gitkeep"), "").unwrap(); // Add .gitkeep to track empty dir
+    fn create_and_commit_files(repo_path: &Path) {
+        fs::create_dir_all(repo_path.join("dir1/subdir1")).unwrap();
+        fs::write(repo_path.join("dir1/subdir1/file1.txt"), "content").unwrap();
+        fs::create_dir_all(repo_path.join("dir1/subdir2")).unwrap();
+        fs::write(repo_path.join("dir1/subdir2/file2.txt"), "content").unwrap();
+        fs::create_dir_all(repo_path.join("dir2/subdir3/subdir4")).unwrap();
+        fs::write(repo_path.join("dir2/subdir3/subdir4/file3.txt"), "content").unwrap();
+        fs::create_dir_all(repo_path.join("dir3")).unwrap(); // Empty dir
+        fs::write(repo_path.join("dir3/.gitkeep"), "").unwrap(); // Add .gitkeep to track empty dir
 
         // Add a Japanese directory
-        fs::create_dir_all(&repo_path.join("日本語ディレクトリ/サブディレクトリ")).unwrap();
-        fs::write(&repo_path.join("日本語ディレクトリ/サブディレクトリ/.gitkeep"), "").unwrap(); // Add .gitkeep to track empty dir
-        fs::write(&repo_path.join("日本語ディレクトリ/ファイル.txt"), "content").unwrap();
+        fs::create_dir_all(repo_path.join("日本語ディレクトリ/サブディレクトリ")).unwrap();
+        fs::write(repo_path.join("日本語ディレクトリ/サブディレクトリ/.gitkeep"), "").unwrap(); // Add .gitkeep to track empty dir
+        fs::write(repo_path.join("日本語ディレクトリ/ファイル.txt"), "content").unwrap();
 
 
-        Command::new("git")
-            .args(&["add", "."])
-            .current_dir(&repo_path)
-            .output()
-            .unwrap();
-        Command::new("git")
-            .args(&["commit", "-m", "Test commit with nested dirs"])
-            .current_dir(&repo_path)
-            .output()
-            .unwrap();
+        git(repo_path, &["add", "."]);
+        git(repo_path, &["commit", "-m", "Test commit with nested dirs"]);
         
         // Initialize sparse-checkout
-        Command::new("git")
-            .args(&["sparse-checkout", "init", "--cone"])
-            .current_dir(&repo_path)
-            .output()
-            .expect("git sparse-checkout init --cone failed");
+        git(repo_path, &["sparse-checkout", "init", "--cone"]);
     }
-    // --- End Helper functions ---
 
     #[test]
     fn test_expand_multiple_subtrees() {
@@ -892,13 +2983,13 @@ mod app_tests {
         create_and_commit_files(&repo_path);
 
         let (test_thread_tx, test_thread_rx) = mpsc::channel(); // Channel for App's spawned threads to send to test
-        let (app_tx_dummy, app_rx_dummy) = mpsc::channel(); // Dummy channel for App's rx, since App's tx is what matters for tests
+        let (_app_tx_dummy, app_rx_dummy) = mpsc::channel(); // Dummy channel for App's rx, since App's tx is what matters for tests
         let mut app = App { tx: test_thread_tx, rx: app_rx_dummy, ..Default::default() };
         
         // Simulate app initialization
         app.current_repo_root = repo_path.clone();
         app.sparse_checkout_dirs = vec![]; // Initially nothing checked out
-        app.uncommitted_paths = HashSet::new();
+        app.uncommitted_paths = HashMap::new();
 
         app.load_initial_tree().unwrap(); // Load root and first level
 
@@ -981,7 +3072,7 @@ mod app_tests {
         create_and_commit_files(&repo_path);
 
         let (test_thread_tx, test_thread_rx) = mpsc::channel(); // Channel for App's spawned threads to send to test
-        let (app_tx_dummy, app_rx_dummy) = mpsc::channel(); // Dummy channel for App's rx
+        let (_app_tx_dummy, app_rx_dummy) = mpsc::channel(); // Dummy channel for App's rx
         let mut app = App { tx: test_thread_tx, rx: app_rx_dummy, ..Default::default() };
 
         app.current_repo_root = repo_path.clone();
@@ -1010,7 +3101,7 @@ mod app_tests {
         create_and_commit_files(&repo_path);
 
         let (test_thread_tx, test_thread_rx) = mpsc::channel(); // Channel for App's spawned threads to send to test
-        let (app_tx_dummy, app_rx_dummy) = mpsc::channel(); // Dummy channel for App's rx
+        let (_app_tx_dummy, app_rx_dummy) = mpsc::channel(); // Dummy channel for App's rx
         let mut app = App { tx: test_thread_tx, rx: app_rx_dummy, ..Default::default() };
 
         app.current_repo_root = repo_path.clone();
@@ -1038,52 +3129,176 @@ mod app_tests {
         assert!(test_thread_rx.try_recv().is_err(), "No new AppMessage should be sent");
     }
 
+    #[test]
+    fn test_expand_served_from_dir_index() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+
+        let (test_thread_tx, test_thread_rx) = mpsc::channel();
+        let (_app_tx_dummy, app_rx_dummy) = mpsc::channel();
+        let mut app = App { tx: test_thread_tx, rx: app_rx_dummy, sparse_mode: SparseMode::Cone, ..Default::default() };
+
+        app.current_repo_root = repo_path.clone();
+        app.load_initial_tree().unwrap();
+
+        // Build the index in the background and deliver it like the main loop would
+        app.rebuild_dir_index();
+        assert!(app.is_indexing);
+        match test_thread_rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive DirIndexBuilt") {
+            AppMessage::DirIndexBuilt(result) => app.handle_dir_index_built(result),
+            other => panic!("Unexpected AppMessage received: {:?}", other),
+        }
+        assert!(!app.is_indexing);
+        let tree_id = app.dir_index.as_ref().expect("index should be built").tree_id.clone();
+        assert_eq!(tree_id, git::get_tree_id("HEAD", &repo_path).unwrap());
+
+        // File counts and sizes are loaded for the indexed tree next
+        assert!(app.is_loading_sizes);
+        match test_thread_rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive TreeSizesLoaded") {
+            AppMessage::TreeSizesLoaded(result) => app.handle_tree_sizes_loaded(result),
+            other => panic!("Unexpected AppMessage received: {:?}", other),
+        }
+        assert!(!app.is_loading_sizes);
+        assert!(app.tree_sizes.contains_key(tree_id.as_ref().unwrap()));
+        app.build_visible_items();
+        let dir1_idx = app.items.iter().position(|item| item.name == "dir1").unwrap();
+        app.selected_item_index = app.filtered_item_indices.iter().position(|&idx| idx == dir1_idx).unwrap();
+        let grid = app.get_grid_view_model().unwrap();
+        assert_eq!(grid.files, "2 files, 14 B");
+        assert_eq!(grid.on_disk, "0 files, 0 B"); // Nothing is checked out
+        app.toggle_size_columns();
+        let tree_items = app.get_tui_tree_items();
+        assert_eq!(tree_items[app.selected_item_index].size_text, format!("{:>7} {:>10}", 2, "14 B"));
+
+        // Expanding is now answered synchronously, without another git query
+        let dir2_global_idx = app.items.iter().position(|item| item.name == "dir2").expect("dir2 not found");
+        app.load_children_and_expand(dir2_global_idx);
+        assert!(test_thread_rx.try_recv().is_err(), "No AppMessage should be sent");
+        let dir2_item = &app.items[dir2_global_idx];
+        assert!(dir2_item.is_expanded && dir2_item.children_loaded);
+        assert_eq!(dir2_item.children_indices.len(), 1);
+        let subdir3_idx = dir2_item.children_indices[0];
+        assert_eq!(app.items[subdir3_idx].path, "dir2/subdir3");
+
+        app.load_children_and_expand(subdir3_idx);
+        let subdir3_item = &app.items[subdir3_idx];
+        assert_eq!(app.items[subdir3_item.children_indices[0]].path, "dir2/subdir3/subdir4");
+
+        // With HEAD unchanged, a rebuild keeps the existing index
+        let index_before = app.dir_index.clone().unwrap();
+        app.rebuild_dir_index();
+        match test_thread_rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive DirIndexBuilt") {
+            AppMessage::DirIndexBuilt(result) => {
+                assert!(matches!(&result, Ok(update) if update.browsed.is_none()), "index should not be rebuilt");
+                app.handle_dir_index_built(result);
+            }
+            other => panic!("Unexpected AppMessage received: {:?}", other),
+        }
+        assert!(Arc::ptr_eq(&index_before, app.dir_index.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn test_browse_other_revision() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+        git(&repo_path, &["checkout", "-q", "-b", "release"]);
+        fs::create_dir_all(repo_path.join("release_dir/nested")).unwrap();
+        fs::write(repo_path.join("release_dir/nested/file.txt"), "content").unwrap();
+        git(&repo_path, &["add", "--sparse", "."]);
+        git(&repo_path, &["commit", "-q", "-m", "Release only dir"]);
+        git(&repo_path, &["checkout", "-q", "-"]);
+
+        let (test_thread_tx, test_thread_rx) = mpsc::channel();
+        let (_app_tx_dummy, app_rx_dummy) = mpsc::channel();
+        let mut app = App { tx: test_thread_tx, rx: app_rx_dummy, sparse_mode: SparseMode::Cone, ..Default::default() };
+        app.current_repo_root = repo_path.clone();
+        app.load_initial_tree().unwrap();
+        app.build_visible_items();
+        assert!(!app.items.iter().any(|item| item.name == "release_dir"));
+        let receive = |app: &mut App, expected: &str| {
+            let message = test_thread_rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive a message");
+            assert!(format!("{message:?}").starts_with(expected), "Unexpected AppMessage received: {message:?}");
+            app.handle_message(message);
+        };
+
+        // The revision is checked in the background
+        app.set_revision("no-such-branch".to_string());
+        assert_eq!(app.loading_revision.as_deref(), Some("no-such-branch"));
+        receive(&mut app, "RevisionLoaded");
+        assert_eq!(app.revision, "HEAD");
+        assert!(app.loading_revision.is_none());
+        assert!(app.message().unwrap().1.contains("no-such-branch"));
+
+        // Open the picker, narrow it down and confirm
+        app.revision_picker = Some(RevisionPicker {
+            candidates: vec!["HEAD".to_string(), "master".to_string(), "release".to_string()],
+            ..Default::default()
+        });
+        for c in "rel".chars() {
+            app.revision_picker_input(c);
+        }
+        assert_eq!(app.revision_picker.as_ref().unwrap().matches(), ["release"]);
+        app.confirm_revision_picker();
+        assert!(app.revision_picker.is_none());
+        assert_eq!(app.revision, "HEAD");
+        assert_eq!(app.loading_revision.as_deref(), Some("release"));
+        assert!(!app.items.iter().any(|item| item.name == "release_dir")); // Until its tree arrives
+        receive(&mut app, "RevisionLoaded");
+        assert_eq!(app.revision, "release");
+
+        let release_idx = app.items.iter().position(|item| item.name == "release_dir").expect("release_dir not found");
+        receive(&mut app, "DirIndexBuilt");
+        assert!(app.head_dir_index.is_some());
+
+        app.selected_item_index = app.filtered_item_indices.iter().position(|&idx| idx == release_idx).unwrap();
+        let grid = app.get_grid_view_model().unwrap();
+        assert_eq!(grid.revision, "release");
+        assert_eq!(grid.in_head, "No (only in release)");
+
+        app.load_children_and_expand(release_idx);
+        let nested_idx = app.items[release_idx].children_indices[0];
+        assert_eq!(app.items[nested_idx].path, "release_dir/nested");
+
+        let dir1_idx = app.items.iter().position(|item| item.name == "dir1").unwrap();
+        app.selected_item_index = app.filtered_item_indices.iter().position(|&idx| idx == dir1_idx).unwrap();
+        assert_eq!(app.get_grid_view_model().unwrap().in_head, "Yes");
+
+        // Pending changes have to be applied before switching back
+        app.toggle_selection();
+        app.set_revision("HEAD".to_string());
+        assert_eq!(app.revision, "release");
+    }
+
     #[test]
     fn test_expand_non_checked_out_directory() {
         let (repo_path, _temp_dir) = setup_git_repo();
         
         // Create a directory structure that is NOT checked out
-        fs::create_dir_all(&repo_path.join("virtual_dir/virtual_subdir1")).unwrap();
-        fs::write(&repo_path.join("virtual_dir/virtual_subdir1/file.txt"), "content").unwrap();
-        fs::create_dir_all(&repo_path.join("virtual_dir/virtual_subdir2")).unwrap();
-        fs::write(&repo_path.join("virtual_dir/virtual_subdir2/file.txt"), "content").unwrap();
+        fs::create_dir_all(repo_path.join("virtual_dir/virtual_subdir1")).unwrap();
+        fs::write(repo_path.join("virtual_dir/virtual_subdir1/file.txt"), "content").unwrap();
+        fs::create_dir_all(repo_path.join("virtual_dir/virtual_subdir2")).unwrap();
+        fs::write(repo_path.join("virtual_dir/virtual_subdir2/file.txt"), "content").unwrap();
         
-        Command::new("git")
-            .args(&["add", "."])
-            .current_dir(&repo_path)
-            .output()
-            .unwrap();
-        Command::new("git")
-            .args(&["commit", "-m", "Add virtual dirs"])
-            .current_dir(&repo_path)
-            .output()
-            .unwrap();
+        git(&repo_path, &["add", "."]);
+        git(&repo_path, &["commit", "-m", "Add virtual dirs"]);
 
         // Initialize sparse-checkout but DO NOT CHECK OUT "virtual_dir"
-        Command::new("git")
-            .args(&["sparse-checkout", "init", "--cone"])
-            .current_dir(&repo_path)
-            .output()
-            .expect("git sparse-checkout init --cone failed");
+        git(&repo_path, &["sparse-checkout", "init", "--cone"]);
         
         // Explicitly set sparse-checkout to something else, ensuring virtual_dir is NOT checked out
-        Command::new("git")
-            .args(&["sparse-checkout", "set", "dir1"]) // Check out 'dir1' if it exists, but not 'virtual_dir'
-            .current_dir(&repo_path)
-            .output()
-            .expect("git sparse-checkout set failed");
+        git(&repo_path, &["sparse-checkout", "set", "dir1"]); // Check out 'dir1' if it exists, but not 'virtual_dir'
 
         // Verify "virtual_dir" does NOT exist physically
         assert!(!repo_path.join("virtual_dir").is_dir(), "virtual_dir should not exist physically");
 
 
         let (test_thread_tx, test_thread_rx) = mpsc::channel();
-        let (app_tx_dummy, app_rx_dummy) = mpsc::channel();
+        let (_app_tx_dummy, app_rx_dummy) = mpsc::channel();
         let mut app = App { tx: test_thread_tx, rx: app_rx_dummy, ..Default::default() };
         
         app.current_repo_root = repo_path.clone();
         app.sparse_checkout_dirs = git::get_sparse_checkout_list(&repo_path).unwrap(); // Load sparse checkout info
-        app.uncommitted_paths = HashSet::new();
+        app.uncommitted_paths = HashMap::new();
 
         app.load_initial_tree().unwrap(); // Load root and first level
 
@@ -1110,4 +3325,741 @@ mod app_tests {
         assert_eq!(app.items[*virtual_subdir1_idx].name, "virtual_subdir1");
         assert_eq!(app.items[*virtual_subdir2_idx].name, "virtual_subdir2");
     }
+
+    #[test]
+    fn test_pattern_mode_marks_and_edits() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+        git(&repo_path, &["sparse-checkout", "set", "--no-cone", "/dir1/subdir1/", "/dir3/"]);
+
+        let (test_thread_tx, test_thread_rx) = mpsc::channel();
+        let (_app_tx_dummy, app_rx_dummy) = mpsc::channel();
+        let mut app = App { tx: test_thread_tx, rx: app_rx_dummy, ..Default::default() };
+        app.current_repo_root = repo_path.clone();
+        let sparse_state = App::read_sparse_state(app.backend.as_ref(), &repo_path).unwrap();
+        assert_eq!(sparse_state.mode, SparseMode::Pattern);
+        app.sparse_mode = sparse_state.mode;
+        app.sparse_patterns = sparse_state.patterns;
+        app.load_initial_tree().unwrap();
+        app.build_visible_items();
+        app.rebuild_dir_index();
+        match test_thread_rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive DirIndexBuilt") {
+            AppMessage::DirIndexBuilt(result) => app.handle_dir_index_built(result),
+            other => panic!("Unexpected AppMessage received: {:?}", other),
+        }
+
+        let pattern_match = |app: &App, path: &str| app.pattern_match_counts[&RepoPath::from(path)].pattern_match();
+        assert_eq!(pattern_match(&app, "dir1"), PatternMatch::Partial);
+        assert_eq!(pattern_match(&app, "dir1/subdir1"), PatternMatch::Full);
+        assert_eq!(pattern_match(&app, "dir2"), PatternMatch::None);
+        assert_eq!(pattern_match(&app, "dir3"), PatternMatch::Full);
+
+        let select = |app: &mut App, name: &str| {
+            let idx = app.items.iter().position(|item| item.name == name).unwrap();
+            app.selected_item_index = app.filtered_item_indices.iter().position(|&i| i == idx).unwrap();
+        };
+        // Toggling adds an include line for a directory that is not fully matched...
+        select(&mut app, "dir2");
+        app.toggle_selection();
+        assert_eq!(pattern_match(&app, "dir2"), PatternMatch::Full);
+        assert_eq!(app.get_grid_view_model().unwrap().status, "Fully Matched (1/1 files)");
+        // ...and an exclude line for one that is, which toggling again takes back
+        select(&mut app, "dir3");
+        app.toggle_selection();
+        assert_eq!(pattern_match(&app, "dir3"), PatternMatch::None);
+        app.toggle_selection();
+        assert_eq!(pattern_match(&app, "dir3"), PatternMatch::Full);
+        assert_eq!(app.get_grid_view_model().unwrap().pending_changes, "1");
+
+        // Negate the first line in the editor and add a line of its own
+        app.open_pattern_editor();
+        app.pattern_editor_toggle_negation();
+        app.pattern_editor_begin_edit(true);
+        for c in "*2.txt".chars() {
+            app.pattern_editor_input(c);
+        }
+        app.pattern_editor_commit_edit();
+        assert_eq!(app.pattern_editor.as_ref().unwrap().selected, 1);
+        let lines: Vec<&[u8]> = app.pattern_lines().iter().map(Vec::as_slice).collect();
+        assert_eq!(lines, [&b"!/dir1/subdir1/"[..], b"*2.txt", b"/dir3/", b"/dir2/"]);
+        assert_eq!(pattern_match(&app, "dir1"), PatternMatch::Partial); // Only file2.txt
+        assert_eq!(pattern_match(&app, "dir1/subdir1"), PatternMatch::None);
+        app.close_pattern_editor();
+
+        app.apply_changes();
+        loop {
+            match test_thread_rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive ApplyChangesCompleted") {
+                AppMessage::ApplyChangesCompleted(result, _) => break result.unwrap(),
+                AppMessage::TreeSizesLoaded(result) => app.handle_tree_sizes_loaded(result),
+                other => panic!("Unexpected AppMessage received: {:?}", other),
+            }
+        }
+        assert!(!repo_path.join("dir1/subdir1/file1.txt").exists());
+        assert!(repo_path.join("dir1/subdir2/file2.txt").exists());
+        assert!(repo_path.join("dir2/subdir3/subdir4/file3.txt").exists());
+        let applied = git::get_sparse_checkout_patterns(&repo_path).unwrap();
+        assert_eq!(applied, app.pending_patterns.unwrap());
+    }
+
+    #[test]
+    fn test_init_and_disable_sparse_checkout() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        fs::create_dir_all(repo_path.join("dir1")).unwrap();
+        fs::write(repo_path.join("dir1/file1.txt"), "content").unwrap();
+        fs::write(repo_path.join("top.txt"), "content").unwrap();
+        git(&repo_path, &["add", "."]);
+        git(&repo_path, &["commit", "-m", "Initial"]);
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        assert_eq!(app.sparse_mode, SparseMode::Disabled);
+        assert_eq!(app.sparse_mode_dialog, Some(SparseModeDialog::Init { sparse_index: false }));
+        // Everything is checked out, and there is nothing to toggle yet
+        let dir1_idx = app.items.iter().position(|item| item.name == "dir1").unwrap();
+        assert!(app.items[dir1_idx].is_checked_out);
+        app.selected_item_index = app.filtered_item_indices.iter().position(|&idx| idx == dir1_idx).unwrap();
+        app.toggle_selection();
+        assert!(app.items[dir1_idx].pending_change.is_none());
+        assert!(app.message().is_some());
+
+        // Handles messages until the refresh that follows `init` or `disable` has landed
+        let wait_for_refresh = |app: &mut App| loop {
+            match app.rx.recv_timeout(Duration::from_secs(5)).expect("No AppMessage received") {
+                AppMessage::SparseModeChanged(result, stamp) => app.handle_sparse_mode_changed(result, stamp),
+                AppMessage::DirIndexBuilt(result) => app.handle_dir_index_built(result),
+                AppMessage::TreeSizesLoaded(result) => app.handle_tree_sizes_loaded(result),
+                AppMessage::RefreshCompleted(generation, result) => break app.handle_refresh_completed(generation, result),
+                other => panic!("Unexpected AppMessage received: {:?}", other),
+            }
+        };
+        app.toggle_sparse_index_option();
+        app.confirm_sparse_mode_dialog();
+        assert!(app.is_changing_sparse_mode);
+        wait_for_refresh(&mut app);
+        assert_eq!(app.sparse_mode, SparseMode::Cone);
+        assert!(!app.items[dir1_idx].is_checked_out);
+        assert!(!repo_path.join("dir1/file1.txt").exists());
+        let index_sparse = Command::new("git")
+            .args(["config", "index.sparse"])
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert_eq!(index_sparse.stdout, b"true\n");
+
+        app.open_init_dialog();
+        assert!(app.sparse_mode_dialog.is_none());
+        app.open_disable_dialog();
+        assert_eq!(app.sparse_mode_dialog, Some(SparseModeDialog::ConfirmDisable));
+        app.close_sparse_mode_dialog();
+        assert!(repo_path.join("top.txt").exists());
+        assert!(!repo_path.join("dir1/file1.txt").exists()); // Cancelled
+
+        app.open_disable_dialog();
+        app.confirm_sparse_mode_dialog();
+        wait_for_refresh(&mut app);
+        assert_eq!(app.sparse_mode, SparseMode::Disabled);
+        assert!(app.items[dir1_idx].is_checked_out);
+        assert!(repo_path.join("dir1/file1.txt").exists());
+    }
+
+    #[test]
+    fn test_preview_before_apply() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        app.preview_changes();
+        assert!(!app.is_previewing);
+        assert_eq!(app.message(), Some((Level::Info, "No pending changes to apply")));
+
+        let dir1_idx = app.items.iter().position(|item| item.name == "dir1").unwrap();
+        app.selected_item_index = app.filtered_item_indices.iter().position(|&idx| idx == dir1_idx).unwrap();
+        app.toggle_selection();
+        app.preview_changes();
+        assert!(app.is_previewing);
+        loop {
+            match app.rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive ApplyPreviewReady") {
+                AppMessage::ApplyPreviewReady(result) => break app.handle_apply_preview_ready(result),
+                AppMessage::DirIndexBuilt(result) => app.handle_dir_index_built(result),
+                AppMessage::TreeSizesLoaded(result) => app.handle_tree_sizes_loaded(result),
+                other => panic!("Unexpected AppMessage received: {:?}", other),
+            }
+        }
+        let preview = app.apply_preview.clone().expect("No preview");
+        assert_eq!(preview.added, ["dir1"]);
+        assert!(preview.removed.is_empty());
+        assert_eq!((preview.files_added, preview.bytes_added), (2, 14)); // Two files of "content"
+        assert!(!repo_path.join("dir1/subdir1/file1.txt").exists()); // Nothing applied yet
+
+        app.cancel_apply_preview();
+        assert!(app.apply_preview.is_none());
+        assert!(app.items[dir1_idx].pending_change.is_some());
+
+        app.apply_preview = Some(preview);
+        app.confirm_apply_preview();
+        assert!(app.is_applying_changes);
+        loop {
+            match app.rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive ApplyChangesCompleted") {
+                AppMessage::ApplyChangesCompleted(result, _) => break result.unwrap(),
+                AppMessage::DirIndexBuilt(result) => app.handle_dir_index_built(result),
+                AppMessage::TreeSizesLoaded(result) => app.handle_tree_sizes_loaded(result),
+                other => panic!("Unexpected AppMessage received: {:?}", other),
+            }
+        }
+        assert!(repo_path.join("dir1/subdir1/file1.txt").exists());
+    }
+
+    #[test]
+    fn test_undo_redo_and_revert_apply() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        let position = |app: &App, name: &str| app.items.iter().position(|item| item.name == name).unwrap();
+        let (dir1_idx, dir2_idx) = (position(&app, "dir1"), position(&app, "dir2"));
+        for idx in [dir1_idx, dir2_idx] {
+            app.selected_item_index = app.filtered_item_indices.iter().position(|&i| i == idx).unwrap();
+            app.toggle_selection();
+        }
+        let pending = |app: &App| (app.items[dir1_idx].pending_change, app.items[dir2_idx].pending_change);
+        assert_eq!(pending(&app), (Some(ChangeType::Add), Some(ChangeType::Add)));
+
+        app.undo();
+        assert_eq!(pending(&app), (Some(ChangeType::Add), None));
+        assert_eq!(app.items[0].cached_pending_changes, 1);
+        app.undo();
+        assert_eq!(pending(&app), (None, None));
+        app.undo();
+        assert_eq!(app.message(), Some((Level::Info, "Nothing to undo")));
+        app.redo();
+        app.redo();
+        assert_eq!(pending(&app), (Some(ChangeType::Add), Some(ChangeType::Add)));
+        assert_eq!(app.items[0].cached_pending_changes, 2);
+
+        // A refused change is not a step of its own
+        assert!(app.set_pending_change(0, ChangeType::Remove).is_err());
+        app.undo();
+        assert_eq!(pending(&app), (Some(ChangeType::Add), None));
+        app.redo();
+
+        app.apply_changes();
+        app.wait_until(|app| !app.is_applying_changes && !app.is_refreshing);
+        let mut dirs = app.sparse_checkout_dirs.clone();
+        dirs.sort();
+        assert_eq!(dirs, [RepoPath::from("dir1"), RepoPath::from("dir2")]);
+        assert_eq!(app.applied_history.len(), 1);
+        app.undo();
+        assert_eq!(app.message(), Some((Level::Info, "Nothing to undo")));
+
+        app.revert_last_apply();
+        app.wait_until(|app| !app.is_previewing);
+        let preview = app.apply_preview.clone().expect("No preview");
+        assert_eq!(preview.removed, ["dir1", "dir2"]);
+        app.confirm_apply_preview();
+        app.wait_until(|app| !app.is_applying_changes && !app.is_refreshing);
+        assert!(app.sparse_checkout_dirs.is_empty());
+        assert!(app.applied_history.is_empty());
+        assert!(!repo_path.join("dir1/subdir1/file1.txt").exists());
+        app.revert_last_apply();
+        assert_eq!(app.message(), Some((Level::Info, "No earlier sparse set to revert to")));
+    }
+
+    #[test]
+    fn test_search_jumps_and_filters() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        app.wait_until(|app| !app.is_indexing);
+        let selected_path = |app: &App| app.items[app.filtered_item_indices[app.selected_item_index]].path.clone();
+
+        app.open_search();
+        for c in "sub4".chars() {
+            app.search_input(c);
+        }
+        // Not loaded before, so the ancestors were expanded to reach it
+        assert_eq!(selected_path(&app), "dir2/subdir3/subdir4");
+        assert!(app.items[app.path_to_index[&RepoPath::from("dir2")]].is_expanded);
+
+        app.search_backspace();
+        assert_eq!(app.search.as_ref().unwrap().matches.len(), 4); // subdir1 to subdir4
+        app.toggle_search_filter();
+        let visible: Vec<String> = app
+            .filtered_item_indices
+            .iter()
+            .map(|&idx| app.items[idx].path.display().into_owned())
+            .collect();
+        assert_eq!(
+            visible,
+            [".", "dir1", "dir1/subdir1", "dir1/subdir2", "dir2", "dir2/subdir3", "dir2/subdir3/subdir4"]
+        );
+
+        app.confirm_search();
+        let first = selected_path(&app);
+        app.search_move(true);
+        assert_ne!(selected_path(&app), first);
+        app.search_move(false);
+        assert_eq!(selected_path(&app), first);
+
+        app.clear_search();
+        assert_eq!(selected_path(&app), first);
+        assert!(app.filtered_item_indices.len() > visible.len()); // dir3 and the others are back
+    }
+
+    #[test]
+    fn test_bulk_selection_skips_locked() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+        fs::create_dir_all(repo_path.join("dir1/subdir2")).unwrap(); // Not checked out
+        fs::write(repo_path.join("dir1/subdir2/staged.txt"), "new").unwrap();
+        git(&repo_path, &["add", "--sparse", "dir1/subdir2"]);
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        // Children that are not loaded yet come from the directory index, once it is built
+        let dir1 = app.path_to_index[&RepoPath::from("dir1")];
+        app.selected_item_index = app.filtered_item_indices.iter().position(|&i| i == dir1).unwrap();
+        assert_eq!(app.select_children(), BulkResult::default());
+        assert_eq!(app.message(), Some((Level::Warn, "Indexing directories... Try again once it is done")));
+        assert!(!app.items[dir1].children_loaded);
+        app.wait_until(|app| !app.is_indexing);
+        let select = |app: &mut App, path: &str| {
+            let idx = app.reveal_path(&RepoPath::from(path)).unwrap();
+            app.selected_item_index = app.filtered_item_indices.iter().position(|&i| i == idx).unwrap();
+        };
+        let pending = |app: &App| -> Vec<String> {
+            let mut dirs: Vec<String> = app
+                .items
+                .iter()
+                .filter(|item| item.pending_change.is_some())
+                .map(|item| item.path.display().into_owned())
+                .collect();
+            dirs.sort();
+            dirs
+        };
+
+        select(&mut app, "dir1");
+        assert_eq!(app.select_children(), BulkResult { changed: 1, locked: 1 });
+        assert_eq!(app.message(), Some((Level::Info, "Selected 1 directory, skipped 1 with uncommitted changes")));
+        assert_eq!(pending(&app), ["dir1/subdir1"]);
+        app.undo();
+        assert!(pending(&app).is_empty());
+
+        select(&mut app, "dir2");
+        app.expand_selected_item();
+        assert_eq!(app.select_subtree(), BulkResult { changed: 2, locked: 0 }); // dir2 and subdir3
+        assert_eq!(pending(&app), ["dir2", "dir2/subdir3"]);
+        assert_eq!(app.invert_subtree(), BulkResult { changed: 2, locked: 0 });
+        assert!(pending(&app).is_empty());
+
+        app.open_search();
+        for c in "subdir".chars() {
+            app.search_input(c);
+        }
+        assert_eq!(app.select_search_matches(), BulkResult { changed: 3, locked: 1 });
+        assert_eq!(pending(&app), ["dir1/subdir1", "dir2/subdir3", "dir2/subdir3/subdir4"]);
+        app.undo(); // The whole bulk selection is one step
+        assert!(pending(&app).is_empty());
+    }
+
+    #[test]
+    fn test_visual_range_actions() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+        fs::create_dir_all(repo_path.join("dir3")).unwrap();
+        fs::write(repo_path.join("dir3/staged.txt"), "new").unwrap();
+        git(&repo_path, &["add", "--sparse", "dir3"]);
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        let position = |app: &App, name: &str| {
+            let idx = app.items.iter().position(|item| item.name == name).unwrap();
+            app.filtered_item_indices.iter().position(|&i| i == idx).unwrap()
+        };
+        let pending = |app: &App| app.items.iter().filter(|item| item.pending_change.is_some()).count();
+
+        app.selected_item_index = position(&app, "dir1");
+        app.toggle_visual_mode();
+        app.move_cursor_down();
+        app.move_cursor_down();
+        assert_eq!(app.visual_range().len(), 3); // dir1, dir2 and the locked dir3
+        assert_eq!(app.apply_to_visual_range(RangeAction::Add), BulkResult { changed: 2, locked: 1 });
+        assert!(app.visual_anchor.is_none());
+        assert_eq!(pending(&app), 2);
+        assert_eq!(app.items[0].cached_pending_changes, 2);
+
+        // The range extends upwards from the anchor as well
+        app.selected_item_index = position(&app, "dir2");
+        app.toggle_visual_mode();
+        app.move_cursor_up();
+        assert_eq!(app.apply_to_visual_range(RangeAction::Clear), BulkResult { changed: 2, locked: 0 });
+        assert_eq!(pending(&app), 0);
+        assert_eq!(app.items[0].cached_pending_changes, 0);
+
+        app.toggle_visual_mode();
+        app.move_cursor_down();
+        assert_eq!(app.apply_to_visual_range(RangeAction::Toggle), BulkResult { changed: 2, locked: 0 });
+        assert_eq!(pending(&app), 2);
+        app.toggle_visual_mode();
+        app.move_cursor_up();
+        assert_eq!(app.apply_to_visual_range(RangeAction::Remove), BulkResult { changed: 2, locked: 0 });
+        assert_eq!(pending(&app), 0);
+        app.undo();
+        assert_eq!(pending(&app), 2);
+    }
+
+    #[test]
+    fn test_lock_reasons_and_force_remove() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+        git(&repo_path, &["sparse-checkout", "set", "dir1", "dir2"]);
+        fs::write(repo_path.join("dir1/build.out"), "untracked").unwrap();
+        fs::write(repo_path.join("dir2/subdir3/subdir4/file3.txt"), "changed").unwrap();
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        let select = |app: &mut App, name: &str| {
+            let idx = app.items.iter().position(|item| item.name == name).unwrap();
+            app.selected_item_index = app.filtered_item_indices.iter().position(|&i| i == idx).unwrap();
+            idx
+        };
+
+        // Untracked files only warn, once they are listed
+        let dir1 = select(&mut app, "dir1");
+        assert!(!app.items[dir1].is_locked);
+        app.load_selected_untracked();
+        app.toggle_selection();
+        assert_eq!(app.items[dir1].pending_change, Some(ChangeType::Remove));
+        assert_eq!(app.message(), None);
+        app.wait_until(|app| !app.is_loading_untracked);
+        assert_eq!(
+            app.message(),
+            Some((Level::Warn, "Warning: dir1 contains untracked files, which stay on disk after it is removed"))
+        );
+        app.toggle_selection();
+        app.toggle_selection();
+        assert_eq!(
+            app.message(),
+            Some((Level::Warn, "Warning: dir1 contains untracked files, which stay on disk after it is removed"))
+        );
+
+        // Modified tracked files block, until the removal is forced
+        let dir2 = select(&mut app, "dir2");
+        assert!(app.items[dir2].is_locked);
+        let grid = app.get_grid_view_model().unwrap();
+        assert_eq!(grid.status, "Locked (modified)");
+        assert_eq!(grid.uncommitted, "1 file: 1 modified");
+        assert_eq!(
+            grid.uncommitted_files,
+            [UncommittedFileViewModel {
+                status: ".M".to_string(),
+                path: "dir2/subdir3/subdir4/file3.txt".to_string(),
+                blocks_removal: true,
+            }]
+        );
+        app.toggle_selection();
+        assert_eq!(app.items[dir2].pending_change, None);
+        assert_eq!(
+            app.message(),
+            Some((Level::Warn, "dir2 contains modified files. Press [F] to remove it anyway"))
+        );
+
+        app.open_force_remove_dialog();
+        assert_eq!(app.force_remove_dialog, Some(dir2));
+        app.cancel_force_remove();
+        assert_eq!(app.items[dir2].pending_change, None);
+        app.open_force_remove_dialog();
+        app.confirm_force_remove();
+        assert_eq!(app.items[dir2].pending_change, Some(ChangeType::Remove));
+        app.toggle_selection(); // A forced removal can be taken back
+        assert_eq!(app.items[dir2].pending_change, None);
+    }
+
+    #[test]
+    fn test_uncommitted_file_list_focus_and_scroll() {
+        let (repo_path, _temp_dir) = setup_git_repo();
+        create_and_commit_files(&repo_path);
+        git(&repo_path, &["sparse-checkout", "set", "dir1"]);
+        for name in ["a", "b", "c", "d", "e"] {
+            fs::write(repo_path.join(format!("dir1/{name}.txt")), "untracked").unwrap();
+        }
+        fs::write(repo_path.join("dir1/subdir1/file1.txt"), "changed").unwrap();
+
+        let mut app = App::new(Some(&repo_path), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+        let idx = app.items.iter().position(|item| item.name == "dir1").unwrap();
+        app.selected_item_index = app.filtered_item_indices.iter().position(|&i| i == idx).unwrap();
+        let files = app.get_grid_view_model().unwrap().uncommitted_files;
+        assert_eq!(files.len(), 1); // Untracked files are listed for the selected directory only
+        app.load_selected_untracked();
+        app.wait_until(|app| !app.is_loading_untracked);
+        let files = app.get_grid_view_model().unwrap().uncommitted_files;
+        let lines: Vec<String> = files.iter().map(|file| format!("{} {}", file.status, file.path)).collect();
+        assert_eq!(
+            lines,
+            ["?? dir1/a.txt", "?? dir1/b.txt", "?? dir1/c.txt", "?? dir1/d.txt", "?? dir1/e.txt", ".M dir1/subdir1/file1.txt"]
+        );
+        assert!(files[5].blocks_removal && !files[0].blocks_removal);
+
+        app.cycle_focus();
+        assert_eq!(app.focus, Focus::Grid);
+        app.uncommitted_page = 4; // As the renderer would set it
+        app.scroll_uncommitted_files(1);
+        assert_eq!(app.uncommitted_scroll, 1);
+        app.scroll_uncommitted_files(isize::MAX);
+        assert_eq!(app.uncommitted_scroll, 2); // The last page stays full
+        app.scroll_uncommitted_files(-5);
+        assert_eq!(app.uncommitted_scroll, 0);
+        app.scroll_uncommitted_files(2);
+        app.cycle_focus();
+        assert_eq!((app.focus, app.uncommitted_scroll), (Focus::Tree, 0));
+    }
+
+    /// An app on `backend`, once the directory index and sizes have been loaded.
+    fn fake_app(backend: FakeBackend) -> (App, Arc<FakeBackend>) {
+        let backend = Arc::new(backend);
+        let mut app = App::new(Some(&PathBuf::from("/fake/repo")), backend.clone(), "HEAD".to_string()).unwrap();
+        app.wait_until(|app| !app.is_indexing && !app.is_loading_sizes);
+        (app, backend)
+    }
+
+    fn item<'a>(app: &'a App, path: &str) -> &'a TreeItem {
+        &app.items[app.path_to_index[&RepoPath::from(path)]]
+    }
+
+    const FAKE_FILES: [&str; 4] = ["a/b/c/file", "a/d/file", "e/file", "top"];
+
+    #[test]
+    fn test_fake_children_loaded_without_index() {
+        let backend = FakeBackend::new(&FAKE_FILES)
+            .with_sparse_dirs(&["a/b"])
+            .with_uncommitted(&[("a/d/file", FileStatus(*b".M"))]);
+        let index_gate = backend.hold_next("get_all_dirs");
+        let backend = Arc::new(backend);
+        let mut app = App::new(Some(&PathBuf::from("/fake/repo")), backend.clone(), "HEAD".to_string()).unwrap();
+        assert_eq!(app.items[0].name, "repo");
+
+        // Until the index is built, expanding asks the backend
+        let a = app.path_to_index[&RepoPath::from("a")];
+        app.load_children_and_expand(a);
+        assert!(app.items[a].is_loading);
+        app.wait_until(|app| !app.items[a].is_loading);
+        assert_eq!(backend.calls_to("get_dirs_at_path"), [". HEAD", "a HEAD"]);
+        let children: Vec<&RepoPath> = app.items[a].children_indices.iter().map(|&idx| &app.items[idx].name).collect();
+        assert_eq!(children, ["b", "d"]);
+        assert!(item(&app, "a/b").is_checked_out && !item(&app, "a/b").is_locked);
+        assert!(item(&app, "a/d").is_locked && !item(&app, "a/d").is_checked_out);
+        assert!(item(&app, "a").has_checked_out_descendant && !item(&app, "a").is_checked_out);
+        assert!(!item(&app, "e").has_checked_out_descendant);
+
+        index_gate.open();
+        app.wait_until(|app| !app.is_indexing);
+        let b = app.path_to_index[&RepoPath::from("a/b")];
+        app.load_children_and_expand(b); // Served from the index
+        assert!(item(&app, "a/b/c").is_implicitly_checked_out);
+        assert_eq!(backend.calls_to("get_dirs_at_path").len(), 2);
+
+        // A failed expansion is reported and can be retried
+        let e = app.path_to_index[&RepoPath::from("e")];
+        app.items[e].is_loading = true;
+        app.handle_children_loaded(Err(git::Error::GitCommand("fatal: bad tree".to_string())));
+        assert!(!app.items[e].is_loading && !app.items[e].children_loaded);
+        assert_eq!(app.message(), Some((Level::Error, "Git command failed: fatal: bad tree")));
+    }
+
+    #[test]
+    fn test_fake_head_moved_while_indexing() {
+        let backend = FakeBackend::new(&FAKE_FILES);
+        let index_gate = backend.hold_next("get_all_dirs");
+        let backend = Arc::new(backend);
+        let mut app = App::new(Some(&PathBuf::from("/fake/repo")), backend.clone(), "HEAD".to_string()).unwrap();
+        index_gate.wait_entered();
+
+        // The refresh for the new commit arrives while the old tree is still being indexed
+        backend.commit(&["a/b/c/file", "f/file"]);
+        app.refresh();
+        app.wait_until(|app| !app.is_refreshing);
+        index_gate.open();
+        app.wait_until(|app| !app.is_indexing);
+        assert_eq!(app.dir_index.as_ref().unwrap().tree_id.as_deref(), Some("fake-head-tree-1"));
+        assert_eq!(backend.calls_to("get_all_dirs"), ["fake-head-tree", "fake-head-tree-1"]);
+    }
+
+    #[test]
+    fn test_fake_revision_picker_listed_in_background() {
+        let (mut app, backend) = fake_app(FakeBackend::new(&FAKE_FILES));
+        let list_gate = backend.hold_next("list_revisions");
+        app.open_revision_picker();
+        list_gate.wait_entered();
+        assert!(app.is_listing_revisions && app.revision_picker.is_none());
+        app.open_revision_picker(); // Already on its way
+        list_gate.open();
+        app.wait_until(|app| !app.is_listing_revisions);
+        assert_eq!(app.revision_picker.as_ref().unwrap().candidates, ["HEAD"]);
+        assert_eq!(backend.calls_to("list_revisions").len(), 1);
+
+        // A revision that does not resolve leaves the tree as it was
+        app.close_revision_picker();
+        app.set_revision("no-such-branch".to_string());
+        app.wait_until(|app| app.loading_revision.is_none());
+        assert_eq!(app.revision, "HEAD");
+        assert!(app.message().unwrap().1.contains("no-such-branch"));
+        assert_eq!(item(&app, "a").name, "a");
+    }
+
+    #[test]
+    fn test_fake_refresh_updates_tree_states() {
+        let (mut app, backend) = fake_app(FakeBackend::new(&FAKE_FILES).with_sparse_dirs(&["a/b"]));
+        let a = app.path_to_index[&RepoPath::from("a")];
+        app.load_children_and_expand(a);
+        assert!(item(&app, "a/b").is_checked_out && !item(&app, "a/b").is_implicitly_checked_out);
+
+        backend.set_sparse_dirs(&["a"]);
+        backend.set_uncommitted(&[("a/b/c/file", FileStatus(*b"M."))]);
+        app.refresh();
+        app.wait_until(|app| !app.is_refreshing);
+        assert!(item(&app, "a").is_checked_out);
+        assert!(item(&app, "a/b").is_implicitly_checked_out && !item(&app, "a/b").is_checked_out);
+        assert_eq!(item(&app, "a/b").change_kinds, BTreeSet::from([ChangeKind::Staged]));
+        assert!(item(&app, "a").is_locked && !item(&app, "e").is_locked);
+        assert!(app.items[0].has_checked_out_descendant);
+    }
+
+    #[test]
+    fn test_fake_repository_changed() {
+        let (mut app, backend) = fake_app(FakeBackend::new(&FAKE_FILES));
+        let refreshes = |backend: &FakeBackend| backend.calls_to("get_uncommitted_paths").len();
+        let before = refreshes(&backend);
+        // The watched files of a real repository stand in for the fake one's
+        let (repo_path, _temp_dir) = setup_git_repo();
+        let (git_dir, common_dir) = git::get_git_dirs(&repo_path).unwrap();
+        let watched = WatchedPaths::new(&git_dir, &common_dir);
+
+        // An outside change refreshes without clearing the footer message
+        backend.set_sparse_dirs(&["e"]);
+        app.report(Level::Warn, "Earlier message");
+        app.handle_message(AppMessage::RepositoryChanged(watched.stamp()));
+        app.wait_until(|app| !app.is_refreshing);
+        assert!(item(&app, "e").is_checked_out);
+        assert_eq!(app.message(), Some((Level::Warn, "Earlier message")));
+        assert_eq!(refreshes(&backend), before + 1);
+
+        // While pickit applies changes itself, and for the watcher's report of its write, the
+        // completion refreshes instead
+        app.is_applying_changes = true;
+        app.handle_message(AppMessage::RepositoryChanged(watched.stamp()));
+        assert!(!app.is_refreshing);
+        assert_eq!(refreshes(&backend), before + 1);
+        app.handle_message(AppMessage::ApplyChangesCompleted(Ok(()), Some(watched.stamp())));
+        app.wait_until(|app| !app.is_refreshing);
+        assert_eq!(refreshes(&backend), before + 2);
+        app.handle_message(AppMessage::RepositoryChanged(watched.stamp()));
+        assert!(!app.is_refreshing);
+        assert_eq!(refreshes(&backend), before + 2);
+
+        // A checkout right after the write is not taken for its report
+        git(&repo_path, &["checkout", "-q", "-b", "feature"]);
+        app.handle_message(AppMessage::RepositoryChanged(watched.stamp()));
+        app.wait_until(|app| !app.is_refreshing);
+        assert_eq!(refreshes(&backend), before + 3);
+    }
+
+    #[test]
+    fn test_fake_apply_changes() {
+        let (mut app, backend) = fake_app(FakeBackend::new(&FAKE_FILES).with_sparse_dirs(&["a"]));
+        let pending = |app: &App| app.items.iter().filter(|item| item.pending_change.is_some()).count();
+
+        // Only additions extend the set
+        app.set_pending_change(app.path_to_index[&RepoPath::from("e")], ChangeType::Add).unwrap();
+        app.apply_changes();
+        assert!(app.is_applying_changes);
+        app.wait_until(|app| !app.is_applying_changes && !app.is_refreshing);
+        assert_eq!(backend.calls_to("add_sparse_checkout_dirs"), ["e"]);
+        assert_eq!(backend.sparse_dirs(), [RepoPath::from("a"), RepoPath::from("e")]);
+        assert_eq!(pending(&app), 0);
+        assert!(item(&app, "e").is_checked_out);
+
+        // Removals rewrite it on top of what git has
+        backend.fail("set_sparse_checkout_dirs", "fatal: cannot update the working tree");
+        app.set_pending_change(app.path_to_index[&RepoPath::from("a")], ChangeType::Remove).unwrap();
+        app.apply_changes();
+        app.wait_until(|app| !app.is_applying_changes);
+        assert_eq!(app.message(), Some((Level::Error, "Git command failed: fatal: cannot update the working tree")));
+        assert_eq!(pending(&app), 1); // Kept for another try
+
+        backend.succeed("set_sparse_checkout_dirs");
+        app.apply_changes();
+        app.wait_until(|app| !app.is_applying_changes && !app.is_refreshing);
+        assert_eq!(backend.calls_to("set_sparse_checkout_dirs"), ["e", "e"]);
+        assert_eq!(backend.sparse_dirs(), [RepoPath::from("e")]);
+        assert!(!item(&app, "a").is_checked_out);
+        assert_eq!(pending(&app), 0);
+        assert_eq!(app.applied_history.len(), 2);
+    }
+
+    #[test]
+    fn test_fake_refresh_results_apply_in_order() {
+        let (mut app, backend) = fake_app(FakeBackend::new(&FAKE_FILES).with_sparse_dirs(&["a"]));
+        backend.set_latency(Duration::from_millis(5));
+
+        // The first refresh reads the old state, then stalls until the second has landed
+        let gate = backend.hold_next("get_uncommitted_paths");
+        app.refresh();
+        gate.wait_entered();
+        backend.set_sparse_dirs(&["e"]);
+        app.refresh();
+        app.wait_until(|app| !app.is_refreshing);
+        assert!(item(&app, "e").is_checked_out && !item(&app, "a").is_checked_out);
+
+        gate.open();
+        let stale = loop {
+            match app.rx.recv_timeout(Duration::from_secs(5)).expect("Did not receive the first RefreshCompleted") {
+                AppMessage::RefreshCompleted(generation, result) => break (generation, result),
+                other => app.handle_message(other),
+            }
+        };
+        assert_eq!(stale.0, 1);
+        app.handle_refresh_completed(stale.0, stale.1);
+        assert!(item(&app, "e").is_checked_out && !item(&app, "a").is_checked_out);
+        assert!(!app.is_refreshing);
+        assert_eq!(backend.calls_to("get_uncommitted_paths").len(), 3); // At startup, then once per refresh
+    }
+
+    #[test]
+    fn test_fake_init_sparse_checkout() {
+        let backend = FakeBackend::new(&FAKE_FILES);
+        backend.set_sparse_mode(SparseMode::Disabled);
+        let (mut app, backend) = fake_app(backend);
+        assert!(item(&app, "a").is_checked_out); // Everything is, without sparse-checkout
+        assert_eq!(app.sparse_mode_dialog, Some(SparseModeDialog::Init { sparse_index: false }));
+
+        app.toggle_sparse_index_option();
+        app.confirm_sparse_mode_dialog();
+        app.wait_until(|app| !app.is_changing_sparse_mode && !app.is_refreshing);
+        assert_eq!(backend.calls_to("init_sparse_checkout"), ["true"]);
+        assert_eq!(app.sparse_mode, SparseMode::Cone);
+        assert!(!item(&app, "a").is_checked_out);
+    }
+
+    #[test]
+    fn test_fake_pattern_range_clear() {
+        let (mut app, _backend) = fake_app(FakeBackend::new(&FAKE_FILES).with_sparse_patterns(&["/*", "!/*/", "/e/"]));
+        let lines = |app: &App| app.pattern_lines().iter().map(|line| String::from_utf8_lossy(line).into_owned()).collect::<Vec<_>>();
+        let select_a_to_e = |app: &mut App| {
+            let idx = app.path_to_index[&RepoPath::from("a")];
+            app.selected_item_index = app.filtered_item_indices.iter().position(|&i| i == idx).unwrap();
+            app.toggle_visual_mode();
+            app.move_cursor_down();
+        };
+
+        select_a_to_e(&mut app);
+        assert_eq!(app.apply_to_visual_range(RangeAction::Toggle), BulkResult { changed: 2, locked: 0 });
+        assert_eq!(lines(&app), ["/*", "!/*/", "/e/", "/a/", "!/e/"]);
+
+        // Lines added since the last apply are dropped, and removed ones come back in place
+        app.edit_patterns(|patterns| patterns.retain(|line| line != b"/e/"));
+        select_a_to_e(&mut app);
+        assert_eq!(app.apply_to_visual_range(RangeAction::Clear), BulkResult { changed: 2, locked: 0 });
+        assert_eq!(lines(&app), ["/*", "!/*/", "/e/"]);
+
+        select_a_to_e(&mut app);
+        assert_eq!(app.apply_to_visual_range(RangeAction::Clear), BulkResult { changed: 0, locked: 0 });
+        app.undo();
+        assert_eq!(lines(&app), ["/*", "!/*/", "/a/", "!/e/"]);
+    }
 }
@@ -0,0 +1,189 @@
+//! What applying the pending changes would do to the working tree.
+//!
+//! Nothing is run: the current and proposed sparse-checkout specifications are both matched
+//! against the blobs of `HEAD`, whose sizes come from `git ls-tree -l`.
+
+use crate::repo_path::RepoPath;
+use crate::sparse_patterns;
+use std::collections::HashSet;
+
+/// The sparse-checkout configuration to evaluate files against.
+#[derive(Debug, Clone)]
+pub enum SparseSpec {
+    Cone(Vec<RepoPath>),
+    Patterns(Vec<Vec<u8>>),
+}
+
+impl SparseSpec {
+    /// Returns a predicate telling whether a file is checked out under this specification.
+    pub fn matcher(&self) -> Box<dyn Fn(&RepoPath) -> bool + '_> {
+        match self {
+            SparseSpec::Cone(dirs) => {
+                let cone = Cone::new(dirs);
+                Box::new(move |file| cone.contains_file(file))
+            }
+            SparseSpec::Patterns(lines) => {
+                let patterns = sparse_patterns::parse(lines);
+                Box::new(move |file| sparse_patterns::is_included(&patterns, file.as_bytes()))
+            }
+        }
+    }
+}
+
+/// The cone-mode rules: files at the top level and directly inside the parents of a
+/// listed directory are checked out, and so is everything below a listed directory.
+struct Cone {
+    recursive: HashSet<RepoPath>,
+    parents: HashSet<RepoPath>,
+}
+
+impl Cone {
+    fn new(dirs: &[RepoPath]) -> Self {
+        let mut parents = HashSet::new();
+        for dir in dirs {
+            let mut parent = dir.parent();
+            while !parent.is_root() && parents.insert(parent.clone()) {
+                parent = parent.parent();
+            }
+        }
+        Cone { recursive: dirs.iter().cloned().collect(), parents }
+    }
+
+    fn contains_file(&self, file: &RepoPath) -> bool {
+        let dir = file.parent();
+        if dir.is_root() || self.parents.contains(&dir) {
+            return true;
+        }
+        let mut ancestor = dir;
+        while !ancestor.is_root() {
+            if self.recursive.contains(&ancestor) {
+                return true;
+            }
+            ancestor = ancestor.parent();
+        }
+        false
+    }
+}
+
+/// The outcome of applying a proposed specification, for the confirmation screen.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyPreview {
+    pub added: Vec<String>,   // Directories (or pattern lines) that will be added
+    pub removed: Vec<String>, // Directories (or pattern lines) that will be removed
+    pub files_added: usize,   // Files that will materialize
+    pub files_removed: usize, // Files that will disappear
+    pub bytes_added: u64,
+    pub bytes_removed: u64,
+}
+
+impl ApplyPreview {
+    /// Compares `current` and `proposed` over `files`, the blobs of `HEAD` with their sizes.
+    pub fn compute(current: &SparseSpec, proposed: &SparseSpec, files: &[(RepoPath, u64)]) -> Self {
+        let (added, removed) = match (current, proposed) {
+            (SparseSpec::Cone(old), SparseSpec::Cone(new)) => {
+                let show = |dirs: Vec<&RepoPath>| dirs.into_iter().map(|dir| dir.display().into_owned()).collect();
+                let mut added: Vec<&RepoPath> = new.iter().filter(|dir| !old.contains(dir)).collect();
+                let mut removed: Vec<&RepoPath> = old.iter().filter(|dir| !new.contains(dir)).collect();
+                added.sort();
+                removed.sort();
+                (show(added), show(removed))
+            }
+            (SparseSpec::Patterns(old), SparseSpec::Patterns(new)) => {
+                let show = |lines: &[Vec<u8>], other: &[Vec<u8>]| {
+                    lines
+                        .iter()
+                        .filter(|line| !other.contains(line))
+                        .map(|line| String::from_utf8_lossy(line).into_owned())
+                        .collect()
+                };
+                (show(new, old), show(old, new))
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        let mut preview = ApplyPreview { added, removed, ..Default::default() };
+        let (was_included, is_included) = (current.matcher(), proposed.matcher());
+        for (file, size) in files {
+            match (was_included(file), is_included(file)) {
+                (false, true) => {
+                    preview.files_added += 1;
+                    preview.bytes_added += size;
+                }
+                (true, false) => {
+                    preview.files_removed += 1;
+                    preview.bytes_removed += size;
+                }
+                _ => {}
+            }
+        }
+        preview
+    }
+
+    /// Estimated change in disk usage, in bytes.
+    pub fn disk_delta(&self) -> i64 {
+        self.bytes_added as i64 - self.bytes_removed as i64
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(entries: &[(&str, u64)]) -> Vec<(RepoPath, u64)> {
+        entries.iter().map(|&(path, size)| (RepoPath::from(path), size)).collect()
+    }
+
+    #[test]
+    fn test_cone_preview_counts_files_and_bytes() {
+        let files = files(&[
+            ("README.md", 10),
+            ("a/top.txt", 20),
+            ("a/b/inner.txt", 300),
+            ("a/b/c/deep.txt", 4000),
+            ("a/other/x.txt", 50000),
+            ("z/z.txt", 600000),
+        ]);
+        let current = SparseSpec::Cone(vec!["z".into()]);
+        let proposed = SparseSpec::Cone(vec!["a/b".into()]);
+        let preview = ApplyPreview::compute(&current, &proposed, &files);
+
+        assert_eq!(preview.added, ["a/b"]);
+        assert_eq!(preview.removed, ["z"]);
+        // a/top.txt comes along because "a" is a parent of "a/b"; a/other does not
+        assert_eq!((preview.files_added, preview.bytes_added), (3, 4320));
+        assert_eq!((preview.files_removed, preview.bytes_removed), (1, 600000));
+        assert_eq!(preview.disk_delta(), 4320 - 600000);
+    }
+
+    #[test]
+    fn test_pattern_preview_lists_changed_lines() {
+        let files = files(&[("docs/a.md", 1), ("docs/b.txt", 2), ("src/main.rs", 4)]);
+        let lines = |lines: &[&str]| lines.iter().map(|line| line.as_bytes().to_vec()).collect();
+        let current = SparseSpec::Patterns(lines(&["/docs/"]));
+        let proposed = SparseSpec::Patterns(lines(&["/docs/", "!*.txt", "/src/"]));
+        let preview = ApplyPreview::compute(&current, &proposed, &files);
+
+        assert_eq!(preview.added, ["!*.txt", "/src/"]);
+        assert!(preview.removed.is_empty());
+        assert_eq!((preview.files_added, preview.bytes_added), (1, 4));
+        assert_eq!((preview.files_removed, preview.bytes_removed), (1, 2));
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(12), "12 B");
+    }
+}
@@ -0,0 +1,159 @@
+use crate::git::{self, FileStatus, SparseMode};
+use crate::native::NativeBackend;
+use crate::repo_path::RepoPath;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The git queries and operations `App` needs, independent of how they are carried out.
+///
+/// Backends are shared with the background threads spawned by `App`, so they must be
+/// `Send + Sync`.
+pub trait GitBackend: Debug + Send + Sync {
+    /// Returns the names of the direct subdirectories of `path` in the tree of `rev`.
+    fn get_dirs_at_path(&self, path: &RepoPath, rev: &str, repo_path: &Path) -> git::Result<Vec<RepoPath>>;
+
+    /// Returns the id of `rev`'s root tree, or `None` if it does not resolve (for `HEAD`,
+    /// an unborn branch).
+    fn get_tree_id(&self, rev: &str, repo_path: &Path) -> git::Result<Option<String>>;
+
+    /// Returns the contents of the file at `path` in `rev`, or `None` if it has no such file.
+    fn get_file_contents(&self, rev: &str, path: &RepoPath, repo_path: &Path) -> git::Result<Option<Vec<u8>>>;
+
+    /// Returns every file (and submodule) in the tree `tree_id`, recursively.
+    fn get_all_files(&self, tree_id: &str, repo_path: &Path) -> git::Result<Vec<RepoPath>>;
+
+    /// Returns every blob in the tree `tree_id` with its size in bytes, recursively.
+    fn get_file_sizes(&self, tree_id: &str, repo_path: &Path) -> git::Result<Vec<(RepoPath, u64)>>;
+
+    /// Lists branch, tag and remote-tracking branch names to offer as revisions.
+    fn list_revisions(&self, repo_path: &Path) -> git::Result<Vec<String>>;
+
+    /// Returns every directory in the tree `tree_id`, recursively, as paths from the root.
+    fn get_all_dirs(&self, tree_id: &str, repo_path: &Path) -> git::Result<Vec<RepoPath>>;
+
+    /// Returns the current sparse-checkout set, or an empty list if the worktree is not sparse.
+    fn get_sparse_checkout_list(&self, repo_path: &Path) -> git::Result<Vec<RepoPath>>;
+
+    /// Returns whether sparse checkout is enabled, and in which mode.
+    fn get_sparse_mode(&self, repo_path: &Path) -> git::Result<SparseMode>;
+
+    /// Returns the non-blank lines of the sparse-checkout file, as written.
+    fn get_sparse_checkout_patterns(&self, repo_path: &Path) -> git::Result<Vec<Vec<u8>>>;
+
+    /// Returns every tracked path that is staged, modified or conflicted, with its status as
+    /// `git status --porcelain=v2` shows it.
+    fn get_uncommitted_paths(&self, repo_path: &Path) -> git::Result<HashMap<RepoPath, FileStatus>>;
+
+    /// Returns the untracked and ignored paths under `dir`, a wholly ignored directory once.
+    fn get_untracked_paths(&self, dir: &RepoPath, repo_path: &Path) -> git::Result<HashMap<RepoPath, FileStatus>>;
+
+    /// Replaces the sparse-checkout set and updates the working tree.
+    fn set_sparse_checkout_dirs(&self, dirs: Vec<RepoPath>, repo_path: &Path) -> git::Result<()>;
+
+    /// Adds directories to the cone-mode set without rewriting the existing entries.
+    fn add_sparse_checkout_dirs(&self, dirs: Vec<RepoPath>, repo_path: &Path) -> git::Result<()>;
+
+    /// Replaces the sparse-checkout patterns in non-cone mode and updates the working tree.
+    fn set_sparse_checkout_patterns(&self, patterns: Vec<Vec<u8>>, repo_path: &Path) -> git::Result<()>;
+
+    /// Turns on sparse-checkout in cone mode, optionally with a sparse index.
+    fn init_sparse_checkout(&self, sparse_index: bool, repo_path: &Path) -> git::Result<()>;
+
+    /// Turns off sparse-checkout, checking out every file again.
+    fn disable_sparse_checkout(&self, repo_path: &Path) -> git::Result<()>;
+}
+
+/// Backend that forks the `git` executable for every query.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CliBackend;
+
+impl GitBackend for CliBackend {
+    fn get_dirs_at_path(&self, path: &RepoPath, rev: &str, repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        git::get_dirs_at_path(path, rev, repo_path)
+    }
+
+    fn get_tree_id(&self, rev: &str, repo_path: &Path) -> git::Result<Option<String>> {
+        git::get_tree_id(rev, repo_path)
+    }
+
+    fn get_all_files(&self, tree_id: &str, repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        git::get_all_files_recursive(tree_id, repo_path)
+    }
+
+    fn get_file_contents(&self, rev: &str, path: &RepoPath, repo_path: &Path) -> git::Result<Option<Vec<u8>>> {
+        git::get_file_contents(rev, path, repo_path)
+    }
+
+    fn get_file_sizes(&self, tree_id: &str, repo_path: &Path) -> git::Result<Vec<(RepoPath, u64)>> {
+        git::get_file_sizes(tree_id, repo_path)
+    }
+
+    fn list_revisions(&self, repo_path: &Path) -> git::Result<Vec<String>> {
+        git::list_revisions(repo_path)
+    }
+
+    fn get_all_dirs(&self, tree_id: &str, repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        git::get_all_directories_recursive(tree_id, repo_path)
+    }
+
+    fn get_sparse_checkout_list(&self, repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        git::get_sparse_checkout_list(repo_path)
+    }
+
+    fn get_sparse_mode(&self, repo_path: &Path) -> git::Result<SparseMode> {
+        git::get_sparse_mode(repo_path)
+    }
+
+    fn get_sparse_checkout_patterns(&self, repo_path: &Path) -> git::Result<Vec<Vec<u8>>> {
+        git::get_sparse_checkout_patterns(repo_path)
+    }
+
+    fn get_uncommitted_paths(&self, repo_path: &Path) -> git::Result<HashMap<RepoPath, FileStatus>> {
+        git::get_uncommitted_paths(repo_path)
+    }
+
+    fn get_untracked_paths(&self, dir: &RepoPath, repo_path: &Path) -> git::Result<HashMap<RepoPath, FileStatus>> {
+        git::get_untracked_paths(dir, repo_path)
+    }
+
+    fn set_sparse_checkout_dirs(&self, dirs: Vec<RepoPath>, repo_path: &Path) -> git::Result<()> {
+        git::set_sparse_checkout_dirs(dirs, repo_path)
+    }
+
+    fn add_sparse_checkout_dirs(&self, dirs: Vec<RepoPath>, repo_path: &Path) -> git::Result<()> {
+        git::add_sparse_checkout_dirs(dirs, repo_path)
+    }
+
+    fn set_sparse_checkout_patterns(&self, patterns: Vec<Vec<u8>>, repo_path: &Path) -> git::Result<()> {
+        git::set_sparse_checkout_patterns(patterns, repo_path)
+    }
+
+    fn init_sparse_checkout(&self, sparse_index: bool, repo_path: &Path) -> git::Result<()> {
+        git::init_sparse_checkout(sparse_index, repo_path)
+    }
+
+    fn disable_sparse_checkout(&self, repo_path: &Path) -> git::Result<()> {
+        git::disable_sparse_checkout(repo_path)
+    }
+}
+
+/// Selects the `GitBackend` implementation from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum BackendKind {
+    /// Run the `git` executable for every query.
+    #[default]
+    Cli,
+    /// Read the object database, index and sparse-checkout file in-process.
+    Native,
+}
+
+impl BackendKind {
+    pub fn create(self) -> Arc<dyn GitBackend> {
+        match self {
+            BackendKind::Cli => Arc::new(CliBackend),
+            BackendKind::Native => Arc::new(NativeBackend::default()),
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! Non-interactive subcommands, for scripts and CI.
+//!
+//! They drive the same `App` state as the TUI, so adding and removing directories follows
+//! the same rules: directories with staged, modified or conflicted files are locked, and
+//! directories that are checked out through a parent cannot be added or removed on their own.
+
+use crate::app::{change_kinds_under, App, ChangeType, TreeItem};
+use crate::git::{ChangeKind, SparseMode};
+use crate::profiles::{Profiles, PROFILES_PATH};
+use crate::repo_path::RepoPath;
+use clap::Subcommand;
+use serde::Serialize;
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::Write;
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Print the sparse-checkout directories, or the patterns in non-cone mode.
+    List,
+    /// Add directories to the sparse-checkout set.
+    Add {
+        /// Directories relative to the repository root.
+        #[arg(required = true, value_name = "DIR")]
+        dirs: Vec<OsString>,
+    },
+    /// Remove directories from the sparse-checkout set.
+    Remove {
+        /// Directories relative to the repository root.
+        #[arg(required = true, value_name = "DIR")]
+        dirs: Vec<OsString>,
+    },
+    /// Print the directories of the browsed revision and whether they are checked out.
+    Tree {
+        /// How many levels below the root to print; all of them by default.
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+    },
+    /// Print the sparse-checkout mode, the checked-out directories and uncommitted paths.
+    Status,
+    /// Manage the named directory sets in `.pickit/profiles.toml`.
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum ProfileCommand {
+    /// Print the names of the profiles.
+    List,
+    /// Save the checked-out directories as a profile, replacing one of the same name.
+    Save { name: String },
+    /// Print the directories a profile resolves to, and which profile each comes from.
+    Show { name: String },
+    /// Check out exactly the directories of a profile.
+    Load { name: String },
+    /// Print the directories that only one of two profiles checks out.
+    Diff { a: String, b: String },
+}
+
+/// Runs `command` and writes its output, as plain text or JSON.
+///
+/// Returns `Ok(false)` if some of the requested directories were skipped; the others
+/// are still applied.
+pub fn run(command: &Command, app: &mut App, json: bool, out: &mut dyn Write) -> Result<bool, Box<dyn Error>> {
+    match command {
+        Command::List => list(app, json, out)?,
+        Command::Add { dirs } => return change_dirs(app, dirs, ChangeType::Add, json, out),
+        Command::Remove { dirs } => return change_dirs(app, dirs, ChangeType::Remove, json, out),
+        Command::Tree { depth } => tree(app, *depth, json, out)?,
+        Command::Status => {
+            app.load_untracked(RepoPath::root());
+            app.wait_until(|app| !app.is_loading_untracked);
+            status(app, json, out)?
+        }
+        Command::Profile { action } => return profile(app, action, json, out),
+    }
+    Ok(true)
+}
+
+fn mode_name(mode: SparseMode) -> &'static str {
+    match mode {
+        SparseMode::Disabled => "disabled",
+        SparseMode::Cone => "cone",
+        SparseMode::Pattern => "pattern",
+    }
+}
+
+fn sorted_dirs(app: &App) -> Vec<String> {
+    let mut dirs: Vec<&RepoPath> = app.sparse_checkout_dirs.iter().collect();
+    dirs.sort();
+    dirs.into_iter().map(|dir| dir.display().into_owned()).collect()
+}
+
+/// The `--json` output of `list`; one of `dirs` and `patterns` is set, by mode.
+#[derive(Serialize)]
+struct ListOutput {
+    mode: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dirs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    patterns: Option<Vec<String>>,
+}
+
+/// The `--json` output of `add`, `remove` and `profile load`.
+#[derive(Serialize)]
+struct ChangeOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    removed: Option<Vec<String>>,
+    skipped: Vec<Skipped>,
+}
+
+#[derive(Serialize)]
+struct Skipped {
+    path: String,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct ProfilesOutput {
+    profiles: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SavedOutput<'a> {
+    saved: &'a str,
+    dirs: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ProfileOutput<'a> {
+    name: &'a str,
+    dirs: Vec<ProfileDir<'a>>,
+}
+
+#[derive(Serialize)]
+struct ProfileDir<'a> {
+    path: String,
+    via: &'a [String], // The chain of profiles that includes it
+}
+
+#[derive(Serialize)]
+struct DiffOutput {
+    only_in_a: Vec<String>,
+    only_in_b: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct TreeNode {
+    name: String,
+    path: String,
+    state: &'static str,
+    locked: bool,
+    children: Vec<TreeNode>,
+}
+
+#[derive(Serialize)]
+struct StatusOutput<'a> {
+    mode: &'static str,
+    revision: &'a str,
+    dirs: Vec<StatusDir>,
+    patterns: Vec<String>,
+    uncommitted: Vec<StatusPath>,
+}
+
+#[derive(Serialize)]
+struct StatusDir {
+    path: String,
+    locked: bool,
+}
+
+#[derive(Serialize)]
+struct StatusPath {
+    path: String,
+    status: String, // The two-letter code of `git status --porcelain`, like `.M`
+}
+
+/// Writes `value` as one line of JSON.
+fn write_json(out: &mut dyn Write, value: &impl Serialize) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *out, value)?;
+    writeln!(out)
+}
+
+fn skipped_output(skipped: Vec<(String, String)>) -> Vec<Skipped> {
+    skipped.into_iter().map(|(path, reason)| Skipped { path, reason }).collect()
+}
+
+fn patterns(app: &App) -> Vec<String> {
+    app.sparse_patterns.iter().map(|line| String::from_utf8_lossy(line).into_owned()).collect()
+}
+
+fn list(app: &App, json: bool, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut output = ListOutput { mode: mode_name(app.sparse_mode), dirs: None, patterns: None };
+    let entries = match app.sparse_mode {
+        SparseMode::Pattern => output.patterns.insert(patterns(app)),
+        _ => output.dirs.insert(sorted_dirs(app)),
+    };
+    if json {
+        write_json(out, &output)
+    } else {
+        entries.iter().try_for_each(|entry| writeln!(out, "{entry}"))
+    }
+}
+
+/// Parses a directory argument, accepting `./` prefixes and trailing slashes.
+fn dir_argument(dir: &OsString) -> RepoPath {
+    let path = RepoPath::from(dir.as_os_str());
+    let mut bytes = path.as_bytes();
+    while let Some(rest) = bytes.strip_prefix(b"./") {
+        bytes = rest;
+    }
+    while let Some(rest) = bytes.strip_suffix(b"/") {
+        bytes = rest;
+    }
+    if bytes.is_empty() {
+        RepoPath::root()
+    } else {
+        RepoPath::from(bytes)
+    }
+}
+
+fn change_dirs(
+    app: &mut App,
+    dirs: &[OsString],
+    change: ChangeType,
+    json: bool,
+    out: &mut dyn Write,
+) -> Result<bool, Box<dyn Error>> {
+    app.wait_until(|app| !app.is_indexing);
+    let mut changed = Vec::new();
+    let mut skipped = Vec::new();
+    for dir in dirs {
+        let path = dir_argument(dir);
+        let idx = match &app.dir_index {
+            Some(index) if !index.contains(&path) => None,
+            _ => app.reveal_path(&path),
+        };
+        let result = match idx {
+            Some(idx) => app.set_pending_change(idx, change),
+            None => Err(format!("no such directory at {}", app.revision)),
+        };
+        match result {
+            Ok(()) => changed.push(path.display().into_owned()),
+            Err(reason) => skipped.push((path.display().into_owned(), reason)),
+        }
+    }
+
+    if !changed.is_empty() {
+        apply(app)?;
+    }
+
+    let completed = skipped.is_empty();
+    if json {
+        let (added, removed) = match change {
+            ChangeType::Add => (Some(changed), None),
+            ChangeType::Remove => (None, Some(changed)),
+        };
+        write_json(out, &ChangeOutput { added, removed, skipped: skipped_output(skipped) })?;
+    } else {
+        let verb = match change {
+            ChangeType::Add => "added",
+            ChangeType::Remove => "removed",
+        };
+        for path in &changed {
+            writeln!(out, "{verb} {path}")?;
+        }
+        for (path, reason) in &skipped {
+            writeln!(out, "skipped {path}: {reason}")?;
+        }
+    }
+    Ok(completed)
+}
+
+/// Applies the pending changes and waits for the state to be read back.
+fn apply(app: &mut App) -> Result<(), Box<dyn Error>> {
+    app.apply_changes();
+    app.wait_until(|app| !app.is_applying_changes);
+    if let Some(error) = app.take_message() {
+        return Err(error.into());
+    }
+    app.wait_until(|app| !app.is_refreshing); // Leave the state as git now reports it
+    Ok(())
+}
+
+fn profile(app: &mut App, action: &ProfileCommand, json: bool, out: &mut dyn Write) -> Result<bool, Box<dyn Error>> {
+    app.wait_until(|app| !app.is_indexing); // Profiles resolve against the directory index
+    match action {
+        ProfileCommand::List => {
+            let names = Profiles::load(app.backend.as_ref(), &app.current_repo_root)?.names();
+            if json {
+                write_json(out, &ProfilesOutput { profiles: names })?;
+            } else {
+                names.iter().try_for_each(|name| writeln!(out, "{name}"))?;
+            }
+        }
+        ProfileCommand::Save { name } => {
+            app.save_profile(name)?;
+            if json {
+                write_json(out, &SavedOutput { saved: name, dirs: sorted_dirs(app) })?;
+            } else {
+                writeln!(out, "saved {name} to {PROFILES_PATH}")?;
+            }
+        }
+        ProfileCommand::Show { name } => {
+            let resolved = app.resolve_profile(name)?;
+            if json {
+                let dirs = resolved
+                    .dirs
+                    .iter()
+                    .map(|(dir, via)| ProfileDir { path: dir.display().into_owned(), via })
+                    .collect();
+                write_json(out, &ProfileOutput { name, dirs })?;
+            } else {
+                for dir in resolved.dirs.keys() {
+                    writeln!(out, "{}  {}", dir.display(), resolved.provenance(dir).unwrap_or_default())?;
+                }
+            }
+        }
+        ProfileCommand::Load { name } => {
+            let skipped: Vec<(String, String)> = app
+                .load_profile(name)?
+                .into_iter()
+                .map(|(path, reason)| (path.display().into_owned(), reason))
+                .collect();
+            let mut added = Vec::new();
+            let mut removed = Vec::new();
+            for item in &app.items {
+                match item.pending_change {
+                    Some(ChangeType::Add) => added.push(item.path.display().into_owned()),
+                    Some(ChangeType::Remove) => removed.push(item.path.display().into_owned()),
+                    None => {}
+                }
+            }
+            added.sort();
+            removed.sort();
+            if !added.is_empty() || !removed.is_empty() {
+                apply(app)?;
+            }
+            let completed = skipped.is_empty();
+            if json {
+                let output = ChangeOutput { added: Some(added), removed: Some(removed), skipped: skipped_output(skipped) };
+                write_json(out, &output)?;
+            } else {
+                added.iter().try_for_each(|path| writeln!(out, "added {path}"))?;
+                removed.iter().try_for_each(|path| writeln!(out, "removed {path}"))?;
+                skipped.iter().try_for_each(|(path, reason)| writeln!(out, "skipped {path}: {reason}"))?;
+            }
+            return Ok(completed);
+        }
+        ProfileCommand::Diff { a, b } => {
+            let diff = app.diff_profiles(a, b)?;
+            let only_in_a: Vec<String> = diff.only_in_a.iter().map(|dir| dir.display().into_owned()).collect();
+            let only_in_b: Vec<String> = diff.only_in_b.iter().map(|dir| dir.display().into_owned()).collect();
+            if json {
+                write_json(out, &DiffOutput { only_in_a, only_in_b })?;
+            } else {
+                only_in_a.iter().try_for_each(|dir| writeln!(out, "- {dir}"))?;
+                only_in_b.iter().try_for_each(|dir| writeln!(out, "+ {dir}"))?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// The checkout state of a directory, as printed by `tree`.
+fn item_state(app: &App, item: &TreeItem) -> &'static str {
+    if app.sparse_mode == SparseMode::Pattern {
+        use crate::sparse_patterns::PatternMatch;
+        return match app.pattern_match_counts.get(&item.path).map(|count| count.pattern_match()) {
+            Some(PatternMatch::Full) => "checked-out",
+            Some(PatternMatch::Partial) => "partial",
+            _ => "not-checked-out",
+        };
+    }
+    if item.is_checked_out {
+        "checked-out"
+    } else if item.is_implicitly_checked_out {
+        "implicit"
+    } else if item.has_checked_out_descendant {
+        "partial"
+    } else {
+        "not-checked-out"
+    }
+}
+
+fn tree(app: &mut App, depth: Option<usize>, json: bool, out: &mut dyn Write) -> std::io::Result<()> {
+    app.wait_until(|app| !app.is_indexing);
+    // Load every level that is printed
+    let mut queue = vec![0];
+    while let Some(idx) = queue.pop() {
+        if depth.is_some_and(|depth| app.items[idx].indentation_level as usize >= depth) {
+            continue;
+        }
+        if !app.items[idx].children_loaded {
+            app.load_children_and_expand(idx);
+            app.wait_until(|app| !app.items[idx].is_loading);
+        }
+        queue.extend(app.items[idx].children_indices.iter().copied());
+    }
+
+    if json {
+        write_json(out, &tree_nodes(app, 0))
+    } else {
+        write_tree(app, 0, out)
+    }
+}
+
+fn write_tree(app: &App, idx: usize, out: &mut dyn Write) -> std::io::Result<()> {
+    for &child in &app.items[idx].children_indices {
+        let item = &app.items[child];
+        let indent = "  ".repeat(item.indentation_level.saturating_sub(1) as usize);
+        let locked = if item.is_locked { " locked" } else { "" };
+        writeln!(out, "{indent}{}  {}{locked}", item.name.display(), item_state(app, item))?;
+        write_tree(app, child, out)?;
+    }
+    Ok(())
+}
+
+/// The children of `idx`, each with its own children.
+fn tree_nodes(app: &App, idx: usize) -> Vec<TreeNode> {
+    app.items[idx]
+        .children_indices
+        .iter()
+        .map(|&child| {
+            let item = &app.items[child];
+            TreeNode {
+                name: item.name.display().into_owned(),
+                path: item.path.display().into_owned(),
+                state: item_state(app, item),
+                locked: item.is_locked,
+                children: tree_nodes(app, child),
+            }
+        })
+        .collect()
+}
+
+fn status(app: &App, json: bool, out: &mut dyn Write) -> std::io::Result<()> {
+    let uncommitted: Vec<StatusPath> = app
+        .uncommitted_under(&RepoPath::root())
+        .into_iter()
+        .map(|(path, status)| StatusPath { path: path.display().into_owned(), status: status.as_str().to_string() })
+        .collect();
+    let mut dirs: Vec<&RepoPath> = app.sparse_checkout_dirs.iter().collect();
+    dirs.sort();
+    let blocking_kinds = |dir: &RepoPath| -> Vec<ChangeKind> {
+        let kinds = change_kinds_under(&app.uncommitted_paths, dir);
+        kinds.into_iter().filter(|kind| kind.blocks_removal()).collect()
+    };
+    let is_locked = |dir: &RepoPath| !blocking_kinds(dir).is_empty();
+
+    if json {
+        let dirs = dirs.iter().map(|dir| StatusDir { path: dir.display().into_owned(), locked: is_locked(dir) }).collect();
+        return write_json(
+            out,
+            &StatusOutput {
+                mode: mode_name(app.sparse_mode),
+                revision: &app.revision,
+                dirs,
+                patterns: patterns(app),
+                uncommitted,
+            },
+        );
+    }
+
+    writeln!(out, "mode: {}", mode_name(app.sparse_mode))?;
+    writeln!(out, "revision: {}", app.revision)?;
+    match app.sparse_mode {
+        SparseMode::Disabled => {}
+        SparseMode::Cone => {
+            writeln!(out, "checked out directories: {}", dirs.len())?;
+            for dir in dirs {
+                let kinds: Vec<&str> = blocking_kinds(dir).into_iter().map(ChangeKind::label).collect();
+                let locked = if kinds.is_empty() { String::new() } else { format!(" (locked: {})", kinds.join(", ")) };
+                writeln!(out, "  {}{locked}", dir.display())?;
+            }
+        }
+        SparseMode::Pattern => {
+            writeln!(out, "patterns: {}", app.sparse_patterns.len())?;
+            for pattern in patterns(app) {
+                writeln!(out, "  {pattern}")?;
+            }
+        }
+    }
+    writeln!(out, "uncommitted paths: {}", uncommitted.len())?;
+    for StatusPath { path, status } in uncommitted {
+        writeln!(out, "  {status} {path}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::CliBackend;
+    use crate::git::tests::{commit_files, git, setup_git_repo};
+    use std::fs;
+    use std::sync::Arc;
+
+    fn run_to_string(app: &mut App, command: Command, json: bool) -> (bool, String) {
+        let mut out = Vec::new();
+        let completed = run(&command, app, json, &mut out).unwrap();
+        (completed, String::from_utf8(out).unwrap())
+    }
+
+    const FILES: [(&str, &str); 4] =
+        [("a/b/file.txt", "content"), ("a/c/file.txt", "content"), ("d/file.txt", "content"), ("top.txt", "content")];
+
+    #[test]
+    fn test_add_remove_and_report() {
+        let (repo, _dir) = setup_git_repo();
+        commit_files(&repo, &FILES);
+        git(&repo, &["sparse-checkout", "init", "--cone"]);
+        let mut app = App::new(Some(&repo), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+
+        let dirs = ["./a/b/", "d", "missing", "."].map(OsString::from).to_vec();
+        let (completed, output) = run_to_string(&mut app, Command::Add { dirs }, false);
+        assert!(!completed);
+        assert_eq!(
+            output,
+            "added a/b\nadded d\nskipped missing: no such directory at HEAD\nskipped .: the root is always checked out\n"
+        );
+        assert!(repo.join("a/b/file.txt").exists());
+
+        let (_, output) = run_to_string(&mut app, Command::List, true);
+        assert_eq!(output, "{\"mode\":\"cone\",\"dirs\":[\"a/b\",\"d\"]}\n");
+
+        let (_, output) = run_to_string(&mut app, Command::Tree { depth: None }, false);
+        assert_eq!(output, "a  partial\n  b  checked-out\n  c  not-checked-out\nd  checked-out\n");
+        let (_, output) = run_to_string(&mut app, Command::Tree { depth: Some(1) }, true);
+        assert!(output.starts_with("[{\"name\":\"a\",\"path\":\"a\",\"state\":\"partial\",\"locked\":false,\"children\":["));
+
+        // Uncommitted changes lock a directory against removal
+        fs::write(repo.join("d/file.txt"), "changed").unwrap();
+        app.refresh();
+        app.wait_until(|app| !app.is_refreshing);
+        let dirs = ["d", "a/b"].map(OsString::from).to_vec();
+        let (completed, output) = run_to_string(&mut app, Command::Remove { dirs }, true);
+        assert!(!completed);
+        assert_eq!(output, "{\"removed\":[\"a/b\"],\"skipped\":[{\"path\":\"d\",\"reason\":\"contains modified files\"}]}\n");
+        assert!(!repo.join("a/b/file.txt").exists());
+
+        let (_, output) = run_to_string(&mut app, Command::Status, false);
+        assert_eq!(
+            output,
+            "mode: cone\nrevision: HEAD\nchecked out directories: 1\n  d (locked: modified)\nuncommitted paths: 1\n  .M d/file.txt\n"
+        );
+        let (_, output) = run_to_string(&mut app, Command::Status, true);
+        assert_eq!(
+            output,
+            "{\"mode\":\"cone\",\"revision\":\"HEAD\",\"dirs\":[{\"path\":\"d\",\"locked\":true}],\"patterns\":[],\"uncommitted\":[{\"path\":\"d/file.txt\",\"status\":\".M\"}]}\n"
+        );
+    }
+
+    #[test]
+    fn test_profiles_save_load_and_diff() {
+        let (repo, _dir) = setup_git_repo();
+        commit_files(&repo, &FILES);
+        git(&repo, &["sparse-checkout", "set", "--cone", "a/b", "d"]);
+        let mut app = App::new(Some(&repo), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+
+        let save = ProfileCommand::Save { name: "frontend".to_string() };
+        let (_, output) = run_to_string(&mut app, Command::Profile { action: save }, false);
+        assert_eq!(output, "saved frontend to .pickit/profiles.toml\n");
+        let mut text = fs::read_to_string(repo.join(PROFILES_PATH)).unwrap();
+        text.push_str("\n[profiles.shared]\ndirs = [\"a\"]\n\n[profiles.backend]\ninclude = [\"shared\"]\n");
+        text.push_str("\n[profiles.broken]\ninclude = [\"backend\"]\ndirs = [\"missing\"]\n");
+        fs::write(repo.join(PROFILES_PATH), text).unwrap();
+
+        let (_, output) = run_to_string(&mut app, Command::Profile { action: ProfileCommand::List }, true);
+        assert_eq!(output, "{\"profiles\":[\"backend\",\"broken\",\"frontend\",\"shared\"]}\n");
+        let diff = ProfileCommand::Diff { a: "frontend".to_string(), b: "backend".to_string() };
+        let (_, output) = run_to_string(&mut app, Command::Profile { action: diff }, false);
+        assert_eq!(output, "- a/b\n- d\n+ a\n");
+        let show = ProfileCommand::Show { name: "backend".to_string() };
+        let (_, output) = run_to_string(&mut app, Command::Profile { action: show }, false);
+        assert_eq!(output, "a  via profile backend → shared\n");
+
+        let load = ProfileCommand::Load { name: "broken".to_string() };
+        let error = run(&Command::Profile { action: load }, &mut app, false, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "Cannot resolve profile broken: missing (via profile broken) does not exist at HEAD");
+
+        let load = ProfileCommand::Load { name: "backend".to_string() };
+        let (completed, output) = run_to_string(&mut app, Command::Profile { action: load }, false);
+        assert!(completed);
+        assert_eq!(output, "added a\nremoved a/b\nremoved d\n");
+        assert_eq!(sorted_dirs(&app), ["a"]);
+        assert!(repo.join("a/c/file.txt").exists());
+
+        // a/b is only implied by a, which the profile drops
+        let load = ProfileCommand::Load { name: "frontend".to_string() };
+        let (completed, output) = run_to_string(&mut app, Command::Profile { action: load }, true);
+        assert!(completed);
+        assert_eq!(output, "{\"added\":[\"a/b\",\"d\"],\"removed\":[\"a\"],\"skipped\":[]}\n");
+        assert_eq!(sorted_dirs(&app), ["a/b", "d"]);
+        assert!(!repo.join("a/c/file.txt").exists());
+
+        let load = ProfileCommand::Load { name: "infra".to_string() };
+        let error = run(&Command::Profile { action: load }, &mut app, false, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown profile: infra");
+    }
+}
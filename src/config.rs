@@ -0,0 +1,255 @@
+//! User settings for the TUI: key bindings, the colors of the tree and its glyphs.
+//!
+//! They are read from `~/.config/pickit/config.toml` (or `$XDG_CONFIG_HOME/pickit/`) and
+//! then from `.pickit/config.toml` in the repository, whose settings win:
+//!
+//! ```toml
+//! glyphs = "ascii"
+//!
+//! [keys]
+//! toggle = ["space", "x"]
+//!
+//! [theme]
+//! checked_out = "light green"
+//! selected = "#303060"
+//! ```
+//!
+//! Colors are ratatui color names, 256-color indices or `#rrggbb`.
+
+use crate::keymap::KeyMap;
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Where the per-repository settings are stored, relative to the repository root.
+pub const REPO_CONFIG_PATH: &str = ".pickit/config.toml";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Cannot read {}: {source}", path.display())]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("Invalid {}: {message}", path.display())]
+    Invalid { path: PathBuf, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The colors of the tree items, by state. Backgrounds mark the cursor and the visual range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub checked_out: Color,
+    pub partial: Color, // Checked out through a parent, or with checked-out descendants
+    pub not_checked_out: Color,
+    pub pending: Color,
+    pub locked: Color,
+    pub selected: Color,
+    pub visual_range: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            checked_out: Color::Green,
+            partial: Color::White,
+            not_checked_out: Color::DarkGray,
+            pending: Color::Yellow,
+            locked: Color::Red,
+            selected: Color::Blue,
+            visual_range: Color::Magenta,
+        }
+    }
+}
+
+impl Theme {
+    fn set(&mut self, name: &str, color: &str) -> std::result::Result<(), String> {
+        let slot = match name {
+            "checked_out" => &mut self.checked_out,
+            "partial" => &mut self.partial,
+            "not_checked_out" => &mut self.not_checked_out,
+            "pending" => &mut self.pending,
+            "locked" => &mut self.locked,
+            "selected" => &mut self.selected,
+            "visual_range" => &mut self.visual_range,
+            _ => return Err(format!("Unknown theme color: {name}")),
+        };
+        *slot = color.parse().map_err(|_| format!("Unknown color for {name}: {color}"))?;
+        Ok(())
+    }
+}
+
+/// The symbols drawn before each tree item's name, each with its trailing space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyphs {
+    pub expanded: &'static str,
+    pub collapsed: &'static str,
+    pub loading: &'static str,
+    pub leaf: &'static str, // Where a directory without subdirectories has no expansion glyph
+    pub checked_out: &'static str,
+    pub not_checked_out: &'static str,
+    pub has_checked_out_descendant: &'static str,
+    pub partial_match: &'static str, // Some of the directory's files match the patterns
+    pub locked: &'static str,
+    pub add: &'static str,
+    pub remove: &'static str,
+}
+
+impl Glyphs {
+    pub const UNICODE: Glyphs = Glyphs {
+        expanded: "▾ ",
+        collapsed: "▸ ",
+        loading: "◌ ",
+        leaf: "  ",
+        checked_out: "✔ ",
+        not_checked_out: "☐ ",
+        has_checked_out_descendant: "☐·",
+        partial_match: "◐ ",
+        locked: "🔒 ",
+        add: "+ ",
+        remove: "- ",
+    };
+
+    /// For terminals that draw the emoji and symbols above at the wrong width.
+    pub const ASCII: Glyphs = Glyphs {
+        expanded: "v ",
+        collapsed: "> ",
+        loading: "~ ",
+        leaf: "  ",
+        checked_out: "[x] ",
+        not_checked_out: "[ ] ",
+        has_checked_out_descendant: "[.] ",
+        partial_match: "[~] ",
+        locked: "[!] ",
+        add: "[+] ",
+        remove: "[-] ",
+    };
+}
+
+impl Default for Glyphs {
+    fn default() -> Self {
+        Glyphs::UNICODE
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum GlyphSet {
+    Unicode,
+    Ascii,
+}
+
+/// One key or several.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// One config file, where everything is optional.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    glyphs: Option<GlyphSet>,
+    #[serde(default)]
+    keys: BTreeMap<String, Keys>,
+    #[serde(default)]
+    theme: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub keys: KeyMap,
+    pub theme: Theme,
+    pub glyphs: Glyphs,
+}
+
+impl Config {
+    /// The defaults, overridden by the user's config file and then by the repository's.
+    pub fn load(repo_path: &Path) -> Result<Self> {
+        let files: Vec<PathBuf> = user_config_path().into_iter().chain([repo_path.join(REPO_CONFIG_PATH)]).collect();
+        Self::load_files(&files)
+    }
+
+    /// The defaults, overridden by each of `files` that exists in turn.
+    fn load_files(files: &[PathBuf]) -> Result<Self> {
+        let mut config = Config::default();
+        for path in files {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(source) => return Err(Error::Io { path: path.clone(), source }),
+            };
+            config.merge(&text).map_err(|message| Error::Invalid { path: path.clone(), message })?;
+        }
+        Ok(config)
+    }
+
+    /// Applies the settings of one config file on top of the current ones.
+    fn merge(&mut self, text: &str) -> std::result::Result<(), String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        let keys = file
+            .keys
+            .into_iter()
+            .map(|(action, keys)| match keys {
+                Keys::One(key) => (action, vec![key]),
+                Keys::Many(keys) => (action, keys),
+            })
+            .collect();
+        self.keys.rebind(&keys)?;
+        for (name, color) in &file.theme {
+            self.theme.set(name, color)?;
+        }
+        match file.glyphs {
+            Some(GlyphSet::Unicode) => self.glyphs = Glyphs::UNICODE,
+            Some(GlyphSet::Ascii) => self.glyphs = Glyphs::ASCII,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// `~/.config/pickit/config.toml`, or below `$XDG_CONFIG_HOME` when that is set.
+fn user_config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("pickit").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::Action;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_repo_config_overrides_user_config() {
+        let dir = tempdir().unwrap();
+        let user = dir.path().join("user.toml");
+        let repo = dir.path().join("repo.toml");
+        fs::write(&user, "glyphs = \"ascii\"\n[keys]\ntoggle = \"x\"\n[theme]\npending = \"light-yellow\"\nlocked = \"#ff8000\"\n").unwrap();
+        fs::write(&repo, "[keys]\ntoggle = [\"t\", \"space\"]\n[theme]\nlocked = \"9\"\n").unwrap();
+
+        let config = Config::load_files(&[user, dir.path().join("missing.toml"), repo.clone()]).unwrap();
+        assert_eq!(config.glyphs, Glyphs::ASCII);
+        assert_eq!(config.keys.keys(Action::Toggle).iter().map(ToString::to_string).collect::<Vec<_>>(), ["t", "Space"]);
+        assert_eq!(config.theme.pending, Color::LightYellow);
+        assert_eq!(config.theme.locked, Color::Indexed(9));
+        assert_eq!(config.theme.checked_out, Color::Green);
+
+        for (text, message) in [
+            ("[theme]\nlocked = \"reddish\"", "Unknown color for locked: reddish"),
+            ("[theme]\nlocke = \"red\"", "Unknown theme color: locke"),
+            ("glyphs = \"emoji\"", "unknown variant `emoji`, expected `unicode` or `ascii`"),
+            ("[keys]\nquit = \"hyper-q\"", "Unknown key: hyper-q"),
+        ] {
+            fs::write(&repo, text).unwrap();
+            let error = Config::load_files(std::slice::from_ref(&repo)).unwrap_err().to_string();
+            assert_eq!(error, format!("Invalid {}: {message}", repo.display()));
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::backend::GitBackend;
+use crate::git;
+use crate::repo_path::RepoPath;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Every directory of one tree, stored as parent→children adjacency.
+///
+/// Listing the directories of a commit means walking the whole tree, so the index is built
+/// once per root tree id and queried for each expand instead of asking git every time.
+#[derive(Debug, Clone, Default)]
+pub struct DirIndex {
+    /// The root tree the index was built from; `None` for an unborn branch.
+    pub tree_id: Option<String>,
+    /// Names of the direct subdirectories of each directory, sorted. The root is `"."`.
+    children: HashMap<RepoPath, Vec<RepoPath>>,
+    /// Every file of the tree, if it was listed (non-cone patterns are matched against files).
+    pub files: Option<Vec<RepoPath>>,
+}
+
+impl DirIndex {
+    /// Builds the index from a flat list of directory paths relative to the root.
+    pub fn from_paths(tree_id: Option<String>, paths: Vec<RepoPath>) -> Self {
+        let mut children: HashMap<RepoPath, Vec<RepoPath>> = HashMap::new();
+        for path in paths {
+            children.entry(path.parent()).or_default().push(path.file_name());
+        }
+        for names in children.values_mut() {
+            names.sort();
+            names.dedup();
+        }
+        DirIndex { tree_id, children, files: None }
+    }
+
+    /// Indexes the tree `tree_id`, which was resolved beforehand, also listing its files
+    /// if `with_files` is set.
+    pub fn build_for_tree(
+        backend: &dyn GitBackend,
+        tree_id: Option<String>,
+        with_files: bool,
+        repo_path: &Path,
+    ) -> git::Result<Self> {
+        let (paths, files) = match &tree_id {
+            Some(tree) if with_files => (backend.get_all_dirs(tree, repo_path)?, backend.get_all_files(tree, repo_path)?),
+            Some(tree) => (backend.get_all_dirs(tree, repo_path)?, Vec::new()),
+            None => (Vec::new(), Vec::new()),
+        };
+        let mut index = Self::from_paths(tree_id, paths);
+        index.files = with_files.then_some(files);
+        Ok(index)
+    }
+
+    /// Names of the direct subdirectories of `path`, sorted; empty for unknown paths.
+    pub fn children(&self, path: &RepoPath) -> &[RepoPath] {
+        let key = if path.is_empty() { RepoPath::root() } else { path.clone() };
+        self.children.get(&key).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Every directory of the tree except the root, in no particular order.
+    pub fn all_dirs(&self) -> Vec<RepoPath> {
+        self.children
+            .iter()
+            .flat_map(|(parent, names)| names.iter().map(move |name| parent.join(name)))
+            .collect()
+    }
+
+    /// Whether the indexed tree has a directory at `path`. The root always exists.
+    pub fn contains(&self, path: &RepoPath) -> bool {
+        path.is_root() || path.is_empty() || self.children(&path.parent()).binary_search(&path.file_name()).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_children_from_flat_paths() {
+        let paths = ["dir2", "dir1", "dir1/sub b", "dir1/sub a", "dir1/sub a/deep", "日本語"];
+        let index = DirIndex::from_paths(Some("tree".to_string()), paths.into_iter().map(RepoPath::from).collect());
+
+        assert_eq!(index.children(&RepoPath::root()), ["dir1", "dir2", "日本語"]);
+        assert_eq!(index.children(&"dir1".into()), ["sub a", "sub b"]);
+        assert_eq!(index.children(&"dir1/sub a".into()), ["deep"]);
+        assert!(index.children(&"dir2".into()).is_empty());
+        assert!(index.children(&"missing".into()).is_empty());
+        assert!(index.contains(&"dir1/sub a/deep".into()));
+        assert!(index.contains(&RepoPath::root()));
+        assert!(!index.contains(&"dir1/sub".into()));
+        let mut all = index.all_dirs();
+        all.sort();
+        assert_eq!(all, ["dir1", "dir1/sub a", "dir1/sub a/deep", "dir1/sub b", "dir2", "日本語"]);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::backend::GitBackend;
+use crate::git;
+use crate::repo_path::RepoPath;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Recursive file count and total blob size of a directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirStats {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// The blobs of one tree with their sizes, and the totals of every directory.
+///
+/// Listing sizes means `git ls-tree -r -l` over the whole tree, so the result is kept per
+/// root tree id and reused until the browsed revision or `HEAD` moves to another tree.
+#[derive(Debug, Clone, Default)]
+pub struct TreeSizes {
+    pub tree_id: String,
+    pub files: Vec<(RepoPath, u64)>,
+    pub totals: HashMap<RepoPath, DirStats>,
+}
+
+impl TreeSizes {
+    pub fn load(backend: &dyn GitBackend, tree_id: &str, repo_path: &Path) -> git::Result<Self> {
+        let files = backend.get_file_sizes(tree_id, repo_path)?;
+        let totals = sum_by_dir(&files);
+        Ok(TreeSizes { tree_id: tree_id.to_string(), files, totals })
+    }
+}
+
+/// Adds each file to the totals of every directory above it, including the root.
+pub fn sum_by_dir<'a>(files: impl IntoIterator<Item = &'a (RepoPath, u64)>) -> HashMap<RepoPath, DirStats> {
+    let mut totals: HashMap<RepoPath, DirStats> = HashMap::new();
+    for (file, size) in files {
+        let mut dir = file.parent();
+        loop {
+            let stats = totals.entry(dir.clone()).or_default();
+            stats.files += 1;
+            stats.bytes += size;
+            if dir.is_root() {
+                break;
+            }
+            dir = dir.parent();
+        }
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sum_by_dir() {
+        let files: Vec<(RepoPath, u64)> = [("top.txt", 1), ("a/x", 10), ("a/b/y", 100), ("a/b/z", 1000)]
+            .into_iter()
+            .map(|(path, size)| (RepoPath::from(path), size))
+            .collect();
+        let totals = sum_by_dir(&files);
+
+        assert_eq!(totals[&RepoPath::root()], DirStats { files: 4, bytes: 1111 });
+        assert_eq!(totals[&RepoPath::from("a")], DirStats { files: 3, bytes: 1110 });
+        assert_eq!(totals[&RepoPath::from("a/b")], DirStats { files: 2, bytes: 1100 });
+        assert!(!totals.contains_key(&RepoPath::from("top.txt")));
+    }
+}
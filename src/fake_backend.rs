@@ -0,0 +1,327 @@
+//! An in-memory `GitBackend` for fast, deterministic `App` tests.
+//!
+//! It serves a scripted commit as `HEAD`, with a sparse-checkout set and uncommitted
+//! paths that tests change as they go. Every call is logged, can be slowed down, made to
+//! fail, or held at a `Gate` until the test lets it return.
+
+use crate::backend::GitBackend;
+use crate::git::{self, FileStatus, SparseMode};
+use crate::repo_path::RepoPath;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The id `get_tree_id` returns for `HEAD`, until `commit` moves it.
+pub const HEAD_TREE_ID: &str = "fake-head-tree";
+
+#[derive(Debug, Default)]
+pub struct FakeBackend {
+    state: Mutex<FakeState>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    files: BTreeMap<RepoPath, Vec<u8>>, // The files of HEAD with their contents
+    commits: usize,                     // Number of times HEAD has moved
+    sparse_mode: SparseMode,
+    sparse_dirs: Vec<RepoPath>,
+    sparse_patterns: Vec<Vec<u8>>,
+    uncommitted: HashMap<RepoPath, FileStatus>,
+    latency: Duration,                               // Added to every call
+    failures: HashMap<&'static str, String>,         // Error message per method name
+    gates: HashMap<&'static str, VecDeque<Arc<Gate>>>, // Taken by the next calls, in order
+    commands: Vec<String>,                           // Method name and arguments of every call
+}
+
+/// Holds one call until the test opens it.
+#[derive(Debug, Default)]
+pub struct Gate {
+    state: Mutex<(bool, bool)>, // Whether the call has arrived, and whether it may return
+    changed: Condvar,
+}
+
+impl Gate {
+    /// Blocks until the held call has arrived.
+    pub fn wait_entered(&self) {
+        let _entered = self.changed.wait_while(self.state.lock().unwrap(), |(entered, _)| !*entered).unwrap();
+    }
+
+    /// Lets the held call return.
+    pub fn open(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.changed.notify_all();
+    }
+
+    fn enter_and_wait(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = true;
+        self.changed.notify_all();
+        let _open = self.changed.wait_while(state, |(_, open)| !*open).unwrap();
+    }
+}
+
+impl FakeBackend {
+    /// A repository whose `HEAD` has `files`, in cone mode with nothing checked out.
+    pub fn new(files: &[&str]) -> Self {
+        let state = FakeState {
+            files: files.iter().map(|&file| (RepoPath::from(file), file.as_bytes().to_vec())).collect(),
+            sparse_mode: SparseMode::Cone,
+            ..Default::default()
+        };
+        FakeBackend { state: Mutex::new(state) }
+    }
+
+    pub fn with_sparse_dirs(self, dirs: &[&str]) -> Self {
+        self.set_sparse_dirs(dirs);
+        self
+    }
+
+    pub fn with_uncommitted(self, paths: &[(&str, FileStatus)]) -> Self {
+        self.set_uncommitted(paths);
+        self
+    }
+
+    pub fn with_sparse_patterns(self, lines: &[&str]) -> Self {
+        self.set_sparse_patterns(lines);
+        self
+    }
+
+    pub fn set_sparse_dirs(&self, dirs: &[&str]) {
+        self.state.lock().unwrap().sparse_dirs = dirs.iter().map(|&dir| RepoPath::from(dir)).collect();
+    }
+
+    /// Switches to pattern mode with `lines` as the sparse-checkout file.
+    pub fn set_sparse_patterns(&self, lines: &[&str]) {
+        let mut state = self.state.lock().unwrap();
+        state.sparse_mode = SparseMode::Pattern;
+        state.sparse_patterns = lines.iter().map(|line| line.as_bytes().to_vec()).collect();
+    }
+
+    pub fn set_uncommitted(&self, paths: &[(&str, FileStatus)]) {
+        self.state.lock().unwrap().uncommitted = paths.iter().map(|&(path, status)| (RepoPath::from(path), status)).collect();
+    }
+
+    /// Moves HEAD to a new commit with `files`, which gets a tree id of its own.
+    pub fn commit(&self, files: &[&str]) {
+        let mut state = self.state.lock().unwrap();
+        state.files = files.iter().map(|&file| (RepoPath::from(file), file.as_bytes().to_vec())).collect();
+        state.commits += 1;
+    }
+
+    pub fn set_sparse_mode(&self, mode: SparseMode) {
+        self.state.lock().unwrap().sparse_mode = mode;
+    }
+
+    /// The sparse-checkout set as the commands left it, sorted.
+    pub fn sparse_dirs(&self) -> Vec<RepoPath> {
+        let mut dirs = self.state.lock().unwrap().sparse_dirs.clone();
+        dirs.sort();
+        dirs
+    }
+
+    /// Slows down every call by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Makes every call to `method` fail with `message`, until `succeed` is called.
+    pub fn fail(&self, method: &'static str, message: &str) {
+        self.state.lock().unwrap().failures.insert(method, message.to_string());
+    }
+
+    pub fn succeed(&self, method: &'static str) {
+        self.state.lock().unwrap().failures.remove(method);
+    }
+
+    /// Holds the next call to `method` that has no gate yet, after it has computed its result.
+    pub fn hold_next(&self, method: &'static str) -> Arc<Gate> {
+        let gate = Arc::new(Gate::default());
+        self.state.lock().unwrap().gates.entry(method).or_default().push_back(gate.clone());
+        gate
+    }
+
+    /// Every call so far, as the method name followed by its arguments.
+    pub fn commands(&self) -> Vec<String> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// The calls so far to `method`, without the name.
+    pub fn calls_to(&self, method: &str) -> Vec<String> {
+        self.commands()
+            .iter()
+            .filter_map(|command| command.strip_prefix(method))
+            .filter(|args| args.is_empty() || args.starts_with(' '))
+            .map(|args| args.trim_start().to_string())
+            .collect()
+    }
+
+    /// Logs the call, fails it if asked to, and otherwise answers it from the state.
+    fn call<T>(&self, method: &'static str, args: &[String], answer: impl FnOnce(&mut FakeState) -> T) -> git::Result<T> {
+        let (result, latency, gate) = {
+            let mut state = self.state.lock().unwrap();
+            state.commands.push(std::iter::once(method.to_string()).chain(args.iter().cloned()).collect::<Vec<_>>().join(" "));
+            let result = match state.failures.get(method) {
+                Some(message) => Err(git::Error::GitCommand(message.clone())),
+                None => Ok(answer(&mut state)),
+            };
+            let gate = state.gates.get_mut(method).and_then(VecDeque::pop_front);
+            (result, state.latency, gate)
+        };
+        thread::sleep(latency);
+        if let Some(gate) = gate {
+            gate.enter_and_wait();
+        }
+        result
+    }
+}
+
+impl FakeState {
+    fn head_tree_id(&self) -> String {
+        match self.commits {
+            0 => HEAD_TREE_ID.to_string(),
+            commits => format!("{HEAD_TREE_ID}-{commits}"),
+        }
+    }
+
+    fn tree_files(&self, tree_id: &str) -> Vec<&RepoPath> {
+        if tree_id == self.head_tree_id() {
+            self.files.keys().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn dirs(&self, tree_id: &str) -> BTreeSet<RepoPath> {
+        let mut dirs = BTreeSet::new();
+        for file in self.tree_files(tree_id) {
+            let mut dir = file.parent();
+            while !dir.is_root() {
+                let parent = dir.parent();
+                dirs.insert(dir);
+                dir = parent;
+            }
+        }
+        dirs
+    }
+}
+
+fn is_untracked(status: &FileStatus) -> bool {
+    matches!(*status, FileStatus::UNTRACKED | FileStatus::IGNORED)
+}
+
+fn paths(paths: &[RepoPath]) -> Vec<String> {
+    paths.iter().map(|path| path.display().into_owned()).collect()
+}
+
+impl GitBackend for FakeBackend {
+    fn get_dirs_at_path(&self, path: &RepoPath, rev: &str, _repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        self.call("get_dirs_at_path", &[path.display().into_owned(), rev.to_string()], |state| {
+            let tree_id = if rev == "HEAD" { state.head_tree_id() } else { String::new() };
+            state
+                .dirs(&tree_id)
+                .into_iter()
+                .filter(|dir| dir.parent() == *path)
+                .map(|dir| dir.file_name())
+                .collect()
+        })
+    }
+
+    fn get_tree_id(&self, rev: &str, _repo_path: &Path) -> git::Result<Option<String>> {
+        self.call("get_tree_id", &[rev.to_string()], |state| (rev == "HEAD").then(|| state.head_tree_id()))
+    }
+
+    fn get_file_contents(&self, rev: &str, path: &RepoPath, _repo_path: &Path) -> git::Result<Option<Vec<u8>>> {
+        self.call("get_file_contents", &[rev.to_string(), path.display().into_owned()], |state| {
+            state.files.get(path).filter(|_| rev == "HEAD").cloned()
+        })
+    }
+
+    fn get_all_files(&self, tree_id: &str, _repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        self.call("get_all_files", &[tree_id.to_string()], |state| {
+            state.tree_files(tree_id).into_iter().cloned().collect()
+        })
+    }
+
+    fn get_file_sizes(&self, tree_id: &str, _repo_path: &Path) -> git::Result<Vec<(RepoPath, u64)>> {
+        self.call("get_file_sizes", &[tree_id.to_string()], |state| {
+            state.tree_files(tree_id).into_iter().map(|file| (file.clone(), state.files[file].len() as u64)).collect()
+        })
+    }
+
+    fn list_revisions(&self, _repo_path: &Path) -> git::Result<Vec<String>> {
+        self.call("list_revisions", &[], |_| Vec::new())
+    }
+
+    fn get_all_dirs(&self, tree_id: &str, _repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        self.call("get_all_dirs", &[tree_id.to_string()], |state| state.dirs(tree_id).into_iter().collect())
+    }
+
+    fn get_sparse_checkout_list(&self, _repo_path: &Path) -> git::Result<Vec<RepoPath>> {
+        self.call("get_sparse_checkout_list", &[], |state| match state.sparse_mode {
+            SparseMode::Cone => state.sparse_dirs.clone(),
+            _ => Vec::new(),
+        })
+    }
+
+    fn get_sparse_mode(&self, _repo_path: &Path) -> git::Result<SparseMode> {
+        self.call("get_sparse_mode", &[], |state| state.sparse_mode)
+    }
+
+    fn get_sparse_checkout_patterns(&self, _repo_path: &Path) -> git::Result<Vec<Vec<u8>>> {
+        self.call("get_sparse_checkout_patterns", &[], |state| state.sparse_patterns.clone())
+    }
+
+    fn get_uncommitted_paths(&self, _repo_path: &Path) -> git::Result<HashMap<RepoPath, FileStatus>> {
+        self.call("get_uncommitted_paths", &[], |state| {
+            state.uncommitted.iter().filter(|(_, status)| !is_untracked(status)).map(|(path, status)| (path.clone(), *status)).collect()
+        })
+    }
+
+    fn get_untracked_paths(&self, dir: &RepoPath, _repo_path: &Path) -> git::Result<HashMap<RepoPath, FileStatus>> {
+        self.call("get_untracked_paths", &[dir.display().into_owned()], |state| {
+            state
+                .uncommitted
+                .iter()
+                .filter(|(path, status)| is_untracked(status) && path.starts_with_component(dir))
+                .map(|(path, status)| (path.clone(), *status))
+                .collect()
+        })
+    }
+
+    fn set_sparse_checkout_dirs(&self, mut dirs: Vec<RepoPath>, _repo_path: &Path) -> git::Result<()> {
+        dirs.sort();
+        self.call("set_sparse_checkout_dirs", &paths(&dirs), |state| state.sparse_dirs = dirs)
+    }
+
+    fn add_sparse_checkout_dirs(&self, mut dirs: Vec<RepoPath>, _repo_path: &Path) -> git::Result<()> {
+        dirs.sort();
+        self.call("add_sparse_checkout_dirs", &paths(&dirs), |state| {
+            for dir in dirs {
+                if !state.sparse_dirs.contains(&dir) {
+                    state.sparse_dirs.push(dir);
+                }
+            }
+        })
+    }
+
+    fn set_sparse_checkout_patterns(&self, patterns: Vec<Vec<u8>>, _repo_path: &Path) -> git::Result<()> {
+        let args: Vec<String> = patterns.iter().map(|pattern| String::from_utf8_lossy(pattern).into_owned()).collect();
+        self.call("set_sparse_checkout_patterns", &args, |state| state.sparse_patterns = patterns)
+    }
+
+    fn init_sparse_checkout(&self, sparse_index: bool, _repo_path: &Path) -> git::Result<()> {
+        self.call("init_sparse_checkout", &[sparse_index.to_string()], |state| {
+            state.sparse_mode = SparseMode::Cone;
+            state.sparse_dirs.clear();
+        })
+    }
+
+    fn disable_sparse_checkout(&self, _repo_path: &Path) -> git::Result<()> {
+        self.call("disable_sparse_checkout", &[], |state| {
+            state.sparse_mode = SparseMode::Disabled;
+            state.sparse_dirs.clear();
+        })
+    }
+}
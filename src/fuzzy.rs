@@ -0,0 +1,67 @@
+//! Fuzzy matching of directory paths for search.
+//!
+//! A path matches if the characters of the query appear in it in order, ignoring case.
+//! Matches are ranked so that runs of consecutive characters, characters at the start of
+//! a path component or word, and matches in the last component come first.
+
+/// Scores `candidate` against `query`, higher being better, or returns `None` if it does
+/// not match. An empty query matches everything equally.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    let last_component = candidate.iter().rposition(|&c| c == '/').map_or(0, |slash| slash + 1);
+
+    let mut score = 0;
+    let mut next = 0; // Position in `candidate` to continue from
+    let mut previous: Option<usize> = None;
+    for q in query {
+        let offset = candidate[next..].iter().position(|&c| c.to_lowercase().eq(std::iter::once(q)))?;
+        let at = next + offset;
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == at) {
+            score += 8;
+        }
+        if at == 0 || matches!(candidate[at - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 6;
+        }
+        if at >= last_component {
+            score += 2;
+        }
+        score -= offset.min(8) as i64; // Gaps between matched characters
+        previous = Some(at);
+        next = at + 1;
+    }
+    Some(score * 100 - candidate.len() as i64)
+}
+
+/// The candidates that match `query`, best first. Ties keep the order of `candidates`.
+pub fn rank<'a, T>(query: &str, candidates: &'a [T], text: impl Fn(&T) -> &str) -> Vec<&'a T> {
+    let mut scored: Vec<(i64, &T)> = candidates
+        .iter()
+        .filter_map(|candidate| score(query, text(candidate)).map(|score| (score, candidate)))
+        .collect();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_prefers_components_and_runs() {
+        assert_eq!(score("xyz", "src/app"), None);
+        assert_eq!(score("pa", "src/app"), None); // Out of order
+        assert!(score("SRCAPP", "src/app").is_some());
+
+        let paths = ["docs/api", "src/app", "src/apply_preview", "assets/pictures/apps", "src/backend/appendix"];
+        let ranked = rank("app", &paths, |path| path);
+        assert_eq!(ranked[0], &"src/app");
+        assert_eq!(ranked.len(), 4);
+        assert!(!ranked.contains(&&"docs/api"));
+
+        let ranked = rank("sa", &paths, |path| path);
+        assert_eq!(ranked[0], &"src/app"); // Both at component starts, shortest path
+        assert_eq!(rank("", &paths, |path| path).len(), paths.len());
+    }
+}
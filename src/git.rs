@@ -1,44 +1,167 @@
+use crate::repo_path::{bytes_to_os_string, RepoPath};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Git command failed: {0}")]
     GitCommand(String),
-    #[error("Failed to decode git command output: {0}")]
-    OutputDecode(#[from] std::string::FromUtf8Error),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Invalid repository data: {0}")]
+    InvalidData(String),
+    #[error("Unsupported repository format: {0}")]
+    Unsupported(String),
+    #[error("Unknown revision: {0}")]
+    UnknownRevision(String),
 }
 
-type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, Error>;
 
-// Helper function to prepend git config core.quotepath=false
-fn git_args_with_quotepath<'a>(args: &'a [&'a str]) -> Vec<&'a str> {
-    let mut new_args = Vec::new();
-    new_args.push("-c");
-    new_args.push("core.quotepath=false"); // Set to false for unescaped output
-    new_args.extend_from_slice(args);
-    new_args
+/// What one git command did, for the message log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRecord {
+    pub args: Vec<String>,   // Without `git` and the `-c` options every command gets
+    pub dir: Option<PathBuf>, // Where it ran
+    pub status: Option<i32>,  // The exit code; `None` if git did not start or was killed
+    pub stderr: String,       // Or why git did not start
+    pub duration: Duration,
 }
 
-fn run_git_command(args: &[&str], current_dir: Option<&Path>) -> Result<std::process::Output> {
+type CommandListener = Arc<dyn Fn(CommandRecord) + Send + Sync>;
+
+static COMMAND_LISTENER: Mutex<Option<CommandListener>> = Mutex::new(None);
+
+/// Calls `listener` with a record of every git command run from now on, on the thread that
+/// ran it. There is one listener per process: the TUI's message log.
+pub fn set_command_listener(listener: impl Fn(CommandRecord) + Send + Sync + 'static) {
+    *COMMAND_LISTENER.lock().unwrap() = Some(Arc::new(listener));
+}
+
+/// Why a path counts as uncommitted, from most to least serious.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeKind {
+    Conflicted,
+    Staged,
+    Modified,
+    Untracked,
+    Ignored,
+}
+
+impl ChangeKind {
+    /// Whether a directory holding such a path is kept out of removals. Git leaves
+    /// untracked files on disk and deletes ignored ones, so only those may be removed.
+    pub fn blocks_removal(self) -> bool {
+        matches!(self, ChangeKind::Conflicted | ChangeKind::Staged | ChangeKind::Modified)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ChangeKind::Conflicted => "conflicted",
+            ChangeKind::Staged => "staged",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Untracked => "untracked",
+            ChangeKind::Ignored => "ignored",
+        }
+    }
+}
+
+/// The two-letter status of an uncommitted path as `git status --porcelain=v2` shows it:
+/// the index against `HEAD`, then the working tree against the index, with `.` for
+/// unchanged. Unmerged paths use git's conflict codes such as `UU`, untracked paths `??`
+/// and ignored paths `!!`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileStatus(pub [u8; 2]);
+
+impl FileStatus {
+    pub const UNTRACKED: FileStatus = FileStatus(*b"??");
+    pub const IGNORED: FileStatus = FileStatus(*b"!!");
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).unwrap_or("??")
+    }
+
+    pub fn kinds(&self) -> Vec<ChangeKind> {
+        match &self.0 {
+            b"??" => vec![ChangeKind::Untracked],
+            b"!!" => vec![ChangeKind::Ignored],
+            b"DD" | b"AU" | b"UD" | b"UA" | b"DU" | b"AA" | b"UU" => vec![ChangeKind::Conflicted],
+            [index, worktree] => {
+                let mut kinds = Vec::new();
+                if *index != b'.' {
+                    kinds.push(ChangeKind::Staged);
+                }
+                if *worktree != b'.' {
+                    kinds.push(ChangeKind::Modified);
+                }
+                kinds
+            }
+        }
+    }
+}
+
+/// How the sparse-checkout file of a worktree is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SparseMode {
+    /// `core.sparseCheckout` is off: the whole tree is checked out.
+    #[default]
+    Disabled,
+    /// The file lists directories (`core.sparseCheckoutCone=true`).
+    Cone,
+    /// The file holds gitignore-style patterns.
+    Pattern,
+}
+
+fn run_git_command<S: AsRef<OsStr>>(args: &[S], current_dir: Option<&Path>) -> Result<std::process::Output> {
+    run_git_command_with_input(args, None, current_dir)
+}
+
+// Like `run_git_command`, optionally writing `input` to the command's stdin
+fn run_git_command_with_input<S: AsRef<OsStr>>(
+    args: &[S],
+    input: Option<&[u8]>,
+    current_dir: Option<&Path>,
+) -> Result<std::process::Output> {
     let mut command = Command::new("git");
 
     // Always add core.quotepath=false for consistent unescaped output
-    let full_args = git_args_with_quotepath(args);
-    command.args(&full_args);
+    command.args(["-c", "core.quotepath=false"]);
+    command.args(args);
 
     if let Some(dir) = current_dir {
         command.current_dir(dir);
     }
-    // Set environment variables for robust UTF-8 handling as a fallback strategy
+    // Set environment variables for robust UTF-8 handling of messages as a fallback strategy
     command.env("LANG", "C.UTF-8");
     command.env("LC_ALL", "C.UTF-8");
 
-    let output = command.output()?;
-    
+    let started = Instant::now();
+    let output = match input {
+        Some(input) => output_with_input(&mut command, input),
+        None => command.output(),
+    };
+    // Called without the lock, so that commands on other threads need not wait for it
+    let listener = COMMAND_LISTENER.lock().unwrap().clone();
+    if let Some(listener) = listener {
+        listener(CommandRecord {
+            args: args.iter().map(|arg| arg.as_ref().to_string_lossy().into_owned()).collect(),
+            dir: current_dir.map(Path::to_path_buf),
+            status: output.as_ref().ok().and_then(|output| output.status.code()),
+            stderr: match &output {
+                Ok(output) => String::from_utf8_lossy(&output.stderr).into_owned(),
+                Err(e) => e.to_string(),
+            },
+            duration: started.elapsed(),
+        });
+    }
+    let output = output?;
+
     if !output.status.success() {
         return Err(Error::GitCommand(
             String::from_utf8_lossy(&output.stderr).to_string(),
@@ -48,30 +171,113 @@ fn run_git_command(args: &[&str], current_dir: Option<&Path>) -> Result<std::pro
     Ok(output) // Return the full output struct
 }
 
+// Like `Command::output`, writing `input` to the command's stdin
+fn output_with_input(command: &mut Command, input: &[u8]) -> std::io::Result<std::process::Output> {
+    command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Write from another thread so that a chatty command cannot fill its stdout
+    // pipe and deadlock against us.
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    writer.join().expect("stdin writer panicked")?;
+    Ok(output)
+}
 
+/// Returns the top-level directory of the repository containing `start_dir`
+/// (or the current directory).
+pub fn find_repo_root(start_dir: Option<&Path>) -> Result<PathBuf> {
+    let output = run_git_command(&["rev-parse", "--show-toplevel"], start_dir)?;
+    let stdout = output.stdout.strip_suffix(b"\n").unwrap_or(&output.stdout);
+    Ok(PathBuf::from(bytes_to_os_string(stdout)))
+}
 
-pub fn find_repo_root() -> Result<PathBuf> {
-    let output = run_git_command(&["rev-parse", "--show-toplevel"], None)?;
-    let s = String::from_utf8(output.stdout)?; // It is expected to be UTF-8
-    Ok(PathBuf::from(s.trim()))
+/// Returns the absolute git directory of the worktree at `repo_path` and the common
+/// directory that holds the refs; they differ in linked worktrees.
+pub fn get_git_dirs(repo_path: &Path) -> Result<(PathBuf, PathBuf)> {
+    let output = run_git_command(
+        &["rev-parse", "--path-format=absolute", "--git-dir", "--git-common-dir"],
+        Some(repo_path),
+    )?;
+    let mut lines = output.stdout.split(|&b| b == b'\n').map(|line| PathBuf::from(bytes_to_os_string(line)));
+    match (lines.next(), lines.next()) {
+        (Some(git_dir), Some(common_dir)) => Ok((git_dir, common_dir)),
+        _ => Err(Error::GitCommand("Unexpected output from git rev-parse".to_string())),
+    }
 }
 
-// Helper to process newline-separated output into a Vec<String> of paths
-fn parse_path_lines(output: std::process::Output) -> Result<Vec<String>> {
-    let s = String::from_utf8_lossy(&output.stdout).to_string(); // Use lossy for initial String conversion
-    Ok(s.lines()
-        .filter(|line| !line.is_empty())
-        .map(|line| line.to_string())
+// Helper to split NUL-terminated output (from `-z` options) into raw paths
+fn parse_nul_separated(output: std::process::Output) -> Vec<RepoPath> {
+    output
+        .stdout
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(RepoPath::from)
+        .collect()
+}
+
+/// Reads a boolean config value, or `None` if it is unset.
+pub fn get_config_bool(key: &str, repo_path: &Path) -> Result<Option<bool>> {
+    match run_git_command(&["config", "--bool", "--get", key], Some(repo_path)) {
+        Ok(output) => Ok(Some(output.stdout.trim_ascii() == b"true")),
+        // Exit status 1 without a message means the key is not set.
+        Err(Error::GitCommand(stderr)) if stderr.trim().is_empty() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn get_sparse_mode(repo_path: &Path) -> Result<SparseMode> {
+    if !get_config_bool("core.sparseCheckout", repo_path)?.unwrap_or(false) {
+        return Ok(SparseMode::Disabled);
+    }
+    // Unset means non-cone, even though `sparse-checkout init` defaults to cone nowadays.
+    match get_config_bool("core.sparseCheckoutCone", repo_path)?.unwrap_or(false) {
+        true => Ok(SparseMode::Cone),
+        false => Ok(SparseMode::Pattern),
+    }
+}
+
+/// Returns the lines of `info/sparse-checkout` verbatim, skipping blank lines.
+pub fn get_sparse_checkout_patterns(repo_path: &Path) -> Result<Vec<Vec<u8>>> {
+    let output = run_git_command(&["rev-parse", "--git-path", "info/sparse-checkout"], Some(repo_path))?;
+    let file = output.stdout.strip_suffix(b"\n").unwrap_or(&output.stdout);
+    let content = match std::fs::read(repo_path.join(bytes_to_os_string(file))) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(content
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.trim_ascii().is_empty())
+        .map(<[u8]>::to_vec)
         .collect())
 }
 
-pub fn get_sparse_checkout_list(repo_path: &Path) -> Result<Vec<String>> {
+/// Replaces the sparse-checkout patterns (non-cone mode) and updates the working tree.
+pub fn set_sparse_checkout_patterns(patterns: Vec<Vec<u8>>, repo_path: &Path) -> Result<()> {
+    let mut input = Vec::new();
+    for pattern in patterns {
+        input.extend_from_slice(&pattern);
+        input.push(b'\n');
+    }
+    run_git_command_with_input(&["sparse-checkout", "set", "--no-cone", "--stdin"], Some(&input), Some(repo_path))?;
+    Ok(())
+}
+
+pub fn get_sparse_checkout_list(repo_path: &Path) -> Result<Vec<RepoPath>> {
     let output_result = run_git_command(&["sparse-checkout", "list"], Some(repo_path));
     match output_result {
         Ok(output) => {
-            // Git's 'sparse-checkout list' now outputs quoted paths when core.quotepath=true.
-            // So we can directly parse and use them.
-            parse_path_lines(output)
+            // 'sparse-checkout list' has no -z option. It prints one path per line and
+            // C-quotes any path containing a newline, quote or backslash, so undo that.
+            Ok(output
+                .stdout
+                .split(|&b| b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(RepoPath::unquote)
+                .collect())
         }
         Err(Error::GitCommand(stderr)) => {
             if stderr.contains("fatal: this worktree is not sparse") {
@@ -84,80 +290,245 @@ pub fn get_sparse_checkout_list(repo_path: &Path) -> Result<Vec<String>> {
     }
 }
 
-pub fn get_dirs_at_path(path: &str, repo_path: &Path) -> Result<Vec<String>> {
-    let target_abs_path = if path.is_empty() || path == "." {
+/// Returns the names of the direct subdirectories of `path` in the tree of `rev`.
+pub fn get_dirs_at_path(path: &RepoPath, rev: &str, repo_path: &Path) -> Result<Vec<RepoPath>> {
+    let target_abs_path = if path.is_empty() || path.is_root() {
         repo_path.to_path_buf()
     } else {
-        repo_path.join(path) // path is already unescaped UTF-8
+        repo_path.join(path.to_path())
     };
 
-    let mut direct_children = Vec::new();
-
     // Strategy 1: Try running ls-tree from the target directory itself (works for physically existing dirs)
     // This is more efficient for checked-out directories.
     if target_abs_path.is_dir() { // Check if the directory physically exists
-        let args = vec!["ls-tree", "-r", "--name-only", "-d", "HEAD"];
+        let args = ["ls-tree", "-z", "-r", "--name-only", "-d", rev];
         let output = run_git_command(&args, Some(&target_abs_path))?;
-        let paths_relative_to_target = parse_path_lines(output)?;
+        let paths_relative_to_target = parse_nul_separated(output);
 
         // Filter for direct children (no slashes)
-        direct_children = paths_relative_to_target.into_iter().filter(|p| p.find('/').is_none()).collect();
+        let direct_children = paths_relative_to_target
+            .into_iter()
+            .filter(|p| !p.as_bytes().contains(&b'/'))
+            .collect();
         return Ok(direct_children);
     }
 
     // Strategy 2: Fallback for virtual directories (not physically checked out)
     // Query all directories recursively from the repository root and filter in Rust.
     // This is necessary because Command::current_dir fails if target_abs_path does not exist.
-    let output = run_git_command(&["ls-tree", "-r", "--name-only", "-d", "HEAD"], Some(repo_path))?;
-    let all_dirs_from_root = parse_path_lines(output)?; // These are unescaped UTF-8 strings
+    let output = run_git_command(&["ls-tree", "-z", "-r", "--name-only", "-d", rev], Some(repo_path))?;
+    let all_dirs_from_root = parse_nul_separated(output);
 
-    let search_prefix = if path.is_empty() || path == "." {
-        "".to_string()
+    let search_prefix = if path.is_empty() || path.is_root() {
+        Vec::new()
     } else {
-        format!("{}/", path)
+        [path.as_bytes(), b"/"].concat()
     };
 
+    let mut direct_children = Vec::new();
     for dir in all_dirs_from_root {
-        if dir.starts_with(&search_prefix) {
-            let suffix = &dir[search_prefix.len()..];
-            if !suffix.is_empty() && suffix.find('/').is_none() {
-                direct_children.push(suffix.to_string());
+        if let Some(suffix) = dir.as_bytes().strip_prefix(search_prefix.as_slice()) {
+            if !suffix.is_empty() && !suffix.contains(&b'/') {
+                direct_children.push(RepoPath::from(suffix));
             }
         }
     }
     Ok(direct_children)
 }
 
-#[allow(dead_code)]
-pub fn get_all_directories_recursive(repo_path: &Path) -> Result<Vec<String>> {
-    let output = run_git_command(&["ls-tree", "-r", "--name-only", "-d", "HEAD"], Some(repo_path))?;
-    parse_path_lines(output) // Returns Vec<String> of unquoted paths
+/// Returns the id of the root tree of `rev`, or `None` if it does not resolve
+/// (which for `HEAD` means an unborn branch).
+pub fn get_tree_id(rev: &str, repo_path: &Path) -> Result<Option<String>> {
+    if rev.starts_with('-') {
+        return Ok(None); // Would be parsed as an option
+    }
+    let spec = format!("{rev}^{{tree}}");
+    match run_git_command(&["rev-parse", "-q", "--verify", &spec], Some(repo_path)) {
+        Ok(output) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string())),
+        // With -q, a revision that does not resolve fails silently.
+        Err(Error::GitCommand(stderr)) if stderr.trim().is_empty() => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
-use std::collections::HashSet;
+/// Returns the contents of the file at `path` in `rev`, or `None` if there is no such file.
+pub fn get_file_contents(rev: &str, path: &RepoPath, repo_path: &Path) -> Result<Option<Vec<u8>>> {
+    if rev.starts_with('-') {
+        return Ok(None); // Would be parsed as an option
+    }
+    let mut spec = OsString::from(format!("{rev}:"));
+    spec.push(path.to_os_string());
+    let object = match run_git_command(&[OsStr::new("rev-parse"), OsStr::new("-q"), OsStr::new("--verify"), &spec], Some(repo_path)) {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        Err(Error::GitCommand(stderr)) if stderr.trim().is_empty() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let output = run_git_command(&["cat-file", "blob", &object], Some(repo_path))?;
+    Ok(Some(output.stdout))
+}
 
-pub fn get_uncommitted_paths(repo_path: &Path) -> Result<HashSet<String>> {
-    let mut uncommitted_paths = HashSet::new();
+/// Lists every directory in the tree `tree_id`, recursively, as paths from the root.
+pub fn get_all_directories_recursive(tree_id: &str, repo_path: &Path) -> Result<Vec<RepoPath>> {
+    let output = run_git_command(&["ls-tree", "-z", "-r", "--name-only", "-d", tree_id], Some(repo_path))?;
+    Ok(parse_nul_separated(output))
+}
 
-    // Get modified and staged files using git diff --name-only HEAD
-    let output = run_git_command(&["diff", "--name-only", "HEAD"], Some(repo_path))?;
-    let modified_paths = parse_path_lines(output)?;
-    uncommitted_paths.extend(modified_paths.into_iter());
+/// Lists every file (and submodule) in the tree `tree_id`, recursively.
+pub fn get_all_files_recursive(tree_id: &str, repo_path: &Path) -> Result<Vec<RepoPath>> {
+    let output = run_git_command(&["ls-tree", "-z", "-r", "--name-only", tree_id], Some(repo_path))?;
+    Ok(parse_nul_separated(output))
+}
 
-    // Get untracked files using git ls-files --others --exclude-standard
-    let output = run_git_command(&["ls-files", "--others", "--exclude-standard"], Some(repo_path))?;
-    let untracked_paths = parse_path_lines(output)?;
-    uncommitted_paths.extend(untracked_paths.into_iter());
+/// Lists every blob in the tree `tree_id` with its size in bytes, recursively.
+pub fn get_file_sizes(tree_id: &str, repo_path: &Path) -> Result<Vec<(RepoPath, u64)>> {
+    let output = run_git_command(&["ls-tree", "-z", "-r", "-l", tree_id], Some(repo_path))?;
+    let mut files = Vec::new();
+    for entry in output.stdout.split(|&b| b == 0).filter(|entry| !entry.is_empty()) {
+        // "<mode> SP <type> SP <object> SP+ <size> TAB <path>"; submodules have no size
+        let invalid = || Error::InvalidData(format!("ls-tree entry {:?}", String::from_utf8_lossy(entry)));
+        let tab = entry.iter().position(|&b| b == b'\t').ok_or_else(invalid)?;
+        let meta = String::from_utf8_lossy(&entry[..tab]);
+        let fields: Vec<&str> = meta.split_whitespace().collect();
+        let [_mode, kind, _object, size] = fields[..] else {
+            return Err(invalid());
+        };
+        if kind == "blob" {
+            let size = size.parse().map_err(|_| invalid())?;
+            files.push((RepoPath::from(&entry[tab + 1..]), size));
+        }
+    }
+    Ok(files)
+}
 
-    Ok(uncommitted_paths)
+/// Lists local branches, tags and remote-tracking branches by their short names.
+pub fn list_revisions(repo_path: &Path) -> Result<Vec<String>> {
+    let output = run_git_command(
+        &["for-each-ref", "--format=%(refname:short)", "refs/heads", "refs/tags", "refs/remotes"],
+        Some(repo_path),
+    )?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
 }
 
-pub fn set_sparse_checkout_dirs(dirs: Vec<String>, repo_path: &Path) -> Result<()> {
-    let mut args = vec!["sparse-checkout", "set"];
-    
-    let dirs_as_strs: Vec<&str> = dirs.iter().map(|s| s.as_str()).collect();
-    args.extend(dirs_as_strs);
+/// Lists every tracked path with uncommitted changes and its status, from
+/// `git status --porcelain=v2`. Untracked and ignored files never lock a directory, and
+/// listing them walks the whole working tree, so they are left to `get_untracked_paths`.
+pub fn get_uncommitted_paths(repo_path: &Path) -> Result<HashMap<RepoPath, FileStatus>> {
+    // Without optional locks, status does not rewrite the index to refresh stat data
+    let output = run_git_command(
+        &["--no-optional-locks", "status", "--porcelain=v2", "-z", "--untracked-files=no", "--no-renames"],
+        Some(repo_path),
+    )?;
+    parse_porcelain_v2(&output.stdout)
+}
 
+/// Lists the untracked and ignored paths under `dir`. Untracked files are listed one by one
+/// and ignored ones as git matches them, so a wholly ignored directory appears once with a
+/// trailing slash.
+pub fn get_untracked_paths(dir: &RepoPath, repo_path: &Path) -> Result<HashMap<RepoPath, FileStatus>> {
+    let mut args: Vec<OsString> = [
+        "--no-optional-locks",
+        "status",
+        "--porcelain=v2",
+        "-z",
+        "--untracked-files=all",
+        "--ignored=matching",
+        "--no-renames",
+    ]
+    .into_iter()
+    .map(OsString::from)
+    .collect();
+    if !dir.is_root() {
+        let mut spec = OsString::from(":(top,literal)");
+        spec.push(dir.to_os_string());
+        args.extend([OsString::from("--"), spec]);
+    }
+    let output = run_git_command(&args, Some(repo_path))?;
+    let mut statuses = parse_porcelain_v2(&output.stdout)?;
+    statuses.retain(|_, status| matches!(*status, FileStatus::UNTRACKED | FileStatus::IGNORED));
+    Ok(statuses)
+}
+
+fn parse_porcelain_v2(output: &[u8]) -> Result<HashMap<RepoPath, FileStatus>> {
+    let mut statuses = HashMap::new();
+    let mut records = output.split(|&b| b == 0).filter(|record| !record.is_empty());
+    while let Some(record) = records.next() {
+        // The number of space-separated fields before the path depends on the record type
+        let fields = match record[0] {
+            b'1' => 8,
+            b'2' => 9,
+            b'u' => 10,
+            b'?' | b'!' => 1,
+            _ => continue, // Headers
+        };
+        let mut parts = record.splitn(fields + 1, |&b| b == b' ');
+        let kind = parts.next().unwrap_or_default();
+        let code = if fields > 1 { parts.next() } else { None };
+        let status = match (kind, code) {
+            (b"?", _) => Some(FileStatus::UNTRACKED),
+            (b"!", _) => Some(FileStatus::IGNORED),
+            (_, Some(&[index, worktree])) => Some(FileStatus([index, worktree])),
+            _ => None,
+        };
+        let (Some(status), Some(path)) = (status, parts.last()) else {
+            return Err(Error::InvalidData(format!(
+                "Unexpected status line: {}",
+                String::from_utf8_lossy(record)
+            )));
+        };
+        if kind == b"2" {
+            records.next(); // The original path of a rename or copy
+        }
+        statuses.insert(RepoPath::from(path), status);
+    }
+    Ok(statuses)
+}
+
+/// Replaces the cone-mode directory set and updates the working tree.
+pub fn set_sparse_checkout_dirs(dirs: Vec<RepoPath>, repo_path: &Path) -> Result<()> {
+    let input = dirs_to_stdin(&dirs);
+    run_git_command_with_input(&["sparse-checkout", "set", "--stdin"], Some(&input), Some(repo_path))?;
+    Ok(())
+}
+
+/// Adds directories to the cone-mode set, leaving the existing entries alone.
+pub fn add_sparse_checkout_dirs(dirs: Vec<RepoPath>, repo_path: &Path) -> Result<()> {
+    let input = dirs_to_stdin(&dirs);
+    run_git_command_with_input(&["sparse-checkout", "add", "--stdin"], Some(&input), Some(repo_path))?;
+    Ok(())
+}
+
+/// Formats directories one per line for `sparse-checkout set/add --stdin`, which has no
+/// argv length limit. Git unquotes C-style quoted lines in cone mode, so names containing
+/// newlines survive.
+fn dirs_to_stdin(dirs: &[RepoPath]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for dir in dirs {
+        input.extend_from_slice(dir.display().as_bytes());
+        input.push(b'\n');
+    }
+    input
+}
+
+/// Turns on sparse-checkout in cone mode, optionally with a sparse index.
+pub fn init_sparse_checkout(sparse_index: bool, repo_path: &Path) -> Result<()> {
+    let mut args = vec!["sparse-checkout", "init", "--cone"];
+    if sparse_index {
+        args.push("--sparse-index");
+    }
     run_git_command(&args, Some(repo_path))?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Turns off sparse-checkout, checking out every file again.
+pub fn disable_sparse_checkout(repo_path: &Path) -> Result<()> {
+    run_git_command(&["sparse-checkout", "disable"], Some(repo_path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "git_test.rs"]
+pub(crate) mod tests;
@@ -1,195 +1,375 @@
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::tempdir;
-
-    pub fn setup_git_repo() -> (PathBuf, tempfile::TempDir) {
-        let dir = tempdir().unwrap();
-        let path = dir.path().to_path_buf();
-        Command::new("git")
-            .arg("init")
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        Command::new("git")
-            .args(&["config", "user.email", "test@example.com"])
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        Command::new("git")
-            .args(&["config", "user.name", "Test User"])
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        // Ensure core.quotepath is false for consistent unescaped test output
-        Command::new("git")
-            .args(&["config", "core.quotepath", "false"])
-            .current_dir(&path)
-            .output()
-            .unwrap();
-        (path, dir)
-    }
+use super::*;
+use std::fs;
+use tempfile::tempdir;
 
-    fn create_and_commit_files(repo_path: &PathBuf) {
-        fs::create_dir_all(repo_path.join("src")).unwrap();
-        fs::write(repo_path.join("src/main.rs"), "fn main() {}").unwrap();
-        fs::create_dir_all(repo_path.join("src/components")).unwrap();
-        fs::write(repo_path.join("src/components/mod.rs"), "pub fn foo() {}").unwrap();
-        fs::create_dir_all(repo_path.join("docs")).unwrap();
-        fs::write(repo_path.join("docs/README.md"), "# Docs").unwrap();
-        fs::create_dir_all(repo_path.join("tests")).unwrap();
-        fs::write(repo_path.join("tests/test.rs"), "# Tests").unwrap();
-        fs::write(repo_path.join(".gitignore"), "target/").unwrap();
-        fs::create_dir_all(repo_path.join("dir with spaces")).unwrap(); // Directory with spaces
-        fs::write(repo_path.join("dir with spaces/file with spaces.txt"), "content").unwrap();
-        fs::create_dir_all(repo_path.join("日本語ディレクトリ")).unwrap(); // Japanese directory
-        fs::write(repo_path.join("日本語ディレクトリ/ファイル.txt"), "japanese content").unwrap();
-
-
-        Command::new("git")
-            .args(&["add", "."])
-            .current_dir(repo_path)
-            .output()
-            .unwrap();
-        Command::new("git")
-            .args(&["commit", "-m", "Initial commit with various paths"])
-            .current_dir(repo_path)
-            .output()
-            .unwrap();
-    }
+/// Runs git in `repo`, failing the test with git's stderr unless it succeeds.
+pub fn git(repo: &Path, args: &[&str]) {
+    let output = Command::new("git").args(args).current_dir(repo).output().unwrap();
+    assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+}
 
-    #[test]
-    fn test_find_repo_root() {
-        let (repo_path, _temp_dir) = setup_git_repo();
-        create_and_commit_files(&repo_path);
-        let root = find_repo_root().unwrap();
-        assert_eq!(root, repo_path);
-    }
+pub fn setup_git_repo() -> (PathBuf, tempfile::TempDir) {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_path_buf();
+    init_git_repo(&path);
+    (path, dir)
+}
 
-    pub fn setup_git_repo_with_subdirs() -> (PathBuf, tempfile::TempDir) {
-        let (repo_path, temp_dir) = setup_git_repo();
-        create_and_commit_files(&repo_path); // Use the helper that creates src, docs, tests, and src/components
-        // No need for separate add/commit here, as create_and_commit_files handles it
-        (repo_path, temp_dir)
-    }
+/// Creates a repository in the existing directory `path`, with an author to commit as.
+pub fn init_git_repo(path: &Path) {
+    git(path, &["init", "-q"]);
+    git(path, &["config", "user.email", "test@example.com"]);
+    git(path, &["config", "user.name", "Test User"]);
+    // Ensure core.quotepath is false for consistent unescaped test output
+    git(path, &["config", "core.quotepath", "false"]);
+}
 
-    #[test]
-    fn test_get_dirs_at_path() {
-        let (repo_path, _temp_dir) = setup_git_repo_with_subdirs();
-
-        // Test at root
-        let mut root_dirs = get_dirs_at_path("", &repo_path).unwrap();
-        root_dirs.sort(); // Sort for consistent comparison
-        let expected_root_dirs = vec![
-            "dir with spaces".to_string(),
-            "docs".to_string(),
-            "src".to_string(),
-            "tests".to_string(),
-            "日本語ディレクトリ".to_string(),
-        ];
-        assert_eq!(root_dirs, expected_root_dirs);
-
-        // Test at a subdirectory
-        let mut src_dirs = get_dirs_at_path("src", &repo_path).unwrap();
-        src_dirs.sort();
-        let expected_src_dirs = vec!["components".to_string()];
-        assert_eq!(src_dirs, expected_src_dirs);
-
-        // Test at a directory with no subdirectories
-        let docs_dirs = get_dirs_at_path("docs", &repo_path).unwrap();
-        assert!(docs_dirs.is_empty());
-        
-        let components_dirs = get_dirs_at_path("src/components", &repo_path).unwrap();
-        assert!(components_dirs.is_empty());
-
-        let mut dir_with_spaces_dirs = get_dirs_at_path("dir with spaces", &repo_path).unwrap();
-        dir_with_spaces_dirs.sort();
-        assert!(dir_with_spaces_dirs.is_empty());
+/// Writes each file with its content, creating its directories, and commits them all.
+pub fn commit_files(repo: &Path, files: &[(&str, &str)]) {
+    for (file, content) in files {
+        fs::create_dir_all(repo.join(file).parent().unwrap()).unwrap();
+        fs::write(repo.join(file), content).unwrap();
     }
+    git(repo, &["add", "."]);
+    git(repo, &["commit", "-q", "--allow-empty", "-m", "Add files"]);
+}
 
-    #[test]
-    fn test_get_sparse_checkout_list() {
-        let (repo_path, _temp_dir) = setup_git_repo();
-        create_and_commit_files(&repo_path);
-
-        Command::new("git")
-            .args(&["sparse-checkout", "init", "--cone"])
-            .current_dir(&repo_path)
-            .output()
-            .expect("git sparse-checkout init --cone failed");
-
-        Command::new("git")
-            .args(&["sparse-checkout", "set", "src", "docs", "dir with spaces", "日本語ディレクトリ"])
-            .current_dir(&repo_path)
-            .output()
-            .expect("git sparse-checkout set failed");
-        
-        let mut sparse_dirs = get_sparse_checkout_list(&repo_path).unwrap();
-        sparse_dirs.sort();
-        let expected_sparse_dirs = vec![
-            "dir with spaces".to_string(),
-            "docs".to_string(),
-            "src".to_string(),
-            "日本語ディレクトリ".to_string(),
-        ];
-        assert_eq!(sparse_dirs, expected_sparse_dirs);
-    }
+fn create_and_commit_files(repo_path: &Path) {
+    fs::create_dir_all(repo_path.join("src")).unwrap();
+    fs::write(repo_path.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::create_dir_all(repo_path.join("src/components")).unwrap();
+    fs::write(repo_path.join("src/components/mod.rs"), "pub fn foo() {}").unwrap();
+    fs::create_dir_all(repo_path.join("docs")).unwrap();
+    fs::write(repo_path.join("docs/README.md"), "# Docs").unwrap();
+    fs::create_dir_all(repo_path.join("tests")).unwrap();
+    fs::write(repo_path.join("tests/test.rs"), "# Tests").unwrap();
+    fs::write(repo_path.join(".gitignore"), "target/").unwrap();
+    fs::create_dir_all(repo_path.join("dir with spaces")).unwrap(); // Directory with spaces
+    fs::write(repo_path.join("dir with spaces/file with spaces.txt"), "content").unwrap();
+    fs::create_dir_all(repo_path.join("日本語ディレクトリ")).unwrap(); // Japanese directory
+    fs::write(repo_path.join("日本語ディレクトリ/ファイル.txt"), "japanese content").unwrap();
+
+
+    git(repo_path, &["add", "."]);
+    git(repo_path, &["commit", "-m", "Initial commit with various paths"]);
+}
+
+#[test]
+fn test_find_repo_root() {
+    let (repo_path, _temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path);
+    let root = find_repo_root(Some(&repo_path)).unwrap();
+    assert_eq!(root, repo_path);
+}
+
+pub fn setup_git_repo_with_subdirs() -> (PathBuf, tempfile::TempDir) {
+    let (repo_path, temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path); // Use the helper that creates src, docs, tests, and src/components
+    // No need for separate add/commit here, as create_and_commit_files handles it
+    (repo_path, temp_dir)
+}
+
+#[test]
+fn test_get_dirs_at_path() {
+    let (repo_path, _temp_dir) = setup_git_repo_with_subdirs();
+
+    // Test at root
+    let mut root_dirs = get_dirs_at_path(&RepoPath::root(), "HEAD", &repo_path).unwrap();
+    root_dirs.sort(); // Sort for consistent comparison
+    let expected_root_dirs: Vec<RepoPath> = vec![
+        "dir with spaces".into(),
+        "docs".into(),
+        "src".into(),
+        "tests".into(),
+        "日本語ディレクトリ".into(),
+    ];
+    assert_eq!(root_dirs, expected_root_dirs);
+
+    // Test at a subdirectory
+    let mut src_dirs = get_dirs_at_path(&"src".into(), "HEAD", &repo_path).unwrap();
+    src_dirs.sort();
+    let expected_src_dirs: Vec<RepoPath> = vec!["components".into()];
+    assert_eq!(src_dirs, expected_src_dirs);
+
+    // Test at a directory with no subdirectories
+    let docs_dirs = get_dirs_at_path(&"docs".into(), "HEAD", &repo_path).unwrap();
+    assert!(docs_dirs.is_empty());
+    
+    let components_dirs = get_dirs_at_path(&"src/components".into(), "HEAD", &repo_path).unwrap();
+    assert!(components_dirs.is_empty());
+
+    let mut dir_with_spaces_dirs = get_dirs_at_path(&"dir with spaces".into(), "HEAD", &repo_path).unwrap();
+    dir_with_spaces_dirs.sort();
+    assert!(dir_with_spaces_dirs.is_empty());
+}
+
+#[test]
+fn test_get_all_directories_recursive() {
+    let (repo_path, _temp_dir) = setup_git_repo();
+    assert_eq!(get_tree_id("HEAD", &repo_path).unwrap(), None); // Unborn branch
+
+    create_and_commit_files(&repo_path);
+    let tree_id = get_tree_id("HEAD", &repo_path).unwrap().expect("HEAD should have a tree");
+    let mut all_dirs = get_all_directories_recursive(&tree_id, &repo_path).unwrap();
+    all_dirs.sort();
+    let expected_dirs: Vec<RepoPath> = vec![
+        "dir with spaces".into(),
+        "docs".into(),
+        "src".into(),
+        "src/components".into(),
+        "tests".into(),
+        "日本語ディレクトリ".into(),
+    ];
+    assert_eq!(all_dirs, expected_dirs);
+}
+
+#[test]
+fn test_get_sparse_checkout_list() {
+    let (repo_path, _temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path);
+
+    git(&repo_path, &["sparse-checkout", "init", "--cone"]);
+
+    git(&repo_path, &["sparse-checkout", "set", "src", "docs", "dir with spaces", "日本語ディレクトリ"]);
+    
+    let mut sparse_dirs = get_sparse_checkout_list(&repo_path).unwrap();
+    sparse_dirs.sort();
+    let expected_sparse_dirs: Vec<RepoPath> = vec![
+        "dir with spaces".into(),
+        "docs".into(),
+        "src".into(),
+        "日本語ディレクトリ".into(),
+    ];
+    assert_eq!(sparse_dirs, expected_sparse_dirs);
+}
+
+#[test]
+fn test_get_uncommitted_paths() {
+    let (repo_path, _temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path);
+
+    // No changes initially
+    let changes = get_uncommitted_paths(&repo_path).unwrap();
+    assert!(changes.is_empty());
+
+    // Create a new untracked file with spaces and Japanese
+    fs::write(repo_path.join("untracked file.txt"), "untracked").unwrap();
+    fs::write(repo_path.join("新規ファイル.txt"), "new file content").unwrap();
+
+    // Modify an existing file
+    fs::write(repo_path.join("src/main.rs"), "fn main() { /* changed */ }").unwrap();
+
+    let mut changes: Vec<(RepoPath, FileStatus)> = get_uncommitted_paths(&repo_path).unwrap().into_iter().collect();
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(changes, vec![("src/main.rs".into(), FileStatus(*b".M"))]);
+
+    // Untracked files are listed separately, for one directory or the whole repository
+    let mut untracked: Vec<(RepoPath, FileStatus)> =
+        get_untracked_paths(&RepoPath::root(), &repo_path).unwrap().into_iter().collect();
+    untracked.sort_by(|a, b| a.0.cmp(&b.0));
+    let expected_untracked: Vec<(RepoPath, FileStatus)> = vec![
+        ("untracked file.txt".into(), FileStatus::UNTRACKED),
+        ("新規ファイル.txt".into(), FileStatus::UNTRACKED),
+    ];
+    assert_eq!(untracked, expected_untracked);
+    fs::write(repo_path.join("src/new.rs"), "new").unwrap();
+    let untracked = get_untracked_paths(&"src".into(), &repo_path).unwrap();
+    assert_eq!(untracked.into_iter().collect::<Vec<_>>(), vec![("src/new.rs".into(), FileStatus::UNTRACKED)]);
+    assert!(get_untracked_paths(&"no such dir".into(), &repo_path).unwrap().is_empty());
 
-    #[test]
-    fn test_get_uncommitted_paths() {
-        let (repo_path, _temp_dir) = setup_git_repo();
-        create_and_commit_files(&repo_path);
-
-        // No changes initially
-        let changes = get_uncommitted_paths(&repo_path).unwrap();
-        assert!(changes.is_empty());
-
-        // Create a new untracked file with spaces and Japanese
-        fs::write(repo_path.join("untracked file.txt"), "untracked").unwrap();
-        fs::write(repo_path.join("新規ファイル.txt"), "new file content").unwrap();
-
-        // Modify an existing file
-        fs::write(repo_path.join("src/main.rs"), "fn main() { /* changed */ }").unwrap();
-
-        let mut changes: Vec<String> = get_uncommitted_paths(&repo_path).unwrap().into_iter().collect();
-        changes.sort();
-        let expected_changes = vec![
-            "src/main.rs".to_string(),
-            "untracked file.txt".to_string(),
-            "新規ファイル.txt".to_string(),
-        ];
-        assert_eq!(changes, expected_changes);
+    // Staged, ignored and conflicting changes are told apart
+    git(&repo_path, &["add", "src/main.rs"]);
+    fs::write(repo_path.join("src/main.rs"), "fn main() { /* changed again */ }").unwrap();
+    fs::write(repo_path.join(".git/info/exclude"), "*.log\n").unwrap();
+    fs::write(repo_path.join("debug.log"), "ignored").unwrap();
+    let changes = get_uncommitted_paths(&repo_path).unwrap();
+    assert_eq!(changes[&RepoPath::from("src/main.rs")], FileStatus(*b"MM"));
+    assert_eq!(changes[&RepoPath::from("src/main.rs")].kinds(), vec![ChangeKind::Staged, ChangeKind::Modified]);
+    assert!(!changes.contains_key(&RepoPath::from("debug.log")));
+    let untracked = get_untracked_paths(&RepoPath::root(), &repo_path).unwrap();
+    assert_eq!(untracked[&RepoPath::from("debug.log")], FileStatus::IGNORED);
+    assert!(!ChangeKind::Ignored.blocks_removal() && !ChangeKind::Untracked.blocks_removal());
+    assert_eq!(FileStatus(*b"UU").kinds(), vec![ChangeKind::Conflicted]);
+
+    let parsed = parse_porcelain_v2(
+        b"1 A. N... 000000 100644 100644 0000 1111 new file.txt\0\
+          2 R. N... 100644 100644 100644 1111 1111 R100 renamed.txt\0old.txt\0\
+          u UU N... 100644 100644 100644 100644 1111 2222 3333 both.txt\0\
+          ! build/\0",
+    )
+    .unwrap();
+    assert_eq!(parsed.len(), 4);
+    assert_eq!(parsed[&RepoPath::from("new file.txt")], FileStatus(*b"A."));
+    assert_eq!(parsed[&RepoPath::from("renamed.txt")], FileStatus(*b"R."));
+    assert_eq!(parsed[&RepoPath::from("both.txt")], FileStatus(*b"UU"));
+    assert_eq!(parsed[&RepoPath::from("build/")], FileStatus::IGNORED);
+}
+
+#[test]
+fn test_set_sparse_checkout_dirs() {
+    let (repo_path, _temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path);
+
+    git(&repo_path, &["sparse-checkout", "init", "--cone"]);
+
+    let dirs_to_set: Vec<RepoPath> = vec![
+        "src".into(),
+        "dir with spaces".into(),
+        "日本語ディレクトリ".into(),
+    ];
+    set_sparse_checkout_dirs(dirs_to_set.clone(), &repo_path).unwrap();
+
+    let mut sparse_list = get_sparse_checkout_list(&repo_path).unwrap();
+    sparse_list.sort();
+    let mut expected_sparse_list = dirs_to_set;
+    expected_sparse_list.sort();
+    assert_eq!(sparse_list, expected_sparse_list);
+
+    // Verify that the files actually exist (Git unquotes them internally)
+    assert!(repo_path.join("src/main.rs").exists());
+    assert!(repo_path.join("dir with spaces/file with spaces.txt").exists());
+    assert!(repo_path.join("日本語ディレクトリ/ファイル.txt").exists());
+    assert!(!repo_path.join("docs/README.md").exists()); // Should not exist
+}
+
+#[test]
+fn test_add_sparse_checkout_dirs() {
+    let (repo_path, _temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path);
+    set_sparse_checkout_dirs(vec!["src".into()], &repo_path).unwrap();
+
+    // More directories than fit on a command line, most of which do not exist
+    let mut dirs: Vec<RepoPath> = (0..20_000)
+        .map(|i| RepoPath::from(format!("{}/missing-{i:05}", "x".repeat(100))))
+        .collect();
+    dirs.push("docs".into());
+    add_sparse_checkout_dirs(dirs.clone(), &repo_path).unwrap();
+
+    let sparse_list = get_sparse_checkout_list(&repo_path).unwrap();
+    assert_eq!(sparse_list.len(), dirs.len() + 1);
+    assert!(sparse_list.contains(&"src".into()));
+    assert!(repo_path.join("src/main.rs").exists());
+    assert!(repo_path.join("docs/README.md").exists());
+    assert!(!repo_path.join("tests/test.rs").exists());
+}
+
+#[test]
+fn test_set_sparse_checkout_patterns() {
+    let (repo_path, _temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path);
+    assert_eq!(get_sparse_mode(&repo_path).unwrap(), SparseMode::Disabled);
+
+    let patterns: Vec<Vec<u8>> = ["/*", "!/*/", "/src/", "!/src/components/", "*.md"]
+        .iter()
+        .map(|line| line.as_bytes().to_vec())
+        .collect();
+    set_sparse_checkout_patterns(patterns.clone(), &repo_path).unwrap();
+
+    assert_eq!(get_sparse_mode(&repo_path).unwrap(), SparseMode::Pattern);
+    assert_eq!(get_sparse_checkout_patterns(&repo_path).unwrap(), patterns);
+    assert!(repo_path.join(".gitignore").exists());
+    assert!(repo_path.join("src/main.rs").exists());
+    assert!(!repo_path.join("src/components/mod.rs").exists());
+    assert!(repo_path.join("docs/README.md").exists());
+    assert!(!repo_path.join("tests/test.rs").exists());
+
+    git(&repo_path, &["sparse-checkout", "set", "--cone", "src"]);
+    assert_eq!(get_sparse_mode(&repo_path).unwrap(), SparseMode::Cone);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unusual_directory_names_round_trip() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let (repo_path, _temp_dir) = setup_git_repo();
+    create_and_commit_files(&repo_path);
+    let unusual: Vec<RepoPath> = vec![
+        RepoPath::from("new\nline"),
+        RepoPath::from(&b"latin1-\xe9t\xe9"[..]),
+        RepoPath::from("quote\"d"),
+    ];
+    for dir in &unusual {
+        let abs = repo_path.join(std::ffi::OsStr::from_bytes(dir.as_bytes()));
+        fs::create_dir_all(&abs).unwrap();
+        fs::write(abs.join("file.txt"), "content").unwrap();
     }
+    git(&repo_path, &["add", "."]);
+    git(&repo_path, &["commit", "-m", "Unusual names"]);
 
-    #[test]
-    fn test_set_sparse_checkout_dirs() {
-        let (repo_path, _temp_dir) = setup_git_repo();
-        create_and_commit_files(&repo_path);
-
-        Command::new("git")
-            .args(&["sparse-checkout", "init", "--cone"])
-            .current_dir(&repo_path)
-            .output()
-            .expect("git sparse-checkout init --cone failed");
-
-        let dirs_to_set = vec![
-            "src".to_string(),
-            "dir with spaces".to_string(),
-            "日本語ディレクトリ".to_string(),
-        ];
-        set_sparse_checkout_dirs(dirs_to_set.clone(), &repo_path).unwrap();
-
-        let mut sparse_list = get_sparse_checkout_list(&repo_path).unwrap();
-        sparse_list.sort();
-        let mut expected_sparse_list = dirs_to_set;
-        expected_sparse_list.sort();
-        assert_eq!(sparse_list, expected_sparse_list);
-
-        // Verify that the files actually exist (Git unquotes them internally)
-        assert!(repo_path.join("src/main.rs").exists());
-        assert!(repo_path.join("dir with spaces/file with spaces.txt").exists());
-        assert!(repo_path.join("日本語ディレクトリ/ファイル.txt").exists());
-        assert!(!repo_path.join("docs/README.md").exists()); // Should not exist
+    let root_dirs = get_dirs_at_path(&RepoPath::root(), "HEAD", &repo_path).unwrap();
+    for dir in &unusual {
+        assert!(root_dirs.contains(dir), "{dir:?} missing from {root_dirs:?}");
     }
-}
\ No newline at end of file
+
+    git(&repo_path, &["sparse-checkout", "init", "--cone"]);
+    set_sparse_checkout_dirs(vec![unusual[1].clone(), unusual[2].clone()], &repo_path).unwrap();
+    let mut sparse_list = get_sparse_checkout_list(&repo_path).unwrap();
+    sparse_list.sort();
+    let mut expected = vec![unusual[1].clone(), unusual[2].clone()];
+    expected.sort();
+    assert_eq!(sparse_list, expected);
+    assert!(repo_path.join(std::ffi::OsStr::from_bytes(unusual[1].as_bytes())).join("file.txt").exists());
+
+    fs::write(repo_path.join(std::ffi::OsStr::from_bytes(unusual[2].as_bytes())).join("file.txt"), "changed").unwrap();
+    let changes = get_uncommitted_paths(&repo_path).unwrap();
+    assert!(changes.contains_key(&RepoPath::from("quote\"d/file.txt")));
+}
+
+#[test]
+fn test_browse_other_revision() {
+    let (repo_path, _temp_dir) = setup_git_repo_with_subdirs();
+    git(&repo_path, &["branch", "-M", "main"]);
+    git(&repo_path, &["checkout", "-q", "-b", "release"]);
+    fs::create_dir_all(repo_path.join("release-only/nested")).unwrap();
+    fs::write(repo_path.join("release-only/nested/file.txt"), "content").unwrap();
+    git(&repo_path, &["add", "."]);
+    git(&repo_path, &["commit", "-q", "-m", "Release only"]);
+    git(&repo_path, &["tag", "-a", "v1.0", "-m", "Annotated"]);
+    git(&repo_path, &["checkout", "-q", "main"]);
+
+    let release_tree = get_tree_id("release", &repo_path).unwrap().expect("release should resolve");
+    assert_eq!(get_tree_id("v1.0", &repo_path).unwrap(), Some(release_tree.clone()));
+    assert_ne!(get_tree_id("HEAD", &repo_path).unwrap(), Some(release_tree));
+    assert_eq!(get_tree_id("no-such-branch", &repo_path).unwrap(), None);
+    assert_eq!(get_tree_id("--help", &repo_path).unwrap(), None);
+
+    let root_dirs = get_dirs_at_path(&RepoPath::root(), "release", &repo_path).unwrap();
+    assert!(root_dirs.contains(&"release-only".into()));
+    assert!(!get_dirs_at_path(&RepoPath::root(), "HEAD", &repo_path).unwrap().contains(&"release-only".into()));
+    // Not checked out in the working tree, so this takes the path that lists from the root
+    let nested_dirs = get_dirs_at_path(&"release-only".into(), "release", &repo_path).unwrap();
+    assert_eq!(nested_dirs, vec![RepoPath::from("nested")]);
+
+    let mut revisions = list_revisions(&repo_path).unwrap();
+    revisions.sort();
+    assert_eq!(revisions, vec!["main", "release", "v1.0"]);
+
+    let contents = get_file_contents("release", &"release-only/nested/file.txt".into(), &repo_path).unwrap();
+    assert_eq!(contents.as_deref(), Some(&b"content"[..]));
+    assert_eq!(get_file_contents("HEAD", &"release-only/nested/file.txt".into(), &repo_path).unwrap(), None);
+}
+
+#[test]
+fn test_command_listener_records_commands() {
+    let (repo_path, _dir) = setup_git_repo();
+    let records = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let recorded = records.clone();
+    let dir = repo_path.clone();
+    // Other tests run git at the same time, so keep only the commands run here
+    set_command_listener(move |record| {
+        if record.dir.as_ref() == Some(&dir) {
+            recorded.lock().unwrap().push(record);
+        }
+    });
+
+    run_git_command(&["status", "--short"], Some(&repo_path)).unwrap();
+    assert!(run_git_command(&["rev-parse", "--verify", "no-such-branch"], Some(&repo_path)).is_err());
+
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].args, ["status", "--short"]);
+    assert_eq!((records[0].status, records[0].stderr.as_str()), (Some(0), ""));
+    assert_eq!(records[1].status, Some(128));
+    assert!(records[1].stderr.starts_with("fatal: "));
+}
@@ -0,0 +1,86 @@
+//! Undo and redo of pending changes.
+//!
+//! Every user action that changes what is pending records the state from before it, so
+//! that a single undo reverts a whole bulk selection or profile load rather than one
+//! directory of it.
+
+use crate::app::ChangeType;
+use crate::repo_path::RepoPath;
+use std::collections::BTreeMap;
+
+/// How many steps are kept before the oldest are dropped.
+const MAX_STEPS: usize = 100;
+
+/// Everything that is pending, in both modes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PendingSnapshot {
+    pub dirs: BTreeMap<RepoPath, ChangeType>, // Cone mode
+    pub patterns: Option<Vec<Vec<u8>>>,       // Pattern mode
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UndoHistory<T> {
+    undo: Vec<T>,
+    redo: Vec<T>,
+}
+
+impl<T> UndoHistory<T> {
+    /// Records the state from before an action. Anything undone before is no longer
+    /// redoable.
+    pub fn record(&mut self, before: T) {
+        if self.undo.len() == MAX_STEPS {
+            self.undo.remove(0);
+        }
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    /// Returns the state to go back to, keeping `current` for a redo.
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let previous = self.undo.pop()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Returns the state an undo went back from, keeping `current` for another undo.
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let next = self.redo.pop()?;
+        self.undo.push(current);
+        Some(next)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo_order() {
+        let mut history = UndoHistory::default();
+        history.record(0);
+        history.record(1);
+        assert_eq!(history.undo(2), Some(1));
+        assert_eq!(history.undo(1), Some(0));
+        assert_eq!(history.undo(0), None);
+        assert_eq!(history.redo(0), Some(1));
+
+        // A new action drops what could still be redone
+        history.record(1);
+        assert_eq!(history.redo(5), None);
+        assert_eq!(history.undo(5), Some(1));
+
+        for step in 0..MAX_STEPS + 10 {
+            history.record(step);
+        }
+        let mut undone = 0;
+        while history.undo(0).is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_STEPS);
+    }
+}
@@ -0,0 +1,371 @@
+//! Gitignore-style pattern matching.
+//!
+//! The matcher follows git's `dir.c` and `wildmatch.c` so that results agree with
+//! `git ls-files --exclude-standard`.
+
+#[derive(Debug, PartialEq, Eq)]
+enum Wild {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToStarStar,
+}
+
+fn is_glob_special(c: u8) -> bool {
+    matches!(c, b'*' | b'?' | b'[' | b'\\')
+}
+
+/// Returns true if `pattern` matches all of `text`.
+///
+/// With `pathname`, wildcards other than `**` never match a `/`.
+pub fn wildmatch(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    dowild(pattern, text, pathname) == Wild::Match
+}
+
+fn dowild(pattern: &[u8], text: &[u8], pathname: bool) -> Wild {
+    // Reading past the end yields NUL, mirroring the C string handling this is ported from.
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    let mut p = 0;
+    let mut t = 0;
+
+    while at(pattern, p) != 0 {
+        let mut p_ch = at(pattern, p);
+        let mut t_ch = at(text, t);
+        if t_ch == 0 && p_ch != b'*' {
+            return Wild::AbortAll;
+        }
+        match p_ch {
+            b'?' => {
+                if pathname && t_ch == b'/' {
+                    return Wild::NoMatch;
+                }
+            }
+            b'*' => {
+                p += 1;
+                let match_slash;
+                if at(pattern, p) == b'*' {
+                    let star_start = p - 1;
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    let after = at(pattern, p);
+                    if (star_start == 0 || pattern[star_start - 1] == b'/')
+                        && (after == 0 || after == b'/' || (after == b'\\' && at(pattern, p + 1) == b'/'))
+                    {
+                        // "**/" may also match nothing at all: "a/**/b" matches "a/b".
+                        if after == b'/' && dowild(&pattern[p + 1..], &text[t..], pathname) == Wild::Match {
+                            return Wild::Match;
+                        }
+                        match_slash = true;
+                    } else {
+                        match_slash = false;
+                    }
+                } else {
+                    match_slash = !pathname;
+                }
+
+                if at(pattern, p) == 0 {
+                    // A trailing "**" matches everything; a trailing "*" only up to the next slash.
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Wild::NoMatch;
+                    }
+                    return Wild::Match;
+                } else if !match_slash && at(pattern, p) == b'/' {
+                    // A single "*" followed by a slash matches exactly one directory level.
+                    match text[t..].iter().position(|&b| b == b'/') {
+                        Some(slash) => {
+                            t += slash + 1;
+                            p += 1;
+                            continue;
+                        }
+                        None => return Wild::NoMatch,
+                    }
+                }
+
+                loop {
+                    if t_ch == 0 {
+                        break;
+                    }
+                    // Skip ahead to the next occurrence of a literal that follows the star.
+                    if !is_glob_special(at(pattern, p)) {
+                        let literal = at(pattern, p);
+                        loop {
+                            t_ch = at(text, t);
+                            if t_ch == 0 || (!match_slash && t_ch == b'/') || t_ch == literal {
+                                break;
+                            }
+                            t += 1;
+                        }
+                        if t_ch != literal {
+                            return Wild::NoMatch;
+                        }
+                    }
+                    let matched = dowild(&pattern[p..], &text[t..], pathname);
+                    if matched != Wild::NoMatch {
+                        if !match_slash || matched != Wild::AbortToStarStar {
+                            return matched;
+                        }
+                    } else if !match_slash && t_ch == b'/' {
+                        return Wild::AbortToStarStar;
+                    }
+                    t += 1;
+                    t_ch = at(text, t);
+                }
+                return Wild::AbortAll;
+            }
+            b'[' => {
+                p += 1;
+                p_ch = at(pattern, p);
+                if p_ch == b'^' {
+                    p_ch = b'!';
+                }
+                let negated = p_ch == b'!';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let mut prev_ch = 0u8;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Wild::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return Wild::AbortAll;
+                        }
+                        if t_ch == p_ch {
+                            matched = true;
+                        }
+                    } else if p_ch == b'-' && prev_ch != 0 && at(pattern, p + 1) != 0 && at(pattern, p + 1) != b']' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return Wild::AbortAll;
+                            }
+                        }
+                        if t_ch <= p_ch && t_ch >= prev_ch {
+                            matched = true;
+                        }
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let class_start = p + 2;
+                        let mut q = class_start;
+                        while at(pattern, q) != 0 && at(pattern, q) != b']' {
+                            q += 1;
+                        }
+                        if at(pattern, q) == 0 {
+                            return Wild::AbortAll;
+                        }
+                        if q == class_start || pattern[q - 1] != b':' {
+                            // No closing ":]", so treat the '[' as an ordinary member.
+                            if t_ch == b'[' {
+                                matched = true;
+                            }
+                            prev_ch = b'[';
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == b']' {
+                                break;
+                            }
+                            continue;
+                        }
+                        let class = &pattern[class_start..q - 1];
+                        let in_class = match class {
+                            b"alnum" => t_ch.is_ascii_alphanumeric(),
+                            b"alpha" => t_ch.is_ascii_alphabetic(),
+                            b"blank" => t_ch == b' ' || t_ch == b'\t',
+                            b"cntrl" => t_ch.is_ascii_control(),
+                            b"digit" => t_ch.is_ascii_digit(),
+                            b"graph" => t_ch.is_ascii_graphic(),
+                            b"lower" => t_ch.is_ascii_lowercase(),
+                            b"print" => t_ch.is_ascii_graphic() || t_ch == b' ',
+                            b"punct" => t_ch.is_ascii_punctuation(),
+                            b"space" => t_ch.is_ascii_whitespace() || t_ch == 0x0b,
+                            b"upper" => t_ch.is_ascii_uppercase(),
+                            b"xdigit" => t_ch.is_ascii_hexdigit(),
+                            _ => return Wild::AbortAll,
+                        };
+                        if in_class {
+                            matched = true;
+                        }
+                        p = q;
+                        p_ch = 0;
+                    } else if t_ch == p_ch {
+                        matched = true;
+                    }
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (pathname && t_ch == b'/') {
+                    return Wild::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                if t_ch != p_ch {
+                    return Wild::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+
+    if at(text, t) != 0 {
+        Wild::NoMatch
+    } else {
+        Wild::Match
+    }
+}
+
+/// A single line of a `.gitignore`-style file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// The pattern text, without the leading `!` or trailing `/`.
+    pub pattern: Vec<u8>,
+    /// Directory of the file the pattern came from, relative to the repository root,
+    /// with a trailing slash (empty for the root).
+    pub base: Vec<u8>,
+    pub negative: bool,
+    pub dir_only: bool,
+    /// Patterns without a slash match the basename at any depth.
+    pub basename_only: bool,
+}
+
+impl Pattern {
+    /// Parses one line, returning `None` for blank lines and comments.
+    pub fn parse(line: &[u8], base: &[u8]) -> Option<Self> {
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() || line[0] == b'#' {
+            return None;
+        }
+        // Trailing spaces are ignored unless escaped with a backslash.
+        while line.last() == Some(&b' ') && !line.ends_with(b"\\ ") {
+            line = &line[..line.len() - 1];
+        }
+
+        let negative = line[0] == b'!';
+        if negative {
+            line = &line[1..];
+        }
+        let dir_only = line.last() == Some(&b'/');
+        if dir_only {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            basename_only: !line.contains(&b'/'),
+            pattern: line.to_vec(),
+            base: base.to_vec(),
+            negative,
+            dir_only,
+        })
+    }
+
+    /// Parses every pattern in the contents of an ignore file.
+    pub fn parse_all(content: &[u8], base: &[u8]) -> Vec<Self> {
+        content
+            .split(|&b| b == b'\n')
+            .filter_map(|line| Self::parse(line, base))
+            .collect()
+    }
+
+    /// Tests `path` (relative to the repository root, without a trailing slash).
+    pub fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        // Patterns only apply below the directory of the file they were read from.
+        let Some(relative) = path.strip_prefix(self.base.as_slice()) else {
+            return false;
+        };
+        if self.basename_only {
+            let basename = relative.rsplit(|&b| b == b'/').next().unwrap_or(relative);
+            return wildmatch(&self.pattern, basename, false);
+        }
+        let pattern = self.pattern.strip_prefix(b"/").unwrap_or(&self.pattern);
+        wildmatch(pattern, relative, true)
+    }
+}
+
+/// An ordered list of patterns where the last matching pattern decides.
+#[derive(Debug, Clone, Default)]
+pub struct PatternList {
+    pub patterns: Vec<Pattern>,
+}
+
+impl PatternList {
+    pub fn extend(&mut self, patterns: Vec<Pattern>) {
+        self.patterns.extend(patterns);
+    }
+
+    /// Returns `Some(true)` if the path is excluded, `Some(false)` if a negated pattern
+    /// re-includes it, and `None` if no pattern applies.
+    pub fn is_excluded(&self, path: &[u8], is_dir: bool) -> Option<bool> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| p.matches(path, is_dir))
+            .map(|p| !p.negative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildmatch() {
+        assert!(wildmatch(b"foo", b"foo", true));
+        assert!(!wildmatch(b"foo", b"bar", true));
+        assert!(wildmatch(b"*.txt", b"a.txt", true));
+        assert!(!wildmatch(b"*.txt", b"dir/a.txt", true));
+        assert!(wildmatch(b"*.txt", b"dir/a.txt", false));
+        assert!(wildmatch(b"**/a.txt", b"a.txt", true));
+        assert!(wildmatch(b"**/a.txt", b"x/y/a.txt", true));
+        assert!(wildmatch(b"a/**/b", b"a/b", true));
+        assert!(wildmatch(b"a/**/b", b"a/x/y/b", true));
+        assert!(wildmatch(b"a/**", b"a/x/y", true));
+        assert!(wildmatch(b"a/*/c", b"a/b/c", true));
+        assert!(!wildmatch(b"a/*/c", b"a/b/x/c", true));
+        assert!(wildmatch(b"?at", b"cat", true));
+        assert!(wildmatch(b"[a-c]at", b"bat", true));
+        assert!(!wildmatch(b"[!a-c]at", b"bat", true));
+        assert!(wildmatch(b"[[:digit:]]x", b"7x", true));
+        assert!(wildmatch(b"\\*", b"*", true));
+        assert!(!wildmatch(b"\\*", b"x", true));
+    }
+
+    #[test]
+    fn test_pattern_list_last_match_wins() {
+        let mut list = PatternList::default();
+        list.extend(Pattern::parse_all(b"# comment\n*.log\n!keep.log\nbuild/\n/root-only\n", b""));
+        list.extend(Pattern::parse_all(b"local\n", b"sub/"));
+
+        assert_eq!(list.is_excluded(b"x.log", false), Some(true));
+        assert_eq!(list.is_excluded(b"deep/x.log", false), Some(true));
+        assert_eq!(list.is_excluded(b"keep.log", false), Some(false));
+        assert_eq!(list.is_excluded(b"build", true), Some(true));
+        assert_eq!(list.is_excluded(b"build", false), None);
+        assert_eq!(list.is_excluded(b"root-only", false), Some(true));
+        assert_eq!(list.is_excluded(b"sub/root-only", false), None);
+        assert_eq!(list.is_excluded(b"sub/local", false), Some(true));
+        assert_eq!(list.is_excluded(b"local", false), None);
+    }
+}
@@ -0,0 +1,521 @@
+//! The actions keys trigger on the main screen, and which keys trigger them.
+//!
+//! Every action has default keys, which the `[keys]` table of the config file replaces:
+//!
+//! ```toml
+//! [keys]
+//! toggle = "x"
+//! quit = ["q", "ctrl-c"]
+//! invert = []
+//! ```
+//!
+//! The same key can mean different actions in the tree, in visual mode, in the list of
+//! uncommitted files or the message log, and in each popup. Where text is typed (the search
+//! bar, the revision picker and a line being edited), plain characters are typed rather
+//! than looked up.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// Where the keys go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Tree,
+    Visual,
+    List, // The grid's list of uncommitted files or the message log has the focus
+    Help,
+    Search, // Typing a search query
+    Revisions, // The revision picker
+    Patterns, // The pattern editor, between edits
+    Profiles,
+    Dialog, // The apply confirmation and the sparse-checkout and force-remove dialogs
+    Input, // Typing a pattern line or a profile name
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    Quit,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    Expand,
+    Collapse,
+    Toggle,
+    Focus,
+    Cancel, // Clears the search, leaves visual mode or returns to the tree
+    SelectSubtree,
+    SelectChildren,
+    Invert,
+    AddMatches,
+    RangeAdd,
+    RangeRemove,
+    RangeClear,
+    Apply,
+    Undo,
+    Redo,
+    Refresh,
+    Revert,
+    Revision,
+    Patterns,
+    Sizes,
+    Init,
+    Disable,
+    ForceRemove,
+    Profiles,
+    Visual,
+    Search,
+    NextMatch,
+    PrevMatch,
+    Help,
+    Log,
+    Confirm,
+    Yes,
+    No,
+    SparseIndex,
+    Filter,
+    Edit,
+    NewLine,
+    Delete,
+    Negate,
+    Save,
+    Diff,
+}
+
+use Mode::{Dialog, Help, Input, List, Patterns, Profiles, Revisions, Search, Tree, Visual};
+
+impl Action {
+    pub const ALL: [Action; 48] = [
+        Action::Quit,
+        Action::Up,
+        Action::Down,
+        Action::PageUp,
+        Action::PageDown,
+        Action::Top,
+        Action::Bottom,
+        Action::Expand,
+        Action::Collapse,
+        Action::Toggle,
+        Action::Focus,
+        Action::Cancel,
+        Action::SelectSubtree,
+        Action::SelectChildren,
+        Action::Invert,
+        Action::AddMatches,
+        Action::RangeAdd,
+        Action::RangeRemove,
+        Action::RangeClear,
+        Action::Apply,
+        Action::Undo,
+        Action::Redo,
+        Action::Refresh,
+        Action::Revert,
+        Action::Revision,
+        Action::Patterns,
+        Action::Sizes,
+        Action::Init,
+        Action::Disable,
+        Action::ForceRemove,
+        Action::Profiles,
+        Action::Visual,
+        Action::Search,
+        Action::NextMatch,
+        Action::PrevMatch,
+        Action::Help,
+        Action::Log,
+        Action::Confirm,
+        Action::Yes,
+        Action::No,
+        Action::SparseIndex,
+        Action::Filter,
+        Action::Edit,
+        Action::NewLine,
+        Action::Delete,
+        Action::Negate,
+        Action::Save,
+        Action::Diff,
+    ];
+
+    /// The action's name in the `[keys]` table, the modes it works in, its default keys and
+    /// what it does, as the help lists it.
+    fn spec(self) -> (&'static str, &'static [Mode], &'static [&'static str], &'static str) {
+        match self {
+            Action::Quit => ("quit", &[Tree, Visual, List, Help], &["q"], "Quit pickit"),
+            Action::Up => ("up", &[Tree, Visual, List, Help, Search, Revisions, Patterns, Profiles], &["up"], "Move up a line"),
+            Action::Down => ("down", &[Tree, Visual, List, Help, Search, Revisions, Patterns, Profiles], &["down"], "Move down a line"),
+            Action::PageUp => ("page_up", &[Tree, Visual, List, Help], &["pageup"], "Move up a page"),
+            Action::PageDown => ("page_down", &[Tree, Visual, List, Help], &["pagedown"], "Move down a page"),
+            Action::Top => ("top", &[List, Help], &["home"], "Go to the first line"),
+            Action::Bottom => ("bottom", &[List, Help], &["end"], "Go to the last line"),
+            Action::Expand => ("expand", &[Tree, Visual], &["right"], "Expand the directory"),
+            Action::Collapse => ("collapse", &[Tree], &["left"], "Collapse the directory, or go to its parent"),
+            Action::Toggle => ("toggle", &[Tree, Visual], &["space"], "Toggle the directory, or the range"),
+            Action::Focus => ("focus", &[Tree, List], &["tab", "backtab"], "Switch between the tree, the files and the log"),
+            Action::Cancel => (
+                "cancel",
+                &[Tree, Visual, List, Help, Search, Revisions, Patterns, Profiles, Dialog, Input],
+                &["esc"],
+                "Leave the search, visual mode, the files or the log",
+            ),
+            Action::SelectSubtree => ("select_subtree", &[Tree], &["A"], "Check out the visible subtree"),
+            Action::SelectChildren => ("select_children", &[Tree], &["c"], "Check out every child of the directory"),
+            Action::Invert => ("invert", &[Tree], &["x"], "Toggle the visible subtree"),
+            Action::AddMatches => ("add_matches", &[Tree], &["+"], "Check out every search match"),
+            Action::RangeAdd => ("range_add", &[Visual], &["+"], "Check out the range"),
+            Action::RangeRemove => ("range_remove", &[Visual], &["-"], "Stop checking out the range"),
+            Action::RangeClear => ("range_clear", &[Visual], &["c"], "Clear the pending changes of the range"),
+            Action::Apply => ("apply", &[Tree], &["a"], "Preview the pending changes and apply them"),
+            Action::Undo => ("undo", &[Tree], &["u"], "Undo the last change"),
+            Action::Redo => ("redo", &[Tree], &["ctrl-r"], "Redo the last undone change"),
+            Action::Refresh => ("refresh", &[Tree], &["r"], "Read the repository again"),
+            Action::Revert => ("revert", &[Tree], &["U"], "Go back to the set before the last apply"),
+            Action::Revision => ("revision", &[Tree], &["b"], "Browse another revision"),
+            Action::Patterns => ("patterns", &[Tree, Patterns], &["p"], "Edit the sparse-checkout patterns"),
+            Action::Sizes => ("sizes", &[Tree], &["s"], "Show file counts and sizes in the tree"),
+            Action::Init => ("init", &[Tree], &["i"], "Initialize sparse-checkout"),
+            Action::Disable => ("disable", &[Tree], &["D"], "Disable sparse-checkout"),
+            Action::ForceRemove => ("force_remove", &[Tree], &["F"], "Remove a locked directory after confirming"),
+            Action::Profiles => ("profiles", &[Tree, Profiles], &["P"], "Load, save and compare profiles"),
+            Action::Visual => ("visual", &[Tree, Visual], &["v"], "Start or leave visual mode"),
+            Action::Search => ("search", &[Tree], &["/"], "Search every directory"),
+            Action::NextMatch => ("next_match", &[Tree], &["n"], "Go to the next search match"),
+            Action::PrevMatch => ("prev_match", &[Tree], &["N"], "Go to the previous search match"),
+            Action::Help => ("help", &[Tree, Visual, List, Help], &["?"], "Show this help"),
+            Action::Log => ("log", &[Tree, Visual, List], &["L"], "Show or hide the message log"),
+            Action::Confirm => ("confirm", &[Search, Revisions, Patterns, Profiles, Dialog, Input], &["enter"], "Go ahead"),
+            Action::Yes => ("yes", &[Dialog], &["y"], "Apply the changes, disable sparse-checkout or force-remove"),
+            Action::No => ("no", &[Dialog], &["n"], "Close the dialog"),
+            Action::SparseIndex => ("sparse_index", &[Dialog], &["s"], "Use a sparse index when initializing"),
+            Action::Filter => ("filter", &[Search], &["tab"], "Show only the matches and their parents"),
+            Action::Edit => ("edit", &[Patterns], &["e"], "Edit the selected line"),
+            Action::NewLine => ("new_line", &[Patterns], &["n"], "Add a line below the selected one"),
+            Action::Delete => ("delete", &[Patterns], &["d"], "Delete the selected line"),
+            Action::Negate => ("negate", &[Patterns], &["!"], "Toggle the `!` of the selected line"),
+            Action::Save => ("save", &[Profiles], &["s"], "Save the checked-out directories as a profile"),
+            Action::Diff => ("diff", &[Profiles], &["d"], "Compare the selected profile with another one"),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.spec().0
+    }
+
+    pub fn modes(self) -> &'static [Mode] {
+        self.spec().1
+    }
+
+    /// What the action does in `mode`, as the help lists it.
+    pub fn description(self, mode: Mode) -> &'static str {
+        match (self, mode) {
+            (Action::Quit | Action::Cancel | Action::Help, Help) => "Close the help",
+            (Action::Up, Search) => "Go to the previous match",
+            (Action::Down, Search) => "Go to the next match",
+            (Action::Up | Action::Down, Revisions | Patterns | Profiles) => "Select the previous or next line",
+            (Action::Cancel, Search) => "Clear the search",
+            (Action::Cancel | Action::Patterns | Action::Profiles, Revisions | Patterns | Profiles) => "Close the popup, or go back",
+            (Action::Cancel, Dialog) => "Close the dialog",
+            (Action::Cancel, Input) => "Stop typing, dropping the text",
+            (Action::Confirm, Search) => "Stop typing and keep the matches",
+            (Action::Confirm, Revisions) => "Browse the selected revision",
+            (Action::Confirm, Patterns) => "Edit the selected line",
+            (Action::Confirm, Profiles) => "Load the selected profile",
+            (Action::Confirm, Dialog) => "Apply the changes, or initialize sparse-checkout",
+            (Action::Confirm, Input) => "Keep the typed text",
+            _ => self.spec().3,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+/// A key with the modifiers that matter for it, written like `ctrl-r`, `A` or `pagedown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // Shift is part of the character, and of `BackTab`, rather than a modifier of its own
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers & !KeyModifiers::SHIFT,
+            _ => modifiers,
+        } & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => KeyCode::Char(c.to_ascii_lowercase()),
+            _ => code,
+        };
+        Key { code, modifiers }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Key::new(event.code, event.modifiers)
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        // A trailing `-` is the key itself, as in `ctrl--`
+        while let Some((prefix, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match prefix.to_ascii_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => break,
+            };
+            rest = key;
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "esc" | "escape" => KeyCode::Esc,
+                "enter" | "return" => KeyCode::Enter,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" | "ins" => KeyCode::Insert,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "pageup" | "pgup" => KeyCode::PageUp,
+                "pagedown" | "pgdn" => KeyCode::PageDown,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=24) => KeyCode::F(n),
+                    _ => return Err(format!("Unknown key: {text}")),
+                },
+            },
+        };
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::Char(c.to_ascii_uppercase()),
+            _ => code,
+        };
+        Ok(Key::new(code, modifiers))
+    }
+}
+
+/// How footers and help show the key, like `^R`, `Space` or `PgDn`.
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            match self.code {
+                KeyCode::Char(c) => return write!(f, "^{}", c.to_ascii_uppercase()),
+                _ => write!(f, "Ctrl-")?,
+            }
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            KeyCode::Delete => write!(f, "Del"),
+            KeyCode::Insert => write!(f, "Ins"),
+            KeyCode::Home => write!(f, "Home"),
+            KeyCode::End => write!(f, "End"),
+            KeyCode::Tab => write!(f, "Tab"),
+            KeyCode::BackTab => write!(f, "BackTab"),
+            KeyCode::Esc => write!(f, "Esc"),
+            KeyCode::Enter => write!(f, "Enter"),
+            KeyCode::Backspace => write!(f, "Backspace"),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// Which keys trigger which action in each mode.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    keys: HashMap<Action, Vec<Key>>,
+    actions: HashMap<(Mode, Key), Action>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let mut keymap = KeyMap { keys: HashMap::new(), actions: HashMap::new() };
+        for action in Action::ALL {
+            let keys = action.spec().2.iter().map(|key| key.parse().expect("default keys parse")).collect();
+            keymap.bind(action, keys);
+        }
+        keymap
+    }
+}
+
+impl KeyMap {
+    /// The action `event` triggers in `mode`.
+    pub fn action(&self, mode: Mode, event: KeyEvent) -> Option<Action> {
+        self.actions.get(&(mode, Key::from(event))).copied()
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.keys.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// The first key of `action` in brackets, like `[F]`, for messages that tell the user
+    /// what to press; the action's name when nothing is bound to it.
+    pub fn hint(&self, action: Action) -> String {
+        match self.keys(action).first() {
+            Some(key) => format!("[{key}]"),
+            None => format!("`{}`", action.name()),
+        }
+    }
+
+    /// Binds the keys of a `[keys]` table, by action name. An action listed there loses
+    /// its previous keys, and takes its new ones from any other action in the same mode;
+    /// two actions of the same table cannot share a key in a mode.
+    pub fn rebind(&mut self, table: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        let mut rebound: HashMap<(Mode, Key), Action> = HashMap::new();
+        let mut bindings = Vec::new();
+        for (name, texts) in table {
+            let action = Action::from_name(name).ok_or_else(|| format!("Unknown action: {name}"))?;
+            let keys = texts.iter().map(|text| text.parse()).collect::<Result<Vec<Key>, _>>()?;
+            for &key in &keys {
+                for &mode in action.modes() {
+                    if let Some(other) = rebound.insert((mode, key), action).filter(|&other| other != action) {
+                        return Err(format!("{key} is bound to both {} and {}", other.name(), action.name()));
+                    }
+                }
+            }
+            bindings.push((action, keys));
+        }
+        let mut keymap = self.clone();
+        for (action, _) in &bindings {
+            keymap.unbind(*action);
+        }
+        for (action, keys) in bindings {
+            keymap.bind(action, keys);
+        }
+        // Without these there would be no way out of pickit, or out of visual mode and the lists
+        for action in [Action::Quit, Action::Cancel] {
+            for &mode in action.modes() {
+                if !keymap.actions.iter().any(|(&(bound_mode, _), &bound)| bound_mode == mode && bound == action) {
+                    return Err(format!("{} would have no key left in {mode:?} mode", action.name()));
+                }
+            }
+        }
+        *self = keymap;
+        Ok(())
+    }
+
+    fn unbind(&mut self, action: Action) {
+        self.keys.remove(&action);
+        self.actions.retain(|_, bound| *bound != action);
+    }
+
+    /// Binds `keys` to `action` in each of its modes, taking them from other actions.
+    fn bind(&mut self, action: Action, keys: Vec<Key>) {
+        for &key in &keys {
+            for &mode in action.modes() {
+                if let Some(other) = self.actions.insert((mode, key), action).filter(|&other| other != action) {
+                    let other_still_bound = other.modes().iter().any(|&mode| self.actions.get(&(mode, key)) == Some(&other));
+                    if !other_still_bound {
+                        self.keys.entry(other).or_default().retain(|&k| k != key);
+                    }
+                }
+            }
+        }
+        self.keys.insert(action, keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(keymap: &KeyMap, mode: Mode, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        keymap.action(mode, KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn test_parse_and_display_keys() {
+        for (text, shown) in [
+            ("q", "q"),
+            ("A", "A"),
+            ("shift-a", "A"),
+            ("ctrl-r", "^R"),
+            ("Ctrl-R", "^R"),
+            ("space", "Space"),
+            ("shift-tab", "BackTab"),
+            ("pgdn", "PgDn"),
+            ("alt-x", "Alt-x"),
+            ("ctrl-up", "Ctrl-↑"),
+            ("-", "-"),
+            ("ctrl--", "^-"),
+            ("f5", "F5"),
+        ] {
+            assert_eq!(text.parse::<Key>().unwrap().to_string(), shown, "{text}");
+        }
+        assert!("hyper-x".parse::<Key>().is_err());
+        assert!("f99".parse::<Key>().is_err());
+
+        // Terminals report shifted letters with the modifier
+        let keymap = KeyMap::default();
+        assert_eq!(press(&keymap, Tree, KeyCode::Char('A'), KeyModifiers::SHIFT), Some(Action::SelectSubtree));
+        assert_eq!(press(&keymap, Tree, KeyCode::BackTab, KeyModifiers::SHIFT), Some(Action::Focus));
+        assert_eq!(press(&keymap, Tree, KeyCode::Char('r'), KeyModifiers::CONTROL), Some(Action::Redo));
+        assert_eq!(press(&keymap, Tree, KeyCode::Char('a'), KeyModifiers::CONTROL), None);
+    }
+
+    #[test]
+    fn test_modes_and_rebinding() {
+        let mut keymap = KeyMap::default();
+        assert_eq!(press(&keymap, Tree, KeyCode::Char('c'), KeyModifiers::NONE), Some(Action::SelectChildren));
+        assert_eq!(press(&keymap, Visual, KeyCode::Char('c'), KeyModifiers::NONE), Some(Action::RangeClear));
+        assert_eq!(press(&keymap, List, KeyCode::Char('c'), KeyModifiers::NONE), None);
+        assert_eq!(press(&keymap, Dialog, KeyCode::Char('n'), KeyModifiers::NONE), Some(Action::No));
+        assert_eq!(press(&keymap, Patterns, KeyCode::Char('n'), KeyModifiers::NONE), Some(Action::NewLine));
+        assert_eq!(press(&keymap, Help, KeyCode::Char('q'), KeyModifiers::NONE), Some(Action::Quit));
+
+        // `x` moves from invert to toggle, which keeps nothing of `space`
+        let table = BTreeMap::from([("toggle".to_string(), vec!["x".to_string()])]);
+        keymap.rebind(&table).unwrap();
+        assert_eq!(press(&keymap, Tree, KeyCode::Char('x'), KeyModifiers::NONE), Some(Action::Toggle));
+        assert_eq!(press(&keymap, Visual, KeyCode::Char('x'), KeyModifiers::NONE), Some(Action::Toggle));
+        assert_eq!(press(&keymap, Tree, KeyCode::Char(' '), KeyModifiers::NONE), None);
+        assert!(keymap.keys(Action::Invert).is_empty());
+        assert_eq!(keymap.hint(Action::Invert), "`invert`");
+        assert_eq!(keymap.hint(Action::Toggle), "[x]");
+
+        let clash = BTreeMap::from([("apply".to_string(), vec!["y".to_string()]), ("undo".to_string(), vec!["y".to_string()])]);
+        assert_eq!(keymap.rebind(&clash), Err("y is bound to both apply and undo".to_string()));
+        let unknown = BTreeMap::from([("launch".to_string(), vec![])]);
+        assert_eq!(keymap.rebind(&unknown), Err("Unknown action: launch".to_string()));
+
+        // Quit and cancel keep a key in every mode, and a rejected table changes nothing
+        let steal_quit = BTreeMap::from([("toggle".to_string(), vec!["q".to_string()])]);
+        assert_eq!(keymap.rebind(&steal_quit), Err("quit would have no key left in Tree mode".to_string()));
+        let unbind_cancel = BTreeMap::from([("cancel".to_string(), vec![])]);
+        assert_eq!(keymap.rebind(&unbind_cancel), Err("cancel would have no key left in Tree mode".to_string()));
+        assert_eq!(press(&keymap, Visual, KeyCode::Char('q'), KeyModifiers::NONE), Some(Action::Quit));
+        assert_eq!(keymap.hint(Action::Toggle), "[x]");
+    }
+}
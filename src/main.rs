@@ -1,17 +1,13 @@
 use clap::Parser;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
-use ratatui::layout::{Alignment, Rect};
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
-    text::Line,
-    widgets::{Block, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table},
+    widgets::ListState,
     Terminal,
 };
 use std::{
@@ -24,7 +20,26 @@ use std::{
 };
 
 mod app;
+mod apply_preview;
+mod backend;
+mod cli;
+mod config;
+mod dir_index;
+mod dir_stats;
+#[cfg(test)]
+mod fake_backend;
+mod fuzzy;
 mod git;
+mod history;
+mod ignore;
+mod keymap;
+mod message_log;
+mod native;
+mod profiles;
+mod repo_path;
+mod sparse_patterns;
+mod tui;
+mod watcher;
 
 /// A TUI for git sparse-checkout.
 #[derive(Parser, Debug)]
@@ -33,6 +48,26 @@ struct Cli {
     /// The path to the git repository.
     #[arg()]
     path: Option<PathBuf>,
+
+    /// How to query the repository.
+    #[arg(long, value_enum, default_value_t)]
+    backend: backend::BackendKind,
+
+    /// The commit-ish whose directories are browsed.
+    #[arg(long, value_name = "COMMIT-ISH", default_value = "HEAD", global = true)]
+    rev: String,
+
+    /// Do not refresh when git changes the repository outside pickit.
+    #[arg(long)]
+    no_watch: bool,
+
+    /// Print the output of subcommands as JSON.
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Run a single command without the TUI.
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 // Event types for main loop
@@ -51,15 +86,27 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 
 
+    if let Some(command) = &cli.command {
+        let mut app = app::App::new(cli.path.as_ref(), cli.backend.create(), cli.rev.clone())?;
+        let completed = cli::run(command, &mut app, cli.json, &mut io::stdout())?;
+        std::process::exit(if completed { 0 } else { 1 });
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = std::io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
+    // Record the git commands from the start, including those of loading the repository
+    let (records_tx, records) = mpsc::channel();
+    git::set_command_listener(move |record| {
+        let _ = records_tx.send(record);
+    });
+
     // Create app and run it
-    let mut app = match app::App::new(cli.path.as_ref()) {
+    let mut app = match app::App::new(cli.path.as_ref(), cli.backend.create(), cli.rev) {
         Ok(app) => app,
         Err(e) => {
             eprintln!("Error initializing application: {}", e);
@@ -68,6 +115,36 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     };
 
+    match config::Config::load(&app.current_repo_root) {
+        Ok(config) => app.config = config,
+        Err(e) => app.report(message_log::Level::Error, format!("{e}. Using the default settings")),
+    }
+
+    let tx = app.tx.clone();
+    std::thread::spawn(move || {
+        for record in records {
+            if tx.send(app::AppMessage::GitCommandFinished(record)).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Kept until the app exits, as dropping it stops watching
+    let _watcher = if cli.no_watch {
+        None
+    } else {
+        match watcher::watch(&app.current_repo_root, app.tx.clone()) {
+            Ok(watcher) => {
+                app.watched_paths = Some(watcher.paths().clone());
+                Some(watcher)
+            }
+            Err(e) => {
+                app.report(message_log::Level::Warn, format!("Not refreshing automatically: {e}"));
+                None
+            }
+        }
+    };
+
     let res = run_app(&mut terminal, &mut app);
 
     // Restore terminal
@@ -84,7 +161,7 @@ fn restore_terminal(
     terminal: &mut Terminal<CrosstermBackend<Stdout>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
     terminal.show_cursor()?;
     Ok(())
 }
@@ -99,7 +176,6 @@ fn run_app(
         let event = if event::poll(Duration::from_millis(10))? {
             Some(InputEvent::Input(event::read()?))
         } else {
-            // Always try to receive from app.rx first, then file_change_receiver
             match app.rx.try_recv() {
                 Ok(app_msg) => Some(InputEvent::App(app_msg)),
                 Err(mpsc::TryRecvError::Disconnected) => {
@@ -115,195 +191,17 @@ fn run_app(
         // Process keyboard input if any
         if let Some(input_event) = event {
             match input_event {
-
-                InputEvent::Input(Event::Key(key)) => {
-                    if key.kind == KeyEventKind::Press {
-                        // Clear error on any key press
-                        app.last_git_error = None;
-
-                        // Normal application key handling
-                        match key.code {
-                            KeyCode::Char('q') => return Ok(()),
-                            KeyCode::Up => app.move_cursor_up(),
-                            KeyCode::Down => app.move_cursor_down(),
-                            KeyCode::PageUp => {
-                                let tree_view_height =
-                                    terminal.size()?.height.saturating_sub(3).saturating_sub(2);
-                                app.move_cursor_page_up(tree_view_height);
-                            }
-                            KeyCode::PageDown => {
-                                let tree_view_height =
-                                    terminal.size()?.height.saturating_sub(3).saturating_sub(2);
-                                app.move_cursor_page_down(tree_view_height);
-                            }
-                            KeyCode::Right => {
-                                app.expand_selected_item();
-                            }
-                            KeyCode::Left => {
-                                app.handle_left_key();
-                            }
-                            KeyCode::Char(' ') => app.toggle_selection(),
-                            KeyCode::Char('a') => {
-                                app.is_applying_changes = true; // Set flag to show loading dialog
-                                app.apply_changes(); // Directly call apply_changes
-                            }
-                            KeyCode::Char('r') => { // New 'r' key handling
-                                app.is_refreshing = true;
-                                app.refresh();
-                            }
-                            _ => {}
-                        }
+                InputEvent::Input(event) => {
+                    let screen_height = terminal.size()?.height;
+                    if tui::handle_event(app, event, screen_height) == tui::Flow::Quit {
+                        return Ok(());
                     }
                 }
-                InputEvent::App(app_msg) => {
-                    match app_msg {
-                        app::AppMessage::ApplyChangesCompleted(result) => {
-                            app.is_applying_changes = false; // Reset the flag
-                            match result {
-                                Ok(_) => {
-                                    // Clear pending changes on all items (this was moved from App::apply_changes)
-                                    for item in app.items.iter_mut() {
-                                        item.pending_change = None;
-                                    }
-                                    app.refresh(); // Now asynchronous
-                                }
-                                Err(e) => {
-                                    app.last_git_error = Some(e.to_string());
-                                }
-                            }
-                        }
-                        app::AppMessage::ChildrenLoaded(result) => {
-                            app.handle_children_loaded(result);
-                        }
-                        app::AppMessage::RefreshCompleted(result) => {
-                            app.handle_refresh_completed(result);
-                        }
-
-                    }
-                }
-                _ => {} // Other events like mouse, resize etc.
+                InputEvent::App(app_msg) => app.handle_message(app_msg),
             }
         }
 
-        terminal.draw(|f| {
-            if app.is_applying_changes {
-                // Render a loading screen
-                let size = f.area();
-                let block = Block::default()
-                    .title("Applying Changes")
-                    .borders(Borders::ALL);
-                let paragraph = Paragraph::new("Applying changes... Please wait.")
-                    .style(Style::default().fg(Color::White).bg(Color::Black))
-                    .alignment(Alignment::Center)
-                    .block(block);
-
-                let area = Rect::new(
-                    size.width / 4,
-                    size.height / 3,
-                    size.width / 2,
-                    size.height / 6,
-                );
-                f.render_widget(paragraph, area);
-            } else if app.is_refreshing {
-                // Render refresh loading dialog
-                let size = f.area();
-                let block = Block::default()
-                    .title("Refreshing")
-                    .borders(Borders::ALL);
-                let paragraph = Paragraph::new("Refreshing application state... Please wait.")
-                    .style(Style::default().fg(Color::White).bg(Color::Black))
-                    .alignment(Alignment::Center)
-                    .block(block);
-
-                let area = Rect::new(
-                    size.width / 4,
-                    size.height / 3,
-                    size.width / 2,
-                    size.height / 6,
-                );
-                f.render_widget(paragraph, area);
-            } else {
-                // Render the main TUI
-                let size = f.area();
-
-                // Define main layout (main_area + footer)
-                let chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints([Constraint::Min(0), Constraint::Length(3)])
-                    .split(size);
-
-                let main_area = chunks[0];
-                let footer_area = chunks[1];
-
-                // Split main_area into tree (left) and grid (right)
-                let main_chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-                    .split(main_area);
-
-                let tree_area = main_chunks[0];
-                let grid_area = main_chunks[1];
-
-                // --- Tree View ---
-                let tree_items_vm = app.get_tui_tree_items();
-                let tree_items: Vec<ListItem> = tree_items_vm
-                    .into_iter()
-                    .map(|vm| ListItem::new(Line::from(vm.display_text)).style(vm.style))
-                    .collect();
-
-                list_state.select(Some(app.selected_item_index));
-
-                let tree_list = List::new(tree_items)
-                    .block(Block::default().borders(Borders::ALL).title(" Tree View "));
-
-                f.render_stateful_widget(tree_list, tree_area, &mut list_state);
-
-                // --- Grid View ---
-                let grid_title = " Grid View ";
-                if let Some(grid_vm) = app.get_grid_view_model() {
-                    let rows = vec![
-                        Row::new(vec![Cell::new("Name"), Cell::new(grid_vm.name)]),
-                        Row::new(vec![Cell::new("Path"), Cell::new(grid_vm.path)]),
-                        Row::new(vec![Cell::new("Status"), Cell::new(grid_vm.status)]),
-                        Row::new(vec![
-                            Cell::new("Uncommitted"),
-                            Cell::new(grid_vm.uncommitted),
-                        ]),
-                        Row::new(vec![
-                            Cell::new("Subdirectories (Total)"),
-                            Cell::new(grid_vm.subdirectories_total),
-                        ]),
-                        Row::new(vec![
-                            Cell::new("Subdirectories (Checked Out)"),
-                            Cell::new(grid_vm.subdirectories_checked_out),
-                        ]),
-                        Row::new(vec![
-                            Cell::new("Pending Changes"),
-                            Cell::new(grid_vm.pending_changes),
-                        ]),
-                    ];
-
-                    let table = Table::new(
-                        rows,
-                        &[Constraint::Percentage(50), Constraint::Percentage(50)],
-                    )
-                    .block(Block::default().borders(Borders::ALL).title(grid_title));
-                    f.render_widget(table, grid_area);
-                } else {
-                    let grid_block = Block::default().borders(Borders::ALL).title(grid_title);
-                    f.render_widget(grid_block, grid_area);
-                }
-
-                // --- Footer ---
-                let footer_text = if let Some(err) = &app.last_git_error {
-                    err.clone()
-                } else {
-                    " [q] Quit [Space] Toggle [a] Apply [r] Refresh [↑/↓] Navigate [→] Expand [←] Coll/Parent [PgUp/Dn] Scroll "
-                        .to_string()
-                };
-                let footer_block = Block::default().borders(Borders::ALL).title(footer_text);
-                f.render_widget(footer_block, footer_area);
-            }
-        })?; // Correctly closes the terminal.draw call
+        app.load_selected_untracked(); // However the selection moved
+        terminal.draw(|f| tui::draw(f, app, &mut list_state))?;
     }
 }
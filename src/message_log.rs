@@ -0,0 +1,153 @@
+//! What pickit did and what went wrong during the session: every git command with its exit
+//! status and stderr, and the errors and warnings the footer showed. The footer only holds a
+//! message until the next key, the log keeps it.
+
+use crate::git::CommandRecord;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many entries are kept before the oldest are dropped.
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn label(self) -> &'static str {
+        match self {
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub time: SystemTime,
+    pub level: Level,
+    pub text: String, // A summary line, then any details such as git's stderr
+}
+
+impl Entry {
+    /// The time of day in UTC, like `14:03:59Z`.
+    pub fn timestamp(&self) -> String {
+        let seconds = self.time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()) % 86400;
+        format!("{:02}:{:02}:{:02}Z", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+
+    pub fn summary(&self) -> &str {
+        self.text.lines().next().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MessageLog {
+    entries: VecDeque<Entry>,
+    unread_warnings: usize,
+    unread_errors: usize,
+}
+
+impl MessageLog {
+    pub fn push(&mut self, level: Level, text: impl Into<String>) {
+        match level {
+            Level::Info => {}
+            Level::Warn => self.unread_warnings += 1,
+            Level::Error => self.unread_errors += 1,
+        }
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry { time: SystemTime::now(), level, text: text.into() });
+    }
+
+    /// Logs a git command. Failures are errors when git said why, and otherwise the quiet
+    /// "no" of a lookup such as `git config --get`; output on stderr of a command that
+    /// succeeded is a warning.
+    pub fn push_command(&mut self, record: &CommandRecord) {
+        let stderr = record.stderr.trim_end();
+        let level = match record.status {
+            Some(0) if stderr.is_empty() => Level::Info,
+            Some(0) => Level::Warn,
+            Some(_) if stderr.is_empty() => Level::Info,
+            _ => Level::Error,
+        };
+        let status = match record.status {
+            Some(code) => format!("exit {code}"),
+            None => "did not finish".to_string(),
+        };
+        let mut text = format!("git {} ({status}, {} ms)", record.args.join(" "), record.duration.as_millis());
+        for line in stderr.lines() {
+            text.push_str("\n  ");
+            text.push_str(line);
+        }
+        self.push(level, text);
+    }
+
+    pub fn entries(&self) -> &VecDeque<Entry> {
+        &self.entries
+    }
+
+    /// Warnings and errors logged since the log was last looked at.
+    pub fn unread(&self) -> (usize, usize) {
+        (self.unread_warnings, self.unread_errors)
+    }
+
+    pub fn mark_read(&mut self) {
+        self.unread_warnings = 0;
+        self.unread_errors = 0;
+    }
+
+    /// The most recent warning or error, for the footer.
+    pub fn latest_problem(&self) -> Option<&Entry> {
+        self.entries.iter().rev().find(|entry| entry.level > Level::Info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn record(args: &[&str], status: Option<i32>, stderr: &str) -> CommandRecord {
+        CommandRecord {
+            args: args.iter().map(ToString::to_string).collect(),
+            dir: None,
+            status,
+            stderr: stderr.to_string(),
+            duration: Duration::from_millis(12),
+        }
+    }
+
+    #[test]
+    fn test_command_levels_and_unread_counts() {
+        let mut log = MessageLog::default();
+        log.push_command(&record(&["sparse-checkout", "list"], Some(0), ""));
+        log.push_command(&record(&["config", "--get", "core.sparseCheckout"], Some(1), ""));
+        log.push_command(&record(&["sparse-checkout", "add", "--stdin"], Some(0), "warning: a\nwarning: b\n"));
+        log.push_command(&record(&["sparse-checkout", "set"], Some(128), "fatal: bad\n"));
+        log.push_command(&record(&["status"], None, "No such file or directory"));
+
+        let levels: Vec<Level> = log.entries().iter().map(|entry| entry.level).collect();
+        assert_eq!(levels, [Level::Info, Level::Info, Level::Warn, Level::Error, Level::Error]);
+        assert_eq!(log.entries()[2].text, "git sparse-checkout add --stdin (exit 0, 12 ms)\n  warning: a\n  warning: b");
+        assert_eq!(log.entries()[3].summary(), "git sparse-checkout set (exit 128, 12 ms)");
+        assert_eq!(log.latest_problem().unwrap().text, "git status (did not finish, 12 ms)\n  No such file or directory");
+        assert_eq!(log.unread(), (1, 2));
+        log.mark_read();
+        assert_eq!(log.unread(), (0, 0));
+
+        let entry = Entry { time: UNIX_EPOCH + Duration::from_secs(86400 * 3 + 3600 * 14 + 60 * 3 + 59), level: Level::Info, text: String::new() };
+        assert_eq!(entry.timestamp(), "14:03:59Z");
+
+        for i in 0..MAX_ENTRIES {
+            log.push(Level::Info, i.to_string());
+        }
+        assert_eq!(log.entries().len(), MAX_ENTRIES);
+        assert_eq!(log.entries()[0].text, "0");
+    }
+}
@@ -0,0 +1,210 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A flattened view of the git configuration files relevant to a repository.
+///
+/// Only the subset of the syntax needed to look up simple keys is supported;
+/// `include` directives are ignored.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// `(key, value)` pairs in file order, keys normalised to `section.subsection.name`
+    /// with the section and name lower-cased.
+    entries: Vec<(String, Option<String>)>,
+}
+
+impl Config {
+    /// Loads the global and repository configuration, later files taking precedence.
+    pub fn load(common_dir: &Path, git_dir: &Path) -> Self {
+        let mut config = Config::default();
+        for path in global_config_paths() {
+            config.add_file(&path);
+        }
+        config.add_file(&common_dir.join("config"));
+        if config.get_bool("extensions.worktreeconfig").unwrap_or(false) {
+            config.add_file(&git_dir.join("config.worktree"));
+        }
+        config
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        if let Ok(content) = fs::read(path) {
+            self.parse(&String::from_utf8_lossy(&content));
+        }
+    }
+
+    fn parse(&mut self, content: &str) {
+        let mut section = String::new();
+        let mut lines = content.lines();
+        while let Some(line) = lines.next() {
+            let mut line = line.trim_start();
+            if line.starts_with('[') {
+                let Some(end) = line.find(']') else {
+                    continue;
+                };
+                let header = &line[1..end];
+                section = match header.find('"') {
+                    Some(quote) => {
+                        let name = header[..quote].trim().to_ascii_lowercase();
+                        let subsection = header[quote + 1..].trim_end().trim_end_matches('"');
+                        format!("{name}.{}", subsection.replace("\\\"", "\"").replace("\\\\", "\\"))
+                    }
+                    // Deprecated `[section.subsection]` syntax: the subsection is lower-cased.
+                    None => header.trim().to_ascii_lowercase(),
+                };
+                line = line[end + 1..].trim_start();
+                if line.is_empty() {
+                    continue;
+                }
+            }
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') || section.is_empty() {
+                continue;
+            }
+
+            let (name, raw_value) = match line.find('=') {
+                Some(eq) => (line[..eq].trim(), Some(line[eq + 1..].to_string())),
+                None => (line.trim(), None),
+            };
+            let value = raw_value.map(|mut raw| {
+                // A trailing backslash continues the value on the next line.
+                while raw.trim_end().ends_with('\\') && !raw.trim_end().ends_with("\\\\") {
+                    let trimmed = raw.trim_end();
+                    raw = trimmed[..trimmed.len() - 1].to_string();
+                    raw.push_str(lines.next().unwrap_or_default());
+                }
+                parse_value(&raw)
+            });
+            self.entries
+                .push((format!("{section}.{}", name.to_ascii_lowercase()), value));
+        }
+    }
+
+    /// Returns the last value for `key`; a key without `=` has an empty value.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_deref().unwrap_or(""))
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        let key = normalize_key(key);
+        let (_, value) = self.entries.iter().rev().find(|(k, _)| *k == key)?;
+        match value.as_deref().map(str::to_ascii_lowercase).as_deref() {
+            None => Some(true),
+            Some("true" | "yes" | "on" | "1") => Some(true),
+            Some("false" | "no" | "off" | "0" | "") => Some(false),
+            Some(_) => None,
+        }
+    }
+
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        let value = self.get(key)?;
+        match value.strip_prefix("~/") {
+            Some(rest) => home_dir().map(|home| home.join(rest)),
+            None => Some(PathBuf::from(value)),
+        }
+    }
+}
+
+/// Lower-cases the section and variable name of a dotted key, leaving any subsection intact.
+fn normalize_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) if first != last => format!(
+            "{}{}{}",
+            key[..first].to_ascii_lowercase(),
+            &key[first..last],
+            key[last..].to_ascii_lowercase()
+        ),
+        _ => key.to_ascii_lowercase(),
+    }
+}
+
+/// Strips comments and quotes from a raw value and resolves escape sequences.
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut pending_space = String::new();
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes => break,
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(other) => value.push(other),
+                    None => {}
+                }
+            }
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+    value
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// The XDG configuration directory for git, honouring `XDG_CONFIG_HOME`.
+pub fn xdg_git_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("git")),
+        _ => home_dir().map(|home| home.join(".config").join("git")),
+    }
+}
+
+fn global_config_paths() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)];
+    }
+    let mut paths = Vec::new();
+    if let Some(xdg) = xdg_git_dir() {
+        paths.push(xdg.join("config"));
+    }
+    if let Some(home) = home_dir() {
+        paths.push(home.join(".gitconfig"));
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sections_and_values() {
+        let mut config = Config::default();
+        config.parse(
+            "[core]\n\
+             \tsparseCheckout = true\n\
+             \tsparseCheckoutCone\n\
+             \texcludesFile = \"~/my ignore\" ; comment\n\
+             [remote \"Origin\"]\n\
+             \turl = https://example.com/repo.git\n\
+             [core]\n\
+             \tsparseCheckout = false\n",
+        );
+        assert_eq!(config.get_bool("core.sparsecheckout"), Some(false));
+        assert_eq!(config.get_bool("core.sparseCheckoutCone"), Some(true));
+        assert_eq!(config.get("core.excludesfile"), Some("~/my ignore"));
+        assert_eq!(config.get("remote.Origin.url"), Some("https://example.com/repo.git"));
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(config.get_bool("core.bare"), None);
+    }
+}
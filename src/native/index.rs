@@ -0,0 +1,197 @@
+use super::odb::{parse_offset_varint, ObjectId};
+use crate::git::{Error, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const EXT_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+const EXT_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub oid: ObjectId,
+    pub flags: u16,
+    pub ext_flags: u16,
+    pub path: Vec<u8>,
+}
+
+impl IndexEntry {
+    pub fn stage(&self) -> u16 {
+        (self.flags & FLAG_STAGE_MASK) >> 12
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        self.ext_flags & EXT_FLAG_SKIP_WORKTREE != 0
+    }
+
+    pub fn intent_to_add(&self) -> bool {
+        self.ext_flags & EXT_FLAG_INTENT_TO_ADD != 0
+    }
+
+    /// A sparse-index directory entry standing in for a whole subtree outside the cone.
+    pub fn is_sparse_dir(&self) -> bool {
+        self.mode == 0o040000
+    }
+
+    pub fn is_gitlink(&self) -> bool {
+        self.mode == 0o160000
+    }
+}
+
+/// The parts of `.git/index` the native backend needs.
+#[derive(Debug, Default)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+    /// Valid cache-tree entries, keyed by directory path ("" for the root).
+    pub cache_tree: HashMap<Vec<u8>, ObjectId>,
+    /// Modification time of the index file, used to detect racily clean entries.
+    pub mtime: Option<SystemTime>,
+}
+
+impl Index {
+    /// Reads the index, returning an empty one if the file does not exist yet.
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Index::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut index = Self::parse(&bytes)?;
+        index.mtime = fs::metadata(path).and_then(|m| m.modified()).ok();
+        Ok(index)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let truncated = || Error::InvalidData("truncated index".to_string());
+        if bytes.len() < 12 + 20 || &bytes[..4] != b"DIRC" {
+            return Err(Error::InvalidData("index has no DIRC signature".to_string()));
+        }
+        let version = be32(bytes, 4);
+        if !(2..=4).contains(&version) {
+            return Err(Error::Unsupported(format!("index version {version}")));
+        }
+        let count = be32(bytes, 8) as usize;
+        let body_end = bytes.len() - 20;
+
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(count);
+        let mut pos = 12;
+        for _ in 0..count {
+            let start = pos;
+            if pos + 62 > body_end {
+                return Err(truncated());
+            }
+            let flags = u16::from_be_bytes([bytes[pos + 60], bytes[pos + 61]]);
+            let mut entry = IndexEntry {
+                ctime: (be32(bytes, pos), be32(bytes, pos + 4)),
+                mtime: (be32(bytes, pos + 8), be32(bytes, pos + 12)),
+                ino: be32(bytes, pos + 20),
+                mode: be32(bytes, pos + 24),
+                uid: be32(bytes, pos + 28),
+                gid: be32(bytes, pos + 32),
+                size: be32(bytes, pos + 36),
+                oid: ObjectId::from_bytes(&bytes[pos + 40..pos + 60])?,
+                flags,
+                ext_flags: 0,
+                path: Vec::new(),
+            };
+            pos += 62;
+            if flags & FLAG_EXTENDED != 0 {
+                if pos + 2 > body_end {
+                    return Err(truncated());
+                }
+                entry.ext_flags = u16::from_be_bytes([bytes[pos], bytes[pos + 1]]);
+                pos += 2;
+            }
+
+            if version == 4 {
+                // Paths are prefix-compressed against the previous entry.
+                let (strip, used) = parse_offset_varint(&bytes[pos..body_end])?;
+                pos += used;
+                let previous = entries.last().map(|e| e.path.as_slice()).unwrap_or_default();
+                let keep = previous
+                    .len()
+                    .checked_sub(strip as usize)
+                    .ok_or_else(|| Error::InvalidData("bad path compression in index".to_string()))?;
+                let nul = bytes[pos..body_end].iter().position(|&b| b == 0).ok_or_else(truncated)?;
+                entry.path = [&previous[..keep], &bytes[pos..pos + nul]].concat();
+                pos += nul + 1;
+            } else {
+                let nul = bytes[pos..body_end].iter().position(|&b| b == 0).ok_or_else(truncated)?;
+                entry.path = bytes[pos..pos + nul].to_vec();
+                // Entries are NUL-padded to a multiple of eight bytes.
+                pos = start + ((pos + nul - start) + 8) / 8 * 8;
+            }
+            entries.push(entry);
+        }
+
+        let mut index = Index {
+            entries,
+            ..Default::default()
+        };
+
+        while pos + 8 <= body_end {
+            let signature = &bytes[pos..pos + 4];
+            let size = be32(bytes, pos + 4) as usize;
+            let data = bytes.get(pos + 8..pos + 8 + size).ok_or_else(truncated)?;
+            match signature {
+                b"TREE" => parse_cache_tree(data, &mut index.cache_tree)?,
+                b"link" => return Err(Error::Unsupported("split index".to_string())),
+                // Other extensions are optional, and those that aren't ("sdir") need no handling here.
+                _ => {}
+            }
+            pos += 8 + size;
+        }
+        Ok(index)
+    }
+}
+
+fn be32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+/// Parses the `TREE` extension, keeping only entries that are still valid.
+fn parse_cache_tree(data: &[u8], out: &mut HashMap<Vec<u8>, ObjectId>) -> Result<()> {
+    fn parse_node(data: &[u8], pos: &mut usize, prefix: &[u8], out: &mut HashMap<Vec<u8>, ObjectId>) -> Result<()> {
+        let malformed = || Error::InvalidData("malformed cache-tree extension".to_string());
+        let rest = data.get(*pos..).ok_or_else(malformed)?;
+        let nul = rest.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+        let name = &rest[..nul];
+        let newline = rest.iter().position(|&b| b == b'\n').ok_or_else(malformed)?;
+        let counts = std::str::from_utf8(&rest[nul + 1..newline]).map_err(|_| malformed())?;
+        let (entry_count, subtree_count) = counts.split_once(' ').ok_or_else(malformed)?;
+        let entry_count: i64 = entry_count.parse().map_err(|_| malformed())?;
+        let subtree_count: usize = subtree_count.parse().map_err(|_| malformed())?;
+        *pos += newline + 1;
+
+        let path = if prefix.is_empty() {
+            name.to_vec()
+        } else {
+            [prefix, b"/", name].concat()
+        };
+        if entry_count >= 0 {
+            let oid = ObjectId::from_bytes(data.get(*pos..*pos + 20).ok_or_else(malformed)?)?;
+            *pos += 20;
+            out.insert(path.clone(), oid);
+        }
+        for _ in 0..subtree_count {
+            parse_node(data, pos, &path, out)?;
+        }
+        Ok(())
+    }
+
+    let mut pos = 0;
+    if !data.is_empty() {
+        parse_node(data, &mut pos, b"", out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,1027 @@
+//! An in-process `GitBackend`.
+//!
+//! Directory listings come straight from the object database, the sparse-checkout set from
+//! `info/sparse-checkout`, and uncommitted paths from comparing `HEAD`, the index and the
+//! working tree. Operations that modify the repository still go through the git CLI, as do
+//! queries against repository formats this reader does not understand (split index,
+//! SHA-256, reftable).
+
+mod config;
+mod index;
+mod odb;
+
+use crate::backend::GitBackend;
+use crate::git::{self, Error, FileStatus, Result, SparseMode};
+use crate::ignore::{Pattern, PatternList};
+use crate::repo_path::{bytes_to_os_string, os_str_to_bytes, RepoPath};
+use config::Config;
+use index::{Index, IndexEntry};
+use odb::{tree_entries, ObjectId, ObjectKind, Odb};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Reads repository data in-process instead of forking `git`.
+#[derive(Debug, Default)]
+pub struct NativeBackend {
+    /// The object database of the most recently used repository, keyed by its common dir.
+    odb_cache: Mutex<Option<(PathBuf, Arc<Odb>)>>,
+}
+
+impl NativeBackend {
+    fn open(&self, repo_path: &Path) -> Result<Repository> {
+        let dot_git = repo_path.join(".git");
+        let git_dir = if dot_git.is_dir() {
+            dot_git
+        } else if dot_git.is_file() {
+            // Linked worktrees and submodules use a "gitdir: <path>" file.
+            let content = fs::read_to_string(&dot_git)?;
+            let target = content
+                .trim()
+                .strip_prefix("gitdir:")
+                .ok_or_else(|| Error::InvalidData(format!("malformed {}", dot_git.display())))?;
+            repo_path.join(target.trim())
+        } else {
+            return Err(Error::InvalidData(format!(
+                "{} is not the root of a git working tree",
+                repo_path.display()
+            )));
+        };
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(content) => git_dir.join(content.trim()),
+            Err(_) => git_dir.clone(),
+        };
+
+        let config = Config::load(&common_dir, &git_dir);
+        if let Some(format) = config.get("extensions.objectformat") {
+            if !format.eq_ignore_ascii_case("sha1") {
+                return Err(Error::Unsupported(format!("object format {format}")));
+            }
+        }
+        if let Some(storage) = config.get("extensions.refstorage") {
+            if !storage.eq_ignore_ascii_case("files") {
+                return Err(Error::Unsupported(format!("ref storage {storage}")));
+            }
+        }
+
+        let odb = {
+            let mut cache = self.odb_cache.lock().unwrap();
+            match cache.as_ref() {
+                Some((dir, odb)) if *dir == common_dir => odb.clone(),
+                _ => {
+                    let odb = Arc::new(Odb::open(&common_dir.join("objects"))?);
+                    *cache = Some((common_dir.clone(), odb.clone()));
+                    odb
+                }
+            }
+        };
+
+        Ok(Repository {
+            worktree: repo_path.to_path_buf(),
+            git_dir,
+            common_dir,
+            config,
+            odb,
+        })
+    }
+
+    /// Runs a native query, falling back to the CLI for repositories we cannot read.
+    fn query<T>(
+        &self,
+        repo_path: &Path,
+        native: impl FnOnce(&Repository) -> Result<T>,
+        cli: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        match self.open(repo_path).and_then(|repo| native(&repo)) {
+            Err(Error::Unsupported(_)) => cli(),
+            result => result,
+        }
+    }
+}
+
+impl GitBackend for NativeBackend {
+    fn get_dirs_at_path(&self, path: &RepoPath, rev: &str, repo_path: &Path) -> Result<Vec<RepoPath>> {
+        self.query(
+            repo_path,
+            |repo| repo.dirs_at_path(path.as_bytes(), rev),
+            || git::get_dirs_at_path(path, rev, repo_path),
+        )
+    }
+
+    fn get_tree_id(&self, rev: &str, repo_path: &Path) -> Result<Option<String>> {
+        self.query(
+            repo_path,
+            |repo| Ok(repo.rev_tree(rev)?.map(ObjectId::to_hex)),
+            || git::get_tree_id(rev, repo_path),
+        )
+    }
+
+    fn get_all_dirs(&self, tree_id: &str, repo_path: &Path) -> Result<Vec<RepoPath>> {
+        self.query(
+            repo_path,
+            |repo| repo.all_dirs(ObjectId::from_hex(tree_id.as_bytes())?),
+            || git::get_all_directories_recursive(tree_id, repo_path),
+        )
+    }
+
+    fn get_all_files(&self, tree_id: &str, repo_path: &Path) -> Result<Vec<RepoPath>> {
+        self.query(
+            repo_path,
+            |repo| repo.all_files(ObjectId::from_hex(tree_id.as_bytes())?),
+            || git::get_all_files_recursive(tree_id, repo_path),
+        )
+    }
+
+    fn get_file_contents(&self, rev: &str, path: &RepoPath, repo_path: &Path) -> Result<Option<Vec<u8>>> {
+        git::get_file_contents(rev, path, repo_path)
+    }
+
+    fn get_file_sizes(&self, tree_id: &str, repo_path: &Path) -> Result<Vec<(RepoPath, u64)>> {
+        // Sizes are in the object headers, which would mean inflating every blob.
+        git::get_file_sizes(tree_id, repo_path)
+    }
+
+    fn list_revisions(&self, repo_path: &Path) -> Result<Vec<String>> {
+        git::list_revisions(repo_path)
+    }
+
+    fn get_sparse_checkout_list(&self, repo_path: &Path) -> Result<Vec<RepoPath>> {
+        self.query(
+            repo_path,
+            Repository::sparse_checkout_list,
+            || git::get_sparse_checkout_list(repo_path),
+        )
+    }
+
+    fn get_sparse_mode(&self, repo_path: &Path) -> Result<SparseMode> {
+        self.query(
+            repo_path,
+            |repo| Ok(repo.sparse_mode()),
+            || git::get_sparse_mode(repo_path),
+        )
+    }
+
+    fn get_sparse_checkout_patterns(&self, repo_path: &Path) -> Result<Vec<Vec<u8>>> {
+        self.query(
+            repo_path,
+            Repository::sparse_checkout_patterns,
+            || git::get_sparse_checkout_patterns(repo_path),
+        )
+    }
+
+    fn get_uncommitted_paths(&self, repo_path: &Path) -> Result<HashMap<RepoPath, FileStatus>> {
+        self.query(
+            repo_path,
+            Repository::uncommitted_paths,
+            || git::get_uncommitted_paths(repo_path),
+        )
+    }
+
+    fn get_untracked_paths(&self, dir: &RepoPath, repo_path: &Path) -> Result<HashMap<RepoPath, FileStatus>> {
+        self.query(
+            repo_path,
+            |repo| repo.untracked_paths(dir.as_bytes()),
+            || git::get_untracked_paths(dir, repo_path),
+        )
+    }
+
+    fn set_sparse_checkout_dirs(&self, dirs: Vec<RepoPath>, repo_path: &Path) -> Result<()> {
+        // Updating the working tree is git's job.
+        git::set_sparse_checkout_dirs(dirs, repo_path)
+    }
+
+    fn add_sparse_checkout_dirs(&self, dirs: Vec<RepoPath>, repo_path: &Path) -> Result<()> {
+        git::add_sparse_checkout_dirs(dirs, repo_path)
+    }
+
+    fn set_sparse_checkout_patterns(&self, patterns: Vec<Vec<u8>>, repo_path: &Path) -> Result<()> {
+        git::set_sparse_checkout_patterns(patterns, repo_path)
+    }
+
+    fn init_sparse_checkout(&self, sparse_index: bool, repo_path: &Path) -> Result<()> {
+        git::init_sparse_checkout(sparse_index, repo_path)
+    }
+
+    fn disable_sparse_checkout(&self, repo_path: &Path) -> Result<()> {
+        git::disable_sparse_checkout(repo_path)
+    }
+}
+
+/// What `HEAD` contains, flattened as far as the index's cache-tree requires.
+#[derive(Debug, Default)]
+struct HeadSnapshot {
+    files: HashMap<Vec<u8>, (u32, ObjectId)>,
+    dirs: HashMap<Vec<u8>, ObjectId>,
+    /// Directories whose cache-tree entry matches `HEAD`, so the index holds `HEAD`'s content.
+    unchanged_dirs: HashSet<Vec<u8>>,
+}
+
+impl HeadSnapshot {
+    fn is_unchanged(&self, path: &[u8]) -> bool {
+        self.unchanged_dirs.contains(&b""[..])
+            || ancestors(path).any(|dir| self.unchanged_dirs.contains(dir))
+    }
+}
+
+#[derive(Debug)]
+struct Repository {
+    worktree: PathBuf,
+    git_dir: PathBuf,
+    common_dir: PathBuf,
+    config: Config,
+    odb: Arc<Odb>,
+}
+
+impl Repository {
+    fn resolve_head(&self) -> Result<Option<ObjectId>> {
+        let content = fs::read(self.git_dir.join("HEAD"))?;
+        self.follow_ref(content)
+    }
+
+    /// Resolves a fully qualified ref such as `refs/heads/main`.
+    fn resolve_ref(&self, name: &[u8]) -> Result<Option<ObjectId>> {
+        match fs::read(self.common_dir.join(bytes_to_os_string(name))) {
+            Ok(content) => self.follow_ref(content),
+            Err(e) if is_missing_ref(&e) => self.packed_ref(name),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Resolves the contents of a ref file, which is either an object id or a symbolic ref.
+    fn follow_ref(&self, mut content: Vec<u8>) -> Result<Option<ObjectId>> {
+        // Follow symbolic refs, bounded to avoid looping on a cycle.
+        for _ in 0..5 {
+            let trimmed = content.trim_ascii();
+            let Some(target) = trimmed.strip_prefix(b"ref:") else {
+                return ObjectId::from_hex(trimmed).map(Some);
+            };
+            let name = target.trim_ascii();
+            match fs::read(self.common_dir.join(bytes_to_os_string(name))) {
+                Ok(next) => content = next,
+                Err(e) if is_missing_ref(&e) => return self.packed_ref(name),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(Error::InvalidData("symbolic ref cycle".to_string()))
+    }
+
+    fn packed_ref(&self, name: &[u8]) -> Result<Option<ObjectId>> {
+        let content = match fs::read(self.common_dir.join("packed-refs")) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        for line in content.split(|&b| b == b'\n') {
+            if line.starts_with(b"#") || line.starts_with(b"^") {
+                continue;
+            }
+            if let Some((oid, ref_name)) = split_once(line, b' ') {
+                if ref_name.trim_ascii_end() == name {
+                    return ObjectId::from_hex(oid).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resolves `rev` to an object id. Only `HEAD`, full object ids and ref names are
+    /// understood; anything else (abbreviations, `~`, `^`, reflog syntax...) is left to git.
+    fn resolve_rev(&self, rev: &str) -> Result<Option<ObjectId>> {
+        if rev == "HEAD" {
+            return self.resolve_head();
+        }
+        if rev.len() == 40 && rev.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return ObjectId::from_hex(rev.as_bytes()).map(Some);
+        }
+        if rev.is_empty() || rev.contains(['~', '^', ':', '@', '*', '?', '[', '\\', ' ']) || rev.contains("..") {
+            return Err(Error::Unsupported(format!("revision syntax in '{rev}'")));
+        }
+        // The same search order as git's ref disambiguation, minus names outside refs/.
+        let candidates = [
+            rev.to_string(),
+            format!("refs/{rev}"),
+            format!("refs/tags/{rev}"),
+            format!("refs/heads/{rev}"),
+            format!("refs/remotes/{rev}"),
+            format!("refs/remotes/{rev}/HEAD"),
+        ];
+        for name in candidates.iter().filter(|name| name.starts_with("refs/")) {
+            if let Some(oid) = self.resolve_ref(name.as_bytes())? {
+                return Ok(Some(oid));
+            }
+        }
+        Err(Error::Unsupported(format!("'{rev}' is not a ref")))
+    }
+
+    /// Returns the root tree of `rev`, peeling tags and commits, or `None` on an unborn branch.
+    fn rev_tree(&self, rev: &str) -> Result<Option<ObjectId>> {
+        let Some(mut oid) = self.resolve_rev(rev)? else {
+            return Ok(None);
+        };
+        // Bounded so that a corrupt chain of tags cannot loop forever.
+        for _ in 0..16 {
+            let object = self.odb.read(&oid)?;
+            let header = match object.kind {
+                ObjectKind::Tree => return Ok(Some(oid)),
+                ObjectKind::Commit => b"tree ".as_slice(),
+                ObjectKind::Tag => b"object ".as_slice(),
+                kind => return Err(Error::InvalidData(format!("{rev} is a {kind:?}, not a tree-ish"))),
+            };
+            let first_line = object.data.split(|&b| b == b'\n').next().unwrap_or_default();
+            let target = first_line
+                .strip_prefix(header)
+                .ok_or_else(|| Error::InvalidData(format!("object {} is malformed", oid.to_hex())))?;
+            oid = ObjectId::from_hex(target)?;
+        }
+        Err(Error::InvalidData(format!("{rev} is nested too deeply")))
+    }
+
+    /// Returns the root tree of `HEAD`, or `None` on an unborn branch.
+    fn head_tree(&self) -> Result<Option<ObjectId>> {
+        self.rev_tree("HEAD")
+    }
+
+    fn dirs_at_path(&self, path: &[u8], rev: &str) -> Result<Vec<RepoPath>> {
+        let Some(mut tree) = self.rev_tree(rev)? else {
+            return Ok(Vec::new());
+        };
+        let components = path
+            .split(|&b| b == b'/')
+            .filter(|c| !c.is_empty() && *c != b".");
+        for component in components {
+            let data = self.odb.read_kind(&tree, ObjectKind::Tree)?;
+            let mut next = None;
+            for entry in tree_entries(&data) {
+                let entry = entry?;
+                if entry.is_tree() && entry.name == component {
+                    next = Some(entry.oid);
+                    break;
+                }
+            }
+            match next {
+                Some(oid) => tree = oid,
+                None => return Ok(Vec::new()),
+            }
+        }
+
+        let data = self.odb.read_kind(&tree, ObjectKind::Tree)?;
+        let mut dirs = Vec::new();
+        for entry in tree_entries(&data) {
+            let entry = entry?;
+            if entry.is_tree() {
+                dirs.push(RepoPath::from(entry.name));
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// Equivalent of `git ls-tree -r -d --name-only <tree>`, in the same (pre-order) order.
+    fn all_dirs(&self, tree: ObjectId) -> Result<Vec<RepoPath>> {
+        let mut dirs = Vec::new();
+        self.walk_tree(tree, &mut |path, is_tree| {
+            if is_tree {
+                dirs.push(RepoPath::from(path));
+            }
+        })?;
+        Ok(dirs)
+    }
+
+    /// Equivalent of `git ls-tree -r --name-only <tree>`.
+    fn all_files(&self, tree: ObjectId) -> Result<Vec<RepoPath>> {
+        let mut files = Vec::new();
+        self.walk_tree(tree, &mut |path, is_tree| {
+            if !is_tree {
+                files.push(RepoPath::from(path));
+            }
+        })?;
+        Ok(files)
+    }
+
+    /// Calls `visit` with the path of every entry below `tree`, parents before children.
+    /// Entries come in the order `git ls-tree -r -t` prints them.
+    fn walk_tree(&self, tree: ObjectId, visit: &mut dyn FnMut(&[u8], bool)) -> Result<()> {
+        self.walk_subtree(tree, &[], visit)
+    }
+
+    fn walk_subtree(&self, tree: ObjectId, prefix: &[u8], visit: &mut dyn FnMut(&[u8], bool)) -> Result<()> {
+        let data = self.odb.read_kind(&tree, ObjectKind::Tree)?;
+        for entry in tree_entries(&data) {
+            let entry = entry?;
+            let path = join_path(prefix, entry.name);
+            visit(&path, entry.is_tree());
+            if entry.is_tree() {
+                self.walk_subtree(entry.oid, &path, visit)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sparse_mode(&self) -> SparseMode {
+        if !self.config.get_bool("core.sparsecheckout").unwrap_or(false) {
+            SparseMode::Disabled
+        } else if self.config.get_bool("core.sparsecheckoutcone").unwrap_or(false) {
+            SparseMode::Cone
+        } else {
+            SparseMode::Pattern
+        }
+    }
+
+    fn sparse_checkout_patterns(&self) -> Result<Vec<Vec<u8>>> {
+        let content = match fs::read(self.git_dir.join("info").join("sparse-checkout")) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(content
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(<[u8]>::to_vec)
+            .collect())
+    }
+
+    fn sparse_checkout_list(&self) -> Result<Vec<RepoPath>> {
+        if !self.config.get_bool("core.sparsecheckout").unwrap_or(false) {
+            return Ok(Vec::new());
+        }
+        let content = match fs::read(self.git_dir.join("info").join("sparse-checkout")) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let lines = content
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty() && !line.starts_with(b"#"));
+
+        if !self.config.get_bool("core.sparsecheckoutcone").unwrap_or(false) {
+            return Ok(lines.map(RepoPath::from).collect());
+        }
+
+        // In cone mode, "/dir/" includes a directory recursively unless it is followed by
+        // "!/dir/*/", which marks it as a parent that only includes its direct files.
+        let mut recursive = Vec::new();
+        let mut parents = HashSet::new();
+        for line in lines {
+            if let Some(negated) = line.strip_prefix(b"!") {
+                if let Some(dir) = negated.strip_prefix(b"/").and_then(|l| l.strip_suffix(b"/*/")) {
+                    parents.insert(unescape_pattern(dir));
+                }
+            } else if let Some(dir) = line.strip_prefix(b"/").and_then(|l| l.strip_suffix(b"/")) {
+                if !dir.is_empty() {
+                    recursive.push(unescape_pattern(dir));
+                }
+            }
+        }
+        let mut dirs: Vec<Vec<u8>> = recursive.into_iter().filter(|d| !parents.contains(d)).collect();
+        dirs.sort();
+        dirs.dedup();
+        Ok(dirs.into_iter().map(RepoPath::from).collect())
+    }
+
+    /// Equivalent of `git status --porcelain=v2 --untracked-files=no`.
+    fn uncommitted_paths(&self) -> Result<HashMap<RepoPath, FileStatus>> {
+        let index = Index::read(&self.git_dir.join("index"))?;
+        let mut head = HeadSnapshot::default();
+        if let Some(tree) = self.head_tree()? {
+            self.collect_head(tree, b"", &index, &mut head)?;
+        }
+
+        let trust_filemode = self.config.get_bool("core.filemode").unwrap_or(true);
+        let mut changed: HashMap<Vec<u8>, FileStatus> = HashMap::new();
+        let mut conflict_stages: HashMap<&[u8], u8> = HashMap::new();
+        let mut sparse_dirs: Vec<&[u8]> = Vec::new();
+        for entry in &index.entries {
+            if entry.stage() != 0 {
+                *conflict_stages.entry(&entry.path).or_default() |= 1 << (entry.stage() - 1);
+                head.files.remove(&entry.path);
+                continue;
+            }
+            if entry.is_sparse_dir() {
+                let dir = entry.path.strip_suffix(b"/").unwrap_or(&entry.path);
+                sparse_dirs.push(dir);
+                if !head.is_unchanged(dir) && head.dirs.get(dir) != Some(&entry.oid) {
+                    changed.insert(dir.to_vec(), FileStatus(*b"M."));
+                }
+                continue;
+            }
+
+            let in_head = if head.is_unchanged(&entry.path) {
+                Some((entry.mode, entry.oid))
+            } else {
+                head.files.remove(&entry.path)
+            };
+            // Intent-to-add entries are placeholders that git reports as added in the worktree
+            let in_index = Some((entry.mode, entry.oid)).filter(|_| !entry.intent_to_add());
+            let in_worktree = self.worktree_state(entry, &index, trust_filemode, in_head)?;
+            let status = FileStatus([change_code(in_head, in_index), change_code(in_index, in_worktree)]);
+            if status != FileStatus(*b"..") {
+                changed.insert(entry.path.clone(), status);
+            }
+        }
+
+        // Stages present: 1 for the common ancestor, 2 for ours and 3 for theirs
+        for (path, stages) in conflict_stages {
+            let code = match stages {
+                0b001 => b"DD",
+                0b010 => b"AU",
+                0b011 => b"UD",
+                0b100 => b"UA",
+                0b101 => b"DU",
+                0b110 => b"AA",
+                _ => b"UU",
+            };
+            changed.insert(path.to_vec(), FileStatus(*code));
+        }
+
+        // Whatever is left in HEAD has been removed from the index.
+        changed.extend(
+            head.files
+                .into_keys()
+                .filter(|path| !sparse_dirs.iter().any(|dir| is_under(path, dir)))
+                .map(|path| (path, FileStatus(*b"D."))),
+        );
+
+        Ok(changed.into_iter().map(|(path, status)| (RepoPath::from(path), status)).collect())
+    }
+
+    /// Equivalent of `git status --porcelain=v2 --untracked-files=all --ignored=matching -- dir`,
+    /// keeping only the untracked and ignored paths.
+    fn untracked_paths(&self, dir: &[u8]) -> Result<HashMap<RepoPath, FileStatus>> {
+        let dir = if dir == b"." { b"" } else { dir }; // The root
+        let index = Index::read(&self.git_dir.join("index"))?;
+        let mut untracked = HashMap::new();
+        self.collect_untracked(&index, dir, &mut untracked)?;
+        Ok(untracked.into_iter().map(|(path, status)| (RepoPath::from(path), status)).collect())
+    }
+
+    fn collect_head(&self, tree: ObjectId, prefix: &[u8], index: &Index, head: &mut HeadSnapshot) -> Result<()> {
+        if index.cache_tree.get(prefix) == Some(&tree) {
+            head.unchanged_dirs.insert(prefix.to_vec());
+            return Ok(());
+        }
+        let data = self.odb.read_kind(&tree, ObjectKind::Tree)?;
+        for entry in tree_entries(&data) {
+            let entry = entry?;
+            let path = join_path(prefix, entry.name);
+            if entry.is_tree() {
+                head.dirs.insert(path.clone(), entry.oid);
+                self.collect_head(entry.oid, &path, index, head)?;
+            } else {
+                head.files.insert(path, (entry.mode, entry.oid));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the mode and blob id of the working tree file behind `entry`, or `None` if
+    /// it has been deleted. Unchanged files are detected from stat data without hashing.
+    fn worktree_state(
+        &self,
+        entry: &IndexEntry,
+        index: &Index,
+        trust_filemode: bool,
+        in_head: Option<(u32, ObjectId)>,
+    ) -> Result<Option<(u32, ObjectId)>> {
+        let assume_unchanged = entry.flags & 0x8000 != 0;
+        if entry.skip_worktree() || entry.is_gitlink() || assume_unchanged {
+            return Ok(Some((entry.mode, entry.oid)));
+        }
+
+        let path = self.worktree.join(bytes_to_os_string(&entry.path));
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mode = if meta.file_type().is_symlink() {
+            0o120000
+        } else if meta.is_file() {
+            if !trust_filemode && entry.mode != 0o120000 {
+                entry.mode
+            } else if is_executable(&meta) {
+                0o100755
+            } else {
+                0o100644
+            }
+        } else {
+            // A directory (or something stranger) now sits where the file was.
+            return Ok(None);
+        };
+
+        if mode == entry.mode && !entry.intent_to_add() && stat_matches(entry, &meta, index) {
+            return Ok(Some((entry.mode, entry.oid)));
+        }
+        // A size change against an index entry that equals HEAD is already a difference.
+        if meta.is_file() && in_head == Some((entry.mode, entry.oid)) && meta.len() as u32 != entry.size {
+            return Ok(Some((mode, ObjectId([0; 20]))));
+        }
+
+        let content = if mode == 0o120000 {
+            os_str_to_bytes(fs::read_link(&path)?.as_os_str())
+        } else {
+            fs::read(&path)?
+        };
+        Ok(Some((mode, hash_blob(&content))))
+    }
+
+    /// Adds the untracked and ignored files, and the ignored directories, under `dir` to `out`.
+    fn collect_untracked(&self, index: &Index, dir: &[u8], out: &mut HashMap<Vec<u8>, FileStatus>) -> Result<()> {
+        let mut tracked_files: HashSet<&[u8]> = HashSet::new();
+        let mut tracked_dirs: HashSet<&[u8]> = HashSet::new();
+        let mut skipped_dirs: HashSet<&[u8]> = HashSet::new();
+        for entry in &index.entries {
+            if entry.is_sparse_dir() {
+                skipped_dirs.insert(entry.path.strip_suffix(b"/").unwrap_or(&entry.path));
+            } else if entry.is_gitlink() {
+                skipped_dirs.insert(&entry.path);
+            } else {
+                tracked_files.insert(&entry.path);
+            }
+            tracked_dirs.extend(ancestors(&entry.path));
+        }
+
+        let mut excludes = PatternList::default();
+        let global_excludes = self
+            .config
+            .get_path("core.excludesfile")
+            .or_else(|| config::xdg_git_dir().map(|dir| dir.join("ignore")));
+        if let Some(content) = global_excludes.and_then(|path| fs::read(path).ok()) {
+            excludes.extend(Pattern::parse_all(&content, b""));
+        }
+        if let Ok(content) = fs::read(self.common_dir.join("info").join("exclude")) {
+            excludes.extend(Pattern::parse_all(&content, b""));
+        }
+
+        let tracked = TrackedPaths {
+            files: tracked_files,
+            dirs: tracked_dirs,
+            skipped_dirs,
+        };
+
+        // The .gitignore files above `dir` apply inside it too, and may ignore it as a whole.
+        let mut parent: &[u8] = b"";
+        for path in ancestors(dir).chain((!dir.is_empty()).then_some(dir)) {
+            let base = if parent.is_empty() { Vec::new() } else { [parent, b"/"].concat() };
+            if let Ok(content) = fs::read(self.worktree.join(bytes_to_os_string(parent)).join(".gitignore")) {
+                excludes.extend(Pattern::parse_all(&content, &base));
+            }
+            let is_dir = fs::symlink_metadata(self.worktree.join(bytes_to_os_string(path))).is_ok_and(|meta| meta.is_dir());
+            if !is_dir || tracked.skipped_dirs.contains(path) {
+                return Ok(());
+            }
+            if excludes.is_excluded(path, true) == Some(true) {
+                out.insert([path, b"/"].concat(), FileStatus::IGNORED);
+                return Ok(());
+            }
+            parent = path;
+        }
+        self.walk_untracked(dir, &tracked, &mut excludes, out)
+    }
+
+    fn walk_untracked(
+        &self,
+        dir: &[u8],
+        tracked: &TrackedPaths,
+        excludes: &mut PatternList,
+        out: &mut HashMap<Vec<u8>, FileStatus>,
+    ) -> Result<()> {
+        let abs_dir = self.worktree.join(bytes_to_os_string(dir));
+        let base = if dir.is_empty() { Vec::new() } else { [dir, b"/"].concat() };
+        let inherited = excludes.patterns.len();
+        if let Ok(content) = fs::read(abs_dir.join(".gitignore")) {
+            excludes.extend(Pattern::parse_all(&content, &base));
+        }
+
+        for entry in fs::read_dir(&abs_dir)? {
+            let entry = entry?;
+            let name = os_str_to_bytes(&entry.file_name());
+            if name == b".git" {
+                continue;
+            }
+            let path = join_path(dir, &name);
+            if entry.file_type()?.is_dir() {
+                if tracked.skipped_dirs.contains(path.as_slice()) {
+                    continue;
+                }
+                if excludes.is_excluded(&path, true) == Some(true) {
+                    // Ignored directories are reported as a whole, like git does.
+                    out.insert([path.as_slice(), b"/"].concat(), FileStatus::IGNORED);
+                    continue;
+                }
+                if !tracked.dirs.contains(path.as_slice()) && entry.path().join(".git").exists() {
+                    // Untracked nested repositories are reported as a whole, like git does.
+                    out.insert([path.as_slice(), b"/"].concat(), FileStatus::UNTRACKED);
+                    continue;
+                }
+                self.walk_untracked(&path, tracked, excludes, out)?;
+            } else if !tracked.files.contains(path.as_slice()) {
+                let status = if excludes.is_excluded(&path, false) == Some(true) {
+                    FileStatus::IGNORED
+                } else {
+                    FileStatus::UNTRACKED
+                };
+                out.insert(path, status);
+            }
+        }
+
+        excludes.patterns.truncate(inherited);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct TrackedPaths<'a> {
+    files: HashSet<&'a [u8]>,
+    dirs: HashSet<&'a [u8]>,
+    /// Submodules and sparse-index directories, which are never scanned.
+    skipped_dirs: HashSet<&'a [u8]>,
+}
+
+/// The status letter for a path that was `from` and is now `to`, as `git status` has it.
+fn change_code(from: Option<(u32, ObjectId)>, to: Option<(u32, ObjectId)>) -> u8 {
+    match (from, to) {
+        _ if from == to => b'.',
+        (None, _) => b'A',
+        (_, None) => b'D',
+        (Some((from_mode, _)), Some((to_mode, _))) if from_mode & 0o170000 != to_mode & 0o170000 => b'T',
+        _ => b'M',
+    }
+}
+
+/// Loose refs live in a directory hierarchy, so a ref can also be missing because one of
+/// its components is a file, or because it names a directory of other refs.
+fn is_missing_ref(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::IsADirectory)
+}
+
+fn hash_blob(content: &[u8]) -> ObjectId {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()).as_bytes());
+    hasher.update(content);
+    ObjectId(hasher.digest().bytes())
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// Compares cached stat data the way git does, treating racily clean entries as dirty.
+#[cfg(unix)]
+fn stat_matches(entry: &IndexEntry, meta: &fs::Metadata, index: &Index) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let matches = meta.size() as u32 == entry.size
+        && meta.mtime() as u32 == entry.mtime.0
+        && (entry.mtime.1 == 0 || meta.mtime_nsec() as u32 == entry.mtime.1)
+        && meta.ctime() as u32 == entry.ctime.0
+        && (entry.ino == 0 || meta.ino() as u32 == entry.ino)
+        && (entry.uid == 0 || meta.uid() == entry.uid)
+        && (entry.gid == 0 || meta.gid() == entry.gid);
+    matches && !is_racy(entry, index)
+}
+
+#[cfg(not(unix))]
+fn stat_matches(entry: &IndexEntry, meta: &fs::Metadata, index: &Index) -> bool {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as u32);
+    meta.len() as u32 == entry.size && mtime == Some(entry.mtime.0) && !is_racy(entry, index)
+}
+
+/// An entry modified in the same second the index was written may hide a change.
+fn is_racy(entry: &IndexEntry, index: &Index) -> bool {
+    let Some(index_mtime) = index
+        .mtime
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+    else {
+        return true;
+    };
+    (entry.mtime.0 as u64, entry.mtime.1) >= (index_mtime.as_secs(), index_mtime.subsec_nanos())
+}
+
+/// Iterates over the proper ancestor directories of a slash-separated path.
+fn ancestors(path: &[u8]) -> impl Iterator<Item = &[u8]> {
+    path.iter()
+        .enumerate()
+        .filter(|&(_, &b)| b == b'/')
+        .map(move |(i, _)| &path[..i])
+}
+
+fn is_under(path: &[u8], dir: &[u8]) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path[dir.len()] == b'/'
+}
+
+fn join_path(prefix: &[u8], name: &[u8]) -> Vec<u8> {
+    if prefix.is_empty() {
+        name.to_vec()
+    } else {
+        [prefix, b"/", name].concat()
+    }
+}
+
+fn split_once(bytes: &[u8], delimiter: u8) -> Option<(&[u8], &[u8])> {
+    let pos = bytes.iter().position(|&b| b == delimiter)?;
+    Some((&bytes[..pos], &bytes[pos + 1..]))
+}
+
+/// Removes the backslash escapes git adds to glob characters in cone patterns.
+fn unescape_pattern(pattern: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pattern.len());
+    let mut bytes = pattern.iter();
+    while let Some(&b) = bytes.next() {
+        if b == b'\\' {
+            if let Some(&escaped) = bytes.next() {
+                out.push(escaped);
+            }
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::{commit_files, git, setup_git_repo};
+    use std::process::Command;
+
+    fn write(repo: &Path, path: &str, content: &str) {
+        let path = repo.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn setup_repo() -> tempfile::TempDir {
+        let (repo, dir) = setup_git_repo();
+        commit_files(&repo, &[
+            ("src/main.rs", "fn main() {}"),
+            ("src/components/mod.rs", "pub fn foo() {}"),
+            ("docs/README.md", "# Docs"),
+            ("docs/guide/intro.md", "intro"),
+            ("dir with spaces/file.txt", "content"),
+            ("日本語ディレクトリ/サブ/ファイル.txt", "japanese content"),
+            (".gitignore", "*.log\nbuild/\n"),
+        ]);
+        dir
+    }
+
+    fn assert_same_as_cli(repo: &Path) {
+        let native = NativeBackend::default();
+        for path in [".", "src", "docs", "日本語ディレクトリ", "missing"] {
+            let path = RepoPath::from(path);
+            let mut expected = git::get_dirs_at_path(&path, "HEAD", repo).unwrap();
+            let mut actual = native.get_dirs_at_path(&path, "HEAD", repo).unwrap();
+            expected.sort();
+            actual.sort();
+            assert_eq!(actual, expected, "dirs at {path:?}");
+        }
+        let tree_id = git::get_tree_id("HEAD", repo).unwrap().unwrap();
+        assert_eq!(native.get_tree_id("HEAD", repo).unwrap(), Some(tree_id.clone()));
+        assert_eq!(
+            native.get_all_dirs(&tree_id, repo).unwrap(),
+            git::get_all_directories_recursive(&tree_id, repo).unwrap()
+        );
+        assert_eq!(
+            native.get_sparse_checkout_list(repo).unwrap(),
+            git::get_sparse_checkout_list(repo).unwrap()
+        );
+        assert_eq!(native.get_sparse_mode(repo).unwrap(), git::get_sparse_mode(repo).unwrap());
+        assert_eq!(
+            native.get_sparse_checkout_patterns(repo).unwrap(),
+            git::get_sparse_checkout_patterns(repo).unwrap()
+        );
+        assert_eq!(
+            native.get_all_files(&tree_id, repo).unwrap(),
+            git::get_all_files_recursive(&tree_id, repo).unwrap()
+        );
+        assert_eq!(
+            native.get_uncommitted_paths(repo).unwrap(),
+            git::get_uncommitted_paths(repo).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_matches_cli_on_loose_and_packed_objects() {
+        let dir = setup_repo();
+        assert_same_as_cli(dir.path());
+
+        git(dir.path(), &["gc", "-q", "--aggressive"]);
+        assert_same_as_cli(dir.path());
+    }
+
+    #[test]
+    fn test_uncommitted_paths_match_cli() {
+        let dir = setup_repo();
+        let repo = dir.path();
+        write(repo, "src/main.rs", "fn main() { /* changed */ }");
+        write(repo, "docs/staged.md", "new");
+        git(repo, &["add", "docs/staged.md"]);
+        fs::remove_file(repo.join("docs/guide/intro.md")).unwrap();
+        write(repo, "untracked dir/nested/file.txt", "untracked");
+        write(repo, "新規ファイル.txt", "new file");
+        write(repo, "debug.log", "ignored");
+        write(repo, "build/output.bin", "ignored");
+        write(repo, "docs/README.md", "# Staged");
+        git(repo, &["add", "docs/README.md"]);
+        write(repo, "docs/README.md", "# Staged, then changed");
+
+        write(repo, "docs/.gitignore", "*.tmp\n");
+        write(repo, "docs/guide/draft.tmp", "ignored");
+        write(repo, "docs/guide/draft.md", "untracked");
+
+        let native = NativeBackend::default().get_uncommitted_paths(repo).unwrap();
+        assert_eq!(native, git::get_uncommitted_paths(repo).unwrap());
+        assert_eq!(native[&RepoPath::from("src/main.rs")], FileStatus(*b".M"));
+        assert_eq!(native[&RepoPath::from("docs/README.md")], FileStatus(*b"MM"));
+        assert!(!native.contains_key(&RepoPath::from("debug.log")));
+
+        for dir in ["", "docs", "docs/guide", "build", "untracked dir", "src", "no such dir"] {
+            let dir = RepoPath::from(dir);
+            let native = NativeBackend::default().get_untracked_paths(&dir, repo).unwrap();
+            assert_eq!(native, git::get_untracked_paths(&dir, repo).unwrap(), "under {dir:?}");
+        }
+        let native = NativeBackend::default().get_untracked_paths(&RepoPath::root(), repo).unwrap();
+        assert_eq!(native[&RepoPath::from("debug.log")], FileStatus::IGNORED);
+        assert_eq!(native[&RepoPath::from("build/")], FileStatus::IGNORED);
+        let native = NativeBackend::default().get_untracked_paths(&"docs/guide".into(), repo).unwrap();
+        assert_eq!(native[&RepoPath::from("docs/guide/draft.tmp")], FileStatus::IGNORED);
+        assert_eq!(native[&RepoPath::from("docs/guide/draft.md")], FileStatus::UNTRACKED);
+    }
+
+    #[test]
+    fn test_conflicts_match_cli() {
+        let dir = setup_repo();
+        let repo = dir.path();
+        git(repo, &["branch", "-M", "main"]);
+        git(repo, &["checkout", "-q", "-b", "other"]);
+        write(repo, "src/main.rs", "fn main() { other() }");
+        git(repo, &["rm", "-q", "docs/README.md"]);
+        git(repo, &["commit", "-q", "-am", "other"]);
+        git(repo, &["checkout", "-q", "main"]);
+        write(repo, "src/main.rs", "fn main() { main() }");
+        write(repo, "docs/README.md", "# Changed");
+        git(repo, &["commit", "-q", "-am", "main"]);
+        let merge = Command::new("git").args(["merge", "-q", "other"]).current_dir(repo).output().unwrap();
+        assert!(!merge.status.success());
+
+        let native = NativeBackend::default().get_uncommitted_paths(repo).unwrap();
+        assert_eq!(native, git::get_uncommitted_paths(repo).unwrap());
+        assert_eq!(native[&RepoPath::from("src/main.rs")], FileStatus(*b"UU"));
+        assert_eq!(native[&RepoPath::from("docs/README.md")], FileStatus(*b"UD"));
+    }
+
+    #[test]
+    fn test_revisions_resolve_like_cli() {
+        let dir = setup_repo();
+        let repo = dir.path();
+        git(repo, &["branch", "-M", "main"]);
+        git(repo, &["checkout", "-q", "-b", "feature/x"]);
+        write(repo, "feature/only.txt", "feature");
+        git(repo, &["add", "."]);
+        git(repo, &["commit", "-q", "-m", "feature"]);
+        git(repo, &["tag", "-a", "v1", "-m", "annotated"]);
+        git(repo, &["tag", "light"]);
+        git(repo, &["checkout", "-q", "main"]);
+        git(repo, &["pack-refs", "--all"]);
+        git(repo, &["branch", "loose"]);
+
+        let native = NativeBackend::default();
+        let commit = String::from_utf8(Command::new("git").args(["rev-parse", "feature/x"]).current_dir(repo).output().unwrap().stdout).unwrap();
+        for rev in ["HEAD", "main", "feature/x", "heads/feature/x", "refs/heads/feature/x", "v1", "light", "loose", "HEAD~0", commit.trim(), "missing"] {
+            assert_eq!(native.get_tree_id(rev, repo).unwrap(), git::get_tree_id(rev, repo).unwrap(), "tree of {rev}");
+        }
+        let mut expected = git::get_dirs_at_path(&RepoPath::root(), "v1", repo).unwrap();
+        let mut actual = native.get_dirs_at_path(&RepoPath::root(), "v1", repo).unwrap();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+        assert!(actual.contains(&RepoPath::from("feature")));
+    }
+
+    #[test]
+    fn test_sparse_checkout_list_matches_cli() {
+        let dir = setup_repo();
+        let repo = dir.path();
+        git(repo, &["sparse-checkout", "init", "--cone"]);
+        git(repo, &["sparse-checkout", "set", "src/components", "dir with spaces", "日本語ディレクトリ"]);
+        assert_same_as_cli(repo);
+
+        git(repo, &["sparse-checkout", "set", "--sparse-index", "docs"]);
+        write(repo, "docs/README.md", "# Changed");
+        assert_same_as_cli(repo);
+    }
+}
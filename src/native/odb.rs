@@ -0,0 +1,551 @@
+use crate::git::{Error, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Longest delta chain we are willing to follow before assuming the pack is corrupt.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// Upper bound for the per-pack cache of resolved delta bases, in bytes.
+const BASE_CACHE_LIMIT: usize = 64 * 1024 * 1024;
+
+/// A SHA-1 object id.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(pub [u8; 20]);
+
+impl ObjectId {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let array: [u8; 20] = bytes
+            .try_into()
+            .map_err(|_| Error::InvalidData(format!("object id of {} bytes", bytes.len())))?;
+        Ok(ObjectId(array))
+    }
+
+    pub fn from_hex(hex: &[u8]) -> Result<Self> {
+        if hex.len() != 40 {
+            return Err(Error::InvalidData(format!(
+                "malformed object id '{}'",
+                String::from_utf8_lossy(hex)
+            )));
+        }
+        let mut array = [0u8; 20];
+        for (i, pair) in hex.chunks(2).enumerate() {
+            let digit = |c: u8| -> Result<u8> {
+                (c as char)
+                    .to_digit(16)
+                    .map(|d| d as u8)
+                    .ok_or_else(|| Error::InvalidData(format!("non-hex digit '{}' in object id", c as char)))
+            };
+            array[i] = digit(pair[0])? << 4 | digit(pair[1])?;
+        }
+        Ok(ObjectId(array))
+    }
+
+    pub fn to_hex(self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl ObjectKind {
+    fn from_pack_type(pack_type: u8) -> Option<Self> {
+        match pack_type {
+            1 => Some(ObjectKind::Commit),
+            2 => Some(ObjectKind::Tree),
+            3 => Some(ObjectKind::Blob),
+            4 => Some(ObjectKind::Tag),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"commit" => Some(ObjectKind::Commit),
+            b"tree" => Some(ObjectKind::Tree),
+            b"blob" => Some(ObjectKind::Blob),
+            b"tag" => Some(ObjectKind::Tag),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub kind: ObjectKind,
+    pub data: Arc<[u8]>,
+}
+
+/// Read-only access to loose objects and packfiles, including alternates.
+#[derive(Debug)]
+pub struct Odb {
+    object_dirs: Vec<PathBuf>,
+    packs: RwLock<Vec<Arc<Pack>>>,
+}
+
+impl Odb {
+    pub fn open(objects_dir: &Path) -> Result<Self> {
+        let mut object_dirs = vec![objects_dir.to_path_buf()];
+        let mut i = 0;
+        while i < object_dirs.len() {
+            let alternates = object_dirs[i].join("info").join("alternates");
+            if let Ok(content) = fs::read_to_string(&alternates) {
+                for line in content.lines().map(str::trim) {
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let alternate = object_dirs[i].join(line);
+                    if !object_dirs.contains(&alternate) {
+                        object_dirs.push(alternate);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let odb = Odb {
+            object_dirs,
+            packs: RwLock::new(Vec::new()),
+        };
+        odb.rescan_packs()?;
+        Ok(odb)
+    }
+
+    pub fn read(&self, oid: &ObjectId) -> Result<Object> {
+        if let Some(object) = self.read_packed(oid)? {
+            return Ok(object);
+        }
+        if let Some(object) = self.read_loose(oid)? {
+            return Ok(object);
+        }
+        // A concurrent `git gc` or fetch may have created packs we have not seen yet.
+        self.rescan_packs()?;
+        if let Some(object) = self.read_packed(oid)? {
+            return Ok(object);
+        }
+        Err(Error::InvalidData(format!("object {} not found", oid.to_hex())))
+    }
+
+    pub fn read_kind(&self, oid: &ObjectId, kind: ObjectKind) -> Result<Arc<[u8]>> {
+        let object = self.read(oid)?;
+        if object.kind != kind {
+            return Err(Error::InvalidData(format!(
+                "object {} is a {:?}, expected a {:?}",
+                oid.to_hex(),
+                object.kind,
+                kind
+            )));
+        }
+        Ok(object.data)
+    }
+
+    fn rescan_packs(&self) -> Result<()> {
+        let mut packs = self.packs.write().unwrap();
+        let mut rescanned = Vec::new();
+        for dir in &self.object_dirs {
+            let Ok(entries) = fs::read_dir(dir.join("pack")) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let idx_path = entry.path();
+                if idx_path.extension().is_none_or(|ext| ext != "idx") {
+                    continue;
+                }
+                let pack_path = idx_path.with_extension("pack");
+                if let Some(existing) = packs.iter().find(|p| p.path == pack_path) {
+                    rescanned.push(existing.clone());
+                } else if pack_path.is_file() {
+                    rescanned.push(Arc::new(Pack::open(&idx_path, &pack_path)?));
+                }
+            }
+        }
+        *packs = rescanned;
+        Ok(())
+    }
+
+    fn read_packed(&self, oid: &ObjectId) -> Result<Option<Object>> {
+        let packs = self.packs.read().unwrap().clone();
+        for pack in packs {
+            if let Some(offset) = pack.index.lookup(oid) {
+                return pack.read_at(offset, self).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_loose(&self, oid: &ObjectId) -> Result<Option<Object>> {
+        let hex = oid.to_hex();
+        for dir in &self.object_dirs {
+            let path = dir.join(&hex[..2]).join(&hex[2..]);
+            let compressed = match fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let raw = inflate(&compressed, None)?;
+            let header_end = raw
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| Error::InvalidData(format!("loose object {hex} has no header")))?;
+            let header = &raw[..header_end];
+            let kind_name = header.split(|&b| b == b' ').next().unwrap_or_default();
+            let kind = ObjectKind::from_name(kind_name)
+                .ok_or_else(|| Error::InvalidData(format!("loose object {hex} has an unknown type")))?;
+            return Ok(Some(Object {
+                kind,
+                data: raw[header_end + 1..].into(),
+            }));
+        }
+        Ok(None)
+    }
+}
+
+/// A version 2 pack index (`.idx`).
+#[derive(Debug)]
+struct PackIndex {
+    fanout: [u32; 256],
+    oids: Vec<u8>,
+    offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 8 + 256 * 4 || &bytes[..4] != b"\xfftOc" {
+            return Err(Error::Unsupported("pack index is not version 2".to_string()));
+        }
+        if read_u32(bytes, 4) != 2 {
+            return Err(Error::Unsupported("pack index is not version 2".to_string()));
+        }
+        let mut fanout = [0u32; 256];
+        for (i, slot) in fanout.iter_mut().enumerate() {
+            *slot = read_u32(bytes, 8 + i * 4);
+        }
+        let count = fanout[255] as usize;
+        let oids_start = 8 + 256 * 4;
+        let crc_start = oids_start + count * 20;
+        let offsets_start = crc_start + count * 4;
+        let large_start = offsets_start + count * 4;
+        if bytes.len() < large_start {
+            return Err(Error::InvalidData("truncated pack index".to_string()));
+        }
+
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let small = read_u32(bytes, offsets_start + i * 4);
+            if small & 0x8000_0000 == 0 {
+                offsets.push(small as u64);
+            } else {
+                let pos = large_start + (small & 0x7fff_ffff) as usize * 8;
+                if bytes.len() < pos + 8 {
+                    return Err(Error::InvalidData("truncated pack index".to_string()));
+                }
+                offsets.push((read_u32(bytes, pos) as u64) << 32 | read_u32(bytes, pos + 4) as u64);
+            }
+        }
+
+        Ok(PackIndex {
+            fanout,
+            oids: bytes[oids_start..crc_start].to_vec(),
+            offsets,
+        })
+    }
+
+    fn lookup(&self, oid: &ObjectId) -> Option<u64> {
+        let first = oid.0[0] as usize;
+        let mut lo = if first == 0 { 0 } else { self.fanout[first - 1] as usize };
+        let mut hi = self.fanout[first] as usize;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.oids[mid * 20..mid * 20 + 20].cmp(&oid.0[..]) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(self.offsets[mid]),
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Pack {
+    path: PathBuf,
+    index: PackIndex,
+    /// Every object offset in ascending order, used to find where an entry ends.
+    sorted_offsets: Vec<u64>,
+    data_end: u64,
+    file: Mutex<File>,
+    base_cache: Mutex<BaseCache>,
+}
+
+#[derive(Debug, Default)]
+struct BaseCache {
+    entries: HashMap<u64, Object>,
+    bytes: usize,
+}
+
+impl Pack {
+    fn open(idx_path: &Path, pack_path: &Path) -> Result<Self> {
+        let index = PackIndex::parse(&fs::read(idx_path)?)?;
+        let file = File::open(pack_path)?;
+        let len = file.metadata()?.len();
+        let mut sorted_offsets = index.offsets.clone();
+        sorted_offsets.sort_unstable();
+        Ok(Pack {
+            path: pack_path.to_path_buf(),
+            index,
+            sorted_offsets,
+            data_end: len.saturating_sub(20),
+            file: Mutex::new(file),
+            base_cache: Mutex::new(BaseCache::default()),
+        })
+    }
+
+    fn read_entry(&self, offset: u64) -> Result<Vec<u8>> {
+        let next = self.sorted_offsets.partition_point(|&o| o <= offset);
+        let end = self.sorted_offsets.get(next).copied().unwrap_or(self.data_end);
+        if end <= offset {
+            return Err(Error::InvalidData(format!("bad pack offset {offset}")));
+        }
+        let mut buf = vec![0u8; (end - offset) as usize];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_at(&self, offset: u64, odb: &Odb) -> Result<Object> {
+        // Walk the delta chain down to its base, then apply the deltas back up.
+        let mut deltas: Vec<(u64, Vec<u8>)> = Vec::new();
+        let mut current = offset;
+        let mut base = loop {
+            if let Some(cached) = self.base_cache.lock().unwrap().entries.get(&current) {
+                break cached.clone();
+            }
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(Error::InvalidData(format!("delta chain too long in {}", self.path.display())));
+            }
+            let entry = self.read_entry(current)?;
+            let (pack_type, size, mut pos) = parse_entry_header(&entry)?;
+            match pack_type {
+                6 => {
+                    let (distance, used) = parse_offset_varint(&entry[pos..])?;
+                    pos += used;
+                    deltas.push((current, inflate(&entry[pos..], Some(size))?));
+                    current = current
+                        .checked_sub(distance)
+                        .ok_or_else(|| Error::InvalidData("delta base before pack start".to_string()))?;
+                }
+                7 => {
+                    let base_oid = ObjectId::from_bytes(entry.get(pos..pos + 20).unwrap_or_default())?;
+                    pos += 20;
+                    deltas.push((current, inflate(&entry[pos..], Some(size))?));
+                    match self.index.lookup(&base_oid) {
+                        Some(base_offset) => current = base_offset,
+                        None => break odb.read(&base_oid)?,
+                    }
+                }
+                _ => {
+                    let kind = ObjectKind::from_pack_type(pack_type)
+                        .ok_or_else(|| Error::InvalidData(format!("unknown pack object type {pack_type}")))?;
+                    break Object {
+                        kind,
+                        data: inflate(&entry[pos..], Some(size))?.into(),
+                    };
+                }
+            }
+        };
+
+        while let Some((delta_offset, delta)) = deltas.pop() {
+            base = Object {
+                kind: base.kind,
+                data: apply_delta(&base.data, &delta)?.into(),
+            };
+            // Intermediate results are likely bases for sibling objects; keep them around.
+            if !deltas.is_empty() {
+                self.cache_base(delta_offset, &base);
+            }
+        }
+        Ok(base)
+    }
+
+    fn cache_base(&self, offset: u64, object: &Object) {
+        let mut cache = self.base_cache.lock().unwrap();
+        if cache.bytes + object.data.len() > BASE_CACHE_LIMIT {
+            cache.entries.clear();
+            cache.bytes = 0;
+        }
+        cache.bytes += object.data.len();
+        cache.entries.insert(offset, object.clone());
+    }
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+/// Parses the type and inflated size at the start of a pack entry.
+fn parse_entry_header(entry: &[u8]) -> Result<(u8, usize, usize)> {
+    let truncated = || Error::InvalidData("truncated pack entry header".to_string());
+    let mut byte = *entry.first().ok_or_else(truncated)?;
+    let pack_type = (byte >> 4) & 0x7;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    let mut pos = 1;
+    while byte & 0x80 != 0 {
+        byte = *entry.get(pos).ok_or_else(truncated)?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        pos += 1;
+    }
+    Ok((pack_type, size, pos))
+}
+
+/// Decodes the "offset encoding" used for `OFS_DELTA` bases and version 4 index paths.
+pub fn parse_offset_varint(bytes: &[u8]) -> Result<(u64, usize)> {
+    let truncated = || Error::InvalidData("truncated variable-length integer".to_string());
+    let mut byte = *bytes.first().ok_or_else(truncated)?;
+    let mut value = (byte & 0x7f) as u64;
+    let mut pos = 1;
+    while byte & 0x80 != 0 {
+        byte = *bytes.get(pos).ok_or_else(truncated)?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+        pos += 1;
+    }
+    Ok((value, pos))
+}
+
+fn inflate(compressed: &[u8], expected_size: Option<usize>) -> Result<Vec<u8>> {
+    let limit = expected_size.unwrap_or(usize::MAX);
+    let data = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(compressed, limit)
+        .map_err(|e| Error::InvalidData(format!("zlib stream: {e}")))?;
+    if expected_size.is_some_and(|size| size != data.len()) {
+        return Err(Error::InvalidData("inflated size does not match pack header".to_string()));
+    }
+    Ok(data)
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let corrupt = || Error::InvalidData("corrupt delta".to_string());
+    let read_size = |pos: &mut usize| -> Result<usize> {
+        let mut size = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(*pos).ok_or_else(corrupt)?;
+            *pos += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+
+    let mut pos = 0;
+    if read_size(&mut pos)? != base.len() {
+        return Err(corrupt());
+    }
+    let result_size = read_size(&mut pos)?;
+    let mut result = Vec::with_capacity(result_size);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            let mut copy_offset = 0usize;
+            let mut copy_size = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    copy_offset |= (*delta.get(pos).ok_or_else(corrupt)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    copy_size |= (*delta.get(pos).ok_or_else(corrupt)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+            let chunk = base
+                .get(copy_offset..copy_offset + copy_size)
+                .ok_or_else(corrupt)?;
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            let chunk = delta.get(pos..pos + op as usize).ok_or_else(corrupt)?;
+            result.extend_from_slice(chunk);
+            pos += op as usize;
+        } else {
+            return Err(corrupt());
+        }
+    }
+    if result.len() != result_size {
+        return Err(corrupt());
+    }
+    Ok(result)
+}
+
+/// One entry of a tree object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry<'a> {
+    pub mode: u32,
+    pub name: &'a [u8],
+    pub oid: ObjectId,
+}
+
+impl TreeEntry<'_> {
+    pub fn is_tree(&self) -> bool {
+        self.mode == 0o040000
+    }
+}
+
+/// Iterates over the entries of a raw tree object.
+pub fn tree_entries(data: &[u8]) -> impl Iterator<Item = Result<TreeEntry<'_>>> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        if pos >= data.len() {
+            return None;
+        }
+        let entry = (|| {
+            let rest = &data[pos..];
+            let space = rest
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or_else(|| Error::InvalidData("malformed tree entry".to_string()))?;
+            let nul = rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| Error::InvalidData("malformed tree entry".to_string()))?;
+            let mode = std::str::from_utf8(&rest[..space])
+                .ok()
+                .and_then(|m| u32::from_str_radix(m, 8).ok())
+                .ok_or_else(|| Error::InvalidData("malformed tree entry mode".to_string()))?;
+            let oid = ObjectId::from_bytes(rest.get(nul + 1..nul + 21).unwrap_or_default())?;
+            pos += nul + 21;
+            Ok(TreeEntry {
+                mode,
+                name: &rest[space + 1..nul],
+                oid,
+            })
+        })();
+        if entry.is_err() {
+            pos = data.len();
+        }
+        Some(entry)
+    })
+}
@@ -0,0 +1,321 @@
+//! Named sets of cone directories, kept in `.pickit/profiles.toml` so they can be committed
+//! and shared. A profile can build on others with `include`, and drop directories it would
+//! get from them with `exclude`:
+//!
+//! ```toml
+//! [profiles.shared]
+//! dirs = ["shared/ui", "shared/proto"]
+//!
+//! [profiles.frontend]
+//! include = ["shared"]
+//! exclude = ["shared/proto"]
+//! dirs = ["web"]
+//! ```
+//!
+//! Directories are written as [`RepoPath::display`] renders them, so unusual names survive
+//! a round trip.
+
+use crate::backend::GitBackend;
+use crate::git;
+use crate::repo_path::RepoPath;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+/// Where profiles are stored, relative to the repository root.
+pub const PROFILES_PATH: &str = ".pickit/profiles.toml";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid {PROFILES_PATH}: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Git(#[from] git::Error),
+    #[error("Unknown profile: {0}")]
+    UnknownProfile(String),
+    #[error("Cannot resolve profile {name}: {}", .errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Resolve { name: String, errors: Vec<ResolveError> },
+}
+
+/// A problem found while resolving the includes and excludes of a profile.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+    #[error("{profile} includes unknown profile {include}")]
+    UnknownInclude { profile: String, include: String },
+    #[error("include cycle {}", .0.join(" → "))]
+    Cycle(Vec<String>),
+    #[error("{} (via profile {}) does not exist at {revision}", .dir.display(), .via.join(" → "))]
+    MissingDir { dir: RepoPath, via: Vec<String>, revision: String },
+    #[error("{profile} cannot exclude {} as it is checked out with {}", .dir.display(), .parent.display())]
+    ExcludedBelowDir { profile: String, dir: RepoPath, parent: RepoPath },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Profiles whose directories are checked out as well.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Directories (and everything below them) dropped from the included profiles.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub dirs: Vec<String>,
+}
+
+impl Profile {
+    pub fn from_dirs(dirs: &[RepoPath]) -> Self {
+        let mut dirs: Vec<String> = dirs.iter().map(|dir| dir.display().into_owned()).collect();
+        dirs.sort();
+        dirs.dedup();
+        Profile { dirs, ..Default::default() }
+    }
+
+    pub fn dirs(&self) -> BTreeSet<RepoPath> {
+        self.dirs.iter().map(|dir| RepoPath::unquote(dir.as_bytes())).collect()
+    }
+
+    fn excluded_dirs(&self) -> Vec<RepoPath> {
+        self.exclude.iter().map(|dir| RepoPath::unquote(dir.as_bytes())).collect()
+    }
+}
+
+/// The cone set a profile stands for once its includes and excludes are applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedProfile {
+    /// Each directory with the chain of profiles it came through, starting with the
+    /// resolved profile and ending with the one that lists it.
+    pub dirs: BTreeMap<RepoPath, Vec<String>>,
+}
+
+impl ResolvedProfile {
+    pub fn dir_set(&self) -> BTreeSet<RepoPath> {
+        self.dirs.keys().cloned().collect()
+    }
+
+    /// How `dir` got into the set, e.g. "via profile backend → shared".
+    pub fn provenance(&self, dir: &RepoPath) -> Option<String> {
+        self.dirs.get(dir).map(|via| format!("via profile {}", via.join(" → ")))
+    }
+}
+
+/// The contents of the profiles file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profiles {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Directories that only one of two profiles checks out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileDiff {
+    pub only_in_a: Vec<RepoPath>,
+    pub only_in_b: Vec<RepoPath>,
+}
+
+impl ProfileDiff {
+    pub fn between(a: &BTreeSet<RepoPath>, b: &BTreeSet<RepoPath>) -> Self {
+        ProfileDiff {
+            only_in_a: a.difference(b).cloned().collect(),
+            only_in_b: b.difference(a).cloned().collect(),
+        }
+    }
+}
+
+impl Profiles {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Reads the profiles of the repository.
+    ///
+    /// The file in the worktree is preferred. As `.pickit/` is outside most cone sets, the
+    /// version committed at `HEAD` is used when the worktree has none.
+    pub fn load(backend: &dyn GitBackend, repo_path: &Path) -> Result<Self> {
+        let text = match fs::read(repo_path.join(PROFILES_PATH)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                match backend.get_file_contents("HEAD", &RepoPath::from(PROFILES_PATH), repo_path)? {
+                    Some(text) => text,
+                    None => return Ok(Profiles::default()),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Self::parse(&String::from_utf8_lossy(&text))
+    }
+
+    /// Writes the profiles to the worktree, creating `.pickit/` if needed.
+    pub fn save(&self, repo_path: &Path) -> Result<()> {
+        let path = repo_path.join(PROFILES_PATH);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self).map_err(|e| std::io::Error::other(e.to_string()))?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| Error::UnknownProfile(name.to_string()))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    /// Resolves the profile `name` into its final cone set.
+    ///
+    /// Every problem is collected rather than stopping at the first: unknown includes,
+    /// include cycles, excludes below a directory that stays checked out, and directories
+    /// that `exists` says are missing at `revision`.
+    pub fn resolve(&self, name: &str, revision: &str, exists: &dyn Fn(&RepoPath) -> bool) -> Result<ResolvedProfile> {
+        self.get(name)?;
+        let mut errors = Vec::new();
+        let dirs = self.resolve_into(name, &mut Vec::new(), &mut errors);
+        for (dir, via) in &dirs {
+            if !exists(dir) {
+                errors.push(ResolveError::MissingDir { dir: dir.clone(), via: via.clone(), revision: revision.to_string() });
+            }
+        }
+        if errors.is_empty() {
+            Ok(ResolvedProfile { dirs })
+        } else {
+            Err(Error::Resolve { name: name.to_string(), errors })
+        }
+    }
+
+    fn resolve_into(
+        &self,
+        name: &str,
+        stack: &mut Vec<String>,
+        errors: &mut Vec<ResolveError>,
+    ) -> BTreeMap<RepoPath, Vec<String>> {
+        let mut dirs = BTreeMap::new();
+        if let Some(start) = stack.iter().position(|entered| entered == name) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(name.to_string());
+            if !errors.contains(&ResolveError::Cycle(cycle.clone())) {
+                errors.push(ResolveError::Cycle(cycle));
+            }
+            return dirs;
+        }
+        let profile = &self.profiles[name];
+        stack.push(name.to_string());
+        for include in &profile.include {
+            if !self.profiles.contains_key(include) {
+                errors.push(ResolveError::UnknownInclude { profile: name.to_string(), include: include.clone() });
+                continue;
+            }
+            for (dir, via) in self.resolve_into(include, stack, errors) {
+                dirs.entry(dir).or_insert_with(|| [name.to_string()].into_iter().chain(via).collect());
+            }
+        }
+        stack.pop();
+        for dir in profile.dirs() {
+            dirs.insert(dir, vec![name.to_string()]);
+        }
+        for excluded in profile.excluded_dirs() {
+            dirs.retain(|dir, _| !dir.starts_with_component(&excluded));
+            if let Some(parent) = dirs.keys().find(|dir| excluded.is_descendant_of(dir)) {
+                errors.push(ResolveError::ExcludedBelowDir { profile: name.to_string(), dir: excluded, parent: parent.clone() });
+            }
+        }
+        dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trip_and_diff() {
+        let text = r#"
+            [profiles.frontend]
+            dirs = ["web", "shared/ui", "\"odd\\nname\""]
+
+            [profiles.backend]
+            dirs = ["server", "shared/ui"]
+        "#;
+        let mut profiles = Profiles::parse(text).unwrap();
+        assert_eq!(profiles.names(), ["backend", "frontend"]);
+        assert!(profiles.get("frontend").unwrap().dirs().contains(&RepoPath::from("odd\nname")));
+
+        let diff = ProfileDiff::between(&profiles.get("frontend").unwrap().dirs(), &profiles.get("backend").unwrap().dirs());
+        assert_eq!(diff.only_in_a, [RepoPath::from("odd\nname"), RepoPath::from("web")]);
+        assert_eq!(diff.only_in_b, [RepoPath::from("server")]);
+        let unknown = profiles.resolve("infra", "HEAD", &|_| true);
+        assert!(matches!(unknown, Err(Error::UnknownProfile(name)) if name == "infra"));
+
+        profiles.profiles.insert("infra".to_string(), Profile::from_dirs(&["ops".into(), "ci".into(), "ops".into()]));
+        let reparsed = Profiles::parse(&toml::to_string(&profiles).unwrap()).unwrap();
+        assert_eq!(reparsed, profiles);
+        assert_eq!(reparsed.get("infra").unwrap().dirs, ["ci", "ops"]);
+        assert!(Profiles::parse("profiles = 3").is_err());
+    }
+
+    #[test]
+    fn test_resolve_includes_and_excludes() {
+        let text = r#"
+            [profiles.shared]
+            dirs = ["shared/ui", "shared/proto", "tools"]
+
+            [profiles.backend]
+            include = ["shared"]
+            exclude = ["shared/ui"]
+            dirs = ["server"]
+
+            [profiles.full]
+            include = ["backend", "shared"]
+            exclude = ["tools"]
+            dirs = ["web"]
+        "#;
+        let profiles = Profiles::parse(text).unwrap();
+        let resolved = profiles.resolve("full", "HEAD", &|_| true).unwrap();
+        let dirs: Vec<&str> = resolved.dirs.keys().map(|dir| std::str::from_utf8(dir.as_bytes()).unwrap()).collect();
+        assert_eq!(dirs, ["server", "shared/proto", "shared/ui", "web"]);
+        assert_eq!(resolved.provenance(&"shared/proto".into()).unwrap(), "via profile full → backend → shared");
+        assert_eq!(resolved.provenance(&"shared/ui".into()).unwrap(), "via profile full → shared");
+        assert_eq!(resolved.provenance(&"web".into()).unwrap(), "via profile full");
+
+        let error = profiles.resolve("backend", "v1", &|dir| dir != &RepoPath::from("server")).unwrap_err();
+        assert_eq!(error.to_string(), "Cannot resolve profile backend: server (via profile backend) does not exist at v1");
+    }
+
+    #[test]
+    fn test_resolve_reports_cycles_and_bad_excludes() {
+        let text = r#"
+            [profiles.a]
+            include = ["b", "missing"]
+            [profiles.b]
+            include = ["c"]
+            dirs = ["lib"]
+            [profiles.c]
+            include = ["b"]
+            exclude = ["lib/sub"]
+        "#;
+        let profiles = Profiles::parse(text).unwrap();
+        let Err(Error::Resolve { name, errors }) = profiles.resolve("a", "HEAD", &|_| true) else {
+            panic!("resolution should fail");
+        };
+        assert_eq!(name, "a");
+        assert_eq!(
+            errors,
+            [
+                ResolveError::Cycle(vec!["b".to_string(), "c".to_string(), "b".to_string()]),
+                ResolveError::UnknownInclude { profile: "a".to_string(), include: "missing".to_string() },
+            ]
+        );
+        assert_eq!(errors[0].to_string(), "include cycle b → c → b");
+
+        let text = "[profiles.a]\ndirs = [\"lib\"]\nexclude = [\"lib/sub\"]\n";
+        let error = Profiles::parse(text).unwrap().resolve("a", "HEAD", &|_| true).unwrap_err();
+        assert_eq!(error.to_string(), "Cannot resolve profile a: a cannot exclude lib/sub as it is checked out with lib");
+    }
+}
@@ -0,0 +1,266 @@
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::PathBuf;
+
+/// A slash-separated path relative to the repository root, kept as the raw bytes git uses.
+///
+/// Git places no encoding requirements on path names, so paths are never decoded except
+/// for display (see [`RepoPath::display`]). The repository root itself is `"."`.
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RepoPath(Vec<u8>);
+
+impl RepoPath {
+    pub fn root() -> Self {
+        RepoPath(b".".to_vec())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0 == b"."
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Appends a single path component, treating the root as an empty prefix.
+    pub fn join(&self, name: &RepoPath) -> RepoPath {
+        if self.is_root() || self.is_empty() {
+            name.clone()
+        } else {
+            RepoPath([self.as_bytes(), b"/", name.as_bytes()].concat())
+        }
+    }
+
+    /// The last component of the path.
+    pub fn file_name(&self) -> RepoPath {
+        let name = self.0.rsplit(|&b| b == b'/').next().unwrap_or_default();
+        RepoPath(name.to_vec())
+    }
+
+    /// The containing directory, which is the root for top-level paths.
+    pub fn parent(&self) -> RepoPath {
+        match self.0.iter().rposition(|&b| b == b'/') {
+            Some(slash) => RepoPath(self.0[..slash].to_vec()),
+            None => RepoPath::root(),
+        }
+    }
+
+    /// Whether `self` equals `prefix` or lies below it, comparing whole components.
+    /// e.g., "foo/bar" starts with "foo" -> true
+    ///       "foo/bar" starts with "foobar" -> false
+    pub fn starts_with_component(&self, prefix: &RepoPath) -> bool {
+        self.0.starts_with(&prefix.0)
+            && (self.0.len() == prefix.0.len() || self.0[prefix.0.len()] == b'/')
+    }
+
+    /// Whether `self` lies strictly below `ancestor`.
+    pub fn is_descendant_of(&self, ancestor: &RepoPath) -> bool {
+        if ancestor.is_root() {
+            return !self.is_root();
+        }
+        self.0.len() > ancestor.0.len() && self.starts_with_component(ancestor)
+    }
+
+    pub fn to_os_string(&self) -> OsString {
+        bytes_to_os_string(&self.0)
+    }
+
+    /// The path relative to the repository root, suitable for joining onto it.
+    pub fn to_path(&self) -> PathBuf {
+        PathBuf::from(self.to_os_string())
+    }
+
+    /// A lossless, human-readable rendering of the path.
+    ///
+    /// Ordinary names are shown as they are. Names containing control characters, quotes,
+    /// backslashes or bytes that are not valid UTF-8 are rendered the way git quotes them
+    /// with `core.quotePath=false`: wrapped in double quotes, with C escapes and octal
+    /// escapes for invalid bytes, so that distinct paths never display the same.
+    pub fn display(&self) -> Cow<'_, str> {
+        let needs_quoting = |c: char| c.is_control() || c == '"' || c == '\\';
+        match std::str::from_utf8(&self.0) {
+            Ok(s) if !s.chars().any(needs_quoting) => Cow::Borrowed(s),
+            _ => Cow::Owned(quote_c_style(&self.0)),
+        }
+    }
+
+    /// Parses a path as printed by git, undoing C-style quoting if present.
+    pub fn unquote(line: &[u8]) -> RepoPath {
+        match line.strip_prefix(b"\"").and_then(|l| l.strip_suffix(b"\"")) {
+            Some(quoted) => RepoPath(unquote_c_style(quoted)),
+            None => RepoPath(line.to_vec()),
+        }
+    }
+}
+
+impl fmt::Debug for RepoPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.display())
+    }
+}
+
+impl From<Vec<u8>> for RepoPath {
+    fn from(bytes: Vec<u8>) -> Self {
+        RepoPath(bytes)
+    }
+}
+
+impl From<&[u8]> for RepoPath {
+    fn from(bytes: &[u8]) -> Self {
+        RepoPath(bytes.to_vec())
+    }
+}
+
+impl From<&str> for RepoPath {
+    fn from(s: &str) -> Self {
+        RepoPath(s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for RepoPath {
+    fn from(s: String) -> Self {
+        RepoPath(s.into_bytes())
+    }
+}
+
+impl From<&OsStr> for RepoPath {
+    fn from(s: &OsStr) -> Self {
+        RepoPath(os_str_to_bytes(s))
+    }
+}
+
+impl PartialEq<str> for RepoPath {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for RepoPath {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+fn quote_c_style(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    // Walk the valid UTF-8 runs, escaping invalid bytes in octal as git does.
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\x07' => out.push_str("\\a"),
+                '\x08' => out.push_str("\\b"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                '\x0b' => out.push_str("\\v"),
+                '\x0c' => out.push_str("\\f"),
+                '\r' => out.push_str("\\r"),
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                c if (c as u32) < 0x80 && c.is_control() => out.push_str(&format!("\\{:03o}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\{b:03o}"));
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn unquote_c_style(quoted: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(quoted.len());
+    let mut i = 0;
+    while i < quoted.len() {
+        let b = quoted[i];
+        i += 1;
+        if b != b'\\' || i == quoted.len() {
+            out.push(b);
+            continue;
+        }
+        let escaped = quoted[i];
+        i += 1;
+        match escaped {
+            b'a' => out.push(0x07),
+            b'b' => out.push(0x08),
+            b't' => out.push(b'\t'),
+            b'n' => out.push(b'\n'),
+            b'v' => out.push(0x0b),
+            b'f' => out.push(0x0c),
+            b'r' => out.push(b'\r'),
+            b'0'..=b'3' if quoted.len() >= i + 2 => {
+                let digits = [escaped, quoted[i], quoted[i + 1]];
+                i += 2;
+                out.push(digits.iter().fold(0u8, |acc, d| acc.wrapping_mul(8).wrapping_add(d - b'0')));
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(unix)]
+pub fn os_str_to_bytes(s: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    s.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn os_str_to_bytes(s: &OsStr) -> Vec<u8> {
+    s.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+#[cfg(unix)]
+pub fn bytes_to_os_string(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).to_os_string()
+}
+
+#[cfg(not(unix))]
+pub fn bytes_to_os_string(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_quotes_only_unusual_names() {
+        assert_eq!(RepoPath::from("src/日本語"), "src/日本語");
+        assert_eq!(RepoPath::from("src/日本語").display(), "src/日本語");
+        assert_eq!(RepoPath::from("dir with spaces").display(), "dir with spaces");
+        assert_eq!(RepoPath::from("new\nline").display(), "\"new\\nline\"");
+        assert_eq!(RepoPath::from(&b"bad\xffbyte"[..]).display(), "\"bad\\377byte\"");
+        assert_eq!(RepoPath::from("back\\slash").display(), "\"back\\\\slash\"");
+    }
+
+    #[test]
+    fn test_unquote_round_trips_display() {
+        for raw in [&b"plain"[..], b"new\nline", b"tab\there", b"bad\xffbyte", b"q\"uote\\", "日本語".as_bytes()] {
+            let path = RepoPath::from(raw);
+            assert_eq!(RepoPath::unquote(path.display().as_bytes()), path);
+        }
+    }
+
+    #[test]
+    fn test_component_relations() {
+        let foo = RepoPath::from("foo");
+        assert!(RepoPath::from("foo/bar").starts_with_component(&foo));
+        assert!(foo.starts_with_component(&foo));
+        assert!(!RepoPath::from("foobar").starts_with_component(&foo));
+        assert!(RepoPath::from("foo/bar").is_descendant_of(&foo));
+        assert!(!foo.is_descendant_of(&foo));
+        assert!(foo.is_descendant_of(&RepoPath::root()));
+        assert_eq!(RepoPath::root().join(&foo), foo);
+        assert_eq!(foo.join(&RepoPath::from("bar")), "foo/bar");
+        assert_eq!(RepoPath::from("foo/bar").file_name(), "bar");
+        assert_eq!(RepoPath::from("foo/bar").parent(), foo);
+        assert!(foo.parent().is_root());
+    }
+}
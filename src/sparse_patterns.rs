@@ -0,0 +1,110 @@
+//! Evaluation of non-cone sparse-checkout patterns.
+//!
+//! The patterns use gitignore syntax, but a match means "check out" and a negated match
+//! means "leave out". A file no pattern decides on inherits the verdict of its closest
+//! decided parent directory, as in git's `path_in_sparse_checkout`.
+
+use crate::ignore::{Pattern, PatternList};
+use crate::repo_path::RepoPath;
+use std::collections::HashMap;
+
+/// How much of a directory the patterns check out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternMatch {
+    Full,
+    Partial,
+    None,
+}
+
+/// Number of files below a directory, and how many of them the patterns check out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchCount {
+    pub matched: usize,
+    pub total: usize,
+}
+
+impl MatchCount {
+    pub fn pattern_match(&self) -> PatternMatch {
+        if self.total > 0 && self.matched == self.total {
+            PatternMatch::Full
+        } else if self.matched > 0 {
+            PatternMatch::Partial
+        } else {
+            PatternMatch::None
+        }
+    }
+}
+
+/// Parses the lines of a sparse-checkout file.
+pub fn parse(lines: &[Vec<u8>]) -> PatternList {
+    let mut list = PatternList::default();
+    list.extend(lines.iter().filter_map(|line| Pattern::parse(line, b"")).collect());
+    list
+}
+
+/// Whether the file at `path` is checked out under `patterns`.
+pub fn is_included(patterns: &PatternList, path: &[u8]) -> bool {
+    let mut candidate = path;
+    let mut is_dir = false;
+    loop {
+        if let Some(excluded) = patterns.is_excluded(candidate, is_dir) {
+            // "Excluded" in gitignore terms is "included" in sparse-checkout terms.
+            return excluded;
+        }
+        match candidate.iter().rposition(|&b| b == b'/') {
+            Some(slash) => candidate = &candidate[..slash],
+            None => return false,
+        }
+        is_dir = true;
+    }
+}
+
+/// Counts matched files for every directory above `files`, including the root.
+pub fn count_matches(patterns: &PatternList, files: &[RepoPath]) -> HashMap<RepoPath, MatchCount> {
+    let mut counts: HashMap<RepoPath, MatchCount> = HashMap::new();
+    for file in files {
+        let included = is_included(patterns, file.as_bytes());
+        let mut dir = file.parent();
+        loop {
+            let count = counts.entry(dir.clone()).or_default();
+            count.total += 1;
+            count.matched += usize::from(included);
+            if dir.is_root() {
+                break;
+            }
+            dir = dir.parent();
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_matches_with_negations() {
+        let lines: Vec<Vec<u8>> = ["/*", "!/*/", "/docs/", "!/docs/drafts/", "*.md"]
+            .iter()
+            .map(|l| l.as_bytes().to_vec())
+            .collect();
+        let patterns = parse(&lines);
+
+        assert!(is_included(&patterns, b"README.md"));
+        assert!(is_included(&patterns, b"docs/guide/intro.txt"));
+        assert!(!is_included(&patterns, b"docs/drafts/wip.txt"));
+        assert!(is_included(&patterns, b"docs/drafts/wip.md"));
+        assert!(!is_included(&patterns, b"src/main.rs"));
+
+        let files: Vec<RepoPath> = ["README.md", "docs/guide/intro.txt", "docs/drafts/wip.txt", "src/main.rs", "src/lib/notes.md"]
+            .into_iter()
+            .map(RepoPath::from)
+            .collect();
+        let counts = count_matches(&patterns, &files);
+        assert_eq!(counts[&RepoPath::root()], MatchCount { matched: 3, total: 5 });
+        assert_eq!(counts[&RepoPath::from("docs/guide")].pattern_match(), PatternMatch::Full);
+        assert_eq!(counts[&RepoPath::from("docs")].pattern_match(), PatternMatch::Partial);
+        assert_eq!(counts[&RepoPath::from("docs/drafts")].pattern_match(), PatternMatch::None);
+        assert_eq!(counts[&RepoPath::from("src")].pattern_match(), PatternMatch::Partial);
+    }
+}
@@ -0,0 +1,1553 @@
+//! Rendering and key handling of the TUI, independent of the terminal they run on.
+
+use crate::app::{self, App};
+use crate::apply_preview::format_size;
+use crate::keymap::{Action, KeyMap, Mode};
+use crate::message_log::{Level, MessageLog};
+use crate::profiles::PROFILES_PATH;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::{Alignment, Margin, Position, Rect};
+use ratatui::{
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, Wrap},
+    Frame,
+};
+
+/// Whether the main loop keeps running after an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// How many lines one notch of the mouse wheel scrolls.
+const WHEEL_LINES: usize = 3;
+
+/// The height of the message log pane, with its borders.
+const LOG_HEIGHT: u16 = 10;
+
+/// Applies a terminal event to the app. `screen_height` sizes page-wise scrolling.
+pub fn handle_event(app: &mut App, event: Event, screen_height: u16) -> Flow {
+    match event {
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.help_scroll.is_some() => {
+            // The key help captures all keys while it is open
+            let page = app.help_page as isize;
+            match app.config.keys.action(Mode::Help, key) {
+                Some(Action::Up) => app.scroll_help(-1),
+                Some(Action::Down) => app.scroll_help(1),
+                Some(Action::PageUp) => app.scroll_help(-page),
+                Some(Action::PageDown) => app.scroll_help(page),
+                Some(Action::Top) => app.scroll_help(isize::MIN),
+                Some(Action::Bottom) => app.scroll_help(isize::MAX),
+                Some(Action::Quit | Action::Cancel | Action::Help) => app.close_help(),
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.revision_picker.is_some() => {
+            // The revision picker captures all keys while it is open
+            app.dismiss_message();
+            if let Some(c) = typed_char(key) {
+                app.revision_picker_input(c);
+                return Flow::Continue;
+            }
+            match app.config.keys.action(Mode::Revisions, key) {
+                Some(Action::Cancel) => app.close_revision_picker(),
+                Some(Action::Confirm) => app.confirm_revision_picker(),
+                Some(Action::Up) => app.revision_picker_move(false),
+                Some(Action::Down) => app.revision_picker_move(true),
+                _ if key.code == KeyCode::Backspace => app.revision_picker_backspace(),
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.apply_preview.is_some() => {
+            // The apply confirmation captures all keys while open
+            match app.config.keys.action(Mode::Dialog, key) {
+                Some(Action::Confirm | Action::Yes) => app.confirm_apply_preview(),
+                Some(Action::Cancel | Action::No) => app.cancel_apply_preview(),
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.sparse_mode_dialog.is_some() => {
+            // The init/disable dialogs capture all keys while open
+            match (app.sparse_mode_dialog, app.config.keys.action(Mode::Dialog, key)) {
+                (Some(app::SparseModeDialog::Init { .. }), Some(Action::Confirm))
+                | (Some(app::SparseModeDialog::ConfirmDisable), Some(Action::Yes)) => app.confirm_sparse_mode_dialog(),
+                (Some(app::SparseModeDialog::Init { .. }), Some(Action::SparseIndex)) => app.toggle_sparse_index_option(),
+                (_, Some(Action::Cancel | Action::No)) => app.close_sparse_mode_dialog(),
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.force_remove_dialog.is_some() => {
+            match app.config.keys.action(Mode::Dialog, key) {
+                Some(Action::Yes) => app.confirm_force_remove(),
+                Some(Action::Cancel | Action::No) => app.cancel_force_remove(),
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.pattern_editor.is_some() => {
+            // The pattern editor captures all keys while it is open
+            app.dismiss_message();
+            let is_typing = app.pattern_editor.as_ref().is_some_and(|editor| editor.input.is_some());
+            if is_typing {
+                if let Some(c) = typed_char(key) {
+                    app.pattern_editor_input(c);
+                    return Flow::Continue;
+                }
+                match app.config.keys.action(Mode::Input, key) {
+                    Some(Action::Cancel) => app.pattern_editor_cancel_edit(),
+                    Some(Action::Confirm) => app.pattern_editor_commit_edit(),
+                    _ if key.code == KeyCode::Backspace => app.pattern_editor_backspace(),
+                    _ => {}
+                }
+            } else {
+                match app.config.keys.action(Mode::Patterns, key) {
+                    Some(Action::Cancel | Action::Patterns) => app.close_pattern_editor(),
+                    Some(Action::Up) => app.pattern_editor_move(false),
+                    Some(Action::Down) => app.pattern_editor_move(true),
+                    Some(Action::Confirm | Action::Edit) => app.pattern_editor_begin_edit(false),
+                    Some(Action::NewLine) => app.pattern_editor_begin_edit(true),
+                    Some(Action::Delete) => app.pattern_editor_delete(),
+                    Some(Action::Negate) => app.pattern_editor_toggle_negation(),
+                    _ => {}
+                }
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.profile_picker.is_some() => {
+            // The profiles popup captures all keys while it is open
+            app.dismiss_message();
+            let is_typing = app.profile_picker.as_ref().is_some_and(|picker| picker.name_input.is_some());
+            if is_typing {
+                if let Some(c) = typed_char(key) {
+                    app.profile_picker_input(c);
+                    return Flow::Continue;
+                }
+                match app.config.keys.action(Mode::Input, key) {
+                    Some(Action::Cancel) => app.close_profile_picker(),
+                    Some(Action::Confirm) => app.profile_picker_commit_save(),
+                    _ if key.code == KeyCode::Backspace => app.profile_picker_backspace(),
+                    _ => {}
+                }
+            } else {
+                match app.config.keys.action(Mode::Profiles, key) {
+                    Some(Action::Cancel | Action::Profiles) => app.close_profile_picker(),
+                    Some(Action::Up) => app.profile_picker_move(false),
+                    Some(Action::Down) => app.profile_picker_move(true),
+                    Some(Action::Confirm) => app.profile_picker_load(),
+                    Some(Action::Save) => app.profile_picker_begin_save(),
+                    Some(Action::Diff) => app.profile_picker_diff(),
+                    _ => {}
+                }
+            }
+        }
+        Event::Key(key)
+            if key.kind == KeyEventKind::Press && app.search.as_ref().is_some_and(|search| search.is_typing) =>
+        {
+            // The search bar captures all keys while typing
+            app.dismiss_message();
+            if let Some(c) = typed_char(key) {
+                app.search_input(c);
+                return Flow::Continue;
+            }
+            match app.config.keys.action(Mode::Search, key) {
+                Some(Action::Cancel) => app.clear_search(),
+                Some(Action::Confirm) => app.confirm_search(),
+                Some(Action::Filter) => app.toggle_search_filter(),
+                Some(Action::Up) => app.search_move(false),
+                Some(Action::Down) => app.search_move(true),
+                _ if key.code == KeyCode::Backspace => app.search_backspace(),
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.visual_anchor.is_some() => {
+            // Visual mode: the cursor extends the range, and actions apply to all of it
+            app.dismiss_message();
+            let tree_view_height = tree_view_height(app, screen_height);
+            match app.config.keys.action(Mode::Visual, key) {
+                Some(Action::Up) => app.move_cursor_up(),
+                Some(Action::Down) => app.move_cursor_down(),
+                Some(Action::PageUp) => app.move_cursor_page_up(tree_view_height),
+                Some(Action::PageDown) => app.move_cursor_page_down(tree_view_height),
+                Some(Action::Expand) => app.expand_selected_item(),
+                Some(Action::Toggle) => {
+                    app.apply_to_visual_range(app::RangeAction::Toggle);
+                }
+                Some(Action::RangeAdd) => {
+                    app.apply_to_visual_range(app::RangeAction::Add);
+                }
+                Some(Action::RangeRemove) => {
+                    app.apply_to_visual_range(app::RangeAction::Remove);
+                }
+                Some(Action::RangeClear) => {
+                    app.apply_to_visual_range(app::RangeAction::Clear);
+                }
+                Some(Action::Visual | Action::Cancel) => app.toggle_visual_mode(),
+                Some(Action::Help) => app.open_help(),
+                Some(Action::Log) => app.toggle_log(),
+                Some(Action::Quit) => return Flow::Quit,
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press && app.focus != app::Focus::Tree => {
+            // The grid's list of uncommitted files or the message log takes the navigation
+            // keys while focused
+            app.dismiss_message();
+            let (page, scroll): (usize, fn(&mut App, isize)) = match app.focus {
+                app::Focus::Log => (app.log_page, App::scroll_log),
+                _ => (app.uncommitted_page, App::scroll_uncommitted_files),
+            };
+            let page = page as isize;
+            match app.config.keys.action(Mode::List, key) {
+                Some(Action::Up) => scroll(app, -1),
+                Some(Action::Down) => scroll(app, 1),
+                Some(Action::PageUp) => scroll(app, -page),
+                Some(Action::PageDown) => scroll(app, page),
+                Some(Action::Top) => scroll(app, isize::MIN),
+                Some(Action::Bottom) => scroll(app, isize::MAX),
+                Some(Action::Focus | Action::Cancel) => app.cycle_focus(),
+                Some(Action::Help) => app.open_help(),
+                Some(Action::Log) => app.toggle_log(),
+                Some(Action::Quit) => return Flow::Quit,
+                _ => {}
+            }
+        }
+        Event::Key(key) if key.kind == KeyEventKind::Press => {
+            // Clear error on any key press
+            app.dismiss_message();
+
+            // Normal application key handling
+            let tree_view_height = tree_view_height(app, screen_height);
+            match app.config.keys.action(Mode::Tree, key) {
+                Some(Action::Quit) => return Flow::Quit,
+                Some(Action::Up) => app.move_cursor_up(),
+                Some(Action::Down) => app.move_cursor_down(),
+                Some(Action::PageUp) => app.move_cursor_page_up(tree_view_height),
+                Some(Action::PageDown) => app.move_cursor_page_down(tree_view_height),
+                Some(Action::Expand) => {
+                    app.expand_selected_item();
+                }
+                Some(Action::Collapse) => {
+                    app.handle_left_key();
+                }
+                Some(Action::Toggle) => app.toggle_selection(),
+                Some(Action::Focus) => app.cycle_focus(),
+                Some(Action::SelectSubtree) => {
+                    app.select_subtree();
+                }
+                Some(Action::SelectChildren) => {
+                    app.select_children();
+                }
+                Some(Action::Invert) => {
+                    app.invert_subtree();
+                }
+                Some(Action::AddMatches) => {
+                    app.select_search_matches();
+                }
+                Some(Action::Apply) => app.preview_changes(), // Confirmed before applying
+                Some(Action::Redo) => app.redo(),
+                Some(Action::Refresh) => app.refresh(),
+                Some(Action::Undo) => app.undo(),
+                Some(Action::Revert) => app.revert_last_apply(),
+                Some(Action::Revision) => app.open_revision_picker(),
+                Some(Action::Patterns) => app.open_pattern_editor(),
+                Some(Action::Sizes) => app.toggle_size_columns(),
+                Some(Action::Init) => app.open_init_dialog(),
+                Some(Action::Disable) => app.open_disable_dialog(),
+                Some(Action::ForceRemove) => app.open_force_remove_dialog(),
+                Some(Action::Profiles) => app.open_profile_picker(),
+                Some(Action::Visual) => app.toggle_visual_mode(),
+                Some(Action::Search) => app.open_search(),
+                Some(Action::NextMatch) => app.search_move(true),
+                Some(Action::PrevMatch) => app.search_move(false),
+                Some(Action::Cancel) => app.clear_search(),
+                Some(Action::Help) => app.open_help(),
+                Some(Action::Log) => app.toggle_log(),
+                _ => {}
+            }
+        }
+        Event::Mouse(mouse) if !is_popup_open(app) => handle_mouse(app, mouse),
+        _ => {} // Other events like resize etc.
+    }
+    Flow::Continue
+}
+
+/// The character a key types where text is entered: any character without Ctrl or Alt.
+fn typed_char(key: KeyEvent) -> Option<char> {
+    match key.code {
+        KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => Some(c),
+        _ => None,
+    }
+}
+
+/// How many tree rows fit between the tree's borders, above the log pane and the footer.
+fn tree_view_height(app: &App, screen_height: u16) -> u16 {
+    let log_height = if app.show_log { LOG_HEIGHT } else { 0 };
+    screen_height.saturating_sub(3).saturating_sub(log_height).saturating_sub(2)
+}
+
+/// Whether a popup or dialog covers the panels and takes the input.
+fn is_popup_open(app: &App) -> bool {
+    app.help_scroll.is_some()
+        || app.revision_picker.is_some()
+        || app.apply_preview.is_some()
+        || app.sparse_mode_dialog.is_some()
+        || app.force_remove_dialog.is_some()
+        || app.pattern_editor.is_some()
+        || app.profile_picker.is_some()
+}
+
+/// Clicks focus the panel under the mouse and act on the tree row under it, and the wheel
+/// scrolls the panel under it. Hit-testing uses where the last frame drew the panels.
+fn handle_mouse(app: &mut App, mouse: MouseEvent) {
+    let position = Position::new(mouse.column, mouse.row);
+    let layout = app.layout;
+    match mouse.kind {
+        MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+            let down = mouse.kind == MouseEventKind::ScrollDown;
+            let lines = if down { WHEEL_LINES as isize } else { -(WHEEL_LINES as isize) };
+            if layout.files.contains(position) {
+                app.scroll_uncommitted_files(lines);
+            } else if layout.log.contains(position) {
+                app.scroll_log(lines);
+            } else if layout.tree.contains(position) {
+                // The list keeps the cursor in view, so the wheel moves the cursor
+                for _ in 0..WHEEL_LINES {
+                    if down {
+                        app.move_cursor_down();
+                    } else {
+                        app.move_cursor_up();
+                    }
+                }
+            }
+        }
+        MouseEventKind::Down(MouseButton::Left) => {
+            app.dismiss_message();
+            if layout.grid.contains(position) {
+                app.set_focus(app::Focus::Grid);
+            } else if layout.log.contains(position) {
+                app.set_focus(app::Focus::Log);
+            } else if layout.tree.contains(position) {
+                app.set_focus(app::Focus::Tree);
+                let rows = layout.tree.inner(Margin::new(1, 1));
+                if rows.contains(position) {
+                    let view_idx = layout.tree_offset + (position.y - rows.y) as usize;
+                    click_tree_row(app, view_idx, position.x - rows.x);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Selects the clicked row, and expands or toggles it when the click hit its glyphs.
+fn click_tree_row(app: &mut App, view_idx: usize, column: u16) {
+    let Some(item) = app.get_tui_tree_items().into_iter().nth(view_idx) else {
+        return; // Below the last row
+    };
+    app.select_row(view_idx);
+    if item.expansion_columns.contains(&column) {
+        app.toggle_selected_expansion();
+    } else if item.state_columns.contains(&column) {
+        if app.visual_anchor.is_some() {
+            app.apply_to_visual_range(app::RangeAction::Toggle);
+        } else {
+            app.toggle_selection();
+        }
+    }
+}
+
+/// Draws the whole screen for the current state of the app.
+pub fn draw(f: &mut Frame, app: &mut App, list_state: &mut ListState) {
+    app.layout = app::ScreenLayout::default(); // Until the panels are drawn
+    if app.is_applying_changes {
+        draw_progress(f, "Applying Changes", "Applying changes... Please wait.");
+    } else if app.is_previewing {
+        draw_progress(f, "Preview", "Computing pending changes... Please wait.");
+    } else if app.is_changing_sparse_mode {
+        draw_progress(f, "Sparse Checkout", "Updating the working tree... Please wait.");
+    } else if app.is_refreshing {
+        draw_progress(f, "Refreshing", "Refreshing application state... Please wait.");
+    } else if let Some(revision) = &app.loading_revision {
+        draw_progress(f, "Browse Revision", &format!("Loading the tree of {revision}... Please wait."));
+    } else if app.is_listing_revisions {
+        draw_progress(f, "Browse Revision", "Listing branches and tags... Please wait.");
+    } else {
+        // Render the main TUI
+        let size = f.area();
+
+        // Define main layout (main_area + message log + footer)
+        let log_height = if app.show_log { LOG_HEIGHT } else { 0 };
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(log_height), Constraint::Length(3)])
+            .split(size);
+
+        let main_area = chunks[0];
+        let log_area = chunks[1];
+        let footer_area = chunks[2];
+
+        // Split main_area into tree (left) and grid (right)
+        let main_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(main_area);
+
+        let tree_area = main_chunks[0];
+        let grid_area = main_chunks[1];
+
+        // --- Tree View ---
+        let tree_items_vm = app.get_tui_tree_items();
+        let inner_width = tree_area.width.saturating_sub(2) as usize;
+        let tree_items: Vec<ListItem> = tree_items_vm
+            .into_iter()
+            .map(|vm| {
+                let mut line = Line::from(vm.display_text);
+                if !vm.size_text.is_empty() {
+                    // Right-align the sizes, keeping at least one space after the name
+                    let padding = inner_width.saturating_sub(line.width() + vm.size_text.len()).max(1);
+                    line.push_span(Span::raw(" ".repeat(padding)));
+                    line.push_span(Span::raw(vm.size_text));
+                }
+                ListItem::new(line).style(vm.style)
+            })
+            .collect();
+
+        list_state.select(Some(app.selected_item_index));
+
+        let tree_title = if app.revision == "HEAD" {
+            " Tree View ".to_string()
+        } else {
+            format!(" Tree View @ {} ", app.revision)
+        };
+        let tree_list = List::new(tree_items)
+            .block(Block::default().borders(Borders::ALL).title(tree_title));
+
+        f.render_stateful_widget(tree_list, tree_area, list_state);
+        app.layout.tree = tree_area;
+        app.layout.tree_offset = list_state.offset();
+        app.layout.grid = grid_area;
+
+        // --- Grid View ---
+        let grid_title = " Grid View ";
+        if let Some(grid_vm) = app.get_grid_view_model() {
+            let mut rows = vec![
+                Row::new(vec![Cell::new("Name"), Cell::new(grid_vm.name)]),
+                Row::new(vec![Cell::new("Path"), Cell::new(grid_vm.path)]),
+                Row::new(vec![Cell::new("Status"), Cell::new(grid_vm.status)]),
+                Row::new(vec![
+                    Cell::new("Uncommitted"),
+                    Cell::new(grid_vm.uncommitted),
+                ]),
+                Row::new(vec![
+                    Cell::new("Subdirectories (Total)"),
+                    Cell::new(grid_vm.subdirectories_total),
+                ]),
+                Row::new(vec![
+                    Cell::new("Subdirectories (Checked Out)"),
+                    Cell::new(grid_vm.subdirectories_checked_out),
+                ]),
+                Row::new(vec![
+                    Cell::new("Pending Changes"),
+                    Cell::new(grid_vm.pending_changes),
+                ]),
+                Row::new(vec![Cell::new("Files"), Cell::new(grid_vm.files)]),
+                Row::new(vec![Cell::new("On Disk"), Cell::new(grid_vm.on_disk)]),
+                Row::new(vec![Cell::new("Revision"), Cell::new(grid_vm.revision)]),
+            ];
+            if !grid_vm.profile.is_empty() {
+                rows.push(Row::new(vec![Cell::new("Profile"), Cell::new(grid_vm.profile)]));
+            }
+            if !grid_vm.in_head.is_empty() {
+                let style = if grid_vm.in_head.starts_with("No") {
+                    Style::default().fg(Color::Yellow)
+                } else {
+                    Style::default()
+                };
+                rows.push(Row::new(vec![Cell::new("In HEAD"), Cell::new(grid_vm.in_head)]).style(style));
+            }
+
+            // The uncommitted files get the space below the table
+            let grid_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(rows.len() as u16 + 2), Constraint::Min(3)])
+                .split(grid_area);
+
+            let table = Table::new(
+                rows,
+                &[Constraint::Percentage(50), Constraint::Percentage(50)],
+            )
+            .block(Block::default().borders(Borders::ALL).title(grid_title));
+            f.render_widget(table, grid_chunks[0]);
+
+            // --- Uncommitted Files ---
+            let files_area = grid_chunks[1];
+            app.layout.files = files_area;
+            let visible_lines = files_area.height.saturating_sub(2) as usize;
+            let file_count = grid_vm.uncommitted_files.len();
+            app.uncommitted_page = visible_lines.max(1);
+            app.uncommitted_scroll = app.uncommitted_scroll.min(file_count.saturating_sub(visible_lines));
+            let lines: Vec<Line> = grid_vm
+                .uncommitted_files
+                .into_iter()
+                .map(|file| {
+                    let line = Line::from(format!("{} {}", file.status, file.path));
+                    if file.blocks_removal {
+                        line.style(Style::default().fg(Color::Red))
+                    } else {
+                        line
+                    }
+                })
+                .collect();
+            let files_title = if app.focus == app::Focus::Grid && file_count > visible_lines {
+                format!(
+                    " Uncommitted Files ({}-{} of {file_count}) ",
+                    app.uncommitted_scroll + 1,
+                    (app.uncommitted_scroll + visible_lines).min(file_count)
+                )
+            } else {
+                format!(" Uncommitted Files ({file_count}) ")
+            };
+            let border_style = if app.focus == app::Focus::Grid {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            let files_list = Paragraph::new(lines).scroll((app.uncommitted_scroll as u16, 0)).block(
+                Block::default().borders(Borders::ALL).border_style(border_style).title(files_title),
+            );
+            f.render_widget(files_list, files_area);
+        } else {
+            let grid_block = Block::default().borders(Borders::ALL).title(grid_title);
+            f.render_widget(grid_block, grid_area);
+        }
+
+        // --- Message Log ---
+        if app.show_log {
+            app.log.mark_read(); // Shown, so read
+            app.layout.log = log_area;
+            let lines = log_lines(&app.log);
+            let visible_lines = log_area.height.saturating_sub(2) as usize;
+            let max_scroll = lines.len().saturating_sub(visible_lines);
+            app.log_page = visible_lines.max(1);
+            app.log_scroll = app.log_scroll.min(max_scroll);
+            let top = max_scroll - app.log_scroll; // The log follows its newest line unless scrolled up
+            let title = if lines.len() > visible_lines {
+                format!(" Message Log (lines {}-{} of {}) ", top + 1, top + visible_lines, lines.len())
+            } else {
+                format!(" Message Log ({}) ", app.log.entries().len())
+            };
+            let border_style = if app.focus == app::Focus::Log {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            let log = Paragraph::new(lines)
+                .scroll((top as u16, 0))
+                .block(Block::default().borders(Borders::ALL).border_style(border_style).title(title));
+            f.render_widget(log, log_area);
+        }
+
+        // --- Footer ---
+        let keys = &app.config.keys;
+        let footer_text = if let Some((_, message)) = app.message() {
+            match message.split_once('\n') {
+                Some((first_line, _)) => format!("{first_line} … {} Log", keys.hint(Action::Log)),
+                None => message.to_string(),
+            }
+        } else if app.focus == app::Focus::Log {
+            key_hints(keys, &[
+                (&[Action::Focus, Action::Cancel], "Tree"),
+                (&[Action::Up, Action::Down], "Scroll"),
+                (&[Action::PageUp, Action::PageDown], "Page"),
+                (&[Action::Top, Action::Bottom], "Oldest/Newest"),
+                (&[Action::Log], "Hide"),
+                (&[Action::Quit], "Quit"),
+                (&[Action::Help], "Help"),
+            ])
+        } else if app.focus == app::Focus::Grid {
+            key_hints(keys, &[
+                (&[Action::Focus, Action::Cancel], "Tree"),
+                (&[Action::Up, Action::Down], "Scroll"),
+                (&[Action::PageUp, Action::PageDown], "Page"),
+                (&[Action::Top, Action::Bottom], "Top/Bottom"),
+                (&[Action::Quit], "Quit"),
+                (&[Action::Help], "Help"),
+            ])
+        } else if app.visual_anchor.is_some() {
+            format!(
+                " -- VISUAL -- {} selected{}",
+                app.visual_range().len(),
+                key_hints(keys, &[
+                    (&[Action::Toggle], "Toggle"),
+                    (&[Action::RangeAdd], "Add"),
+                    (&[Action::RangeRemove], "Remove"),
+                    (&[Action::RangeClear], "Clear"),
+                    (&[Action::Visual, Action::Cancel], "Exit"),
+                    (&[Action::Help], "Help"),
+                ])
+            )
+        } else if let Some(search) = &app.search {
+            let position = if search.matches.is_empty() {
+                "no matches".to_string()
+            } else {
+                format!("{}/{}", search.selected + 1, search.matches.len())
+            };
+            let filter = if search.is_filtering { " filtered" } else { "" };
+            if search.is_typing {
+                let hints = key_hints(keys, &[
+                    (&[Action::Confirm], "Done"),
+                    (&[Action::Filter], "Filter"),
+                    (&[Action::Up, Action::Down], "Match"),
+                    (&[Action::Cancel], "Clear"),
+                ]);
+                format!(" /{} ({position}{filter}){hints}", search.query)
+            } else {
+                let hints = key_hints(keys, &[
+                    (&[Action::NextMatch, Action::PrevMatch], "Next/Prev"),
+                    (&[Action::Search], "New Search"),
+                    (&[Action::Cancel], "Clear"),
+                ]);
+                format!(" /{} ({position}{filter}){hints}", search.query)
+            }
+        } else {
+            key_hints(keys, &[
+                (&[Action::Help], "Help"),
+                (&[Action::Log], "Log"),
+                (&[Action::Quit], "Quit"),
+                (&[Action::Toggle], "Toggle"),
+                (&[Action::SelectSubtree, Action::SelectChildren, Action::Invert], "Subtree/Children/Invert"),
+                (&[Action::AddMatches], "Add Matches"),
+                (&[Action::Visual], "Visual"),
+                (&[Action::Undo, Action::Redo], "Undo/Redo"),
+                (&[Action::Apply], "Apply"),
+                (&[Action::Revert], "Revert"),
+                (&[Action::Refresh], "Refresh"),
+                (&[Action::Revision], "Revision"),
+                (&[Action::Patterns], "Patterns"),
+                (&[Action::Sizes], "Sizes"),
+                (&[Action::Init], "Init"),
+                (&[Action::Disable], "Disable"),
+                (&[Action::ForceRemove], "Force Remove"),
+                (&[Action::Profiles], "Profiles"),
+                (&[Action::Search], "Search"),
+                (&[Action::Focus], "Files"),
+                (&[Action::Up, Action::Down], "Navigate"),
+                (&[Action::Expand], "Expand"),
+                (&[Action::Collapse], "Coll/Parent"),
+                (&[Action::PageUp, Action::PageDown], "Scroll"),
+            ])
+        };
+        let footer_style = app.message().map_or_else(Style::default, |(level, _)| level_style(level));
+        let footer_block = Block::default().borders(Borders::ALL).title(footer_text).title_style(footer_style);
+        f.render_widget(Paragraph::new(status_line(app)).block(footer_block), footer_area);
+
+        // --- Revision Picker ---
+        if let Some(picker) = &app.revision_picker {
+            let area = Rect::new(
+                size.width / 4,
+                size.height / 4,
+                size.width / 2,
+                size.height / 2,
+            );
+            let matches = picker.matches();
+            let mut lines = vec![Line::from(format!("> {}", picker.input))];
+            lines.extend(matches.iter().enumerate().map(|(i, candidate)| {
+                let line = Line::from(format!("  {candidate}"));
+                if i == picker.selected {
+                    line.style(Style::default().bg(Color::Blue))
+                } else {
+                    line
+                }
+            }));
+            // Keep the selected candidate (line `selected + 1`) in view
+            let visible_lines = area.height.saturating_sub(2) as usize;
+            let scroll = (picker.selected + 2).saturating_sub(visible_lines) as u16;
+            let paragraph = Paragraph::new(lines).scroll((scroll, 0)).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(
+                        " Browse Revision{}",
+                        key_hints(&app.config.keys, &[(&[Action::Confirm], "Select"), (&[Action::Cancel], "Cancel")])
+                    )),
+            );
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+
+        // --- Apply Confirmation ---
+        if let Some(preview) = &app.apply_preview {
+            let area = Rect::new(
+                size.width / 6,
+                size.height / 6,
+                size.width * 2 / 3,
+                size.height * 2 / 3,
+            );
+            let delta = preview.disk_delta();
+            let mut lines = vec![
+                Line::from(format!(
+                    "Files: +{} / -{}",
+                    preview.files_added, preview.files_removed
+                )),
+                Line::from(format!(
+                    "Disk:  {}{} (+{} / -{})",
+                    if delta < 0 { "-" } else { "+" },
+                    format_size(delta.unsigned_abs()),
+                    format_size(preview.bytes_added),
+                    format_size(preview.bytes_removed)
+                )),
+                Line::from(""),
+            ];
+            lines.extend(preview.added.iter().map(|added| {
+                Line::from(format!("+ {added}")).style(Style::default().fg(Color::Green))
+            }));
+            lines.extend(preview.removed.iter().map(|removed| {
+                Line::from(format!("- {removed}")).style(Style::default().fg(Color::Red))
+            }));
+            let paragraph = Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(
+                        " Apply Changes?{}",
+                        key_hints(&app.config.keys, &[(&[Action::Confirm], "Apply"), (&[Action::Cancel], "Cancel")])
+                    )),
+            );
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+
+        // --- Sparse Checkout Init/Disable Dialog ---
+        if let Some(dialog) = app.sparse_mode_dialog {
+            let area = Rect::new(
+                size.width / 4,
+                size.height / 3,
+                size.width / 2,
+                size.height / 4,
+            );
+            let keys = &app.config.keys;
+            let (title, lines) = match dialog {
+                app::SparseModeDialog::Init { sparse_index } => (
+                    format!(
+                        " Sparse Checkout{}",
+                        key_hints(keys, &[
+                            (&[Action::Confirm], "Initialize"),
+                            (&[Action::SparseIndex], "Sparse Index"),
+                            (&[Action::Cancel], "Not Now"),
+                        ])
+                    ),
+                    vec![
+                        Line::from("This repository does not use sparse-checkout."),
+                        Line::from("Initialize it in cone mode? Only files at the top level stay checked out."),
+                        Line::from(""),
+                        Line::from(format!(
+                            "[{}] Use a sparse index (--sparse-index)",
+                            if sparse_index { "x" } else { " " }
+                        )),
+                    ],
+                ),
+                app::SparseModeDialog::ConfirmDisable => (
+                    format!(
+                        " Disable Sparse Checkout{}",
+                        key_hints(keys, &[(&[Action::Yes], "Disable"), (&[Action::No], "Cancel")])
+                    ),
+                    vec![
+                        Line::from("Disable sparse-checkout?"),
+                        Line::from("Every file of HEAD will be checked out again."),
+                    ],
+                ),
+            };
+            let paragraph = Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+
+        // --- Force-Remove Confirmation ---
+        if let Some(idx) = app.force_remove_dialog {
+            let area = Rect::new(
+                size.width / 4,
+                size.height / 4,
+                size.width / 2,
+                size.height / 2,
+            );
+            let item = &app.items[idx];
+            let mut lines = vec![
+                Line::from(format!("Remove {} from the sparse checkout anyway?", item.path.display())),
+                Line::from("Git keeps files with uncommitted changes on disk until they are committed or discarded."),
+                Line::from(""),
+            ];
+            lines.extend(
+                app.uncommitted_under(&item.path)
+                    .into_iter()
+                    .filter(|(_, status)| status.kinds().iter().any(|kind| kind.blocks_removal()))
+                    .map(|(path, status)| {
+                        Line::from(format!("{} {}", status.as_str(), path.display()))
+                            .style(Style::default().fg(Color::Red))
+                    }),
+            );
+            let paragraph = Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                " Force Remove{}",
+                key_hints(&app.config.keys, &[(&[Action::Yes], "Remove"), (&[Action::No], "Cancel")])
+            )));
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+
+        // --- Pattern Editor ---
+        if let Some(editor) = &app.pattern_editor {
+            let area = Rect::new(
+                size.width / 4,
+                size.height / 4,
+                size.width / 2,
+                size.height / 2,
+            );
+            let patterns = app.pattern_lines();
+            let mut lines: Vec<Line> = patterns
+                .iter()
+                .enumerate()
+                .map(|(i, pattern)| {
+                    let text = match &editor.input {
+                        Some(input) if i == editor.selected && !editor.is_new_line => format!("> {input}"),
+                        _ => format!("  {}", String::from_utf8_lossy(pattern)),
+                    };
+                    let line = Line::from(text);
+                    let is_typing_new_line = editor.input.is_some() && editor.is_new_line;
+                    if i == editor.selected && !is_typing_new_line {
+                        line.style(Style::default().bg(Color::Blue))
+                    } else {
+                        line
+                    }
+                })
+                .collect();
+            if let Some(input) = editor.input.as_ref().filter(|_| editor.is_new_line) {
+                let at = if patterns.is_empty() { 0 } else { editor.selected + 1 };
+                lines.insert(at, Line::from(format!("> {input}")).style(Style::default().bg(Color::Blue)));
+            }
+            let visible_lines = area.height.saturating_sub(2) as usize;
+            let scroll = (editor.selected + 2).saturating_sub(visible_lines) as u16;
+            let hints = if editor.input.is_some() {
+                key_hints(&app.config.keys, &[(&[Action::Confirm], "Done"), (&[Action::Cancel], "Cancel")])
+            } else {
+                key_hints(&app.config.keys, &[
+                    (&[Action::NewLine], "New"),
+                    (&[Action::Confirm], "Edit"),
+                    (&[Action::Delete], "Delete"),
+                    (&[Action::Negate], "Negate"),
+                    (&[Action::Cancel], "Close"),
+                ])
+            };
+            let title = format!(" Sparse Patterns{hints}");
+            let paragraph = Paragraph::new(lines)
+                .scroll((scroll, 0))
+                .block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+
+        // --- Profiles ---
+        if let Some(picker) = &app.profile_picker {
+            let area = Rect::new(
+                size.width / 4,
+                size.height / 4,
+                size.width / 2,
+                size.height / 2,
+            );
+            let (title, lines, scroll) = if let Some((a, b, diff)) = &picker.diff {
+                let mut lines = vec![Line::from(format!("{a} → {b}")), Line::from("")];
+                lines.extend(diff.only_in_b.iter().map(|dir| {
+                    Line::from(format!("+ {}", dir.display())).style(Style::default().fg(Color::Green))
+                }));
+                lines.extend(diff.only_in_a.iter().map(|dir| {
+                    Line::from(format!("- {}", dir.display())).style(Style::default().fg(Color::Red))
+                }));
+                if diff.only_in_a.is_empty() && diff.only_in_b.is_empty() {
+                    lines.push(Line::from("The profiles check out the same directories."));
+                }
+                (format!(" Profile Diff{}", key_hints(&app.config.keys, &[(&[Action::Cancel], "Back")])), lines, 0)
+            } else {
+                let mut lines: Vec<Line> = picker
+                    .profiles
+                    .profiles
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, profile))| {
+                        let marker = if picker.diff_base.as_ref() == Some(name) { "*" } else { " " };
+                        let mut text = format!("{marker} {name} ({} directories", profile.dirs.len());
+                        if !profile.include.is_empty() {
+                            text.push_str(&format!(", includes {}", profile.include.join(", ")));
+                        }
+                        let line = Line::from(text + ")");
+                        if i == picker.selected {
+                            line.style(Style::default().bg(Color::Blue))
+                        } else {
+                            line
+                        }
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    lines.push(Line::from(format!("No profiles in {}", PROFILES_PATH)));
+                }
+                if let Some(input) = &picker.name_input {
+                    lines.push(Line::from(""));
+                    lines.push(Line::from(format!("Save as: {input}")));
+                }
+                let keys = &app.config.keys;
+                let title = if picker.name_input.is_some() {
+                    format!(" Save Profile{}", key_hints(keys, &[(&[Action::Confirm], "Save"), (&[Action::Cancel], "Cancel")]))
+                } else if let Some(base) = &picker.diff_base {
+                    format!(" Diff {base} With{}", key_hints(keys, &[(&[Action::Diff], "Select"), (&[Action::Cancel], "Cancel")]))
+                } else {
+                    let hints = key_hints(keys, &[
+                        (&[Action::Confirm], "Load"),
+                        (&[Action::Save], "Save Current"),
+                        (&[Action::Diff], "Diff"),
+                        (&[Action::Cancel], "Close"),
+                    ]);
+                    format!(" Profiles{hints}")
+                };
+                let visible_lines = area.height.saturating_sub(2) as usize;
+                let scroll = (picker.selected + 1).saturating_sub(visible_lines) as u16;
+                (title, lines, scroll)
+            };
+            let paragraph = Paragraph::new(lines)
+                .scroll((scroll, 0))
+                .block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+
+        // --- Key Help ---
+        if let Some(scroll) = app.help_scroll {
+            let area = Rect::new(
+                size.width / 8,
+                size.height / 8,
+                size.width * 3 / 4,
+                size.height * 3 / 4,
+            );
+            let lines = help_lines(&app.config.keys);
+            let visible_lines = area.height.saturating_sub(2) as usize;
+            let scroll = scroll.min(lines.len().saturating_sub(visible_lines));
+            app.help_scroll = Some(scroll);
+            app.help_page = visible_lines.max(1);
+            let title = format!(
+                " Keys {}-{} of {}{}",
+                scroll + 1,
+                (scroll + visible_lines).min(lines.len()),
+                lines.len(),
+                key_hints(&app.config.keys, &[(&[Action::Up, Action::Down], "Scroll"), (&[Action::Cancel], "Close")])
+            );
+            let paragraph = Paragraph::new(lines)
+                .scroll((scroll as u16, 0))
+                .block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+    }
+}
+
+/// The key help: the actions of each mode with the keys that trigger them, read from the
+/// keymap that dispatches them, so it shows any rebinding.
+fn help_lines(keys: &KeyMap) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for (mode, title) in [
+        (Mode::Tree, "Tree"),
+        (Mode::Visual, "Visual Mode"),
+        (Mode::List, "Uncommitted Files and Log"),
+        (Mode::Search, "Search Bar"),
+        (Mode::Revisions, "Revision Picker"),
+        (Mode::Patterns, "Pattern Editor"),
+        (Mode::Profiles, "Profiles"),
+        (Mode::Input, "Typing a Pattern or Profile Name"),
+        (Mode::Dialog, "Dialogs"),
+        (Mode::Help, "This Help"),
+    ] {
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from(title).style(Style::default().add_modifier(Modifier::BOLD)));
+        for action in Action::ALL.into_iter().filter(|action| action.modes().contains(&mode)) {
+            let bound: Vec<String> = keys.keys(action).iter().map(ToString::to_string).collect();
+            let bound = if bound.is_empty() { "-".to_string() } else { bound.join(", ") };
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {bound:<13} ")),
+                Span::styled(format!("{:<16}", action.name()), Style::default().fg(Color::DarkGray)),
+                Span::raw(action.description(mode)),
+            ]));
+        }
+    }
+    lines
+}
+
+/// The message log, oldest first: one line per entry with its time and level, then its
+/// details indented below it.
+fn log_lines(log: &MessageLog) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for entry in log.entries() {
+        let style = level_style(entry.level);
+        let mut text = entry.text.lines();
+        lines.push(Line::from(vec![
+            Span::styled(format!("{} ", entry.timestamp()), Style::default().fg(Color::DarkGray)),
+            Span::styled(format!("{:<5} {}", entry.level.label(), text.next().unwrap_or_default()), style),
+        ]));
+        lines.extend(text.map(|detail| Line::from(format!("{:10}{detail}", "")).style(style)));
+    }
+    lines
+}
+
+fn level_style(level: Level) -> Style {
+    match level {
+        Level::Info => Style::default(),
+        Level::Warn => Style::default().fg(Color::Yellow),
+        Level::Error => Style::default().fg(Color::Red),
+    }
+}
+
+/// The footer's status line: what pickit is busy with, and the warnings and errors logged
+/// since the message log was last shown. Empty when there is neither.
+fn status_line(app: &App) -> Line<'static> {
+    let mut spans = Vec::new();
+    if app.is_indexing {
+        spans.push(Span::styled(" Indexing directories…", Style::default().fg(Color::Cyan)));
+    }
+    if app.is_loading_sizes {
+        spans.push(Span::styled(" Loading sizes…", Style::default().fg(Color::Cyan)));
+    }
+    let (warnings, errors) = app.log.unread();
+    if let Some(entry) = app.log.latest_problem().filter(|_| warnings + errors > 0) {
+        let mut counts = Vec::new();
+        if errors > 0 {
+            counts.push(format!("{errors} error{}", if errors == 1 { "" } else { "s" }));
+        }
+        if warnings > 0 {
+            counts.push(format!("{warnings} warning{}", if warnings == 1 { "" } else { "s" }));
+        }
+        let style = level_style(if errors > 0 { Level::Error } else { Level::Warn });
+        // The footer's title already shows a message that was just reported
+        let text = match app.message() {
+            Some(_) => format!(" {}", counts.join(", ")),
+            None => format!(" {}: {}", counts.join(", "), entry.summary()),
+        };
+        spans.push(Span::styled(text, style));
+        spans.push(Span::raw(format!(" {} Log", app.config.keys.hint(Action::Log))));
+    }
+    Line::from(spans)
+}
+
+/// Footer hints like ` [A/c/x] Subtree/Children/Invert [q] Quit `, naming the first key
+/// of each action. Actions without a key are left out.
+fn key_hints(keys: &KeyMap, hints: &[(&[Action], &str)]) -> String {
+    let mut text = String::from(" ");
+    for (actions, label) in hints {
+        let bound: Vec<String> = actions.iter().filter_map(|&action| keys.keys(action).first()).map(ToString::to_string).collect();
+        if !bound.is_empty() {
+            text.push_str(&format!("[{}] {label} ", bound.join("/")));
+        }
+    }
+    text
+}
+
+/// A centered box shown instead of the main screen while git is busy.
+fn draw_progress(f: &mut Frame, title: &str, message: &str) {
+    let size = f.area();
+    let block = Block::default().title(title).borders(Borders::ALL);
+    let paragraph = Paragraph::new(message)
+        .style(Style::default().fg(Color::White).bg(Color::Black))
+        .alignment(Alignment::Center)
+        .block(block);
+
+    let area = Rect::new(size.width / 4, size.height / 3, size.width / 2, size.height / 6);
+    f.render_widget(paragraph, area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::CliBackend;
+    use crate::git::tests::{commit_files, git, init_git_repo};
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::{tempdir, TempDir};
+
+    const WIDTH: u16 = 100;
+    const HEIGHT: u16 = 20;
+
+    /// Drives the TUI like `run_app` does, on an in-memory terminal and without timing.
+    struct Harness {
+        terminal: Terminal<TestBackend>,
+        app: App,
+        list_state: ListState,
+        _dir: TempDir,
+    }
+
+    impl Harness {
+        /// A repository named `repo` with `src/app` checked out and one of its files modified.
+        fn new() -> Self {
+            let dir = tempdir().unwrap();
+            let repo = dir.path().join("repo");
+            fs::create_dir(&repo).unwrap();
+            init_git_repo(&repo);
+            commit_files(&repo, &[
+                ("docs/guide.md", "# Guide\n"),
+                ("src/app/main.rs", "fn main() {}\n"),
+                ("src/lib/mod.rs", "pub fn lib() {}\n"),
+                ("web/index.html", "<html></html>\n"),
+            ]);
+            git(&repo, &["sparse-checkout", "set", "--cone", "src/app"]);
+            fs::write(repo.join("src/app/main.rs"), "fn main() { changed() }\n").unwrap();
+
+            let app = App::new(Some(&repo), Arc::new(CliBackend), "HEAD".to_string()).unwrap();
+            let mut harness = Harness {
+                terminal: Terminal::new(TestBackend::new(WIDTH, HEIGHT)).unwrap(),
+                app,
+                list_state: ListState::default(),
+                _dir: dir,
+            };
+            harness.settle();
+            harness
+        }
+
+        fn press(&mut self, code: KeyCode) -> Flow {
+            handle_event(&mut self.app, Event::Key(KeyEvent::from(code)), HEIGHT)
+        }
+
+        fn mouse(&mut self, kind: MouseEventKind, column: u16, row: u16) {
+            let event = MouseEvent { kind, column, row, modifiers: KeyModifiers::NONE };
+            handle_event(&mut self.app, Event::Mouse(event), HEIGHT);
+        }
+
+        fn click(&mut self, column: u16, row: u16) {
+            self.mouse(MouseEventKind::Down(MouseButton::Left), column, row);
+        }
+
+        fn selected_name(&self) -> String {
+            self.app.items[self.app.filtered_item_indices[self.app.selected_item_index]].name.display().into_owned()
+        }
+
+        /// Handles the messages of background work until none is running.
+        fn settle(&mut self) {
+            self.app.wait_until(|app| {
+                !(app.is_refreshing
+                    || app.is_indexing
+                    || app.is_applying_changes
+                    || app.is_previewing
+                    || app.is_changing_sparse_mode
+                    || app.is_loading_sizes
+                    || app.is_listing_revisions
+                    || app.loading_revision.is_some()
+                    || app.items.iter().any(|item| item.is_loading))
+            });
+        }
+
+        /// Draws the screen and returns its lines, without trailing spaces.
+        fn screen(&mut self) -> Vec<String> {
+            let Harness { terminal, app, list_state, .. } = self;
+            terminal.draw(|f| draw(f, app, list_state)).unwrap();
+            let buffer = terminal.backend().buffer();
+            buffer
+                .content
+                .chunks(buffer.area.width as usize)
+                .map(|row| {
+                    let mut line = String::new();
+                    let mut hidden = 0; // Cells covered by a wide character
+                    for cell in row {
+                        if hidden > 0 {
+                            hidden -= 1;
+                            continue;
+                        }
+                        hidden = Span::raw(cell.symbol()).width().saturating_sub(1);
+                        line.push_str(cell.symbol());
+                    }
+                    line.trim_end().to_string()
+                })
+                .collect()
+        }
+
+        /// Selects the visible item with `name`.
+        fn select(&mut self, name: &str) {
+            let idx = self.app.items.iter().position(|item| item.name == name).unwrap();
+            self.app.selected_item_index = self.app.filtered_item_indices.iter().position(|&i| i == idx).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_tree_screen() {
+        let mut harness = Harness::new();
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Right); // Expand src
+        harness.settle();
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Char(' ')); // Mark src/lib to be added
+        assert_eq!(
+            harness.screen(),
+            [
+                "┌ Tree View ─────────────────────────────────────┐┌ Grid View ─────────────────────────────────────┐",
+                "│▾ 🔒 repo                                       ││Name                     lib                    │",
+                "│  ▸ ☐ docs                                      ││Path                     src/lib                │",
+                "│  ▾ 🔒 src                                      ││Status                   Not Checked Out        │",
+                "│    ▸ 🔒 app                                    ││Uncommitted              No                     │",
+                "│    ▸ + lib                                     ││Subdirectories (Total)   0                      │",
+                "│  ▸ ☐ web                                       ││Subdirectories (Checked  0                      │",
+                "│                                                ││Pending Changes          1                      │",
+                "│                                                ││Files                    1 files, 16 B          │",
+                "│                                                ││On Disk                  0 files, 0 B           │",
+                "│                                                ││Revision                 HEAD                   │",
+                "│                                                │└────────────────────────────────────────────────┘",
+                "│                                                │┌ Uncommitted Files (0) ─────────────────────────┐",
+                "│                                                ││                                                │",
+                "│                                                ││                                                │",
+                "│                                                ││                                                │",
+                "└────────────────────────────────────────────────┘└────────────────────────────────────────────────┘",
+                "┌ [?] Help [L] Log [q] Quit [Space] Toggle [A/c/x] Subtree/Children/Invert [+] Add Matches [v] Visu┐",
+                "│                                                                                                  │",
+                "└──────────────────────────────────────────────────────────────────────────────────────────────────┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_grid_screen() {
+        let mut harness = Harness::new();
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Right);
+        harness.settle();
+        harness.select("app");
+        harness.press(KeyCode::Tab);
+        assert_eq!(
+            harness.screen(),
+            [
+                "┌ Tree View ─────────────────────────────────────┐┌ Grid View ─────────────────────────────────────┐",
+                "│▾ 🔒 repo                                       ││Name                     app                    │",
+                "│  ▸ ☐ docs                                      ││Path                     src/app                │",
+                "│  ▾ 🔒 src                                      ││Status                   Locked (modified)      │",
+                "│    ▸ 🔒 app                                    ││Uncommitted              1 file: 1 modified     │",
+                "│    ▸ ☐ lib                                     ││Subdirectories (Total)   0                      │",
+                "│  ▸ ☐ web                                       ││Subdirectories (Checked  0                      │",
+                "│                                                ││Pending Changes          0                      │",
+                "│                                                ││Files                    1 files, 13 B          │",
+                "│                                                ││On Disk                  1 files, 13 B          │",
+                "│                                                ││Revision                 HEAD                   │",
+                "│                                                │└────────────────────────────────────────────────┘",
+                "│                                                │┌ Uncommitted Files (1) ─────────────────────────┐",
+                "│                                                ││.M src/app/main.rs                              │",
+                "│                                                ││                                                │",
+                "│                                                ││                                                │",
+                "└────────────────────────────────────────────────┘└────────────────────────────────────────────────┘",
+                "┌ [Tab/Esc] Tree [↑/↓] Scroll [PgUp/PgDn] Page [Home/End] Top/Bottom [q] Quit [?] Help ────────────┐",
+                "│                                                                                                  │",
+                "└──────────────────────────────────────────────────────────────────────────────────────────────────┘",
+            ]
+        );
+
+        // The tree gets the keys back
+        harness.press(KeyCode::Esc);
+        harness.press(KeyCode::Up);
+        assert_eq!(harness.app.focus, app::Focus::Tree);
+        assert_eq!(harness.app.items[harness.app.filtered_item_indices[harness.app.selected_item_index]].name, "src");
+    }
+
+    #[test]
+    fn test_footer_error_screen() {
+        let mut harness = Harness::new();
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Right);
+        harness.settle();
+        harness.select("app");
+        harness.press(KeyCode::Char(' ')); // Locked by the modified file
+        assert_eq!(
+            harness.screen(),
+            [
+                "┌ Tree View ─────────────────────────────────────┐┌ Grid View ─────────────────────────────────────┐",
+                "│▾ 🔒 repo                                       ││Name                     app                    │",
+                "│  ▸ ☐ docs                                      ││Path                     src/app                │",
+                "│  ▾ 🔒 src                                      ││Status                   Locked (modified)      │",
+                "│    ▸ 🔒 app                                    ││Uncommitted              1 file: 1 modified     │",
+                "│    ▸ ☐ lib                                     ││Subdirectories (Total)   0                      │",
+                "│  ▸ ☐ web                                       ││Subdirectories (Checked  0                      │",
+                "│                                                ││Pending Changes          0                      │",
+                "│                                                ││Files                    1 files, 13 B          │",
+                "│                                                ││On Disk                  1 files, 13 B          │",
+                "│                                                ││Revision                 HEAD                   │",
+                "│                                                │└────────────────────────────────────────────────┘",
+                "│                                                │┌ Uncommitted Files (1) ─────────────────────────┐",
+                "│                                                ││.M src/app/main.rs                              │",
+                "│                                                ││                                                │",
+                "│                                                ││                                                │",
+                "└────────────────────────────────────────────────┘└────────────────────────────────────────────────┘",
+                "┌src/app contains modified files. Press [F] to remove it anyway────────────────────────────────────┐",
+                "│ 1 warning [L] Log                                                                                │",
+                "└──────────────────────────────────────────────────────────────────────────────────────────────────┘",
+            ]
+        );
+
+        // Any key clears the error
+        harness.press(KeyCode::Down);
+        assert!(harness.screen()[17].starts_with("┌ [?] Help [L] Log [q] Quit"));
+    }
+
+    #[test]
+    fn test_applying_screen() {
+        let mut harness = Harness::new();
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Right);
+        harness.settle();
+        harness.select("lib");
+        harness.press(KeyCode::Char(' '));
+        harness.press(KeyCode::Char('a'));
+        assert_eq!(harness.screen()[7], "                         │    Computing pending changes... Please wait.   │");
+        harness.settle();
+        assert_eq!(harness.screen()[3], "│  ▾ 🔒 src     ┌ Apply Changes? [Enter] Apply [Esc] Cancel ─────────────────────┐ecked Out        │");
+
+        harness.press(KeyCode::Enter);
+        assert_eq!(
+            harness.screen(),
+            [
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "                         ┌Applying Changes────────────────────────────────┐",
+                "                         │        Applying changes... Please wait.        │",
+                "                         └────────────────────────────────────────────────┘",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+            ]
+        );
+        harness.settle();
+        assert_eq!(harness.screen()[5], "│    ▸ ✔ lib                                     ││Subdirectories (Total)   0                      │");
+    }
+
+    #[test]
+    fn test_refreshing_screen() {
+        let mut harness = Harness::new();
+        let before = harness.screen();
+        harness.press(KeyCode::Char('r'));
+        assert_eq!(
+            harness.screen(),
+            [
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "                         ┌Refreshing──────────────────────────────────────┐",
+                "                         │  Refreshing application state... Please wait.  │",
+                "                         └────────────────────────────────────────────────┘",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+            ]
+        );
+        harness.settle();
+        assert_eq!(harness.screen(), before);
+        assert_eq!(harness.press(KeyCode::Char('q')), Flow::Quit);
+    }
+
+    #[test]
+    fn test_mouse() {
+        let mut harness = Harness::new();
+        harness.screen();
+
+        // The expansion glyph of src, one level deep
+        harness.click(3, 3);
+        harness.settle();
+        assert_eq!(harness.selected_name(), "src");
+        assert_eq!(harness.screen()[3], "│  ▾ 🔒 src                                      ││Status                   Locked (modified)      │");
+
+        // The state glyph of lib toggles it, its name only selects it
+        harness.click(7, 5);
+        assert_eq!(harness.screen()[5], "│    ▸ + lib                                     ││Subdirectories (Total)   0                      │");
+        harness.click(12, 6);
+        assert_eq!(harness.selected_name(), "web");
+        harness.click(12, 12); // Below the last row
+        assert_eq!(harness.selected_name(), "web");
+
+        // Clicks move the focus, and the wheel scrolls the panel under it
+        harness.click(60, 13);
+        assert_eq!(harness.app.focus, app::Focus::Grid);
+        harness.click(20, 2);
+        assert_eq!(harness.app.focus, app::Focus::Tree);
+        assert_eq!(harness.selected_name(), "docs");
+        harness.mouse(MouseEventKind::ScrollDown, 20, 8);
+        assert_eq!(harness.selected_name(), "lib");
+        harness.mouse(MouseEventKind::ScrollUp, 20, 8);
+        assert_eq!(harness.selected_name(), "docs");
+
+        // On a short screen, rows count from the first one scrolled into view
+        harness.terminal.backend_mut().resize(WIDTH, 8);
+        harness.select("web");
+        harness.screen();
+        assert_eq!(harness.app.layout.tree_offset, 3);
+        harness.click(20, 1);
+        assert_eq!(harness.selected_name(), "app");
+
+        // Popups take the mouse away from the panels
+        harness.press(KeyCode::Char('b'));
+        harness.settle();
+        harness.click(20, 2);
+        assert_eq!(harness.selected_name(), "app");
+    }
+
+    #[test]
+    fn test_config_screen() {
+        let mut harness = Harness::new();
+        harness.app.config.glyphs = crate::config::Glyphs::ASCII;
+        let keys = [("toggle", "x"), ("quit", "ctrl-q")].map(|(action, key)| (action.to_string(), vec![key.to_string()]));
+        harness.app.config.keys.rebind(&keys.into()).unwrap();
+
+        harness.press(KeyCode::Down);
+        assert_eq!(harness.press(KeyCode::Char('q')), Flow::Continue);
+        harness.press(KeyCode::Char(' ')); // Unbound now
+        harness.press(KeyCode::Char('x'));
+        let screen = harness.screen();
+        assert_eq!(screen[1], "│v [!] repo                                      ││Name                     docs                   │");
+        assert_eq!(screen[2], "│  > [+] docs                                    ││Path                     docs                   │");
+        assert!(screen[17].starts_with("┌ [?] Help [L] Log [^Q] Quit [x] Toggle [A/c] Subtree/Children/Invert [+] Add Matches"));
+
+        // Clicks hit the wider glyphs
+        harness.click(5, 2);
+        assert_eq!(harness.app.items[harness.app.path_to_index[&crate::repo_path::RepoPath::from("docs")]].pending_change, None);
+        let quit = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL);
+        assert_eq!(handle_event(&mut harness.app, Event::Key(quit), HEIGHT), Flow::Quit);
+    }
+
+    #[test]
+    fn test_popup_keys() {
+        let mut harness = Harness::new();
+        let keys = [("filter", vec!["ctrl-f"]), ("cancel", vec!["esc", "ctrl-g"]), ("yes", vec!["o"])]
+            .map(|(action, keys)| (action.to_string(), keys.into_iter().map(String::from).collect()));
+        harness.app.config.keys.rebind(&keys.into()).unwrap();
+
+        // While searching, characters are typed even where they are bound elsewhere
+        harness.press(KeyCode::Char('/'));
+        assert_eq!(harness.press(KeyCode::Char('q')), Flow::Continue);
+        harness.press(KeyCode::Backspace);
+        harness.press(KeyCode::Char('s'));
+        assert_eq!(harness.app.search.as_ref().unwrap().query, "s");
+        assert!(harness.screen()[17].starts_with("┌ /s (1/4) [Enter] Done [^F] Filter [↑/↓] Match [Esc] Clear "));
+        harness.press(KeyCode::Tab); // Unbound now
+        assert!(!harness.app.search.as_ref().unwrap().is_filtering);
+        handle_event(&mut harness.app, Event::Key(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::CONTROL)), HEIGHT);
+        assert!(harness.app.search.as_ref().unwrap().is_filtering);
+        handle_event(&mut harness.app, Event::Key(KeyEvent::new(KeyCode::Char('g'), KeyModifiers::CONTROL)), HEIGHT);
+        assert!(harness.app.search.is_none());
+
+        // Dialogs answer to their rebound keys, and their titles show them
+        harness.press(KeyCode::Char('D'));
+        assert!(harness.screen().iter().any(|line| line.contains("Disable Sparse Checkout [o] Disable [n] Cancel")));
+        harness.press(KeyCode::Char('y'));
+        assert_eq!(harness.app.sparse_mode_dialog, Some(app::SparseModeDialog::ConfirmDisable));
+        harness.press(KeyCode::Char('n'));
+        assert_eq!(harness.app.sparse_mode_dialog, None);
+    }
+
+    #[test]
+    fn test_help_screen() {
+        let mut harness = Harness::new();
+        let keys = [("toggle".to_string(), vec!["space".to_string(), "x".to_string()])];
+        harness.app.config.keys.rebind(&keys.into()).unwrap();
+        harness.press(KeyCode::Char('?'));
+        let screen = harness.screen();
+        assert_eq!(
+            screen[2..9],
+            [
+                "│  ▸ ☐ docs ┌ Keys 1-13 of 117 [↑/↓] Scroll [Esc] Close ──────────────────────────────┐            │",
+                "│  ▸ 🔒 src │Tree                                                                     │            │",
+                "│  ▸ ☐ web  │  q             quit            Quit pickit                              │odified     │",
+                "│           │  ↑             up              Move up a line                           │            │",
+                "│           │  ↓             down            Move down a line                         │            │",
+                "│           │  PgUp          page_up         Move up a page                           │            │",
+                "│           │  PgDn          page_down       Move down a page                         │ B          │",
+            ]
+        );
+        // The rebinding shows
+        assert!(screen.contains(&"│           │  Space, x      toggle          Toggle the directory, or the range       │────────────┘".to_string()));
+
+        // Keys scroll the help instead of the tree
+        harness.press(KeyCode::End);
+        let screen = harness.screen();
+        assert!(screen[2].contains("Keys 105-117 of 117"));
+        // Ending with the keys of the help itself, from the table that handles them
+        assert_eq!(
+            screen[6..8],
+            [
+                "│           │This Help                                                                │            │",
+                "│           │  q             quit            Close the help                           │            │",
+            ]
+        );
+        harness.press(KeyCode::PageUp);
+        assert!(harness.screen()[2].contains("Keys 92-104 of 117"));
+        harness.press(KeyCode::Down);
+        harness.press(KeyCode::Char('?'));
+        assert_eq!(harness.app.help_scroll, None);
+        assert_eq!(harness.app.selected_item_index, 0);
+    }
+
+    #[test]
+    fn test_message_log_screen() {
+        let mut harness = Harness::new();
+        harness.app.report(Level::Error, "Git command failed: fatal: bad object\nhint: run git fsck");
+        let screen = harness.screen();
+        assert!(screen[17].starts_with("┌Git command failed: fatal: bad object … [L] Log──"));
+        assert!(screen[18].starts_with("│ 1 error [L] Log "));
+
+        // The footer message goes with the next key, the indicator once the log is shown
+        harness.press(KeyCode::Down);
+        assert!(harness.screen()[18].starts_with("│ 1 error: Git command failed: fatal: bad object [L] Log "));
+        harness.press(KeyCode::Char('L'));
+        let screen = harness.screen();
+        assert_eq!(harness.app.focus, app::Focus::Log);
+        assert!(screen[7].starts_with("┌ Message Log (1) ──"));
+        assert!(screen[8].contains(" ERROR Git command failed: fatal: bad object "));
+        assert!(screen[9].starts_with("│          hint: run git fsck "));
+        assert!(screen[17].contains("[L] Hide"));
+        assert_eq!(screen[18], format!("│{}│", " ".repeat(98)));
+        assert_eq!(harness.app.log.unread(), (0, 0));
+        assert_eq!(tree_view_height(&harness.app, HEIGHT), 5); // Paging the tree skips only what the pane leaves
+
+        // The log follows its newest line until scrolled up
+        for i in 0..20 {
+            harness.app.log.push(Level::Info, format!("entry {i}"));
+        }
+        assert!(harness.screen()[7].starts_with("┌ Message Log (lines 15-22 of 22) ──"));
+        harness.press(KeyCode::Up);
+        harness.mouse(MouseEventKind::ScrollUp, 10, 10);
+        assert!(harness.screen()[7].starts_with("┌ Message Log (lines 11-18 of 22) ──"));
+        harness.press(KeyCode::End);
+        assert!(harness.screen()[15].contains(" INFO  entry 19 "));
+
+        harness.press(KeyCode::Char('L'));
+        assert_eq!((harness.app.show_log, harness.app.focus), (false, app::Focus::Tree));
+        assert_eq!(tree_view_height(&harness.app, HEIGHT), 15);
+        assert_eq!(harness.screen()[7], "│                                                ││Pending Changes          0                      │");
+    }
+}
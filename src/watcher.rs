@@ -0,0 +1,251 @@
+//! Refreshes the app when git changes the repository outside pickit, such as a branch
+//! switch or a `sparse-checkout set` in another terminal.
+//!
+//! Only the files that decide what pickit shows are watched — `HEAD`, the index, the
+//! sparse-checkout file and the branch refs — never the worktree, so builds and editors
+//! do not wake it up.
+
+use crate::app::AppMessage;
+use crate::git;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// How long the repository must stay quiet before a refresh, so that one git command,
+/// which writes several of the watched files, causes one refresh.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Git(#[from] git::Error),
+    #[error("Cannot watch the repository: {0}")]
+    Notify(#[from] notify::Error),
+}
+
+/// The modification time and size of each watched file, or `None` where it is missing.
+/// Git replaces a file to change it, so an equal stamp means git has written none of
+/// them since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoStamp(Vec<(PathBuf, Option<(SystemTime, u64)>)>);
+
+/// The files whose changes trigger a refresh.
+#[derive(Debug, Clone)]
+pub struct WatchedPaths {
+    files: Vec<PathBuf>,  // `HEAD`, `index`, `info/` and `info/sparse-checkout`
+    info_dir: PathBuf,    // Watched once it exists
+    refs_heads: PathBuf, // Any branch below it
+}
+
+impl WatchedPaths {
+    pub fn new(git_dir: &Path, common_dir: &Path) -> Self {
+        let info_dir = git_dir.join("info");
+        WatchedPaths {
+            files: vec![git_dir.join("HEAD"), git_dir.join("index"), info_dir.clone(), info_dir.join("sparse-checkout")],
+            info_dir,
+            refs_heads: common_dir.join("refs").join("heads"),
+        }
+    }
+
+    /// The watched files as they are now, to compare with the stamp of a later
+    /// `AppMessage::RepositoryChanged`.
+    pub fn stamp(&self) -> RepoStamp {
+        let mut refs = Vec::new();
+        let mut dirs = vec![self.refs_heads.clone()];
+        while let Some(dir) = dirs.pop() {
+            for path in fs::read_dir(&dir).into_iter().flatten().flatten().map(|entry| entry.path()) {
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    refs.push(path);
+                }
+            }
+        }
+        refs.sort();
+        let stamps = self.files.iter().cloned().chain(refs).map(|path| {
+            let stamp = fs::metadata(&path).ok().and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+            (path, stamp)
+        });
+        RepoStamp(stamps.collect())
+    }
+
+    /// Whether the event created `info/`, which then needs a watch of its own.
+    fn creates_info_dir(&self, event: &Event) -> bool {
+        matches!(event.kind, EventKind::Create(_) | EventKind::Any) && event.paths.contains(&self.info_dir)
+    }
+
+    /// Whether the event changed a watched file. Reads are ignored, or each refresh
+    /// would trigger the next one, and so are the `*.lock` files git writes before
+    /// renaming them over the real ones.
+    fn is_relevant(&self, event: &Event) -> bool {
+        let is_write = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Any
+        );
+        is_write
+            && event.paths.iter().any(|path| {
+                path.extension().is_none_or(|extension| extension != "lock")
+                    && (self.files.contains(path) || path.starts_with(&self.refs_heads))
+            })
+    }
+}
+
+/// Watches a repository until it is dropped.
+pub struct RepoWatcher {
+    _watcher: Arc<Mutex<RecommendedWatcher>>,
+    paths: WatchedPaths,
+}
+
+impl RepoWatcher {
+    pub fn paths(&self) -> &WatchedPaths {
+        &self.paths
+    }
+}
+
+/// Watches the repository at `repo_path` and sends `AppMessage::RepositoryChanged` to
+/// `tx` after each burst of changes. Watching stops when the returned watcher is dropped.
+pub fn watch(repo_path: &Path, tx: mpsc::Sender<AppMessage>) -> Result<RepoWatcher, Error> {
+    let (git_dir, common_dir) = git::get_git_dirs(repo_path)?;
+    let paths = WatchedPaths::new(&git_dir, &common_dir);
+
+    // Git replaces files by renaming a lock file over them, which drops a watch on the
+    // file itself, so the directories holding them are watched instead.
+    let (event_tx, event_rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(event_tx)?;
+    watcher.watch(&git_dir, RecursiveMode::NonRecursive)?;
+    // `info/` may only be created by the first `sparse-checkout`, after the git dir's
+    // watch is in place to see it appear
+    if paths.info_dir.is_dir() {
+        watcher.watch(&paths.info_dir, RecursiveMode::NonRecursive)?;
+    }
+    if paths.refs_heads.is_dir() {
+        watcher.watch(&paths.refs_heads, RecursiveMode::Recursive)?;
+    }
+
+    // The thread only borrows the watcher, so dropping the `RepoWatcher` still stops it
+    let watcher = Arc::new(Mutex::new(watcher));
+    let weak_watcher = Arc::downgrade(&watcher);
+    let watch_dir = move |dir: &Path| {
+        if let Some(watcher) = weak_watcher.upgrade() {
+            let _ = watcher.lock().unwrap().watch(dir, RecursiveMode::NonRecursive);
+        }
+    };
+    let debounced_paths = paths.clone();
+    thread::spawn(move || debounce(event_rx, &debounced_paths, DEBOUNCE, &tx, watch_dir));
+    Ok(RepoWatcher { _watcher: watcher, paths })
+}
+
+/// Sends one message per burst of relevant events, once no event has arrived for
+/// `quiet`, and calls `watch_dir` with `info/` when it is created. Returns when either
+/// channel is closed.
+fn debounce(
+    events: mpsc::Receiver<notify::Result<Event>>,
+    paths: &WatchedPaths,
+    quiet: Duration,
+    tx: &mpsc::Sender<AppMessage>,
+    watch_dir: impl Fn(&Path),
+) {
+    // Watches `info/` once it appears, and tells whether the event triggers a refresh
+    let handle_event = |event: &notify::Result<Event>| {
+        let Ok(event) = event else {
+            return false;
+        };
+        if paths.creates_info_dir(event) {
+            watch_dir(&paths.info_dir);
+        }
+        paths.is_relevant(event)
+    };
+    while let Ok(event) = events.recv() {
+        if !handle_event(&event) {
+            continue;
+        }
+        loop {
+            match events.recv_timeout(quiet) {
+                Ok(event) => {
+                    handle_event(&event); // Still busy
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        if tx.send(AppMessage::RepositoryChanged(paths.stamp())).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::{commit_files, git, setup_git_repo};
+    use notify::event::{AccessKind, AccessMode, CreateKind, ModifyKind};
+
+    fn event(kind: EventKind, path: &Path) -> notify::Result<Event> {
+        Ok(Event::new(kind).add_path(path.to_path_buf()))
+    }
+
+    #[test]
+    fn test_debounce_sends_one_message_per_burst() {
+        let git_dir = Path::new("/repo/.git");
+        let paths = WatchedPaths::new(git_dir, git_dir);
+        let (event_tx, event_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || debounce(event_rx, &paths, Duration::from_millis(50), &tx, |_| {}));
+
+        let modify = EventKind::Modify(ModifyKind::Any);
+        // Reads, lock files and other files in the git dir are ignored
+        event_tx.send(event(EventKind::Access(AccessKind::Open(AccessMode::Any)), &git_dir.join("index"))).unwrap();
+        event_tx.send(event(EventKind::Create(CreateKind::File), &git_dir.join("index.lock"))).unwrap();
+        event_tx.send(event(modify, &git_dir.join("COMMIT_EDITMSG"))).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        // A burst of writes, as from one `git checkout`, refreshes once
+        event_tx.send(event(modify, &git_dir.join("refs/heads/feature/x"))).unwrap();
+        event_tx.send(event(modify, &git_dir.join("HEAD"))).unwrap();
+        event_tx.send(event(modify, &git_dir.join("index"))).unwrap();
+        assert!(matches!(rx.recv_timeout(Duration::from_secs(2)), Ok(AppMessage::RepositoryChanged(_))));
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        drop(event_tx);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_watch_reports_branch_switch() {
+        let (repo, _temp_dir) = setup_git_repo();
+        commit_files(&repo, &[]);
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = watch(&repo, tx).unwrap();
+        let stamp = watcher.paths().stamp();
+        // Creating a file in the worktree is not watched
+        std::fs::write(repo.join("file.txt"), "content").unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(600)).is_err());
+        assert_eq!(watcher.paths().stamp(), stamp);
+
+        git(&repo, &["checkout", "-q", "-b", "feature"]);
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(AppMessage::RepositoryChanged(changed)) => assert_ne!(changed, stamp),
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_watch_sees_info_dir_created_later() {
+        let (repo, _temp_dir) = setup_git_repo();
+        let info_dir = repo.join(".git/info");
+        std::fs::remove_dir_all(&info_dir).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let _watcher = watch(&repo, tx).unwrap();
+        std::fs::create_dir(&info_dir).unwrap();
+        assert!(matches!(rx.recv_timeout(Duration::from_secs(5)), Ok(AppMessage::RepositoryChanged(_))));
+        std::fs::write(info_dir.join("sparse-checkout"), "/*\n").unwrap();
+        assert!(matches!(rx.recv_timeout(Duration::from_secs(5)), Ok(AppMessage::RepositoryChanged(_))));
+    }
+}